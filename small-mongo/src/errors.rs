@@ -22,6 +22,30 @@ impl ToString for DatabaseConnectionError {
     }
 }
 
+pub enum ConfigError {
+    VarError(std::env::VarError),
+    InvalidValue { key: &'static str, value: String },
+}
+
+impl From<std::env::VarError> for ConfigError {
+    fn from(error: std::env::VarError) -> Self {
+        Self::VarError(error)
+    }
+}
+
+impl ToString for ConfigError {
+    fn to_string(&self) -> String {
+        match self {
+            ConfigError::VarError(error) => {
+                format!("Failed to read environment variable: {}", error)
+            }
+            ConfigError::InvalidValue { key, value } => {
+                format!("Invalid value `{}` for environment variable {}", value, key)
+            }
+        }
+    }
+}
+
 impl<T, E> UnwrapPrint<T> for Result<T, E>
 where
     E: ToString,
@@ -0,0 +1,32 @@
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde_json::Value;
+use wither::bson::doc;
+use wither::mongodb::Database;
+
+pub fn get_router() -> Router<Database> {
+    return Router::new()
+        .route("/healthz", get(get_health))
+        .route("/readyz", get(get_readiness));
+}
+
+pub async fn get_health() -> (StatusCode, Json<Value>) {
+    return (StatusCode::OK, Json(serde_json::json!({"status": "ok"})));
+}
+
+pub async fn get_readiness(State(database): State<Database>) -> (StatusCode, Json<Value>) {
+    let result = database.run_command(doc! {"ping": 1}, None).await;
+    // Probes are unauthenticated, the reason the ping failed only goes to the
+    // log.
+    if let Err(error) = result {
+        tracing::error!("Readiness ping failed: {}", error);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"status": "unavailable", "database": "unavailable"})),
+        );
+    }
+
+    return (
+        StatusCode::OK,
+        Json(serde_json::json!({"status": "ok", "database": "ok"})),
+    );
+}
@@ -1,15 +1,20 @@
 mod errors;
 mod fruit;
+mod health;
 
-use crate::errors::UnwrapPrint;
+use crate::errors::{ConfigError, UnwrapPrint};
 use crate::fruit::Fruit;
 use axum::Router;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
 use wither::mongodb::{Client, Database};
 use wither::prelude::Model;
 
 #[tokio::main]
 async fn main() {
+    tracing_subscriber::fmt::init();
     dotenv::dotenv().expect("Failed to read environment file");
 
     let mongo_url = std::env::var("MONGO_URL").expect("Failed to read MONGO_URL from environment");
@@ -26,15 +31,87 @@ async fn main() {
     sync_models(&database).await.expect("Failed to sync models");
 
     let app = Router::new()
+        .merge(health::get_router())
         .nest("/", fruit::get_router())
         .with_state(database);
 
     let port = get_server_socket_addr().unwrap_print();
-    println!("Server starting on {}", &port);
-    axum::Server::bind(&port)
+    let shutdown_timeout = get_shutdown_timeout().unwrap_print();
+    tracing::info!("Server starting on {}", &port);
+
+    // Once a signal arrives the server stops accepting connections and waits
+    // for in-flight requests, but for no longer than the shutdown timeout.
+    let shutdown_started = Arc::new(Notify::new());
+    let server = axum::Server::bind(&port)
         .serve(app.into_make_service())
-        .await
-        .expect("Failed to start server");
+        .with_graceful_shutdown({
+            let shutdown_started = shutdown_started.clone();
+            async move {
+                shutdown_signal().await;
+                shutdown_started.notify_one();
+            }
+        });
+    let drain_deadline = async {
+        shutdown_started.notified().await;
+        tokio::time::sleep(shutdown_timeout).await;
+    };
+
+    tokio::select! {
+        result = server => result.expect("Failed to start server"),
+        _ = drain_deadline => {
+            tracing::warn!("Requests still running after {:?}, shutting down anyway", shutdown_timeout);
+        }
+    }
+
+    // The driver has no explicit close, dropping the last handle closes its
+    // connection pool.
+    drop(client);
+    tracing::info!("Server stopped");
+}
+
+/// Resolves on the first SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+}
+
+/// Reads `SHUTDOWN_TIMEOUT_SECONDS`, 30 seconds when it is not set. A value
+/// that is not a number of seconds fails startup.
+fn get_shutdown_timeout() -> Result<Duration, ConfigError> {
+    let seconds = match std::env::var("SHUTDOWN_TIMEOUT_SECONDS") {
+        Ok(seconds) => seconds,
+        Err(std::env::VarError::NotPresent) => return Ok(Duration::from_secs(30)),
+        Err(error) => return Err(ConfigError::from(error)),
+    };
+
+    let seconds_result: Result<u64, _> = seconds.trim().parse();
+    match seconds_result {
+        Ok(seconds) => return Ok(Duration::from_secs(seconds)),
+        Err(_) => {
+            return Err(ConfigError::InvalidValue {
+                key: "SHUTDOWN_TIMEOUT_SECONDS",
+                value: seconds,
+            })
+        }
+    }
 }
 
 fn get_server_socket_addr() -> Result<SocketAddr, std::env::VarError> {
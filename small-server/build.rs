@@ -0,0 +1,4 @@
+// Embedded migrations are only picked up again when this file says so.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
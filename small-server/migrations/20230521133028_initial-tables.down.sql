@@ -0,0 +1,4 @@
+DROP TABLE SALAD_INGREDIENTS;
+DROP TABLE FRUIT_SALAD;
+DROP TABLE PERSON;
+DROP TABLE FRUIT;
@@ -0,0 +1,5 @@
+-- Duplicate ingredients merged by the up migration stay merged.
+ALTER TABLE SALAD_INGREDIENTS
+    DROP CONSTRAINT SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE,
+    DROP CONSTRAINT SALAD_INGREDIENTS_QUANTITY_POSITIVE,
+    DROP COLUMN QUANTITY_GRAMS;
@@ -0,0 +1,24 @@
+-- Existing ingredients count as one whole fruit.
+ALTER TABLE SALAD_INGREDIENTS ADD COLUMN QUANTITY_GRAMS INTEGER;
+
+UPDATE SALAD_INGREDIENTS SET QUANTITY_GRAMS = GREATEST(FRUIT.FRUIT_WEIGHT, 1)
+FROM FRUIT
+WHERE FRUIT.ID = SALAD_INGREDIENTS.ID_FRUIT;
+
+-- A fruit added twice to the same salad becomes a single row with the summed quantity.
+UPDATE SALAD_INGREDIENTS SET QUANTITY_GRAMS = TOTALS.QUANTITY_GRAMS
+FROM (SELECT MIN(ID) AS ID, SUM(QUANTITY_GRAMS) AS QUANTITY_GRAMS
+      FROM SALAD_INGREDIENTS
+      GROUP BY ID_SALAD, ID_FRUIT
+      HAVING COUNT(1) > 1) AS TOTALS
+WHERE SALAD_INGREDIENTS.ID = TOTALS.ID;
+
+DELETE FROM SALAD_INGREDIENTS AS DUPLICATE USING SALAD_INGREDIENTS AS KEPT
+WHERE DUPLICATE.ID_SALAD = KEPT.ID_SALAD
+  AND DUPLICATE.ID_FRUIT = KEPT.ID_FRUIT
+  AND DUPLICATE.ID > KEPT.ID;
+
+ALTER TABLE SALAD_INGREDIENTS
+    ALTER COLUMN QUANTITY_GRAMS SET NOT NULL,
+    ADD CONSTRAINT SALAD_INGREDIENTS_QUANTITY_POSITIVE CHECK (QUANTITY_GRAMS > 0),
+    ADD CONSTRAINT SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE UNIQUE (ID_SALAD, ID_FRUIT);
@@ -0,0 +1,3 @@
+DROP TABLE AUTH_SESSION;
+DROP INDEX PERSON_LOGIN_EMAIL_UNIQUE;
+ALTER TABLE PERSON DROP COLUMN PASSWORD_HASH;
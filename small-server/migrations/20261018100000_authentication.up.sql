@@ -0,0 +1,12 @@
+ALTER TABLE PERSON ADD COLUMN PASSWORD_HASH VARCHAR(255);
+
+-- Only people who can log in need a unique email, older rows may share one.
+CREATE UNIQUE INDEX PERSON_LOGIN_EMAIL_UNIQUE ON PERSON (LOWER(EMAIL)) WHERE PASSWORD_HASH IS NOT NULL;
+
+CREATE TABLE AUTH_SESSION (ID bigserial,
+                           ID_PERSON BIGINT NOT NULL,
+                           CREATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                           EXPIRES_AT TIMESTAMPTZ NOT NULL,
+                           REVOKED_AT TIMESTAMPTZ,
+                           PRIMARY KEY(ID),
+                           FOREIGN KEY(ID_PERSON) REFERENCES PERSON(ID));
@@ -0,0 +1,2 @@
+ALTER TABLE PERSON DROP CONSTRAINT PERSON_ROLE_KNOWN;
+ALTER TABLE PERSON DROP COLUMN PERSON_ROLE;
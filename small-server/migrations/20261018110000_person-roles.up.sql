@@ -0,0 +1,5 @@
+-- Everybody starts as a regular user, admins are promoted through
+-- `PUT /person/:id/role` by another admin (or directly in the database for
+-- the first one).
+ALTER TABLE PERSON ADD COLUMN PERSON_ROLE VARCHAR(20) NOT NULL DEFAULT 'user';
+ALTER TABLE PERSON ADD CONSTRAINT PERSON_ROLE_KNOWN CHECK (PERSON_ROLE IN ('user', 'admin'));
@@ -0,0 +1,17 @@
+-- Soft-deleted rows are deleted for good, as they would have been before.
+DELETE FROM AUTH_SESSION WHERE ID_PERSON IN (SELECT ID FROM PERSON WHERE DELETED_AT IS NOT NULL);
+DELETE FROM SALAD_INGREDIENTS WHERE DELETED_AT IS NOT NULL;
+DELETE FROM FRUIT_SALAD WHERE DELETED_AT IS NOT NULL;
+DELETE FROM PERSON WHERE DELETED_AT IS NOT NULL;
+DELETE FROM FRUIT WHERE DELETED_AT IS NOT NULL;
+
+DROP INDEX PERSON_LOGIN_EMAIL_UNIQUE;
+CREATE UNIQUE INDEX PERSON_LOGIN_EMAIL_UNIQUE ON PERSON (LOWER(EMAIL)) WHERE PASSWORD_HASH IS NOT NULL;
+
+DROP INDEX SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE;
+ALTER TABLE SALAD_INGREDIENTS ADD CONSTRAINT SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE UNIQUE (ID_SALAD, ID_FRUIT);
+
+ALTER TABLE SALAD_INGREDIENTS DROP COLUMN CREATED_AT, DROP COLUMN UPDATED_AT, DROP COLUMN DELETED_AT;
+ALTER TABLE FRUIT_SALAD DROP COLUMN CREATED_AT, DROP COLUMN UPDATED_AT, DROP COLUMN DELETED_AT;
+ALTER TABLE PERSON DROP COLUMN CREATED_AT, DROP COLUMN UPDATED_AT, DROP COLUMN DELETED_AT;
+ALTER TABLE FRUIT DROP COLUMN CREATED_AT, DROP COLUMN UPDATED_AT, DROP COLUMN DELETED_AT;
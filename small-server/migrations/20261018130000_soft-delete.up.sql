@@ -0,0 +1,28 @@
+-- Existing rows get the time of the migration, there is no better guess.
+ALTER TABLE FRUIT
+    ADD COLUMN CREATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN UPDATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN DELETED_AT TIMESTAMPTZ;
+
+ALTER TABLE PERSON
+    ADD COLUMN CREATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN UPDATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN DELETED_AT TIMESTAMPTZ;
+
+ALTER TABLE FRUIT_SALAD
+    ADD COLUMN CREATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN UPDATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN DELETED_AT TIMESTAMPTZ;
+
+ALTER TABLE SALAD_INGREDIENTS
+    ADD COLUMN CREATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN UPDATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN DELETED_AT TIMESTAMPTZ;
+
+-- Deleted rows no longer count towards uniqueness, restoring one fails with
+-- a unique violation when a live row took its place.
+ALTER TABLE SALAD_INGREDIENTS DROP CONSTRAINT SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE;
+CREATE UNIQUE INDEX SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE ON SALAD_INGREDIENTS (ID_SALAD, ID_FRUIT) WHERE DELETED_AT IS NULL;
+
+DROP INDEX PERSON_LOGIN_EMAIL_UNIQUE;
+CREATE UNIQUE INDEX PERSON_LOGIN_EMAIL_UNIQUE ON PERSON (LOWER(EMAIL)) WHERE PASSWORD_HASH IS NOT NULL AND DELETED_AT IS NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE FRUIT_SALAD DROP COLUMN ROW_VERSION;
+ALTER TABLE PERSON DROP COLUMN ROW_VERSION;
+ALTER TABLE FRUIT DROP COLUMN ROW_VERSION;
@@ -0,0 +1,5 @@
+-- Bumped on every change to a row, sent as its `ETag` and checked against
+-- `If-Match` before an update.
+ALTER TABLE FRUIT ADD COLUMN ROW_VERSION BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE PERSON ADD COLUMN ROW_VERSION BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE FRUIT_SALAD ADD COLUMN ROW_VERSION BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,31 @@
+-- Partner systems notified of change events. The secret signs every
+-- delivery and is only shown once, when the subscription is created.
+CREATE TABLE WEBHOOK (ID bigserial,
+                      TARGET_URL VARCHAR(500) NOT NULL,
+                      EVENT_TYPES JSONB NOT NULL,
+                      SECRET VARCHAR(100) NOT NULL,
+                      ACTIVE BOOLEAN NOT NULL DEFAULT TRUE,
+                      CREATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                      UPDATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                      PRIMARY KEY(ID));
+
+-- One row per event and subscription, kept as the delivery log. Pending rows
+-- are retried until they are delivered or, after the last attempt, dead.
+CREATE TABLE WEBHOOK_DELIVERY (ID bigserial,
+                               ID_WEBHOOK BIGINT NOT NULL,
+                               EVENT_TYPE VARCHAR(50) NOT NULL,
+                               PAYLOAD JSONB NOT NULL,
+                               STATUS VARCHAR(20) NOT NULL DEFAULT 'pending',
+                               ATTEMPTS INTEGER NOT NULL DEFAULT 0,
+                               NEXT_ATTEMPT_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                               LAST_ATTEMPT_AT TIMESTAMPTZ,
+                               LAST_STATUS_CODE INTEGER,
+                               LAST_ERROR TEXT,
+                               CREATED_AT TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                               DELIVERED_AT TIMESTAMPTZ,
+                               PRIMARY KEY(ID),
+                               FOREIGN KEY(ID_WEBHOOK) REFERENCES WEBHOOK(ID) ON DELETE CASCADE,
+                               CONSTRAINT WEBHOOK_DELIVERY_STATUS_KNOWN CHECK (STATUS IN ('pending', 'delivered', 'dead')));
+
+CREATE INDEX WEBHOOK_DELIVERY_DUE ON WEBHOOK_DELIVERY (NEXT_ATTEMPT_AT) WHERE STATUS = 'pending';
+CREATE INDEX WEBHOOK_DELIVERY_LOG ON WEBHOOK_DELIVERY (ID_WEBHOOK, ID);
@@ -0,0 +1,5 @@
+DROP TABLE AUTH_SESSION;
+DROP TABLE SALAD_INGREDIENTS;
+DROP TABLE FRUIT_SALAD;
+DROP TABLE PERSON;
+DROP TABLE FRUIT;
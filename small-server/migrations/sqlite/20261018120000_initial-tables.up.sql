@@ -0,0 +1,44 @@
+-- The schema of every Postgres migration in `migrations/` up to
+-- `person-roles`, in a single step since SQLite databases start empty.
+-- `INTEGER PRIMARY KEY AUTOINCREMENT` stands in for `bigserial`. Columns are
+-- declared in lowercase because SQLite reports them as declared, and rows are
+-- read by their lowercase field names.
+CREATE TABLE FRUIT (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    fruit_name VARCHAR(100) NOT NULL,
+                    color_red SMALLINT NOT NULL,
+                    color_green SMALLINT NOT NULL,
+                    color_blue SMALLINT NOT NULL,
+                    fruit_weight INTEGER NOT NULL);
+
+CREATE TABLE PERSON (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                     person_name VARCHAR(100) NOT NULL,
+                     age INTEGER NOT NULL,
+                     email VARCHAR(100) NOT NULL,
+                     password_hash VARCHAR(255),
+                     person_role VARCHAR(20) NOT NULL DEFAULT 'user',
+                     CONSTRAINT PERSON_ROLE_KNOWN CHECK (person_role IN ('user', 'admin')));
+
+-- Only people who can log in need a unique email, older rows may share one.
+CREATE UNIQUE INDEX PERSON_LOGIN_EMAIL_UNIQUE ON PERSON (LOWER(email)) WHERE password_hash IS NOT NULL;
+
+CREATE TABLE FRUIT_SALAD (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                          salad_name VARCHAR(100) NOT NULL,
+                          id_creator INTEGER NOT NULL,
+                          FOREIGN KEY(id_creator) REFERENCES PERSON(id));
+
+CREATE TABLE SALAD_INGREDIENTS (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                id_salad INTEGER NOT NULL,
+                                id_fruit INTEGER NOT NULL,
+                                quantity_grams INTEGER NOT NULL,
+                                FOREIGN KEY (id_salad) REFERENCES FRUIT_SALAD(id),
+                                FOREIGN KEY (id_fruit) REFERENCES FRUIT(id),
+                                CONSTRAINT SALAD_INGREDIENTS_QUANTITY_POSITIVE CHECK (quantity_grams > 0),
+                                CONSTRAINT SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE UNIQUE (id_salad, id_fruit));
+
+-- Timestamps are RFC 3339 text, compare them with `julianday()`.
+CREATE TABLE AUTH_SESSION (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                           id_person INTEGER NOT NULL,
+                           created_at TEXT NOT NULL DEFAULT (STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now')),
+                           expires_at TEXT NOT NULL,
+                           revoked_at TEXT,
+                           FOREIGN KEY(id_person) REFERENCES PERSON(id));
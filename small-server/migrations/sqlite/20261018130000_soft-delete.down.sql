@@ -0,0 +1,34 @@
+-- Soft-deleted rows are deleted for good, as they would have been before.
+DELETE FROM AUTH_SESSION WHERE id_person IN (SELECT id FROM PERSON WHERE deleted_at IS NOT NULL);
+DELETE FROM SALAD_INGREDIENTS WHERE deleted_at IS NOT NULL;
+DELETE FROM FRUIT_SALAD WHERE deleted_at IS NOT NULL;
+DELETE FROM PERSON WHERE deleted_at IS NOT NULL;
+DELETE FROM FRUIT WHERE deleted_at IS NOT NULL;
+
+CREATE TABLE SALAD_INGREDIENTS_REBUILT (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                        id_salad INTEGER NOT NULL,
+                                        id_fruit INTEGER NOT NULL,
+                                        quantity_grams INTEGER NOT NULL,
+                                        FOREIGN KEY (id_salad) REFERENCES FRUIT_SALAD(id),
+                                        FOREIGN KEY (id_fruit) REFERENCES FRUIT(id),
+                                        CONSTRAINT SALAD_INGREDIENTS_QUANTITY_POSITIVE CHECK (quantity_grams > 0),
+                                        CONSTRAINT SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE UNIQUE (id_salad, id_fruit));
+
+INSERT INTO SALAD_INGREDIENTS_REBUILT (id, id_salad, id_fruit, quantity_grams)
+SELECT id, id_salad, id_fruit, quantity_grams FROM SALAD_INGREDIENTS;
+
+DROP TABLE SALAD_INGREDIENTS;
+ALTER TABLE SALAD_INGREDIENTS_REBUILT RENAME TO SALAD_INGREDIENTS;
+
+DROP INDEX PERSON_LOGIN_EMAIL_UNIQUE;
+CREATE UNIQUE INDEX PERSON_LOGIN_EMAIL_UNIQUE ON PERSON (LOWER(email)) WHERE password_hash IS NOT NULL;
+
+ALTER TABLE FRUIT_SALAD DROP COLUMN deleted_at;
+ALTER TABLE FRUIT_SALAD DROP COLUMN updated_at;
+ALTER TABLE FRUIT_SALAD DROP COLUMN created_at;
+ALTER TABLE PERSON DROP COLUMN deleted_at;
+ALTER TABLE PERSON DROP COLUMN updated_at;
+ALTER TABLE PERSON DROP COLUMN created_at;
+ALTER TABLE FRUIT DROP COLUMN deleted_at;
+ALTER TABLE FRUIT DROP COLUMN updated_at;
+ALTER TABLE FRUIT DROP COLUMN created_at;
@@ -0,0 +1,40 @@
+-- `ALTER TABLE ADD COLUMN` only takes constant defaults, so existing rows are
+-- stamped afterwards and new rows get their timestamps from the repository.
+ALTER TABLE FRUIT ADD COLUMN created_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE FRUIT ADD COLUMN updated_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE FRUIT ADD COLUMN deleted_at TEXT;
+UPDATE FRUIT SET created_at = STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now'), updated_at = STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now');
+
+ALTER TABLE PERSON ADD COLUMN created_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE PERSON ADD COLUMN updated_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE PERSON ADD COLUMN deleted_at TEXT;
+UPDATE PERSON SET created_at = STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now'), updated_at = STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now');
+
+ALTER TABLE FRUIT_SALAD ADD COLUMN created_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE FRUIT_SALAD ADD COLUMN updated_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE FRUIT_SALAD ADD COLUMN deleted_at TEXT;
+UPDATE FRUIT_SALAD SET created_at = STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now'), updated_at = STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now');
+
+DROP INDEX PERSON_LOGIN_EMAIL_UNIQUE;
+CREATE UNIQUE INDEX PERSON_LOGIN_EMAIL_UNIQUE ON PERSON (LOWER(email)) WHERE password_hash IS NOT NULL AND deleted_at IS NULL;
+
+-- A table constraint cannot be dropped in SQLite, the table is rebuilt with
+-- the unique index limited to live rows instead. Nothing references it.
+CREATE TABLE SALAD_INGREDIENTS_REBUILT (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                        id_salad INTEGER NOT NULL,
+                                        id_fruit INTEGER NOT NULL,
+                                        quantity_grams INTEGER NOT NULL,
+                                        created_at TEXT NOT NULL,
+                                        updated_at TEXT NOT NULL,
+                                        deleted_at TEXT,
+                                        FOREIGN KEY (id_salad) REFERENCES FRUIT_SALAD(id),
+                                        FOREIGN KEY (id_fruit) REFERENCES FRUIT(id),
+                                        CONSTRAINT SALAD_INGREDIENTS_QUANTITY_POSITIVE CHECK (quantity_grams > 0));
+
+INSERT INTO SALAD_INGREDIENTS_REBUILT (id, id_salad, id_fruit, quantity_grams, created_at, updated_at)
+SELECT id, id_salad, id_fruit, quantity_grams, STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now'), STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now')
+FROM SALAD_INGREDIENTS;
+
+DROP TABLE SALAD_INGREDIENTS;
+ALTER TABLE SALAD_INGREDIENTS_REBUILT RENAME TO SALAD_INGREDIENTS;
+CREATE UNIQUE INDEX SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE ON SALAD_INGREDIENTS (id_salad, id_fruit) WHERE deleted_at IS NULL;
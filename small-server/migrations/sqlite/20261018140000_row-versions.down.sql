@@ -0,0 +1,3 @@
+ALTER TABLE FRUIT_SALAD DROP COLUMN row_version;
+ALTER TABLE PERSON DROP COLUMN row_version;
+ALTER TABLE FRUIT DROP COLUMN row_version;
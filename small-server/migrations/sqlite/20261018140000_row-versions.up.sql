@@ -0,0 +1,3 @@
+ALTER TABLE FRUIT ADD COLUMN row_version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE PERSON ADD COLUMN row_version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE FRUIT_SALAD ADD COLUMN row_version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+DROP TABLE WEBHOOK_DELIVERY;
+DROP TABLE WEBHOOK;
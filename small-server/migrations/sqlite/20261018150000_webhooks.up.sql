@@ -0,0 +1,26 @@
+-- Event types are a JSON array, read with `json_each()`.
+CREATE TABLE WEBHOOK (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                      target_url VARCHAR(500) NOT NULL,
+                      event_types TEXT NOT NULL,
+                      secret VARCHAR(100) NOT NULL,
+                      active BOOLEAN NOT NULL DEFAULT TRUE,
+                      created_at TEXT NOT NULL,
+                      updated_at TEXT NOT NULL);
+
+CREATE TABLE WEBHOOK_DELIVERY (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                               id_webhook INTEGER NOT NULL,
+                               event_type VARCHAR(50) NOT NULL,
+                               payload TEXT NOT NULL,
+                               status VARCHAR(20) NOT NULL DEFAULT 'pending',
+                               attempts INTEGER NOT NULL DEFAULT 0,
+                               next_attempt_at TEXT NOT NULL,
+                               last_attempt_at TEXT,
+                               last_status_code INTEGER,
+                               last_error TEXT,
+                               created_at TEXT NOT NULL,
+                               delivered_at TEXT,
+                               FOREIGN KEY(id_webhook) REFERENCES WEBHOOK(id) ON DELETE CASCADE,
+                               CONSTRAINT WEBHOOK_DELIVERY_STATUS_KNOWN CHECK (status IN ('pending', 'delivered', 'dead')));
+
+CREATE INDEX WEBHOOK_DELIVERY_DUE ON WEBHOOK_DELIVERY (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX WEBHOOK_DELIVERY_LOG ON WEBHOOK_DELIVERY (id_webhook, id);
@@ -0,0 +1,245 @@
+use axum::{response::Html, routing::get, Json, Router};
+use utoipa::openapi::path::{Parameter, ParameterBuilder, ParameterIn, PathItemType};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{ObjectBuilder, SchemaType};
+use utoipa::{Modify, OpenApi};
+
+use super::Auth::{Credentials, LoginSession, Registration};
+use super::Authorization::Role;
+use super::Errors::{Problem, ProblemResponse};
+use super::Filter::{FieldKind, FilterField};
+use super::Fruit::{Fruit, FruitPatch, NewFruit, FRUIT_FILTERS};
+use super::Person::{NewPerson, Person, PersonPatch, PersonRole, RoleChange, PERSON_FILTERS};
+use super::Salad::{
+    Color, FruitSalad, FruitSaladPatch, FruitShare, FullFruitSalad, NewFruitSalad,
+    SaladIngredientsView, SaladSummary, SaladView, FRUIT_SALAD_FILTERS,
+};
+use super::SaladIngredient::{NewSaladIngredient, SaladIngredient, SaladIngredientPatch};
+use super::Transfer::{ImportSummary, RowError, TransferFormat};
+use super::Validation::FieldError;
+use super::Webhook::{CreatedWebhook, NewWebhook, Webhook, WebhookDelivery};
+
+/// Body of every list endpoint. `total` is left out with `with_total=false`;
+/// the cursors are only present in cursor mode (`after`/`before`).
+#[derive(serde::Serialize, utoipa::ToSchema)]
+#[aliases(
+    FruitList = ListPage<Fruit>,
+    PersonList = ListPage<Person>,
+    FruitSaladList = ListPage<FruitSalad>,
+    SaladViewList = ListPage<SaladView>,
+    SaladIngredientList = ListPage<SaladIngredient>,
+    SaladIngredientsViewList = ListPage<SaladIngredientsView>,
+    WebhookList = ListPage<Webhook>,
+    WebhookDeliveryList = ListPage<WebhookDelivery>
+)]
+pub struct ListPage<T> {
+    pub hits: Vec<T>,
+    pub total: Option<i64>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "small-server", description = "Fruits, people and the salads they make."),
+    paths(
+        crate::Health::get_health,
+        crate::Health::get_readiness,
+        crate::Auth::register,
+        crate::Auth::login,
+        crate::Auth::logout,
+        crate::Person::list_person,
+        crate::Person::export_person,
+        crate::Person::import_person,
+        crate::Person::insert_person,
+        crate::Person::get_person_by_id,
+        crate::Person::update_person,
+        crate::Person::patch_person,
+        crate::Person::delete_person,
+        crate::Person::update_person_role,
+        crate::Person::restore_person,
+        crate::Salad::list_salads_by_user_id,
+        crate::Fruit::list_fruit,
+        crate::Fruit::export_fruit,
+        crate::Fruit::import_fruit,
+        crate::Fruit::insert_fruit,
+        crate::Fruit::get_fruit_by_id,
+        crate::Fruit::update_fruit,
+        crate::Fruit::patch_fruit,
+        crate::Fruit::delete_fruit,
+        crate::Fruit::restore_fruit,
+        crate::Salad::list_salad,
+        crate::Salad::export_salad,
+        crate::Salad::insert_salad,
+        crate::Salad::get_salad_by_id,
+        crate::Salad::update_salad,
+        crate::Salad::patch_salad,
+        crate::Salad::delete_salad,
+        crate::Salad::restore_salad,
+        crate::Salad::list_salad_all_ingredients,
+        crate::Salad::get_salad_summary,
+        crate::SaladIngredient::list_salad_ingredients,
+        crate::SaladIngredient::insert_salad_ingredient,
+        crate::SaladIngredient::get_salad_ingredient_by_id,
+        crate::SaladIngredient::update_salad_ingredient,
+        crate::SaladIngredient::patch_salad_ingredient,
+        crate::SaladIngredient::delete_salad_ingredient,
+        crate::SaladIngredient::restore_salad_ingredient,
+        crate::GraphQL::execute,
+        crate::Events::stream_events,
+        crate::Events::open_socket,
+        crate::Webhook::list_webhooks,
+        crate::Webhook::get_webhook_by_id,
+        crate::Webhook::insert_webhook,
+        crate::Webhook::update_webhook,
+        crate::Webhook::delete_webhook,
+        crate::Webhook::list_webhook_deliveries,
+        crate::Webhook::get_webhook_delivery_by_id,
+        crate::Webhook::retry_webhook_delivery,
+    ),
+    components(
+        schemas(
+            Registration, Credentials, LoginSession, Role,
+            NewPerson, PersonPatch, Person, RoleChange, PersonRole, PersonList,
+            NewFruit, FruitPatch, Fruit, FruitList,
+            NewFruitSalad, FruitSaladPatch, FruitSalad, FullFruitSalad, FruitSaladList,
+            SaladView, SaladViewList, SaladIngredientsView, SaladIngredientsViewList,
+            Color, FruitShare, SaladSummary,
+            NewSaladIngredient, SaladIngredientPatch, SaladIngredient, SaladIngredientList,
+            TransferFormat, ImportSummary, RowError,
+            NewWebhook, Webhook, CreatedWebhook, WebhookList, WebhookDelivery, WebhookDeliveryList,
+            Problem, FieldError,
+        ),
+        responses(ProblemResponse),
+    ),
+    modifiers(&BearerSecurity, &ListFilters),
+    tags(
+        (name = "health", description = "Liveness and readiness probes"),
+        (name = "auth", description = "Registration and bearer tokens"),
+        (name = "person"),
+        (name = "fruit", description = "The shared fruit catalogue, managed by admins"),
+        (name = "salad", description = "Salads, managed by their creator"),
+        (name = "ingredient", description = "Fruits in a salad, managed by the salad's creator"),
+        (name = "graphql", description = "The same records as one graph, see the schema by introspection"),
+        (name = "events", description = "Changes to every record, as they happen"),
+        (name = "webhooks", description = "Change events POSTed to partner systems, managed by admins"),
+    )
+)]
+pub struct ApiDoc;
+
+struct BearerSecurity;
+
+impl Modify for BearerSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Documents the filter and `sort` query parameters of the list endpoints
+/// from the same whitelists `ListQuery::parse` checks them against.
+struct ListFilters;
+
+impl Modify for ListFilters {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let list_endpoints = [
+            ("/fruit", FRUIT_FILTERS),
+            ("/person", PERSON_FILTERS),
+            ("/salad", FRUIT_SALAD_FILTERS),
+        ];
+        for (path, fields) in list_endpoints {
+            let Some(operation) = openapi
+                .paths
+                .paths
+                .get_mut(path)
+                .and_then(|path_item| path_item.operations.get_mut(&PathItemType::Get))
+            else {
+                continue;
+            };
+            let parameters = operation.parameters.get_or_insert_with(Vec::new);
+            parameters.extend(fields.iter().map(filter_parameter));
+            parameters.push(sort_parameter(fields));
+        }
+    }
+}
+
+fn query_parameter(name: String, schema_type: SchemaType, description: String) -> Parameter {
+    return ParameterBuilder::new()
+        .name(name)
+        .parameter_in(ParameterIn::Query)
+        .description(Some(description))
+        .schema(Some(ObjectBuilder::new().schema_type(schema_type)))
+        .build();
+}
+
+fn filter_parameter(field: &FilterField) -> Parameter {
+    return match field.kind {
+        FieldKind::Text => query_parameter(
+            field.name.to_string(),
+            SchemaType::String,
+            format!(
+                "Exact match. Use `{}~` for a case-insensitive contains.",
+                field.name
+            ),
+        ),
+        FieldKind::Integer => query_parameter(
+            field.name.to_string(),
+            SchemaType::Integer,
+            format!(
+                "Exact match. Use `{0}_gt`, `{0}_gte`, `{0}_lt` or `{0}_lte` for ranges.",
+                field.name
+            ),
+        ),
+    };
+}
+
+fn sort_parameter(fields: &[FilterField]) -> Parameter {
+    let names: Vec<&str> = fields.iter().map(|field| field.name).collect();
+    return query_parameter(
+        String::from("sort"),
+        SchemaType::String,
+        format!(
+            "Comma-separated fields, prefixed with `-` for descending order. One of: {}. \
+             Not available in cursor mode.",
+            names.join(", ")
+        ),
+    );
+}
+
+/// Swagger UI is pinned to an exact release, a floating `@5` would run
+/// whatever unpkg serves next on the docs origin.
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <title>small-server API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui.css"
+        crossorigin="anonymous" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui-bundle.js"
+          crossorigin="anonymous"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+pub fn get_router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    return Router::new()
+        .route("/openapi.json", get(get_openapi))
+        .route("/docs", get(get_docs));
+}
+
+pub async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    return Json(ApiDoc::openapi());
+}
+
+pub async fn get_docs() -> Html<&'static str> {
+    return Html(DOCS_PAGE);
+}
@@ -0,0 +1,2832 @@
+//! End-to-end tests of every route, run against `MemoryRepository` and, with
+//! the `sqlite` feature, an in-memory SQLite database, so they need no
+//! database server. With `DATABASE_URL` set to a Postgres database they also
+//! run against `PostgresRepository`, each test in a schema of its own.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use argon2::password_hash::PasswordHash;
+use argon2::{Argon2, PasswordVerifier};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Router;
+use futures::{SinkExt, StreamExt};
+use hyper::body::HttpBody;
+use serde_json::{json, Value};
+use sqlx::migrate::Migrate;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Connection, PgConnection, Pool, Postgres};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite;
+use tower::ServiceExt;
+use utoipa::OpenApi;
+
+use super::AppState::AppState;
+use super::Auth::{AuthKeys, DUMMY_PASSWORD_HASH};
+use super::Authorization::{promote_admin, Role};
+use super::Config::{Config, CorsOrigins, LogLevel, RateLimits, WebhookSettings};
+use super::Errors::ApiError;
+use super::Events::EventBus;
+use super::MemoryRepository::MemoryRepository;
+use super::Migrations::{run_command, MigrateCommand, MigratedDatabase};
+use super::PostgresRepository::PostgresRepository;
+use super::RateLimit::{limit_in_flight, RateLimit};
+use super::Repository::{
+    Deletion, FruitRepository, PersonRepository, Repository, SaladIngredientRepository,
+    SaladRepository,
+};
+use super::Salad::NewFruitSalad;
+use super::SaladIngredient::NewSaladIngredient;
+use super::Transfer::IMPORT_BATCH_SIZE;
+use super::Webhook::signature;
+
+const PASSWORD: &str = "correct horse battery";
+
+struct TestApp {
+    app: Router,
+    people: Arc<dyn PersonRepository>,
+    /// Dropped with the app when it runs against Postgres.
+    schema: Option<PostgresSchema>,
+}
+
+/// A Postgres schema holding the tables of one test, dropped when the test
+/// is done with it.
+struct PostgresSchema {
+    database_url: String,
+    name: String,
+}
+
+impl Drop for PostgresSchema {
+    fn drop(&mut self) {
+        // `drop` cannot await, so the schema is dropped from a thread with a
+        // runtime of its own. A test that failed may have left a transaction
+        // open on it, so the drop gives up rather than wait for that forever.
+        let database_url = self.database_url.clone();
+        let statement = format!("DROP SCHEMA {} CASCADE", self.name);
+        let dropped = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("a runtime starts");
+            return runtime.block_on(async {
+                let mut connection = PgConnection::connect(&database_url).await?;
+                sqlx::query("SET lock_timeout = '5s'")
+                    .execute(&mut connection)
+                    .await?;
+                sqlx::query(&statement).execute(&mut connection).await?;
+                return connection.close().await;
+            });
+        })
+        .join();
+        if !matches!(dropped, Ok(Ok(()))) {
+            eprintln!("Failed to drop test schema {}", self.name);
+        }
+    }
+}
+
+struct Response {
+    status: StatusCode,
+    content_type: String,
+    etag: Option<String>,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(&self) -> Value {
+        return serde_json::from_slice(&self.body).expect("response body is JSON");
+    }
+
+    fn text(&self) -> String {
+        return String::from_utf8(self.body.clone()).expect("response body is UTF-8");
+    }
+}
+
+/// An open `/events` stream.
+struct EventStream {
+    body: axum::body::BoxBody,
+    buffer: String,
+}
+
+/// An event of `/events`, its data parsed as JSON.
+struct StreamedEvent {
+    name: String,
+    id: Option<String>,
+    data: Value,
+}
+
+impl EventStream {
+    /// The next event, skipping keep-alive comments. Fails after five seconds
+    /// without one.
+    async fn next(&mut self) -> StreamedEvent {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let frame: String = self.buffer.drain(..end + 2).collect();
+                let mut event = StreamedEvent {
+                    name: String::new(),
+                    id: None,
+                    data: Value::Null,
+                };
+                for line in frame.lines() {
+                    let Some((field, value)) = line.split_once(':') else {
+                        continue;
+                    };
+                    let value = value.trim_start();
+                    match field {
+                        "event" => event.name = value.to_string(),
+                        "id" => event.id = Some(value.to_string()),
+                        "data" => event.data = serde_json::from_str(value).expect("data is JSON"),
+                        _ => {}
+                    }
+                }
+                if event.name.is_empty() {
+                    continue;
+                }
+                return event;
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(5), self.body.data())
+                .await
+                .expect("an event arrives in time")
+                .expect("the stream stays open")
+                .expect("the stream is readable");
+            self.buffer
+                .push_str(std::str::from_utf8(&chunk).expect("events are UTF-8"));
+        }
+    }
+}
+
+/// A registered and logged in person.
+struct Login {
+    id: i64,
+    token: String,
+}
+
+impl TestApp {
+    /// A fresh, empty app for each backend.
+    async fn backends() -> Vec<TestApp> {
+        #[allow(unused_mut)]
+        let mut backends = vec![TestApp::new(MemoryRepository::default())];
+        #[cfg(feature = "sqlite")]
+        backends.push(TestApp::new(sqlite_repository().await));
+        if let Some((repository, schema)) = postgres_repository().await {
+            let mut app = TestApp::new(repository);
+            app.schema = Some(schema);
+            backends.push(app);
+        }
+        return backends;
+    }
+
+    fn new<R: Repository>(repository: R) -> TestApp {
+        let rate_limits = RateLimits {
+            person: RateLimit::Off,
+            fruit: RateLimit::Off,
+            salad: RateLimit::Off,
+            ingredient: RateLimit::Off,
+            graphql: RateLimit::Off,
+            auth: RateLimit::Off,
+        };
+        return TestApp::with_rate_limits(repository, &rate_limits);
+    }
+
+    fn with_rate_limits<R: Repository>(repository: R, rate_limits: &RateLimits) -> TestApp {
+        let app_state = AppState {
+            repository: repository.clone(),
+            auth_keys: AuthKeys::new(b"test-secret", Duration::from_secs(3600)),
+            events: EventBus::default(),
+        };
+        // Short delays, so retries and dead deliveries happen within a test.
+        let webhook_settings = WebhookSettings {
+            max_attempts: 3,
+            retry_delay: Duration::from_millis(50),
+            timeout: Duration::from_secs(2),
+        };
+        super::Webhook::spawn_worker(repository.clone(), &app_state.events, webhook_settings);
+        return TestApp {
+            app: super::get_app(app_state, rate_limits, 0),
+            people: Arc::new(repository),
+            schema: None,
+        };
+    }
+
+    async fn send(&self, request: Request<Body>) -> Response {
+        let response = self
+            .app
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("router is infallible");
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .map(|value| value.to_str().unwrap().to_string());
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("response body is readable");
+        return Response {
+            status,
+            content_type,
+            etag,
+            headers,
+            body: body.to_vec(),
+        };
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        login: Option<&Login>,
+        body: Option<Value>,
+    ) -> Response {
+        return self.request_with(method, uri, login, body, &[]).await;
+    }
+
+    async fn request_with(
+        &self,
+        method: Method,
+        uri: &str,
+        login: Option<&Login>,
+        body: Option<Value>,
+        headers: &[(header::HeaderName, &str)],
+    ) -> Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(login) = login {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", login.token));
+        }
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        return self.send(request.body(body).unwrap()).await;
+    }
+
+    async fn get(&self, uri: &str) -> Response {
+        return self.request(Method::GET, uri, None, None).await;
+    }
+
+    /// Sends `method` to `uri` with the `ETag` the row at `uri` has now.
+    async fn update(
+        &self,
+        method: Method,
+        uri: &str,
+        login: Option<&Login>,
+        body: Option<Value>,
+    ) -> Response {
+        let etag = self.get(uri).await.etag.expect("the row has an ETag");
+        return self
+            .request_with(method, uri, login, body, &[(header::IF_MATCH, &etag)])
+            .await;
+    }
+
+    async fn register(&self, email: &str) -> Login {
+        let registration = json!({
+            "person_name": email.split('@').next().unwrap(),
+            "age": 30,
+            "email": email,
+            "password": PASSWORD,
+        });
+        let response = self
+            .request(Method::POST, "/auth/register", None, Some(registration))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+
+        let credentials = json!({ "email": email, "password": PASSWORD });
+        let response = self
+            .request(Method::POST, "/auth/login", None, Some(credentials))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        let session = response.json();
+        return Login {
+            id: session["person"]["id"].as_i64().unwrap(),
+            token: session["token"].as_str().unwrap().to_string(),
+        };
+    }
+
+    /// Admins can only be made by other admins, so the first one is promoted
+    /// directly in the repository.
+    async fn register_admin(&self, email: &str) -> Login {
+        let login = self.register(email).await;
+        let role = self.people.set_person_role(login.id, Role::Admin).await;
+        assert!(matches!(role, Ok(Some(Role::Admin))));
+        return login;
+    }
+
+    /// Runs a GraphQL request, which answers 200 even when it has errors.
+    async fn graphql(&self, login: Option<&Login>, query: &str, variables: Value) -> Value {
+        let request = json!({ "query": query, "variables": variables });
+        let response = self
+            .request(Method::POST, "/graphql", login, Some(request))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        return response.json();
+    }
+
+    /// Opens `/events` at `uri`, which may hold query parameters.
+    async fn events(&self, uri: &str, last_event_id: Option<&str>) -> EventStream {
+        let mut request = Request::builder().uri(uri);
+        if let Some(last_event_id) = last_event_id {
+            request = request.header("last-event-id", last_event_id);
+        }
+        let response = self
+            .app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .expect("router is infallible");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        return EventStream {
+            body: response.into_body(),
+            buffer: String::new(),
+        };
+    }
+
+    async fn insert_fruit(&self, admin: &Login, fruit_name: &str, fruit_weight: i32) -> i64 {
+        let fruit = json!({
+            "fruit_name": fruit_name,
+            "color_red": 200,
+            "color_green": 100,
+            "color_blue": 0,
+            "fruit_weight": fruit_weight,
+        });
+        let response = self
+            .request(Method::POST, "/fruit", Some(admin), Some(fruit))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        return response.json()["id"].as_i64().unwrap();
+    }
+}
+
+/// Every connection to `sqlite::memory:` opens a database of its own, so the
+/// pool keeps a single connection for as long as it lives.
+#[cfg(feature = "sqlite")]
+async fn sqlite_repository() -> super::SqliteRepository::SqliteRepository {
+    let database_connection_pool = sqlite_pool().await;
+    return super::SqliteRepository::SqliteRepository::new(database_connection_pool, 1);
+}
+
+#[cfg(feature = "sqlite")]
+async fn sqlite_pool() -> Pool<sqlx::Sqlite> {
+    let database_connection_pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("in-memory SQLite opens");
+    super::Migrations::run_pending(&database_connection_pool)
+        .await
+        .expect("SQLite migrations apply");
+    return database_connection_pool;
+}
+
+/// A pool on a fresh, migrated schema of the database at `DATABASE_URL`, or
+/// `None` unless that is a Postgres URL.
+async fn postgres_pool() -> Option<(Pool<Postgres>, PostgresSchema)> {
+    static SCHEMA_COUNT: AtomicU32 = AtomicU32::new(0);
+
+    let database_url = std::env::var("DATABASE_URL")
+        .ok()
+        .filter(|database_url| database_url.starts_with("postgres"))?;
+    let name = format!(
+        "api_test_{}_{}",
+        std::process::id(),
+        SCHEMA_COUNT.fetch_add(1, Ordering::Relaxed)
+    );
+    let mut connection = PgConnection::connect(&database_url)
+        .await
+        .expect("DATABASE_URL opens");
+    sqlx::query(&format!("CREATE SCHEMA {}", name))
+        .execute(&mut connection)
+        .await
+        .expect("the test schema is created");
+    let schema = PostgresSchema {
+        database_url: database_url.clone(),
+        name,
+    };
+
+    let connect_options = PgConnectOptions::from_str(&database_url)
+        .expect("DATABASE_URL is valid")
+        .options([("search_path", schema.name.as_str())]);
+    let database_connection_pool = PgPoolOptions::new()
+        .max_connections(4)
+        .connect_with(connect_options)
+        .await
+        .expect("DATABASE_URL opens");
+    super::Migrations::run_pending(&database_connection_pool)
+        .await
+        .expect("Postgres migrations apply");
+    return Some((database_connection_pool, schema));
+}
+
+async fn postgres_repository() -> Option<(PostgresRepository, PostgresSchema)> {
+    let (database_connection_pool, schema) = postgres_pool().await?;
+    return Some((PostgresRepository::new(database_connection_pool, 4), schema));
+}
+
+/// Runs `sql` and returns the id of the row it inserted.
+async fn insert_row(database_connection_pool: &Pool<Postgres>, sql: &str) -> i64 {
+    let (id,): (i64,) = sqlx::query_as(sql)
+        .fetch_one(database_connection_pool)
+        .await
+        .expect("the row is inserted");
+    return id;
+}
+
+/// Whether `task` is still running after a moment, i.e. waits on a lock.
+async fn is_blocked<T>(task: &tokio::task::JoinHandle<T>) -> bool {
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    return !task.is_finished();
+}
+
+/// The next WebSocket message, parsed as JSON. Fails after five seconds
+/// without one.
+async fn next_message<S>(socket: &mut S) -> Value
+where
+    S: futures::Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin,
+{
+    let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+        .await
+        .expect("a message arrives in time")
+        .expect("the socket stays open")
+        .expect("the socket is readable");
+    return serde_json::from_str(&message.into_text().unwrap()).expect("messages are JSON");
+}
+
+/// A request received by `webhook_receiver`.
+struct ReceivedWebhook {
+    headers: HeaderMap,
+    body: String,
+}
+
+/// Stands in for a partner system: `/hook` answers with the queued statuses,
+/// then 200, and `/fail` always with 503. Returns its address and the
+/// requests `/hook` received.
+async fn webhook_receiver(
+    statuses: Vec<StatusCode>,
+) -> (
+    std::net::SocketAddr,
+    mpsc::UnboundedReceiver<ReceivedWebhook>,
+) {
+    type ReceiverState = (
+        Arc<Mutex<VecDeque<StatusCode>>>,
+        mpsc::UnboundedSender<ReceivedWebhook>,
+    );
+    async fn hook(
+        State((statuses, sender)): State<ReceiverState>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let _ = sender.send(ReceivedWebhook { headers, body });
+        return statuses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(StatusCode::OK);
+    }
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let receiver_state: ReceiverState = (Arc::new(Mutex::new(statuses.into())), sender);
+    let app = Router::new()
+        .route("/hook", post(hook))
+        .route("/fail", post(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+        .with_state(receiver_state);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service());
+    tokio::spawn(server);
+    return (address, receiver);
+}
+
+async fn next_webhook(receiver: &mut mpsc::UnboundedReceiver<ReceivedWebhook>) -> ReceivedWebhook {
+    return tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("a webhook arrives in time")
+        .expect("the receiver keeps running");
+}
+
+/// Polls the delivery log at `uri` until `count` deliveries are in it.
+/// Fails after five seconds.
+async fn wait_for_deliveries(app: &TestApp, admin: &Login, uri: &str, count: usize) -> Vec<Value> {
+    for _ in 0..250 {
+        let response = app.request(Method::GET, uri, Some(admin), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        let hits = response.json()["hits"].as_array().unwrap().clone();
+        if hits.len() == count {
+            return hits;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("{} never listed {} deliveries", uri, count);
+}
+
+/// Writes `contents` to a config file of this test, removed when dropped.
+struct ConfigFile(std::path::PathBuf);
+
+impl ConfigFile {
+    fn new(name: &str, contents: &str) -> ConfigFile {
+        let path = std::env::temp_dir().join(format!("{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        return ConfigFile(path);
+    }
+
+    fn flag(&self) -> String {
+        return format!("--config={}", self.0.display());
+    }
+}
+
+impl Drop for ConfigFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Loads a config from `args` after the program name and from `env` alone.
+fn load_config(args: &[&str], env: &[(&str, &str)]) -> Result<Config, String> {
+    let args = std::iter::once("small-server").chain(args.iter().copied());
+    let lookup = |name: &str| {
+        return env
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string());
+    };
+    return Config::from_sources(args, lookup).map_err(|error| error.to_string());
+}
+
+/// How many migrations are applied, and how many tables exist as counted by
+/// `count_tables`, after `command`.
+async fn migrate<DB>(
+    database_connection_pool: &Pool<DB>,
+    command: MigrateCommand,
+    count_tables: &str,
+) -> (usize, i64)
+where
+    DB: MigratedDatabase,
+    DB::Connection: Migrate,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    (i64,): for<'r> sqlx::FromRow<'r, DB::Row>,
+    for<'q> <DB as sqlx::database::HasArguments<'q>>::Arguments: sqlx::IntoArguments<'q, DB>,
+{
+    run_command(database_connection_pool, command)
+        .await
+        .expect("the migrate command runs");
+    let mut connection = database_connection_pool.acquire().await.unwrap();
+    let applied = connection.list_applied_migrations().await.unwrap().len();
+    let (tables,) = sqlx::query_as::<DB, (i64,)>(count_tables)
+        .fetch_one(&mut *connection)
+        .await
+        .unwrap();
+    return (applied, tables);
+}
+
+/// Reverts the latest migration, then all of them, and applies them again.
+async fn check_migrations_roll_back<DB>(database_connection_pool: &Pool<DB>, count_tables: &str)
+where
+    DB: MigratedDatabase,
+    DB::Connection: Migrate,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    (i64,): for<'r> sqlx::FromRow<'r, DB::Row>,
+    for<'q> <DB as sqlx::database::HasArguments<'q>>::Arguments: sqlx::IntoArguments<'q, DB>,
+{
+    let migrations = DB::migrator()
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .count();
+    let (applied, tables) =
+        migrate(database_connection_pool, MigrateCommand::Up, count_tables).await;
+    assert_eq!(applied, migrations);
+
+    let latest = MigrateCommand::Down { to: None };
+    let (applied, fewer_tables) = migrate(database_connection_pool, latest, count_tables).await;
+    assert_eq!(applied, migrations - 1);
+    assert!(fewer_tables < tables);
+
+    let all = MigrateCommand::Down { to: Some(0) };
+    let (applied, no_tables) = migrate(database_connection_pool, all, count_tables).await;
+    assert_eq!(
+        (applied, no_tables),
+        (0, 1),
+        "only _sqlx_migrations is left"
+    );
+
+    let (applied, all_tables) =
+        migrate(database_connection_pool, MigrateCommand::Up, count_tables).await;
+    assert_eq!((applied, all_tables), (migrations, tables));
+}
+
+fn ids(page: &Value) -> Vec<i64> {
+    return page["hits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["id"].as_i64().unwrap())
+        .collect();
+}
+
+/// The router source of every module `get_app` mounts, by module name.
+const ROUTER_SOURCES: &[(&str, &str)] = &[
+    ("ApiDoc", include_str!("ApiDoc.rs")),
+    ("Auth", include_str!("Auth.rs")),
+    ("Events", include_str!("Events.rs")),
+    ("Fruit", include_str!("Fruit.rs")),
+    ("GraphQL", include_str!("GraphQL.rs")),
+    ("Health", include_str!("Health.rs")),
+    ("Metrics", include_str!("Metrics.rs")),
+    ("Person", include_str!("Person.rs")),
+    ("Salad", include_str!("Salad.rs")),
+    ("SaladIngredient", include_str!("SaladIngredient.rs")),
+    ("Webhook", include_str!("Webhook.rs")),
+];
+
+/// The arguments of every `.<name>(...)` call in `source`, parentheses
+/// balanced.
+fn call_arguments<'a>(source: &'a str, name: &str) -> Vec<&'a str> {
+    let opening = format!(".{}(", name);
+    let mut calls = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find(&opening) {
+        let arguments = &rest[start + opening.len()..];
+        let mut depth = 1;
+        let end = arguments
+            .char_indices()
+            .find_map(|(index, character)| {
+                match character {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                return (depth == 0).then_some(index);
+            })
+            .expect("calls are closed");
+        calls.push(&arguments[..end]);
+        rest = &arguments[end..];
+    }
+    return calls;
+}
+
+fn first_string_literal(code: &str) -> Option<&str> {
+    let start = code.find('"')? + 1;
+    let end = start + code[start..].find('"')?;
+    return Some(&code[start..end]);
+}
+
+/// Every method and path `get_app` mounts, read from the `nest`, `merge` and
+/// `route` calls of the sources, with `:param` segments written the OpenAPI
+/// way.
+fn mounted_routes() -> Vec<(String, String)> {
+    let main = include_str!("main.rs");
+    let get_app = &main[main.find("fn get_app").unwrap()..];
+    let get_app = &get_app[..get_app.find("\n}\n").unwrap()];
+    let mounts = call_arguments(get_app, "nest")
+        .into_iter()
+        .map(|arguments| (first_string_literal(arguments).unwrap(), arguments))
+        .chain(
+            call_arguments(get_app, "merge")
+                .into_iter()
+                .map(|arguments| ("", arguments)),
+        );
+
+    let mut routes = Vec::new();
+    for (prefix, arguments) in mounts {
+        let Some(module) = arguments
+            .split("crate::")
+            .skip(1)
+            .map(|path| path.split("::").next().unwrap())
+            .find(|module| *module != "RateLimit")
+        else {
+            continue;
+        };
+        let source = ROUTER_SOURCES
+            .iter()
+            .find(|(name, _)| *name == module)
+            .unwrap_or_else(|| panic!("ROUTER_SOURCES lacks {}.rs", module))
+            .1;
+        let router = &source[source
+            .find("fn get_router")
+            .or_else(|| source.find("fn getRouter"))
+            .unwrap()..];
+        let router = &router[..router.find("\n}\n").unwrap()];
+        for route in call_arguments(router, "route") {
+            let path = first_string_literal(route).unwrap();
+            let path = format!("{}{}", prefix, path);
+            let path = path
+                .strip_suffix('/')
+                .filter(|path| !path.is_empty())
+                .unwrap_or(&path);
+            let path = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(param) => format!("{{{}}}", param),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            for method in ["get", "post", "put", "patch", "delete"] {
+                let called = route
+                    .match_indices(&format!("{}(", method))
+                    .any(|(index, _)| {
+                        let before = route[..index].chars().last();
+                        return matches!(before, Some(' ' | '\n' | '.'));
+                    });
+                if called {
+                    routes.push((method.to_string(), path.clone()));
+                }
+            }
+        }
+    }
+    return routes;
+}
+
+#[tokio::test]
+async fn probes_metrics_and_docs_are_served() {
+    for app in TestApp::backends().await {
+        let response = app.get("/healthz").await;
+        assert_eq!(response.status, StatusCode::OK);
+        let response = app.get("/readyz").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["database"], "ok");
+
+        let response = app.get("/metrics").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.text().contains("http_requests_total"));
+        assert!(response.text().contains("db_pool_waiting_acquires"));
+
+        let response = app.get("/openapi.json").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.json()["paths"]["/salad/{salad_id}/summary"].is_object());
+        let response = app.get("/docs").await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+}
+
+/// Probes need no token, so they do not say why the database is unreachable.
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn readiness_hides_why_the_database_is_unreachable() {
+    let database_connection_pool = sqlite_pool().await;
+    database_connection_pool.close().await;
+    let repository = super::SqliteRepository::SqliteRepository::new(database_connection_pool, 1);
+    let app = TestApp::new(repository);
+    let response = app.get("/readyz").await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.json(),
+        json!({ "status": "unavailable", "database": "unavailable" })
+    );
+}
+
+/// `ApiDoc`'s path list is kept by hand, this catches a route left out of it.
+#[test]
+fn every_mounted_route_is_documented() {
+    let spec = serde_json::to_value(super::ApiDoc::ApiDoc::openapi()).unwrap();
+    // The documentation itself and the Prometheus scrape are not API.
+    let undocumented = ["/openapi.json", "/docs", "/metrics"];
+    let routes = mounted_routes();
+    assert!(routes.len() > 50, "the routes are found: {:?}", routes);
+    for (method, path) in routes {
+        if undocumented.contains(&path.as_str()) {
+            continue;
+        }
+        assert!(
+            spec["paths"][&path][&method].is_object(),
+            "{} {} is not in the OpenAPI document",
+            method.to_uppercase(),
+            path
+        );
+    }
+}
+
+/// Other tests may be waiting on pools of their own meanwhile, so the gauge
+/// is only checked to count at least this test's acquire.
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn waiting_acquires_are_counted() {
+    let app = TestApp::new(MemoryRepository::default());
+    let waiting_acquires = || async {
+        let metrics = app.get("/metrics").await.text();
+        return metrics
+            .lines()
+            .find_map(|line| line.strip_prefix("db_pool_waiting_acquires "))
+            .and_then(|value| value.parse::<i64>().ok())
+            .expect("the gauge is exported");
+    };
+    let database_connection_pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("in-memory SQLite opens");
+
+    let held = database_connection_pool.acquire().await.unwrap();
+    let waiting = tokio::spawn({
+        let database_connection_pool = database_connection_pool.clone();
+        async move {
+            return super::Metrics::acquire(&database_connection_pool)
+                .await
+                .map(drop);
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(waiting_acquires().await >= 1);
+    drop(held);
+    waiting
+        .await
+        .unwrap()
+        .expect("the waiting acquire gets the connection");
+}
+
+#[tokio::test]
+async fn register_login_and_logout() {
+    for app in TestApp::backends().await {
+        let ann = app.register("ann@example.com").await;
+
+        let duplicate = json!({
+            "person_name": "Ann",
+            "age": 30,
+            "email": "ANN@example.com",
+            "password": PASSWORD,
+        });
+        let response = app
+            .request(Method::POST, "/auth/register", None, Some(duplicate))
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        assert_eq!(response.json()["code"], "unique_violation");
+
+        let credentials = json!({ "email": "ann@example.com", "password": "wrong password" });
+        let response = app
+            .request(Method::POST, "/auth/login", None, Some(credentials))
+            .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response.json()["code"], "invalid_credentials");
+        let credentials = json!({ "email": "nobody@example.com", "password": PASSWORD });
+        let response = app
+            .request(Method::POST, "/auth/login", None, Some(credentials))
+            .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response.json()["code"], "invalid_credentials");
+
+        let response = app
+            .request(Method::POST, "/auth/logout", Some(&ann), None)
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app
+            .request(Method::POST, "/auth/logout", Some(&ann), None)
+            .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[tokio::test]
+async fn database_errors_are_not_shown_to_clients() {
+    let error = ApiError::from(sqlx::Error::Protocol(String::from(
+        "relation \"person\" does not exist",
+    )));
+    let response = error.into_response();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let problem: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(problem["code"], "internal_error");
+    assert_eq!(problem["detail"], "Internal database error");
+}
+
+#[test]
+fn dummy_password_hash_is_a_real_argon2_hash() {
+    let parsed_hash = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+    assert_eq!(parsed_hash.algorithm.as_str(), "argon2id");
+    assert!(Argon2::default()
+        .verify_password(PASSWORD.as_bytes(), &parsed_hash)
+        .is_err());
+}
+
+#[tokio::test]
+async fn people_can_only_change_themselves() {
+    for app in TestApp::backends().await {
+        let ann = app.register("ann@example.com").await;
+        let bob = app.register("bob@example.com").await;
+
+        let patch = json!({ "age": 41 });
+        let response = app
+            .update(
+                Method::PATCH,
+                &format!("/person/{}", bob.id),
+                Some(&ann),
+                Some(patch.clone()),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .update(
+                Method::PATCH,
+                &format!("/person/{}", ann.id),
+                None,
+                Some(patch.clone()),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        let response = app
+            .update(
+                Method::PATCH,
+                &format!("/person/{}", ann.id),
+                Some(&ann),
+                Some(patch),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["age"], 41);
+
+        // Bob's login email is taken, whatever its case.
+        let person = json!({ "person_name": "Ann", "age": 41, "email": "Bob@example.com" });
+        let response = app
+            .update(
+                Method::PUT,
+                &format!("/person/{}", ann.id),
+                Some(&ann),
+                Some(person),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+
+        let person = json!({ "person_name": "Annie", "age": 42, "email": "annie@example.com" });
+        let response = app
+            .update(
+                Method::PUT,
+                &format!("/person/{}", ann.id),
+                Some(&ann),
+                Some(person),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        let response = app.get(&format!("/person/{}", ann.id)).await;
+        assert_eq!(response.json()["person_name"], "Annie");
+
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/person/{}", ann.id),
+                Some(&ann),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.get(&format!("/person/{}", ann.id)).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(response.json()["code"], "not_found");
+        // Deleting a person ends their sessions.
+        let response = app
+            .request(Method::POST, "/auth/logout", Some(&ann), None)
+            .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[tokio::test]
+async fn admins_manage_people_and_roles() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let ann = app.register("ann@example.com").await;
+
+        let person = json!({ "person_name": "Carl", "age": 25, "email": "carl@example.com" });
+        let response = app
+            .request(Method::POST, "/person", Some(&ann), Some(person.clone()))
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .request(Method::POST, "/person", Some(&admin), Some(person))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED);
+
+        let invalid = json!({ "person_name": "", "age": -1, "email": "carl" });
+        let response = app
+            .request(Method::POST, "/person", Some(&admin), Some(invalid))
+            .await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.json()["code"], "validation_failed");
+
+        let role = json!({ "role": "admin" });
+        let response = app
+            .request(
+                Method::PUT,
+                &format!("/person/{}/role", ann.id),
+                Some(&admin),
+                Some(role),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json(), json!({ "id": ann.id, "role": "admin" }));
+
+        // Ann is an admin now, with the token she already had.
+        let role = json!({ "role": "user" });
+        let response = app
+            .request(
+                Method::PUT,
+                &format!("/person/{}/role", ann.id),
+                Some(&ann),
+                Some(role),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+    }
+}
+
+#[tokio::test]
+async fn the_admin_email_is_promoted_at_startup() {
+    let env = [
+        ("AUTH_SECRET", "secret"),
+        ("ADMIN_EMAIL", " ann@example.com "),
+    ];
+    let Ok(config) = load_config(&["--database-url=memory:"], &env) else {
+        panic!("the config loads");
+    };
+    let admin_email = config.admin_email.unwrap();
+    assert_eq!(admin_email, "ann@example.com");
+
+    for app in TestApp::backends().await {
+        // Nobody to promote yet, which does not stop the server.
+        assert!(promote_admin(&*app.people, &admin_email).await.is_ok());
+        let ann = app.register("ann@example.com").await;
+        let response = app
+            .request(Method::GET, "/webhooks", Some(&ann), None)
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+        assert!(promote_admin(&*app.people, &admin_email).await.is_ok());
+        let response = app
+            .request(Method::GET, "/webhooks", Some(&ann), None)
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        // Promoting an admin again changes nothing.
+        assert!(promote_admin(&*app.people, &admin_email).await.is_ok());
+        let response = app
+            .request(Method::GET, "/webhooks", Some(&ann), None)
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn people_are_filtered_sorted_and_paged() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+
+        let people = [
+            json!({ "person_name": "Dora", "age": 52, "email": "dora@fruit.org" }),
+            json!({ "person_name": "Eve", "age": 19, "email": "eve@FRUIT.org" }),
+            json!({ "person_name": "Finn", "age": 33, "email": "finn@example.com" }),
+            json!({ "person_name": "Gil", "age": 33, "email": "gil@fruit.org" }),
+        ];
+        let ndjson: String = people
+            .iter()
+            .map(|person| format!("{}\n", person))
+            .collect();
+        let request = Request::post("/person/import")
+            .header(header::AUTHORIZATION, format!("Bearer {}", admin.token))
+            .header(header::CONTENT_TYPE, "application/x-ndjson")
+            .body(Body::from(ndjson))
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        assert_eq!(response.json()["imported"], 4);
+
+        let response = app
+            .get("/person?email_domain=fruit.org&sort=-age,person_name")
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        let page = response.json();
+        assert_eq!(ids(&page), vec![2, 5, 3]);
+        assert_eq!(page["total"], 3);
+
+        let response = app
+            .get("/person?person_name~=I&age_gte=33&size=1&page=1")
+            .await;
+        let page = response.json();
+        assert_eq!(ids(&page), vec![5]);
+        assert_eq!(page["total"], 2);
+
+        let response = app.get("/person?size=2&after=&with_total=false").await;
+        let page = response.json();
+        assert_eq!(ids(&page), vec![1, 2]);
+        assert!(page.get("total").is_none());
+        let next_cursor = page["next_cursor"].as_str().unwrap().to_string();
+        let response = app
+            .get(&format!("/person?size=2&after={}", next_cursor))
+            .await;
+        let page = response.json();
+        assert_eq!(ids(&page), vec![3, 4]);
+        let prev_cursor = page["prev_cursor"].as_str().unwrap();
+        let response = app
+            .get(&format!("/person?size=2&before={}", prev_cursor))
+            .await;
+        let page = response.json();
+        assert_eq!(ids(&page), vec![1, 2]);
+        assert!(page["prev_cursor"].is_null());
+        assert!(page["next_cursor"].is_string());
+        let response = app.get("/person?size=2&after=").await;
+        assert!(response.json()["prev_cursor"].is_null());
+
+        let malformed = [
+            "size=0",
+            "size=-1",
+            "size=101",
+            "size=500",
+            "after=&size=500",
+            "size=abc",
+            "page=-1",
+            "with_total=yes",
+        ];
+        for query in malformed {
+            let response = app.get(&format!("/person?{}", query)).await;
+            assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", query);
+            assert_eq!(response.json()["code"], "invalid_query");
+        }
+
+        let response = app.get("/person?after=&sort=age").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let response = app.get("/person?height=3").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.json()["code"], "invalid_query");
+
+        let response = app.get("/person/export?format=csv").await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        let response = app
+            .request(Method::GET, "/person/export?format=csv", Some(&admin), None)
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.content_type.starts_with("text/csv"));
+        let csv = response.text();
+        assert_eq!(csv.lines().count(), 6);
+        assert!(csv.lines().nth(5).unwrap().contains("gil@fruit.org"));
+    }
+}
+
+#[tokio::test]
+async fn imports_report_bad_rows_and_insert_all_or_nothing() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let import = |content_type: &str, body: String| {
+            let request = Request::post("/fruit/import")
+                .header(header::AUTHORIZATION, format!("Bearer {}", admin.token))
+                .header(header::CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .unwrap();
+            return app.send(request);
+        };
+
+        let csv = "fruit_name,color_red,color_green,color_blue,fruit_weight\n\
+                   Apple,200,100,0,150\n\
+                   Cherry,300,0,0,5\n\
+                   Kiwi,green,200,0,50\n\
+                   Plum,100,0,100,60\n";
+        let response = import("text/csv", csv.to_string()).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        let problem = response.json();
+        assert_eq!(problem["code"], "invalid_rows");
+        assert_eq!(problem["rows"][0]["line"], 3);
+        assert_eq!(problem["rows"][0]["errors"][0]["field"], "color_red");
+        assert_eq!(problem["rows"][1]["line"], 4);
+        assert_eq!(problem["rows"][1]["errors"][0]["code"], "malformed");
+        assert_eq!(problem["rows"].as_array().unwrap().len(), 2);
+
+        let ndjson = [
+            json!({ "fruit_name": "Apple", "color_red": 200, "color_green": 100, "color_blue": 0, "fruit_weight": 150 }).to_string(),
+            String::new(),
+            json!({ "fruit_name": "", "color_red": 0, "color_green": 0, "color_blue": 0, "fruit_weight": 10 }).to_string(),
+        ]
+        .join("\n");
+        let response = import("application/x-ndjson; charset=utf-8", ndjson).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.json()["rows"][0]["line"], 3);
+        assert_eq!(
+            response.json()["rows"][0]["errors"][0]["field"],
+            "fruit_name"
+        );
+
+        let response = import("application/json", String::from("[]")).await;
+        assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let response = app.get("/fruit").await;
+        assert_eq!(response.json()["total"], 0);
+
+        // One row more than a batch, so the import spans two inserts.
+        let mut csv = String::from("fruit_name,color_red,color_green,color_blue,fruit_weight\n");
+        for index in 0..=IMPORT_BATCH_SIZE {
+            csv.push_str(&format!("Fruit {},{},0,0,100\n", index, index % 256));
+        }
+        let response = import("text/csv", csv).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        assert_eq!(response.json()["imported"], IMPORT_BATCH_SIZE + 1);
+        let response = app.get("/fruit/export?format=csv").await;
+        let exported = response.text();
+        assert_eq!(exported.lines().count(), IMPORT_BATCH_SIZE + 2);
+        assert!(exported
+            .lines()
+            .last()
+            .unwrap()
+            .contains("Fruit 1000,232,0,0,100"));
+    }
+}
+
+#[tokio::test]
+async fn fruits_in_use_cannot_be_deleted() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let ann = app.register("ann@example.com").await;
+        let apple = app.insert_fruit(&admin, "Apple", 150).await;
+        let kiwi = app.insert_fruit(&admin, "Kiwi", 75).await;
+
+        let fruit = json!({
+            "fruit_name": "Pear",
+            "color_red": 0,
+            "color_green": 0,
+            "color_blue": 0,
+            "fruit_weight": 10,
+        });
+        let response = app
+            .request(Method::POST, "/fruit", Some(&ann), Some(fruit))
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+        let response = app
+            .update(
+                Method::PATCH,
+                &format!("/fruit/{}", kiwi),
+                Some(&admin),
+                Some(json!({ "fruit_weight": 80 })),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["fruit_weight"], 80);
+        let response = app.get("/fruit?fruit_weight_lt=100").await;
+        assert_eq!(ids(&response.json()), vec![kiwi]);
+
+        let salad = json!({ "salad_name": "Green", "ingredients": [apple] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&ann), Some(salad))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED);
+
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/fruit/{}", apple),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/fruit/{}", kiwi),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.get(&format!("/fruit/{}", kiwi)).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+        let response = app.get("/fruit/export?format=ndjson").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.text().lines().count(), 1);
+    }
+}
+
+#[tokio::test]
+async fn salads_belong_to_their_creator() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let ann = app.register("ann@example.com").await;
+        let bob = app.register("bob@example.com").await;
+        let apple = app.insert_fruit(&admin, "Apple", 150).await;
+        let kiwi = app.insert_fruit(&admin, "Kiwi", 50).await;
+
+        let salad = json!({ "salad_name": "Mixed", "ingredients": [apple, 999, 998] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&ann), Some(salad))
+            .await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.json()["detail"], "Fruit(s) [998, 999] not found");
+
+        let salad = json!({ "salad_name": "Mixed", "ingredients": [apple, kiwi] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&ann), Some(salad))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED);
+        let salad = response.json();
+        let salad_id = salad["id"].as_i64().unwrap();
+        assert_eq!(salad["id_creator"], ann.id);
+        assert_eq!(salad["ingredients"].as_array().unwrap().len(), 2);
+
+        let response = app.get(&format!("/salad/{}/summary", salad_id)).await;
+        let summary = response.json();
+        assert_eq!(summary["total_weight_grams"], 200);
+        assert_eq!(summary["fruits"][0]["fruit_name"], "Apple");
+        assert_eq!(summary["fruits"][0]["share"], 0.75);
+
+        let response = app.get(&format!("/salad/{}/ingredients", salad_id)).await;
+        let page = response.json();
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["hits"][1]["fruit_name"], "Kiwi");
+        assert_eq!(page["hits"][1]["person_name"], "ann");
+
+        let response = app.get(&format!("/person/{}/salad", ann.id)).await;
+        assert_eq!(ids(&response.json()), vec![salad_id]);
+        let response = app.get("/salad?salad_name=Mixed").await;
+        assert_eq!(response.json()["total"], 1);
+
+        let rename = json!({ "salad_name": "Stolen" });
+        let response = app
+            .update(
+                Method::PATCH,
+                &format!("/salad/{}", salad_id),
+                Some(&bob),
+                Some(rename.clone()),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .update(
+                Method::PUT,
+                &format!("/salad/{}", salad_id),
+                Some(&admin),
+                Some(rename),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["salad_name"], "Stolen");
+
+        // Ann still owns a salad.
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/person/{}", ann.id),
+                Some(&ann),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/salad/{}", salad_id),
+                Some(&ann),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.get("/ingredient").await;
+        assert_eq!(response.json()["total"], 0);
+        let response = app.get("/salad/export?format=ndjson").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn salad_summaries_weigh_colours_and_shares_by_quantity() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let mut fruit_ids = Vec::new();
+        for (fruit_name, color_red, color_green) in [("Cherry", 255, 0), ("Lime", 0, 255)] {
+            let fruit = json!({
+                "fruit_name": fruit_name,
+                "color_red": color_red,
+                "color_green": color_green,
+                "color_blue": 0,
+                "fruit_weight": 100,
+            });
+            let response = app
+                .request(Method::POST, "/fruit", Some(&admin), Some(fruit))
+                .await;
+            fruit_ids.push(response.json()["id"].as_i64().unwrap());
+        }
+        let salad = json!({ "salad_name": "Red and green", "ingredients": [] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&admin), Some(salad))
+            .await;
+        let salad_id = response.json()["id"].as_i64().unwrap();
+        let summary_uri = format!("/salad/{}/summary", salad_id);
+
+        let response = app.get(&summary_uri).await;
+        assert_eq!(response.status, StatusCode::OK);
+        let summary = response.json();
+        assert_eq!(summary["salad_name"], "Red and green");
+        assert_eq!(summary["total_weight_grams"], 0);
+        assert!(summary["average_color"].is_null());
+        assert_eq!(summary["fruits"], json!([]));
+
+        let mut ingredient_ids = Vec::new();
+        for (fruit_id, quantity_grams) in fruit_ids.iter().zip([300, 100]) {
+            let ingredient = json!({
+                "id_salad": salad_id,
+                "id_fruit": fruit_id,
+                "quantity_grams": quantity_grams,
+            });
+            let response = app
+                .request(Method::POST, "/ingredient", Some(&admin), Some(ingredient))
+                .await;
+            assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+            ingredient_ids.push(response.json()["id"].as_i64().unwrap());
+        }
+
+        let summary = app.get(&summary_uri).await.json();
+        assert_eq!(summary["total_weight_grams"], 400);
+        // 255 * 300 / 400 = 191.25 and 255 * 100 / 400 = 63.75.
+        assert_eq!(
+            summary["average_color"],
+            json!({ "red": 191, "green": 64, "blue": 0 })
+        );
+        assert_eq!(summary["fruits"][0]["id_fruit"], fruit_ids[0]);
+        assert_eq!(summary["fruits"][0]["quantity_grams"], 300);
+        assert_eq!(summary["fruits"][0]["share"], 0.75);
+        assert_eq!(summary["fruits"][1]["fruit_name"], "Lime");
+        assert_eq!(summary["fruits"][1]["share"], 0.25);
+
+        let uri = format!("/ingredient/{}", ingredient_ids[1]);
+        let response = app.request(Method::DELETE, &uri, Some(&admin), None).await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let summary = app.get(&summary_uri).await.json();
+        assert_eq!(summary["total_weight_grams"], 300);
+        assert_eq!(
+            summary["average_color"],
+            json!({ "red": 255, "green": 0, "blue": 0 })
+        );
+        assert_eq!(summary["fruits"].as_array().unwrap().len(), 1);
+
+        let uri = format!("/salad/{}", salad_id);
+        let response = app.request(Method::DELETE, &uri, Some(&admin), None).await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.get(&summary_uri).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        let response = app.get("/salad/999/summary").await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn ingredients_follow_their_salad() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let ann = app.register("ann@example.com").await;
+        let bob = app.register("bob@example.com").await;
+        let apple = app.insert_fruit(&admin, "Apple", 150).await;
+        let kiwi = app.insert_fruit(&admin, "Kiwi", 50).await;
+
+        let salad = json!({ "salad_name": "Ann's" });
+        let response = app
+            .request(Method::POST, "/salad", Some(&ann), Some(salad))
+            .await;
+        let salad_id = response.json()["id"].as_i64().unwrap();
+
+        let ingredient = json!({ "id_salad": salad_id, "id_fruit": apple });
+        let response = app
+            .request(
+                Method::POST,
+                "/ingredient",
+                Some(&bob),
+                Some(ingredient.clone()),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .request(
+                Method::POST,
+                "/ingredient",
+                Some(&ann),
+                Some(ingredient.clone()),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED);
+        let created = response.json();
+        let ingredient_id = created["id"].as_i64().unwrap();
+        assert_eq!(created["quantity_grams"], 150);
+
+        let response = app
+            .request(Method::POST, "/ingredient", Some(&ann), Some(ingredient))
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        let missing_fruit = json!({ "id_salad": salad_id, "id_fruit": 999 });
+        let response = app
+            .request(Method::POST, "/ingredient", Some(&ann), Some(missing_fruit))
+            .await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        let missing_salad = json!({ "id_salad": 999, "id_fruit": kiwi });
+        let response = app
+            .request(Method::POST, "/ingredient", Some(&ann), Some(missing_salad))
+            .await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let update = json!({ "id_salad": salad_id, "id_fruit": kiwi, "quantity_grams": 20 });
+        let response = app
+            .request(
+                Method::PUT,
+                &format!("/ingredient/{}", ingredient_id),
+                Some(&ann),
+                Some(update),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["id_fruit"], kiwi);
+        let response = app
+            .request(
+                Method::PATCH,
+                &format!("/ingredient/{}", ingredient_id),
+                Some(&ann),
+                Some(json!({ "quantity_grams": 35 })),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        let response = app.get(&format!("/ingredient/{}", ingredient_id)).await;
+        assert_eq!(response.json()["quantity_grams"], 35);
+        let response = app.get("/ingredient?after=").await;
+        assert_eq!(ids(&response.json()), vec![ingredient_id]);
+
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/ingredient/{}", ingredient_id),
+                Some(&bob),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/ingredient/{}", ingredient_id),
+                Some(&ann),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.get(&format!("/ingredient/{}", ingredient_id)).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn deleted_rows_are_hidden_until_restored() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let ann = app.register("ann@example.com").await;
+        let apple = app.insert_fruit(&admin, "Apple", 150).await;
+        let kiwi = app.insert_fruit(&admin, "Kiwi", 50).await;
+
+        let response = app.get(&format!("/fruit/{}", apple)).await;
+        let fruit = response.json();
+        assert!(fruit["created_at"].is_string());
+        assert_eq!(fruit["created_at"], fruit["updated_at"]);
+        assert!(fruit["deleted_at"].is_null());
+
+        let salad = json!({ "salad_name": "Mixed", "ingredients": [apple, kiwi] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&ann), Some(salad))
+            .await;
+        let salad_id = response.json()["id"].as_i64().unwrap();
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/salad/{}", salad_id),
+                Some(&ann),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.get("/salad").await;
+        assert_eq!(response.json()["total"], 0);
+
+        // Only admins may see deleted rows.
+        let response = app
+            .request(Method::GET, "/salad?include_deleted=true", Some(&ann), None)
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .request(
+                Method::GET,
+                &format!("/salad/{}?include_deleted=true", salad_id),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.json()["deleted_at"].is_string());
+        let response = app
+            .request(
+                Method::GET,
+                "/ingredient?include_deleted=true",
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.json()["total"], 2);
+
+        // The fruits are no longer in use, a deleted fruit blocks the restore.
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/fruit/{}", kiwi),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app
+            .request(
+                Method::POST,
+                &format!("/salad/{}/restore", salad_id),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+
+        let response = app
+            .request(
+                Method::POST,
+                &format!("/fruit/{}/restore", kiwi),
+                Some(&ann),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .request(
+                Method::POST,
+                &format!("/fruit/{}/restore", kiwi),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.json()["deleted_at"].is_null());
+        let response = app
+            .request(
+                Method::POST,
+                &format!("/salad/{}/restore", salad_id),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        let response = app.get(&format!("/salad/{}/ingredients", salad_id)).await;
+        assert_eq!(response.json()["total"], 2);
+
+        let response = app
+            .request(Method::POST, "/person/999/restore", Some(&admin), None)
+            .await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn updates_need_the_current_etag() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let apple = app.insert_fruit(&admin, "Apple", 150).await;
+        let uri = format!("/fruit/{}", apple);
+
+        let response = app.get(&uri).await;
+        let etag = response.etag.expect("GET sends an ETag");
+        assert_eq!(etag, "\"1\"");
+        let response = app
+            .request_with(
+                Method::GET,
+                &uri,
+                None,
+                None,
+                &[(header::IF_NONE_MATCH, &etag)],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(response.etag.as_deref(), Some(etag.as_str()));
+
+        let patch = json!({ "fruit_weight": 160 });
+        let response = app
+            .request(Method::PATCH, &uri, Some(&admin), Some(patch.clone()))
+            .await;
+        assert_eq!(response.status, StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(response.json()["code"], "precondition_required");
+        let response = app
+            .request_with(
+                Method::PATCH,
+                &uri,
+                Some(&admin),
+                Some(patch.clone()),
+                &[(header::IF_MATCH, &etag)],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["row_version"], 2);
+        assert_eq!(response.etag.as_deref(), Some("\"2\""));
+
+        // The first ETag is stale now, for writes and for reads.
+        let response = app
+            .request_with(
+                Method::PATCH,
+                &uri,
+                Some(&admin),
+                Some(patch),
+                &[(header::IF_MATCH, &etag)],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(response.json()["code"], "precondition_failed");
+        let response = app
+            .request_with(
+                Method::GET,
+                &uri,
+                None,
+                None,
+                &[(header::IF_NONE_MATCH, &etag)],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["fruit_weight"], 160);
+
+        // Missing rows are still 404, whatever the headers say.
+        let response = app
+            .request_with(
+                Method::PATCH,
+                "/fruit/999",
+                Some(&admin),
+                Some(json!({ "fruit_weight": 1 })),
+                &[(header::IF_MATCH, "*")],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn lists_and_rows_follow_the_accept_header() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let apple = app.insert_fruit(&admin, "Apple", 150).await;
+        let kiwi = app.insert_fruit(&admin, "Kiwi", 50).await;
+        let accept = |value| [(header::ACCEPT, value)];
+
+        let response = app
+            .request_with(Method::GET, "/fruit", None, None, &accept("text/csv"))
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.content_type, "text/csv");
+        assert_eq!(response.headers["x-total-count"], "2");
+        let csv = response.text();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("id,fruit_name,"));
+        assert!(lines[2].starts_with(&format!("{},Kiwi,", kiwi)));
+
+        let response = app
+            .request_with(
+                Method::GET,
+                "/fruit?after=&size=1",
+                None,
+                None,
+                &accept("text/csv"),
+            )
+            .await;
+        assert_eq!(response.text().lines().count(), 2);
+        assert!(response.headers.contains_key("x-next-cursor"));
+
+        // MessagePack carries the same members as JSON.
+        let response = app
+            .request_with(
+                Method::GET,
+                "/fruit",
+                None,
+                None,
+                &accept("text/csv;q=0.5, application/msgpack"),
+            )
+            .await;
+        assert_eq!(response.content_type, "application/msgpack");
+        let page: Value = rmp_serde::from_slice(&response.body).unwrap();
+        assert_eq!(ids(&page), vec![apple, kiwi]);
+        assert_eq!(page["total"], 2);
+        let response = app
+            .request_with(Method::GET, "/fruit", None, None, &accept("*/*"))
+            .await;
+        assert_eq!(response.content_type, "application/json");
+        assert_eq!(response.json()["total"], 2);
+
+        let response = app
+            .request_with(
+                Method::GET,
+                &format!("/fruit/{}", apple),
+                None,
+                None,
+                &accept("text/csv"),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.etag.is_some());
+        assert_eq!(response.text().lines().count(), 2);
+
+        let salad = json!({ "salad_name": "Mixed", "ingredients": [apple, kiwi] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&admin), Some(salad))
+            .await;
+        let salad_id = response.json()["id"].as_i64().unwrap();
+        let response = app
+            .request_with(
+                Method::GET,
+                &format!("/salad/{}/ingredients", salad_id),
+                None,
+                None,
+                &accept("text/*"),
+            )
+            .await;
+        let csv = response.text();
+        assert_eq!(
+            csv.lines().next(),
+            Some("id,person_name,salad_name,fruit_name,quantity_grams")
+        );
+
+        let response = app
+            .request_with(
+                Method::GET,
+                "/fruit",
+                None,
+                None,
+                &accept("application/xml, application/json;q=0"),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(response.json()["code"], "not_acceptable");
+
+        for encoding in ["gzip", "br"] {
+            let response = app
+                .request_with(
+                    Method::GET,
+                    "/fruit",
+                    None,
+                    None,
+                    &[(header::ACCEPT_ENCODING, encoding)],
+                )
+                .await;
+            assert_eq!(response.headers[header::CONTENT_ENCODING], encoding);
+        }
+    }
+}
+
+#[tokio::test]
+async fn graphql_reads_the_graph_and_creates_records() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let user = app.register("user@example.com").await;
+        let apple = app.insert_fruit(&admin, "Apple", 150).await;
+
+        let create_fruit = r#"
+            mutation($input: NewFruit!) { createFruit(input: $input) { id fruitName } }
+        "#;
+        let input = json!({
+            "input": {
+                "fruitName": "Kiwi",
+                "colorRed": 100,
+                "colorGreen": 200,
+                "colorBlue": 0,
+                "fruitWeight": 50,
+            }
+        });
+        let response = app.graphql(Some(&user), create_fruit, input.clone()).await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], "forbidden");
+        assert_eq!(
+            response["errors"][0]["extensions"]["reason"],
+            "admin_required"
+        );
+        let response = app.graphql(Some(&admin), create_fruit, input).await;
+        assert_eq!(response["data"]["createFruit"]["fruitName"], "Kiwi");
+        let kiwi = response["data"]["createFruit"]["id"].as_i64().unwrap();
+
+        let create_salad = r#"
+            mutation($name: String!, $fruits: [Int!]!) {
+                createSalad(input: { saladName: $name, ingredients: $fruits }) { id }
+            }
+        "#;
+        let response = app
+            .graphql(
+                None,
+                create_salad,
+                json!({ "name": "Green", "fruits": [kiwi] }),
+            )
+            .await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], "unauthorized");
+        let response = app
+            .graphql(
+                Some(&user),
+                create_salad,
+                json!({ "name": "", "fruits": [] }),
+            )
+            .await;
+        assert_eq!(
+            response["errors"][0]["extensions"]["code"],
+            "validation_failed"
+        );
+        assert_eq!(
+            response["errors"][0]["extensions"]["errors"][0]["field"],
+            "salad_name"
+        );
+        let response = app
+            .graphql(
+                Some(&user),
+                create_salad,
+                json!({ "name": "Green", "fruits": [kiwi] }),
+            )
+            .await;
+        let salad = response["data"]["createSalad"]["id"].as_i64().unwrap();
+
+        let add_ingredient = r#"
+            mutation($salad: Int!, $fruit: Int!) {
+                addSaladIngredient(input: { idSalad: $salad, idFruit: $fruit }) {
+                    id
+                    quantityGrams
+                    fruit { fruitName }
+                }
+            }
+        "#;
+        let ingredient = json!({ "salad": salad, "fruit": apple });
+        let response = app
+            .graphql(Some(&admin), add_ingredient, ingredient.clone())
+            .await;
+        assert!(response["errors"].is_null(), "{}", response);
+        let added = &response["data"]["addSaladIngredient"];
+        assert_eq!(added["quantityGrams"], 150);
+        assert_eq!(added["fruit"]["fruitName"], "Apple");
+        let other = app.register("other@example.com").await;
+        let response = app.graphql(Some(&other), add_ingredient, ingredient).await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], "forbidden");
+
+        let by_id = r#"
+            query($id: Int!) {
+                ingredient(id: $id) { quantityGrams fruit { fruitName } }
+                missing: ingredient(id: 0) { id }
+            }
+        "#;
+        let response = app.graphql(None, by_id, json!({ "id": added["id"] })).await;
+        assert!(response["errors"].is_null(), "{}", response);
+        assert_eq!(
+            response["data"],
+            json!({
+                "ingredient": { "quantityGrams": 150, "fruit": { "fruitName": "Apple" } },
+                "missing": null,
+            })
+        );
+
+        let nested = r#"
+            {
+                people(first: 10) {
+                    total
+                    hits { personName salads { saladName fruits { fruitName } } }
+                }
+                fruit(id: $apple) { salads { creator { email } } }
+            }
+        "#;
+        let response = app
+            .graphql(
+                None,
+                &nested.replace("$apple", &apple.to_string()),
+                json!({}),
+            )
+            .await;
+        assert!(response["errors"].is_null(), "{}", response);
+        let people = &response["data"]["people"];
+        assert_eq!(people["total"], 3);
+        assert_eq!(people["hits"][0]["salads"], json!([]));
+        assert_eq!(
+            people["hits"][1]["salads"],
+            json!([{
+                "saladName": "Green",
+                "fruits": [{ "fruitName": "Kiwi" }, { "fruitName": "Apple" }],
+            }])
+        );
+        assert_eq!(
+            response["data"]["fruit"]["salads"],
+            json!([{ "creator": { "email": "user@example.com" } }])
+        );
+
+        // Cursor paging, like the REST lists.
+        let page =
+            "query($after: String) { fruits(first: 1, after: $after) { hits { id } nextCursor } }";
+        let response = app.graphql(None, page, json!({})).await;
+        let first_page = &response["data"]["fruits"];
+        assert_eq!(first_page["hits"], json!([{ "id": apple }]));
+        let after = first_page["nextCursor"].clone();
+        let response = app.graphql(None, page, json!({ "after": after })).await;
+        assert_eq!(response["data"]["fruits"]["hits"], json!([{ "id": kiwi }]));
+        assert!(response["data"]["fruits"]["nextCursor"].is_null());
+        let response = app
+            .graphql(None, "{ fruits(first: 1000) { hits { id } } }", json!({}))
+            .await;
+        assert!(response["data"].is_null());
+
+        // Queries nested too deep or fanning out too wide are refused
+        // before they run.
+        let deep = r#"
+            query($id: Int!) {
+                ingredient(id: $id) {
+                    salad { creator { salads { ingredients { fruit { salads { creator { id } } } } } } }
+                }
+            }
+        "#;
+        let response = app.graphql(None, deep, json!({ "id": 1 })).await;
+        assert!(response["data"].is_null());
+        assert_eq!(
+            response["errors"][0]["message"],
+            "Query is nested too deep."
+        );
+        let wide = "{ fruits(first: 100) { hits { salads { fruits { id } } } } }";
+        let response = app.graphql(None, wide, json!({})).await;
+        assert!(response["data"].is_null());
+        assert_eq!(response["errors"][0]["message"], "Query is too complex.");
+        let response = app
+            .request(
+                Method::POST,
+                "/graphql",
+                Some(&Login {
+                    id: user.id,
+                    token: String::from("not-a-token"),
+                }),
+                Some(json!({ "query": "{ fruits { total } }" })),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[tokio::test]
+async fn changes_are_streamed_as_server_sent_events() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let ann = app.register("ann@example.com").await;
+
+        let mut fruits = app.events("/events?resources=fruit", None).await;
+        let mut everything = app.events("/events", None).await;
+        let apple = app.insert_fruit(&admin, "Apple", 150).await;
+        let event = fruits.next().await;
+        assert_eq!(event.name, "fruit.created");
+        assert_eq!(event.data["resource_id"], apple);
+        assert_eq!(event.data["data"]["fruit_name"], "Apple");
+        assert_eq!(everything.next().await.id, event.id);
+
+        let salad = json!({ "salad_name": "Mixed", "ingredients": [apple] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&ann), Some(salad.clone()))
+            .await;
+        let mixed = response.json()["id"].as_i64().unwrap();
+        let response = app
+            .request(Method::POST, "/salad", Some(&ann), Some(salad))
+            .await;
+        let other = response.json()["id"].as_i64().unwrap();
+        let event = everything.next().await;
+        assert_eq!(event.name, "salad.created");
+        assert_eq!(event.data["salad_id"], mixed);
+        assert_eq!(everything.next().await.name, "ingredient.created");
+
+        let kiwi = app.insert_fruit(&admin, "Kiwi", 50).await;
+        let mut one_salad = app
+            .events(&format!("/events?salad_ids={}", mixed), None)
+            .await;
+        for salad_id in [other, mixed] {
+            let ingredient = json!({ "id_salad": salad_id, "id_fruit": kiwi });
+            let response = app
+                .request(Method::POST, "/ingredient", Some(&ann), Some(ingredient))
+                .await;
+            assert_eq!(response.status, StatusCode::CREATED);
+        }
+        let event = one_salad.next().await;
+        assert_eq!(event.name, "ingredient.created");
+        assert_eq!(event.data["salad_id"], mixed);
+        let ingredient_id = event.data["resource_id"].as_i64().unwrap();
+        let response = app
+            .request(
+                Method::DELETE,
+                &format!("/ingredient/{}", ingredient_id),
+                Some(&ann),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let event = one_salad.next().await;
+        assert_eq!(event.name, "ingredient.deleted");
+        assert_eq!(event.data["resource_id"], ingredient_id);
+        assert!(event.data.get("data").is_none());
+
+        // Registering admin and ann published events 1 and 2.
+        let mut replayed = app.events("/events?resources=person", Some("1")).await;
+        let event = replayed.next().await;
+        assert_eq!(event.name, "person.created");
+        assert_eq!(event.id.as_deref(), Some("2"));
+        assert_eq!(event.data["resource_id"], ann.id);
+        let mut restarted = app.events("/events", Some("100000")).await;
+        assert_eq!(restarted.next().await.name, "resync");
+
+        let response = app.get("/events?resources=vegetable").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let response = app.get("/events?salad_ids=mixed").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn changes_are_pushed_over_websockets() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.app.clone().into_make_service());
+        tokio::spawn(server);
+
+        let url = format!("ws://{}/ws?resources=salad", address);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let unsubscribe = json!({ "type": "unsubscribe" }).to_string();
+        socket
+            .send(tungstenite::Message::Text(unsubscribe))
+            .await
+            .unwrap();
+        assert_eq!(next_message(&mut socket).await["type"], "error");
+
+        // Only salads, so the fruit is skipped.
+        app.insert_fruit(&admin, "Apple", 150).await;
+        let salad = json!({ "salad_name": "Mixed", "ingredients": [] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&admin), Some(salad))
+            .await;
+        let salad_id = response.json()["id"].as_i64().unwrap();
+        let message = next_message(&mut socket).await;
+        assert_eq!(message["type"], "salad.created");
+        assert_eq!(message["resource_id"], salad_id);
+
+        let subscribe = json!({ "type": "subscribe", "resources": ["fruit"] }).to_string();
+        socket
+            .send(tungstenite::Message::Text(subscribe))
+            .await
+            .unwrap();
+        assert_eq!(next_message(&mut socket).await["type"], "subscribed");
+        let kiwi = app.insert_fruit(&admin, "Kiwi", 50).await;
+        let message = next_message(&mut socket).await;
+        assert_eq!(message["type"], "fruit.created");
+        assert_eq!(message["resource_id"], kiwi);
+        assert_eq!(message["data"]["fruit_name"], "Kiwi");
+    }
+}
+
+#[tokio::test]
+async fn imports_and_salad_cascades_publish_an_event_per_row() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.app.clone().into_make_service());
+        tokio::spawn(server);
+        let url = format!("ws://{}/ws", address);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        let csv = "fruit_name,color_red,color_green,color_blue,fruit_weight\n\
+                   Apple,200,100,0,150\n\
+                   Kiwi,100,200,0,50\n";
+        let request = Request::post("/fruit/import")
+            .header(header::AUTHORIZATION, format!("Bearer {}", admin.token))
+            .header(header::CONTENT_TYPE, "text/csv")
+            .body(Body::from(csv))
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        let mut fruit_ids = Vec::new();
+        for fruit_name in ["Apple", "Kiwi"] {
+            let message = next_message(&mut socket).await;
+            assert_eq!(message["type"], "fruit.created");
+            assert_eq!(message["data"]["fruit_name"], fruit_name);
+            fruit_ids.push(message["resource_id"].as_i64().unwrap());
+        }
+
+        let person = json!({ "person_name": "Dora", "age": 52, "email": "dora@fruit.org" });
+        let request = Request::post("/person/import")
+            .header(header::AUTHORIZATION, format!("Bearer {}", admin.token))
+            .header(header::CONTENT_TYPE, "application/x-ndjson")
+            .body(Body::from(format!("{}\n", person)))
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        let message = next_message(&mut socket).await;
+        assert_eq!(message["type"], "person.created");
+        assert_eq!(message["data"]["email"], "dora@fruit.org");
+
+        let salad = json!({ "salad_name": "Mixed", "ingredients": fruit_ids });
+        let response = app
+            .request(Method::POST, "/salad", Some(&admin), Some(salad))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        let salad_id = response.json()["id"].as_i64().unwrap();
+        let ingredient_ids: Vec<i64> = response.json()["ingredients"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|ingredient| ingredient["id"].as_i64().unwrap())
+            .collect();
+        for _ in 0..3 {
+            next_message(&mut socket).await;
+        }
+
+        let uri = format!("/salad/{}", salad_id);
+        let response = app.request(Method::DELETE, &uri, Some(&admin), None).await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        for ingredient_id in &ingredient_ids {
+            let message = next_message(&mut socket).await;
+            assert_eq!(message["type"], "ingredient.deleted");
+            assert_eq!(message["resource_id"], *ingredient_id);
+            assert_eq!(message["salad_id"], salad_id);
+        }
+        assert_eq!(next_message(&mut socket).await["type"], "salad.deleted");
+
+        let uri = format!("/salad/{}/restore", salad_id);
+        let response = app.request(Method::POST, &uri, Some(&admin), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        assert_eq!(next_message(&mut socket).await["type"], "salad.restored");
+        for ingredient_id in &ingredient_ids {
+            let message = next_message(&mut socket).await;
+            assert_eq!(message["type"], "ingredient.restored");
+            assert_eq!(message["resource_id"], *ingredient_id);
+        }
+    }
+}
+
+#[tokio::test]
+async fn webhooks_are_signed_retried_and_logged() {
+    for app in TestApp::backends().await {
+        let admin = app.register_admin("admin@example.com").await;
+        let ann = app.register("ann@example.com").await;
+        let (address, mut received) =
+            webhook_receiver(vec![StatusCode::INTERNAL_SERVER_ERROR]).await;
+
+        let subscription = json!({
+            "target_url": format!("http://{}/hook", address),
+            "event_types": ["salad.created"],
+        });
+        let response = app
+            .request(
+                Method::POST,
+                "/webhooks",
+                Some(&ann),
+                Some(subscription.clone()),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        for invalid in [
+            json!({ "target_url": "ftp://example.com/hook", "event_types": ["salad.created"] }),
+            json!({ "target_url": "http://example.com/hook", "event_types": ["salad.eaten"] }),
+            json!({ "target_url": "http://example.com/hook", "event_types": [] }),
+        ] {
+            let response = app
+                .request(Method::POST, "/webhooks", Some(&admin), Some(invalid))
+                .await;
+            assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        let response = app
+            .request(Method::POST, "/webhooks", Some(&admin), Some(subscription))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        let webhook = response.json();
+        let webhook_id = webhook["id"].as_i64().unwrap();
+        let secret = webhook["secret"].as_str().unwrap();
+        assert!(secret.starts_with("whsec_"));
+        let response = app
+            .request(
+                Method::GET,
+                &format!("/webhooks/{}", webhook_id),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert!(response.json().get("secret").is_none());
+
+        // Not subscribed to fruits, so only the salad is delivered.
+        app.insert_fruit(&admin, "Apple", 150).await;
+        let salad = json!({ "salad_name": "Mixed", "ingredients": [] });
+        let response = app
+            .request(Method::POST, "/salad", Some(&ann), Some(salad))
+            .await;
+        let salad_id = response.json()["id"].as_i64().unwrap();
+        let failed = next_webhook(&mut received).await;
+        let delivered = next_webhook(&mut received).await;
+        assert_eq!(delivered.body, failed.body);
+        assert_eq!(
+            delivered.headers["x-webhook-delivery"],
+            failed.headers["x-webhook-delivery"]
+        );
+        assert_eq!(delivered.headers["x-webhook-event"], "salad.created");
+        let timestamp: i64 = delivered.headers["x-webhook-timestamp"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            delivered.headers["x-webhook-signature"],
+            signature(secret, timestamp, &delivered.body).as_str()
+        );
+        let payload: Value = serde_json::from_str(&delivered.body).unwrap();
+        assert_eq!(payload["type"], "salad.created");
+        assert_eq!(payload["resource_id"], salad_id);
+
+        let log = format!("/webhooks/{}/deliveries?status=delivered", webhook_id);
+        let deliveries = wait_for_deliveries(&app, &admin, &log, 1).await;
+        assert_eq!(deliveries[0]["attempts"], 2);
+        assert_eq!(deliveries[0]["last_status_code"], 200);
+        let delivered_id = deliveries[0]["id"].as_i64().unwrap();
+        let response = app
+            .request(
+                Method::POST,
+                &format!("/webhooks/{}/deliveries/{}/retry", webhook_id, delivered_id),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+
+        let subscription = json!({
+            "target_url": format!("http://{}/fail", address),
+            "event_types": ["fruit.created"],
+        });
+        let response = app
+            .request(Method::POST, "/webhooks", Some(&admin), Some(subscription))
+            .await;
+        let failing_id = response.json()["id"].as_i64().unwrap();
+        app.insert_fruit(&admin, "Kiwi", 50).await;
+        let log = format!("/webhooks/{}/deliveries?status=dead", failing_id);
+        let deliveries = wait_for_deliveries(&app, &admin, &log, 1).await;
+        assert_eq!(deliveries[0]["attempts"], 3);
+        assert_eq!(deliveries[0]["last_status_code"], 503);
+        let dead_id = deliveries[0]["id"].as_i64().unwrap();
+        let retry = format!("/webhooks/{}/deliveries/{}/retry", failing_id, dead_id);
+        let response = app.request(Method::POST, &retry, Some(&admin), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        assert_eq!(response.json()["status"], "pending");
+        assert_eq!(response.json()["attempts"], 0);
+        let response = app
+            .request(
+                Method::GET,
+                &format!("/webhooks/{}/deliveries/{}", webhook_id, dead_id),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        let response = app
+            .request(
+                Method::GET,
+                &format!("/webhooks/{}/deliveries?status=lost", webhook_id),
+                Some(&admin),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+        let response = app
+            .request(Method::GET, "/webhooks", Some(&admin), None)
+            .await;
+        assert_eq!(ids(&response.json()), vec![webhook_id, failing_id]);
+        assert_eq!(response.json()["total"], 2);
+        let paused = json!({
+            "target_url": format!("http://{}/hook", address),
+            "event_types": ["salad.created", "salad.deleted"],
+            "active": false,
+        });
+        let response = app
+            .request(
+                Method::PUT,
+                &format!("/webhooks/{}", webhook_id),
+                Some(&admin),
+                Some(paused),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["active"], false);
+        assert_eq!(response.json()["event_types"][1], "salad.deleted");
+
+        let failing = format!("/webhooks/{}", failing_id);
+        let response = app
+            .request(Method::DELETE, &failing, Some(&admin), None)
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let response = app.request(Method::GET, &failing, Some(&admin), None).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        let response = app.request(Method::POST, &retry, Some(&admin), None).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[test]
+fn rate_limits_are_parsed() {
+    assert_eq!("off".parse::<RateLimit>(), Ok(RateLimit::Off));
+    assert_eq!(
+        " 60 / min ".parse::<RateLimit>(),
+        Ok(RateLimit::PerPeriod {
+            requests: 60,
+            period: Duration::from_secs(60),
+        })
+    );
+    assert!("0/s".parse::<RateLimit>().is_err());
+    assert!("5/day".parse::<RateLimit>().is_err());
+    assert!("many".parse::<RateLimit>().is_err());
+}
+
+#[tokio::test]
+async fn clients_are_throttled_per_address_person_and_route_group() {
+    let rate_limits = RateLimits {
+        person: RateLimit::Off,
+        fruit: "2/min".parse().unwrap(),
+        salad: RateLimit::Off,
+        ingredient: RateLimit::Off,
+        graphql: RateLimit::Off,
+        auth: RateLimit::Off,
+    };
+    let app = TestApp::with_rate_limits(MemoryRepository::default(), &rate_limits);
+    let ann = app.register("ann@example.com").await;
+    let get = |uri: &str, address: &str, login: Option<&Login>| {
+        let mut request = Request::get(uri);
+        if let Some(login) = login {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", login.token));
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        let address: std::net::SocketAddr = address.parse().unwrap();
+        request
+            .extensions_mut()
+            .insert(axum::extract::ConnectInfo(address));
+        return app.send(request);
+    };
+
+    for _ in 0..2 {
+        assert_eq!(
+            get("/fruit", "10.0.0.1:1000", None).await.status,
+            StatusCode::OK
+        );
+    }
+    let response = get("/fruit", "10.0.0.1:2000", None).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.json()["code"], "rate_limited");
+    // 2/min refills a token every 30 seconds.
+    let retry_after = response.headers[header::RETRY_AFTER].to_str().unwrap();
+    assert!((29..=30).contains(&retry_after.parse::<u64>().unwrap()));
+
+    assert_eq!(
+        get("/fruit", "10.0.0.2:1000", None).await.status,
+        StatusCode::OK
+    );
+    assert_eq!(
+        get("/person", "10.0.0.1:1000", None).await.status,
+        StatusCode::OK
+    );
+    // Requests with Ann's token share one bucket, whatever their address.
+    for address in ["10.0.0.1:1000", "10.0.0.3:1000"] {
+        let response = get("/fruit", address, Some(&ann)).await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    let response = get("/fruit", "10.0.0.4:1000", Some(&ann)).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn logins_are_throttled_per_address() {
+    let rate_limits = RateLimits {
+        person: RateLimit::Off,
+        fruit: RateLimit::Off,
+        salad: RateLimit::Off,
+        ingredient: RateLimit::Off,
+        graphql: RateLimit::Off,
+        auth: "3/min".parse().unwrap(),
+    };
+    let app = TestApp::with_rate_limits(MemoryRepository::default(), &rate_limits);
+    let ann = app.register("ann@example.com").await;
+    let login = |password: &str, address: &str| {
+        let credentials = json!({ "email": "ann@example.com", "password": password });
+        // A token of its own does not move a client to another bucket.
+        let mut request = Request::post("/auth/login")
+            .header(header::AUTHORIZATION, format!("Bearer {}", ann.token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(credentials.to_string()))
+            .unwrap();
+        let address: std::net::SocketAddr = address.parse().unwrap();
+        request
+            .extensions_mut()
+            .insert(axum::extract::ConnectInfo(address));
+        return app.send(request);
+    };
+
+    for _ in 0..3 {
+        let response = login("wrong password", "10.0.0.1:1000").await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+    let response = login(PASSWORD, "10.0.0.1:2000").await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.json()["code"], "rate_limited");
+    let response = login(PASSWORD, "10.0.0.2:1000").await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[test]
+fn config_layers_override_each_other() {
+    let file = ConfigFile::new(
+        "config-layers",
+        r#"
+        [server]
+        bind_address = "0.0.0.0:8080"
+
+        [database]
+        url = "memory:"
+        max_connections = 5
+        acquire_timeout_seconds = 3
+
+        [rate_limit]
+        fruit = "30/min"
+
+        [cors]
+        allowed_origins = ["https://a.example", "https://b.example"]
+        "#,
+    );
+    let env = [("AUTH_SECRET", "secret"), ("DATABASE_MAX_CONNECTIONS", "7")];
+
+    let Ok(config) = load_config(&[&file.flag()], &env) else {
+        panic!("the config loads");
+    };
+    assert_eq!(config.bind_address.to_string(), "0.0.0.0:8080");
+    assert_eq!(config.database_url, "memory:");
+    assert_eq!(config.database_max_connections, 7);
+    assert_eq!(config.database_acquire_timeout, Duration::from_secs(3));
+    assert_eq!(config.shutdown_timeout, Duration::from_secs(30));
+    assert_eq!(config.log_level, LogLevel::Info);
+    assert_eq!(
+        config.rate_limits.fruit,
+        RateLimit::PerPeriod {
+            requests: 30,
+            period: Duration::from_secs(60),
+        }
+    );
+    assert_eq!(
+        config.rate_limits.auth,
+        RateLimit::PerPeriod {
+            requests: 10,
+            period: Duration::from_secs(60),
+        }
+    );
+    assert!(matches!(
+        config.cors_allowed_origins,
+        CorsOrigins::List(origins) if origins.len() == 2
+    ));
+
+    let args = [file.flag(), String::from("--database-max-connections=9")];
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let Ok(config) = load_config(&args, &env) else {
+        panic!("the config loads");
+    };
+    assert_eq!(config.database_max_connections, 9);
+
+    let env = [
+        ("AUTH_SECRET", "secret"),
+        ("SMALL_SERVER_CONFIG", file.0.to_str().unwrap()),
+    ];
+    let Ok(config) = load_config(&[], &env) else {
+        panic!("the config loads");
+    };
+    assert_eq!(config.database_max_connections, 5);
+}
+
+#[test]
+fn config_errors_name_the_key_and_value() {
+    let env = [("AUTH_SECRET", "secret"), ("DATABASE_URL", "memory:")];
+    let error = load_config(&["--database-max-connections", "lots"], &env)
+        .err()
+        .unwrap();
+    assert!(error.contains("`lots`"), "{}", error);
+    assert!(error.contains("--database-max-connections"), "{}", error);
+
+    let env = [("DATABASE_URL", "memory:"), ("SOCKET_ADDRESS", "localhost")];
+    let error = load_config(&["--auth-secret=secret"], &env).err().unwrap();
+    assert!(error.contains("`localhost`"), "{}", error);
+    assert!(error.contains("SOCKET_ADDRESS"), "{}", error);
+
+    let error = load_config(&[], &[("DATABASE_URL", "memory:")])
+        .err()
+        .unwrap();
+    assert!(error.contains("`auth.secret`"), "{}", error);
+
+    let env = [("AUTH_SECRET", "secret"), ("DATABASE_URL", "memory:")];
+    let file = ConfigFile::new("config-errors", "[database]\nmax_connections = -1\n");
+    let error = load_config(&[&file.flag()], &env).err().unwrap();
+    assert!(error.contains("`-1`"), "{}", error);
+    assert!(error.contains("`database.max_connections` in"), "{}", error);
+
+    let file = ConfigFile::new("config-unknown", "[database]\npool = 3\n");
+    let error = load_config(&[&file.flag()], &env).err().unwrap();
+    assert!(
+        error.contains("unknown setting `database.pool`"),
+        "{}",
+        error
+    );
+
+    let error = load_config(&["--config=/nonexistent/small-server.toml"], &env)
+        .err()
+        .unwrap();
+    assert!(
+        error.starts_with("Cannot read /nonexistent/small-server.toml"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn in_flight_permits_last_until_the_body_is_sent() {
+    let (mut sender, body) = Body::channel();
+    let body = Arc::new(Mutex::new(Some(body)));
+    let handler = move || {
+        let body = body.lock().unwrap().take().unwrap_or_else(Body::empty);
+        async move { axum::body::boxed(body) }
+    };
+    let app = limit_in_flight(Router::new().route("/stream", get(handler)), 1);
+    let request = || Request::get("/stream").body(Body::empty()).unwrap();
+
+    let streaming = app.clone().oneshot(request()).await.unwrap();
+    assert_eq!(streaming.status(), StatusCode::OK);
+    let response = app.clone().oneshot(request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    sender.send_data("done".into()).await.unwrap();
+    drop(sender);
+    let bytes = hyper::body::to_bytes(streaming.into_body()).await.unwrap();
+    assert_eq!(bytes, "done");
+    let response = app.clone().oneshot(request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn postgres_migrations_roll_back_and_forth() {
+    let Some((database_connection_pool, _schema)) = postgres_pool().await else {
+        return;
+    };
+    check_migrations_roll_back(
+        &database_connection_pool,
+        "SELECT COUNT(1) FROM information_schema.tables WHERE table_schema = current_schema()",
+    )
+    .await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn sqlite_migrations_roll_back_and_forth() {
+    check_migrations_roll_back(
+        &sqlite_pool().await,
+        "SELECT COUNT(1) FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+    )
+    .await;
+}
+
+/// Needs a Postgres `DATABASE_URL`. A fruit cannot be deleted while an
+/// ingredient using it is being added, nor an ingredient added while the
+/// fruit is being deleted.
+#[tokio::test]
+async fn fruit_deletes_and_ingredient_inserts_exclude_each_other() {
+    let Some((database_connection_pool, _schema)) = postgres_pool().await else {
+        return;
+    };
+    let repository = PostgresRepository::new(database_connection_pool.clone(), 4);
+    let insert_fruit = r#"
+        INSERT INTO FRUIT ( FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, FRUIT_WEIGHT )
+        VALUES ( 'Apple', 200, 100, 0, 150 ) RETURNING ID
+    "#;
+    let apple = insert_row(&database_connection_pool, insert_fruit).await;
+    let kiwi = insert_row(&database_connection_pool, insert_fruit).await;
+    let person_id = insert_row(
+        &database_connection_pool,
+        "INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL ) VALUES ( 'Ann', 30, 'ann@example.com' ) RETURNING ID",
+    )
+    .await;
+    let salad_id = insert_row(
+        &database_connection_pool,
+        &format!(
+            "INSERT INTO FRUIT_SALAD ( SALAD_NAME, ID_CREATOR ) VALUES ( 'Mixed', {} ) RETURNING ID",
+            person_id
+        ),
+    )
+    .await;
+
+    let mut adding = database_connection_pool.begin().await.unwrap();
+    sqlx::query("INSERT INTO SALAD_INGREDIENTS ( ID_SALAD, ID_FRUIT, QUANTITY_GRAMS ) VALUES ( $1, $2, 150 )")
+        .bind(salad_id)
+        .bind(apple)
+        .execute(&mut adding)
+        .await
+        .unwrap();
+    let deletion = tokio::spawn({
+        let repository = repository.clone();
+        async move { repository.delete_fruit(apple).await }
+    });
+    assert!(is_blocked(&deletion).await);
+    adding.commit().await.unwrap();
+    assert!(matches!(deletion.await.unwrap(), Ok(Deletion::InUse(1))));
+
+    let mut deleting = database_connection_pool.begin().await.unwrap();
+    sqlx::query("SELECT ID FROM FRUIT WHERE ID = $1 FOR UPDATE")
+        .bind(kiwi)
+        .execute(&mut deleting)
+        .await
+        .unwrap();
+    let insertion = tokio::spawn({
+        let repository = repository.clone();
+        let ingredient = NewSaladIngredient {
+            id_salad: salad_id,
+            id_fruit: kiwi,
+            quantity_grams: None,
+        };
+        async move { repository.insert_salad_ingredient(&ingredient).await }
+    });
+    assert!(is_blocked(&insertion).await);
+    sqlx::query("UPDATE FRUIT SET DELETED_AT = NOW() WHERE ID = $1")
+        .bind(kiwi)
+        .execute(&mut deleting)
+        .await
+        .unwrap();
+    deleting.commit().await.unwrap();
+    assert!(matches!(insertion.await.unwrap(), Ok(None)));
+}
+
+#[tokio::test]
+async fn person_deletes_and_salad_inserts_exclude_each_other() {
+    let Some((database_connection_pool, _schema)) = postgres_pool().await else {
+        return;
+    };
+    let repository = PostgresRepository::new(database_connection_pool.clone(), 4);
+    let ann = insert_row(
+        &database_connection_pool,
+        "INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL ) VALUES ( 'Ann', 30, 'ann@example.com' ) RETURNING ID",
+    )
+    .await;
+    let bob = insert_row(
+        &database_connection_pool,
+        "INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL ) VALUES ( 'Bob', 40, 'bob@example.com' ) RETURNING ID",
+    )
+    .await;
+
+    let mut adding = database_connection_pool.begin().await.unwrap();
+    sqlx::query("INSERT INTO FRUIT_SALAD ( SALAD_NAME, ID_CREATOR ) VALUES ( 'Mixed', $1 )")
+        .bind(ann)
+        .execute(&mut adding)
+        .await
+        .unwrap();
+    let deletion = tokio::spawn({
+        let repository = repository.clone();
+        async move { repository.delete_person(ann).await }
+    });
+    assert!(is_blocked(&deletion).await);
+    adding.commit().await.unwrap();
+    assert!(matches!(deletion.await.unwrap(), Ok(Deletion::InUse(1))));
+
+    let mut deleting = database_connection_pool.begin().await.unwrap();
+    sqlx::query("SELECT ID FROM PERSON WHERE ID = $1 FOR UPDATE")
+        .bind(bob)
+        .execute(&mut deleting)
+        .await
+        .unwrap();
+    let insertion = tokio::spawn({
+        let repository = repository.clone();
+        let salad = NewFruitSalad {
+            salad_name: "Green".to_string(),
+            ingredients: Vec::new(),
+        };
+        async move { repository.insert_salad(bob, &salad).await }
+    });
+    assert!(is_blocked(&insertion).await);
+    sqlx::query("UPDATE PERSON SET DELETED_AT = NOW() WHERE ID = $1")
+        .bind(bob)
+        .execute(&mut deleting)
+        .await
+        .unwrap();
+    deleting.commit().await.unwrap();
+    assert!(insertion.await.unwrap().is_err());
+}
@@ -0,0 +1,45 @@
+use axum::extract::FromRef;
+
+use super::Auth::AuthKeys;
+use super::Events::EventBus;
+use super::MemoryRepository::MemoryRepository;
+use super::PostgresRepository::PostgresRepository;
+#[cfg(feature = "sqlite")]
+use super::SqliteRepository::SqliteRepository;
+
+/// State shared by every router, generic over the storage backend `R`.
+/// Handlers extract only the part they need, e.g. `State<R>`, through the
+/// `FromRef` impls below.
+#[derive(Clone)]
+pub struct AppState<R> {
+    pub repository: R,
+    pub auth_keys: AuthKeys,
+    pub events: EventBus,
+}
+
+// `impl<R> FromRef<AppState<R>> for R` is not allowed by the orphan rules,
+// so each backend gets its own.
+impl FromRef<AppState<PostgresRepository>> for PostgresRepository {
+    fn from_ref(app_state: &AppState<PostgresRepository>) -> PostgresRepository {
+        return app_state.repository.clone();
+    }
+}
+
+impl FromRef<AppState<MemoryRepository>> for MemoryRepository {
+    fn from_ref(app_state: &AppState<MemoryRepository>) -> MemoryRepository {
+        return app_state.repository.clone();
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl FromRef<AppState<SqliteRepository>> for SqliteRepository {
+    fn from_ref(app_state: &AppState<SqliteRepository>) -> SqliteRepository {
+        return app_state.repository.clone();
+    }
+}
+
+impl<R> FromRef<AppState<R>> for AuthKeys {
+    fn from_ref(app_state: &AppState<R>) -> AuthKeys {
+        return app_state.auth_keys.clone();
+    }
+}
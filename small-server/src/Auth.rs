@@ -0,0 +1,299 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use argon2::{Argon2, PasswordVerifier};
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRequestParts, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::types::time::OffsetDateTime;
+
+use super::AppState::AppState;
+use super::Authorization::Role;
+use super::Errors::{ApiError, ApiResult, ProblemResponse};
+use super::Events::{Action, EventBus};
+use super::Metrics::PEOPLE_CREATED;
+use super::Person::{NewPerson, Person};
+use super::Repository::{PersonRepository, Repository};
+use super::Validation::{FieldError, Validate, Validator};
+
+/// Secret used to sign bearer tokens and how long the tokens stay valid.
+#[derive(Clone)]
+pub struct AuthKeys {
+    secret: Arc<Vec<u8>>,
+    token_ttl: Duration,
+}
+
+impl AuthKeys {
+    pub fn new(secret: &[u8], token_ttl: Duration) -> AuthKeys {
+        return AuthKeys {
+            secret: Arc::new(secret.to_vec()),
+            token_ttl,
+        };
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        return Hmac::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+    }
+
+    /// Tokens look like `<session id>.<person id>.<expiry unix time>.<signature>`.
+    fn sign(&self, session_id: i64, person_id: i64, expires_at: OffsetDateTime) -> String {
+        let payload = format!(
+            "{}.{}.{}",
+            session_id,
+            person_id,
+            expires_at.unix_timestamp()
+        );
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        return format!("{}.{}", payload, signature);
+    }
+
+    /// Returns the claims of a token whose signature is valid and which has
+    /// not expired yet. The session may still have been revoked since.
+    pub fn verify(&self, token: &str) -> Option<TokenClaims> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+
+        let mut parts = payload.split('.');
+        let session_id = parts.next()?.parse().ok()?;
+        let person_id = parts.next()?.parse().ok()?;
+        let expires_at: i64 = parts.next()?.parse().ok()?;
+        if parts.next().is_some() || expires_at <= OffsetDateTime::now_utc().unix_timestamp() {
+            return None;
+        }
+        return Some(TokenClaims {
+            session_id,
+            person_id,
+        });
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct Registration {
+    #[serde(flatten)]
+    pub person: NewPerson,
+    pub password: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct Credentials {
+    pub email: String,
+    pub password: String,
+}
+
+impl Validate for Registration {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = self.person.validate().err().unwrap_or_default();
+        if let Err(password_errors) = Validator::new()
+            .length("password", &self.password, 8, 128)
+            .finish()
+        {
+            errors.extend(password_errors);
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        return Err(errors);
+    }
+}
+
+/// What a valid bearer token says about its holder.
+pub struct TokenClaims {
+    pub session_id: i64,
+    pub person_id: i64,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct LoginSession {
+    /// Send back as `Authorization: Bearer <token>`.
+    pub token: String,
+    #[schema(example = "Bearer")]
+    pub token_type: String,
+    /// Unix time after which the token is rejected.
+    pub expires_at: i64,
+    pub person: Person,
+    pub role: Role,
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    return headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+}
+
+/// The person behind the bearer token of the current request. Rejects the
+/// request with 401 when the token is missing, invalid, expired or revoked.
+pub struct CurrentPerson {
+    pub person: Person,
+    pub role: Role,
+    pub session_id: i64,
+}
+
+#[async_trait]
+impl<R: Repository> FromRequestParts<AppState<R>> for CurrentPerson {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &AppState<R>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(&parts.headers)
+            .ok_or_else(|| ApiError::Unauthorized(String::from("Missing bearer token")))?;
+
+        let session_id = app_state
+            .auth_keys
+            .verify(token)
+            .ok_or_else(|| ApiError::Unauthorized(String::from("Invalid or expired token")))?
+            .session_id;
+
+        let (person, role) = app_state
+            .repository
+            .find_session(session_id)
+            .await?
+            .ok_or_else(|| ApiError::Unauthorized(String::from("Session has ended")))?;
+
+        return Ok(CurrentPerson {
+            person,
+            role,
+            session_id,
+        });
+    }
+}
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new()
+        .route("/register", post(register::<R>))
+        .route("/login", post(login::<R>))
+        .route("/logout", post(logout::<R>));
+}
+
+/// Hash checked for unknown emails, so a login costs one argon2 verification
+/// whether the account exists or not.
+pub(crate) const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$yFBqBZqnbLSus4M70CN9jQ$kGysbeFD04GJPT4E+KzkcTyBRBZLUofBTvAw+qXnFE4";
+
+async fn hash_password(password: String) -> ApiResult<String> {
+    let hash_result = tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        return Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string());
+    })
+    .await
+    .expect("Password hashing task panicked");
+    return hash_result.map_err(|error| ApiError::Internal(error.to_string()));
+}
+
+async fn verify_password(password: String, password_hash: String) -> bool {
+    return tokio::task::spawn_blocking(move || {
+        let Ok(parsed_hash) = PasswordHash::new(&password_hash) else {
+            return false;
+        };
+        return Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok();
+    })
+    .await
+    .unwrap_or(false);
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/register",
+    tag = "auth",
+    request_body = Registration,
+    responses(
+        (status = 201, description = "The registered person", body = Person),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+)]
+pub async fn register<R: PersonRepository>(
+    State(people): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<Registration>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(registration) = body?;
+    registration.validate()?;
+
+    let password_hash = hash_password(registration.password).await?;
+    let person = people
+        .register_person(&registration.person, &password_hash)
+        .await?;
+    PEOPLE_CREATED.inc();
+    events.changed(Action::Created, &person);
+    return Ok((StatusCode::CREATED, Json(serde_json::json!(person))));
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "A new session", body = LoginSession),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+    ),
+)]
+pub async fn login<R: PersonRepository>(
+    State(people): State<R>,
+    State(auth_keys): State<AuthKeys>,
+    body: Result<Json<Credentials>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(credentials) = body?;
+    let Some(account) = people.find_account(&credentials.email).await? else {
+        verify_password(credentials.password, DUMMY_PASSWORD_HASH.to_string()).await;
+        return Err(ApiError::InvalidCredentials);
+    };
+    if !verify_password(credentials.password, account.password_hash).await {
+        return Err(ApiError::InvalidCredentials);
+    }
+
+    let expires_at = OffsetDateTime::now_utc() + auth_keys.token_ttl;
+    let session_id = people.create_session(account.person.id, expires_at).await?;
+
+    let login_session = LoginSession {
+        token: auth_keys.sign(session_id, account.person.id, expires_at),
+        token_type: String::from("Bearer"),
+        expires_at: expires_at.unix_timestamp(),
+        role: account.role,
+        person: account.person,
+    };
+    return Ok((StatusCode::OK, Json(serde_json::json!(login_session))));
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    tag = "auth",
+    responses(
+        (status = 204, description = "The session was revoked"),
+        (status = 401, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn logout<R: PersonRepository>(
+    current_person: CurrentPerson,
+    State(people): State<R>,
+) -> ApiResult<StatusCode> {
+    people.revoke_session(current_person.session_id).await?;
+    return Ok(StatusCode::NO_CONTENT);
+}
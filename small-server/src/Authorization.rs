@@ -0,0 +1,131 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+
+use super::AppState::AppState;
+use super::Auth::CurrentPerson;
+use super::Errors::{ApiError, ApiResult};
+use super::Repository::{PersonRepository, Repository};
+
+/// Machine-readable reasons sent in the `reason` member of 403 responses.
+pub const ADMIN_REQUIRED: &str = "admin_required";
+pub const NOT_OWNER: &str = "not_owner";
+
+/// Role stored in `PERSON.PERSON_ROLE`.
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Unknown values are treated as the least privileged role.
+    pub fn parse(role: &str) -> Role {
+        match role {
+            "admin" => Role::Admin,
+            _ => Role::User,
+        }
+    }
+}
+
+impl CurrentPerson {
+    pub fn is_admin(&self) -> bool {
+        return self.role == Role::Admin;
+    }
+
+    /// Fails with 403 unless the person is an admin.
+    pub fn ensure_admin(&self) -> ApiResult<()> {
+        if !self.is_admin() {
+            return Err(ApiError::Forbidden(
+                ADMIN_REQUIRED,
+                String::from("Only admins can do this"),
+            ));
+        }
+        return Ok(());
+    }
+
+    /// Lets admins through, and everybody else only when they are
+    /// `owner_id`. `resource` names what is being protected in the 403.
+    pub fn ensure_owner_or_admin(&self, owner_id: i64, resource: &str) -> ApiResult<()> {
+        if self.is_admin() || self.person.id == owner_id {
+            return Ok(());
+        }
+        return Err(ApiError::Forbidden(
+            NOT_OWNER,
+            format!("{} can only be managed by its owner or an admin", resource),
+        ));
+    }
+}
+
+/// An authenticated person with the admin role. Rejects the request with 401
+/// when nobody is logged in and with 403 when the person is not an admin.
+pub struct Admin(pub CurrentPerson);
+
+#[async_trait]
+impl<R: Repository> FromRequestParts<AppState<R>> for Admin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &AppState<R>,
+    ) -> Result<Self, Self::Rejection> {
+        let current_person = CurrentPerson::from_request_parts(parts, app_state).await?;
+        current_person.ensure_admin()?;
+        return Ok(Admin(current_person));
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct IncludeDeletedQuery {
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+/// Whether `?include_deleted=true` asked for soft-deleted rows as well. Only
+/// admins may ask, anybody else gets a 401 or 403 like with `Admin`.
+pub struct IncludeDeleted(pub bool);
+
+#[async_trait]
+impl<R: Repository> FromRequestParts<AppState<R>> for IncludeDeleted {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &AppState<R>,
+    ) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<IncludeDeletedQuery>::from_request_parts(parts, app_state)
+            .await
+            .map_err(|rejection| ApiError::InvalidQuery(rejection.body_text()))?;
+        if query.include_deleted {
+            Admin::from_request_parts(parts, app_state).await?;
+        }
+        return Ok(IncludeDeleted(query.include_deleted));
+    }
+}
+
+/// Gives the person registered with `email` the admin role, the only way to
+/// get a first admin, see `Config::admin_email`. Only logs a warning when
+/// nobody registered with it yet, they are promoted at the next start.
+pub async fn promote_admin<R: PersonRepository + ?Sized>(people: &R, email: &str) -> ApiResult<()> {
+    let Some(account) = people.find_account(email).await? else {
+        tracing::warn!(email, "nobody is registered with the admin email yet");
+        return Ok(());
+    };
+    if account.role != Role::Admin {
+        people
+            .set_person_role(account.person.id, Role::Admin)
+            .await?;
+        tracing::info!(email, person_id = account.person.id, "promoted to admin");
+    }
+    return Ok(());
+}
@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::num::{NonZeroU16, ParseIntError};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::http::HeaderValue;
+use clap::{Arg, ArgMatches, Command};
+
+use super::Migrations::MigrateCommand;
+use super::RateLimit::RateLimit;
+use super::Tracing::LogFormat;
+
+/// Config file read when neither `--config` nor `SMALL_SERVER_CONFIG` is
+/// given. Unlike an explicitly named file, it may be missing.
+const DEFAULT_CONFIG_FILE: &str = "small-server.toml";
+const CONFIG_FILE_ENV: &str = "SMALL_SERVER_CONFIG";
+
+/// A setting as it is spelled in each layer: `key` in the TOML file (dotted
+/// for `[section] name`), `env` as an environment variable and `flag` on the
+/// command line.
+pub struct Setting {
+    key: &'static str,
+    env: &'static str,
+    flag: &'static str,
+    help: &'static str,
+}
+
+const BIND_ADDRESS: Setting = Setting {
+    key: "server.bind_address",
+    env: "SOCKET_ADDRESS",
+    flag: "bind-address",
+    help: "Address and port to listen on [default: 127.0.0.1:3000]",
+};
+const SHUTDOWN_TIMEOUT: Setting = Setting {
+    key: "server.shutdown_timeout_seconds",
+    env: "SHUTDOWN_TIMEOUT_SECONDS",
+    flag: "shutdown-timeout-seconds",
+    help: "How long in-flight requests may take to finish on shutdown [default: 30]",
+};
+const MAX_IN_FLIGHT: Setting = Setting {
+    key: "server.max_in_flight_requests",
+    env: "MAX_IN_FLIGHT_REQUESTS",
+    flag: "max-in-flight-requests",
+    help: "Requests handled at once before new ones get 503, 0 for no cap [default: 512]",
+};
+const DATABASE_URL: Setting = Setting {
+    key: "database.url",
+    env: "DATABASE_URL",
+    flag: "database-url",
+    help: "Postgres or `sqlite:` connection string, or `memory:` to keep everything in memory",
+};
+const DATABASE_RUN_MIGRATIONS: Setting = Setting {
+    key: "database.run_migrations",
+    env: "DATABASE_RUN_MIGRATIONS",
+    flag: "database-run-migrations",
+    help: "Apply pending migrations on startup, true or false [default: false]",
+};
+const DATABASE_MAX_CONNECTIONS: Setting = Setting {
+    key: "database.max_connections",
+    env: "DATABASE_MAX_CONNECTIONS",
+    flag: "database-max-connections",
+    help: "Size of the connection pool [default: 10]",
+};
+const DATABASE_ACQUIRE_TIMEOUT: Setting = Setting {
+    key: "database.acquire_timeout_seconds",
+    env: "DATABASE_ACQUIRE_TIMEOUT_SECONDS",
+    flag: "database-acquire-timeout-seconds",
+    help: "How long a request waits for a free connection [default: 10]",
+};
+const DATABASE_IDLE_TIMEOUT: Setting = Setting {
+    key: "database.idle_timeout_seconds",
+    env: "DATABASE_IDLE_TIMEOUT_SECONDS",
+    flag: "database-idle-timeout-seconds",
+    help: "How long an unused connection stays open [default: 600]",
+};
+const AUTH_SECRET: Setting = Setting {
+    key: "auth.secret",
+    env: "AUTH_SECRET",
+    flag: "auth-secret",
+    help: "Secret used to sign bearer tokens",
+};
+const AUTH_TOKEN_TTL: Setting = Setting {
+    key: "auth.token_ttl_seconds",
+    env: "AUTH_TOKEN_TTL_SECONDS",
+    flag: "auth-token-ttl-seconds",
+    help: "How long bearer tokens stay valid [default: 86400]",
+};
+const AUTH_ADMIN_EMAIL: Setting = Setting {
+    key: "auth.admin_email",
+    env: "ADMIN_EMAIL",
+    flag: "admin-email",
+    help: "Email of a registered person to make an admin at startup",
+};
+const RATE_LIMIT_PERSON: Setting = Setting {
+    key: "rate_limit.person",
+    env: "RATE_LIMIT_PERSON",
+    flag: "rate-limit-person",
+    help: "Requests per client to /person, e.g. 60/min, or off [default: 600/min]",
+};
+const RATE_LIMIT_FRUIT: Setting = Setting {
+    key: "rate_limit.fruit",
+    env: "RATE_LIMIT_FRUIT",
+    flag: "rate-limit-fruit",
+    help: "Requests per client to /fruit, e.g. 60/min, or off [default: 600/min]",
+};
+const RATE_LIMIT_SALAD: Setting = Setting {
+    key: "rate_limit.salad",
+    env: "RATE_LIMIT_SALAD",
+    flag: "rate-limit-salad",
+    help: "Requests per client to /salad, e.g. 60/min, or off [default: 600/min]",
+};
+const RATE_LIMIT_INGREDIENT: Setting = Setting {
+    key: "rate_limit.ingredient",
+    env: "RATE_LIMIT_INGREDIENT",
+    flag: "rate-limit-ingredient",
+    help: "Requests per client to /ingredient, e.g. 60/min, or off [default: 600/min]",
+};
+const RATE_LIMIT_GRAPHQL: Setting = Setting {
+    key: "rate_limit.graphql",
+    env: "RATE_LIMIT_GRAPHQL",
+    flag: "rate-limit-graphql",
+    help: "Requests per client to /graphql, e.g. 60/min, or off [default: 600/min]",
+};
+const RATE_LIMIT_AUTH: Setting = Setting {
+    key: "rate_limit.auth",
+    env: "RATE_LIMIT_AUTH",
+    flag: "rate-limit-auth",
+    help: "Requests per address to /auth, e.g. 10/min, or off [default: 10/min]",
+};
+const WEBHOOK_MAX_ATTEMPTS: Setting = Setting {
+    key: "webhook.max_attempts",
+    env: "WEBHOOK_MAX_ATTEMPTS",
+    flag: "webhook-max-attempts",
+    help: "Attempts at a webhook delivery before it is dead [default: 8]",
+};
+const WEBHOOK_RETRY_DELAY: Setting = Setting {
+    key: "webhook.retry_delay_seconds",
+    env: "WEBHOOK_RETRY_DELAY_SECONDS",
+    flag: "webhook-retry-delay-seconds",
+    help: "Wait before the first retry of a webhook delivery, doubled after every further failure up to an hour [default: 30]",
+};
+const WEBHOOK_TIMEOUT: Setting = Setting {
+    key: "webhook.timeout_seconds",
+    env: "WEBHOOK_TIMEOUT_SECONDS",
+    flag: "webhook-timeout-seconds",
+    help: "How long a webhook receiver may take to respond [default: 10]",
+};
+const LOG_LEVEL: Setting = Setting {
+    key: "log.level",
+    env: "LOG_LEVEL",
+    flag: "log-level",
+    help: "One of error, warn, info, debug, trace [default: info]",
+};
+const LOG_FORMAT: Setting = Setting {
+    key: "log.format",
+    env: "LOG_FORMAT",
+    flag: "log-format",
+    help: "Either pretty or json [default: pretty]",
+};
+const CORS_ALLOWED_ORIGINS: Setting = Setting {
+    key: "cors.allowed_origins",
+    env: "CORS_ALLOWED_ORIGINS",
+    flag: "cors-allowed-origins",
+    help: "Comma-separated origins allowed by CORS, `*` for any [default: none, CORS disabled]",
+};
+
+const SETTINGS: &[&Setting] = &[
+    &BIND_ADDRESS,
+    &SHUTDOWN_TIMEOUT,
+    &MAX_IN_FLIGHT,
+    &DATABASE_URL,
+    &DATABASE_RUN_MIGRATIONS,
+    &DATABASE_MAX_CONNECTIONS,
+    &DATABASE_ACQUIRE_TIMEOUT,
+    &DATABASE_IDLE_TIMEOUT,
+    &AUTH_SECRET,
+    &AUTH_TOKEN_TTL,
+    &AUTH_ADMIN_EMAIL,
+    &RATE_LIMIT_PERSON,
+    &RATE_LIMIT_FRUIT,
+    &RATE_LIMIT_SALAD,
+    &RATE_LIMIT_INGREDIENT,
+    &RATE_LIMIT_GRAPHQL,
+    &RATE_LIMIT_AUTH,
+    &WEBHOOK_MAX_ATTEMPTS,
+    &WEBHOOK_RETRY_DELAY,
+    &WEBHOOK_TIMEOUT,
+    &LOG_LEVEL,
+    &LOG_FORMAT,
+    &CORS_ALLOWED_ORIGINS,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<LogLevel, String> {
+        match level.to_ascii_lowercase().as_str() {
+            "error" => return Ok(LogLevel::Error),
+            "warn" => return Ok(LogLevel::Warn),
+            "info" => return Ok(LogLevel::Info),
+            "debug" => return Ok(LogLevel::Debug),
+            "trace" => return Ok(LogLevel::Trace),
+            _ => return Err(String::from("expected error, warn, info, debug or trace")),
+        }
+    }
+}
+
+pub enum CorsOrigins {
+    Disabled,
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+pub struct Config {
+    /// Set by `small-server migrate ...`, the server is not started then.
+    pub migrate_command: Option<MigrateCommand>,
+    pub bind_address: SocketAddr,
+    pub shutdown_timeout: Duration,
+    pub max_in_flight_requests: usize,
+    pub database_url: String,
+    pub database_run_migrations: bool,
+    pub database_max_connections: u32,
+    pub database_acquire_timeout: Duration,
+    pub database_idle_timeout: Duration,
+    pub auth_secret: String,
+    pub auth_token_ttl: Duration,
+    /// Bootstraps the first admin: register with this email, then start
+    /// (or restart) the server with it set.
+    pub admin_email: Option<String>,
+    pub rate_limits: RateLimits,
+    pub webhooks: WebhookSettings,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    pub cors_allowed_origins: CorsOrigins,
+}
+
+/// Token bucket of each route group, see `RateLimit::limit_rate`.
+pub struct RateLimits {
+    pub person: RateLimit,
+    pub fruit: RateLimit,
+    pub salad: RateLimit,
+    pub ingredient: RateLimit,
+    pub graphql: RateLimit,
+    /// Keyed by address only, a bearer token does not buy more logins.
+    pub auth: RateLimit,
+}
+
+/// How the webhook worker sends deliveries, see `Webhook::spawn_worker`.
+#[derive(Clone)]
+pub struct WebhookSettings {
+    pub max_attempts: i32,
+    pub retry_delay: Duration,
+    pub timeout: Duration,
+}
+
+pub enum ConfigError {
+    Invalid {
+        source: String,
+        value: String,
+        reason: String,
+    },
+    Missing(&'static Setting),
+    File {
+        path: PathBuf,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Invalid {
+                source,
+                value,
+                reason,
+            } => write!(
+                formatter,
+                "Invalid value `{}` for {}: {}",
+                value, source, reason
+            ),
+            ConfigError::Missing(setting) => write!(
+                formatter,
+                "Missing setting `{}`, set it in the config file, with {} or with --{}",
+                setting.key, setting.env, setting.flag
+            ),
+            ConfigError::File { path, reason } => {
+                write!(formatter, "Cannot read {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+/// A raw value and where it came from, e.g. `DATABASE_MAX_CONNECTIONS` or
+/// `database.max_connections in small-server.toml`.
+struct RawValue {
+    source: String,
+    value: String,
+}
+
+impl Config {
+    /// Loads the configuration from, in increasing order of precedence,
+    /// defaults, the TOML config file, environment variables and command line
+    /// flags.
+    pub fn load() -> Result<Config, ConfigError> {
+        return Config::from_sources(std::env::args_os(), |name| std::env::var(name).ok());
+    }
+
+    /// `load` with the command line `args` and a lookup of environment
+    /// variables `env` in place of the process' own.
+    pub fn from_sources<I, T>(
+        args: I,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = command().get_matches_from(args);
+        let mut raw_values = HashMap::new();
+
+        let (config_path, required) = match matches.get_one::<String>("config") {
+            Some(path) => (PathBuf::from(path), true),
+            None => match env(CONFIG_FILE_ENV) {
+                Some(path) => (PathBuf::from(path), true),
+                None => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+            },
+        };
+        read_config_file(&config_path, required, &mut raw_values)?;
+
+        for setting in SETTINGS {
+            if let Some(value) = env(setting.env) {
+                raw_values.insert(
+                    setting.key,
+                    RawValue {
+                        source: setting.env.to_string(),
+                        value,
+                    },
+                );
+            }
+            if let Some(value) = matches.get_one::<String>(setting.key) {
+                raw_values.insert(
+                    setting.key,
+                    RawValue {
+                        source: format!("--{}", setting.flag),
+                        value: value.clone(),
+                    },
+                );
+            }
+        }
+
+        let migrate_command = parse_migrate_command(&matches)?;
+        // Migrating never signs tokens, so it works without a secret.
+        let auth_secret = match migrate_command {
+            Some(_) => String::new(),
+            None => required_string(&raw_values, &AUTH_SECRET)?,
+        };
+        return Ok(Config {
+            migrate_command,
+            bind_address: parse_or(&raw_values, &BIND_ADDRESS, || {
+                SocketAddr::from(([127, 0, 0, 1], 3000))
+            })?,
+            shutdown_timeout: Duration::from_secs(parse_or(&raw_values, &SHUTDOWN_TIMEOUT, || 30)?),
+            max_in_flight_requests: parse_or(&raw_values, &MAX_IN_FLIGHT, || 512)?,
+            database_url: required_string(&raw_values, &DATABASE_URL)?,
+            database_run_migrations: parse_or(&raw_values, &DATABASE_RUN_MIGRATIONS, || false)?,
+            database_max_connections: parse_or(&raw_values, &DATABASE_MAX_CONNECTIONS, || 10)?,
+            database_acquire_timeout: Duration::from_secs(parse_or(
+                &raw_values,
+                &DATABASE_ACQUIRE_TIMEOUT,
+                || 10,
+            )?),
+            database_idle_timeout: Duration::from_secs(parse_or(
+                &raw_values,
+                &DATABASE_IDLE_TIMEOUT,
+                || 600,
+            )?),
+            auth_secret,
+            auth_token_ttl: Duration::from_secs(parse_or(&raw_values, &AUTH_TOKEN_TTL, || {
+                24 * 60 * 60
+            })?),
+            admin_email: raw_values
+                .get(AUTH_ADMIN_EMAIL.key)
+                .map(|raw_value| raw_value.value.trim().to_string())
+                .filter(|email| !email.is_empty()),
+            rate_limits: RateLimits {
+                person: parse_or(&raw_values, &RATE_LIMIT_PERSON, default_rate_limit)?,
+                fruit: parse_or(&raw_values, &RATE_LIMIT_FRUIT, default_rate_limit)?,
+                salad: parse_or(&raw_values, &RATE_LIMIT_SALAD, default_rate_limit)?,
+                ingredient: parse_or(&raw_values, &RATE_LIMIT_INGREDIENT, default_rate_limit)?,
+                graphql: parse_or(&raw_values, &RATE_LIMIT_GRAPHQL, default_rate_limit)?,
+                auth: parse_or(&raw_values, &RATE_LIMIT_AUTH, default_auth_rate_limit)?,
+            },
+            webhooks: WebhookSettings {
+                max_attempts: i32::from(
+                    parse_or(&raw_values, &WEBHOOK_MAX_ATTEMPTS, || {
+                        NonZeroU16::new(8).unwrap()
+                    })?
+                    .get(),
+                ),
+                retry_delay: Duration::from_secs(parse_or(
+                    &raw_values,
+                    &WEBHOOK_RETRY_DELAY,
+                    || 30,
+                )?),
+                timeout: Duration::from_secs(parse_or(&raw_values, &WEBHOOK_TIMEOUT, || 10)?),
+            },
+            log_level: parse_or(&raw_values, &LOG_LEVEL, || LogLevel::Info)?,
+            log_format: parse_or(&raw_values, &LOG_FORMAT, || LogFormat::Pretty)?,
+            cors_allowed_origins: parse_cors_origins(raw_values.get(CORS_ALLOWED_ORIGINS.key))?,
+        });
+    }
+}
+
+fn default_rate_limit() -> RateLimit {
+    return RateLimit::PerPeriod {
+        requests: 600,
+        period: Duration::from_secs(60),
+    };
+}
+
+/// Low enough to slow down password guessing from one address.
+fn default_auth_rate_limit() -> RateLimit {
+    return RateLimit::PerPeriod {
+        requests: 10,
+        period: Duration::from_secs(60),
+    };
+}
+
+fn command() -> Command {
+    let migrate = Command::new("migrate")
+        .about("Manage the database schema instead of starting the server")
+        .subcommand_required(true)
+        .subcommand(Command::new("up").about("Apply every pending migration"))
+        .subcommand(
+            Command::new("down")
+                .about("Revert the latest migration")
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("VERSION")
+                        .help("Revert every migration newer than VERSION instead, 0 for all"),
+                ),
+        )
+        .subcommand(Command::new("status").about("List applied and pending migrations"));
+
+    let mut command = Command::new("small-server").subcommand(migrate).arg(
+        Arg::new("config")
+            .long("config")
+            .value_name("PATH")
+            .global(true)
+            .help("TOML config file [default: small-server.toml, if it exists]"),
+    );
+    for setting in SETTINGS {
+        command = command.arg(
+            Arg::new(setting.key)
+                .long(setting.flag)
+                .value_name(setting.env)
+                .global(true)
+                .help(setting.help),
+        );
+    }
+    return command;
+}
+
+fn parse_migrate_command(matches: &ArgMatches) -> Result<Option<MigrateCommand>, ConfigError> {
+    let Some(("migrate", migrate_matches)) = matches.subcommand() else {
+        return Ok(None);
+    };
+    match migrate_matches.subcommand() {
+        Some(("up", _)) => return Ok(Some(MigrateCommand::Up)),
+        Some(("status", _)) => return Ok(Some(MigrateCommand::Status)),
+        Some(("down", down_matches)) => {
+            let to = match down_matches.get_one::<String>("to") {
+                Some(version) => Some(version.trim().parse().map_err(|error: ParseIntError| {
+                    ConfigError::Invalid {
+                        source: String::from("--to"),
+                        value: version.clone(),
+                        reason: error.to_string(),
+                    }
+                })?),
+                None => None,
+            };
+            return Ok(Some(MigrateCommand::Down { to }));
+        }
+        _ => unreachable!("clap requires a migrate subcommand"),
+    }
+}
+
+fn read_config_file(
+    path: &Path,
+    required: bool,
+    raw_values: &mut HashMap<&'static str, RawValue>,
+) -> Result<(), ConfigError> {
+    let file_error = |reason: String| ConfigError::File {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound && !required => return Ok(()),
+        Err(error) => return Err(file_error(error.to_string())),
+    };
+    let table: toml::Table = contents
+        .parse()
+        .map_err(|error: toml::de::Error| file_error(error.to_string()))?;
+
+    for (section_name, section) in &table {
+        let toml::Value::Table(section) = section else {
+            return Err(file_error(format!(
+                "`{}` must be a [section]",
+                section_name
+            )));
+        };
+        for (name, value) in section {
+            let key = format!("{}.{}", section_name, name);
+            let setting = SETTINGS
+                .iter()
+                .find(|setting| setting.key == key)
+                .ok_or_else(|| file_error(format!("unknown setting `{}`", key)))?;
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Array(values) => values
+                    .iter()
+                    .map(|value| match value {
+                        toml::Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(","),
+                value => value.to_string(),
+            };
+            raw_values.insert(
+                setting.key,
+                RawValue {
+                    source: format!("`{}` in {}", key, path.display()),
+                    value,
+                },
+            );
+        }
+    }
+    return Ok(());
+}
+
+fn parse_or<T>(
+    raw_values: &HashMap<&'static str, RawValue>,
+    setting: &Setting,
+    default: impl FnOnce() -> T,
+) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let Some(raw_value) = raw_values.get(setting.key) else {
+        return Ok(default());
+    };
+    return raw_value
+        .value
+        .trim()
+        .parse()
+        .map_err(|error: T::Err| ConfigError::Invalid {
+            source: raw_value.source.clone(),
+            value: raw_value.value.clone(),
+            reason: error.to_string(),
+        });
+}
+
+fn required_string(
+    raw_values: &HashMap<&'static str, RawValue>,
+    setting: &'static Setting,
+) -> Result<String, ConfigError> {
+    return raw_values
+        .get(setting.key)
+        .map(|raw_value| raw_value.value.clone())
+        .filter(|value| !value.is_empty())
+        .ok_or(ConfigError::Missing(setting));
+}
+
+fn parse_cors_origins(raw_value: Option<&RawValue>) -> Result<CorsOrigins, ConfigError> {
+    let Some(raw_value) = raw_value else {
+        return Ok(CorsOrigins::Disabled);
+    };
+    let origins: Vec<&str> = raw_value
+        .value
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    if origins.is_empty() {
+        return Ok(CorsOrigins::Disabled);
+    }
+    if origins == ["*"] {
+        return Ok(CorsOrigins::Any);
+    }
+
+    let mut header_values = Vec::new();
+    for origin in origins {
+        let header_value = HeaderValue::from_str(origin)
+            .ok()
+            .filter(|_| origin.starts_with("http://") || origin.starts_with("https://"))
+            .ok_or_else(|| ConfigError::Invalid {
+                source: raw_value.source.clone(),
+                value: origin.to_string(),
+                reason: String::from("expected an origin such as https://example.com, or `*`"),
+            })?;
+        header_values.push(header_value);
+    }
+    return Ok(CorsOrigins::List(header_values));
+}
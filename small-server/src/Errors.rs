@@ -1,32 +1,35 @@
+use std::time::Duration;
+
+use axum::{
+    extract::rejection::{JsonRejection, QueryRejection},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use super::Tracing::current_request_id;
+use super::Transfer::RowError;
+use super::Validation::FieldError;
+
 pub trait UnwrapPrint<T> {
     fn unwrap_print(self) -> T;
 }
 
 pub enum DatabaseConnectionError {
-    VarError(std::env::VarError),
     ConnectionError(sqlx::Error),
 }
 
-impl From<std::env::VarError> for DatabaseConnectionError {
-    fn from(error: std::env::VarError) -> Self {
-        Self::VarError(error)
-    }
-}
-
 impl From<sqlx::Error> for DatabaseConnectionError {
     fn from(error: sqlx::Error) -> Self {
         Self::ConnectionError(error)
     }
 }
 
-impl ToString for DatabaseConnectionError {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for DatabaseConnectionError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DatabaseConnectionError::VarError(error) => {
-                format!("Failed to read environment variable: {}", error)
-            }
             DatabaseConnectionError::ConnectionError(error) => {
-                format!("Failed to connect to database: {}", error)
+                write!(formatter, "Failed to connect to database: {}", error)
             }
         }
     }
@@ -40,3 +43,307 @@ where
         return self.unwrap_or_else(|error| panic!("{}", error.to_string()));
     }
 }
+
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+const STRING_DATA_RIGHT_TRUNCATION: &str = "22001";
+const CHECK_VIOLATION: &str = "23514";
+// SQLite extended result codes. SQLite does not enforce `VARCHAR` lengths.
+const SQLITE_CONSTRAINT_UNIQUE: &str = "2067";
+const SQLITE_CONSTRAINT_PRIMARYKEY: &str = "1555";
+const SQLITE_CONSTRAINT_FOREIGNKEY: &str = "787";
+const SQLITE_CONSTRAINT_CHECK: &str = "275";
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// Error returned by every handler. It is rendered as an RFC 7807
+/// `application/problem+json` body whose `code` member is stable and can be
+/// matched on by clients.
+pub enum ApiError {
+    NotFound(String),
+    Conflict(String),
+    UniqueViolation(String),
+    ForeignKeyViolation(String),
+    ValueTooLong(String),
+    CheckViolation(String),
+    Validation(Vec<FieldError>),
+    /// Number of bad rows in an import, and the first of them.
+    InvalidRows(usize, Vec<RowError>),
+    InvalidCursor(String),
+    InvalidQuery(String),
+    InvalidJson(JsonRejection),
+    UnsupportedMediaType(String),
+    /// None of the formats in `Accept` can be produced.
+    NotAcceptable(String),
+    /// The row changed since the client read it.
+    PreconditionFailed(String),
+    /// An update came without `If-Match`.
+    PreconditionRequired(String),
+    Unauthorized(String),
+    InvalidCredentials,
+    /// Carries a machine-readable reason, see `Authorization`.
+    Forbidden(&'static str, String),
+    /// How long until the client may try again.
+    TooManyRequests(Duration),
+    Overloaded,
+    Internal(String),
+    Database(sqlx::Error),
+}
+
+impl ApiError {
+    pub fn not_found(resource: &str, id: i64) -> ApiError {
+        return ApiError::NotFound(format!("{} {} not found", resource, id));
+    }
+
+    /// The row is no longer at the version the client sent in `If-Match`.
+    pub fn stale(resource: &str, id: i64) -> ApiError {
+        return ApiError::PreconditionFailed(format!(
+            "{} {} has changed, read it again for its current ETag",
+            resource, id
+        ));
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::UniqueViolation(_) => StatusCode::CONFLICT,
+            ApiError::ForeignKeyViolation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::ValueTooLong(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::CheckViolation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::InvalidRows(..) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::InvalidCursor(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidJson(JsonRejection::MissingJsonContentType(_)) => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            ApiError::InvalidJson(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(..) => StatusCode::FORBIDDEN,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::UniqueViolation(_) => "unique_violation",
+            ApiError::ForeignKeyViolation(_) => "foreign_key_violation",
+            ApiError::ValueTooLong(_) => "value_too_long",
+            ApiError::CheckViolation(_) => "check_violation",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::InvalidRows(..) => "invalid_rows",
+            ApiError::InvalidCursor(_) => "invalid_cursor",
+            ApiError::InvalidQuery(_) => "invalid_query",
+            ApiError::InvalidJson(JsonRejection::MissingJsonContentType(_)) => {
+                "unsupported_media_type"
+            }
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::NotAcceptable(_) => "not_acceptable",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::PreconditionRequired(_) => "precondition_required",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::Forbidden(..) => "forbidden",
+            ApiError::TooManyRequests(_) => "rate_limited",
+            ApiError::Overloaded => "overloaded",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::Database(_) => "internal_error",
+        }
+    }
+
+    pub fn detail(&self) -> String {
+        match self {
+            ApiError::NotFound(detail)
+            | ApiError::Conflict(detail)
+            | ApiError::UniqueViolation(detail)
+            | ApiError::ForeignKeyViolation(detail)
+            | ApiError::ValueTooLong(detail)
+            | ApiError::CheckViolation(detail)
+            | ApiError::InvalidCursor(detail)
+            | ApiError::InvalidQuery(detail)
+            | ApiError::UnsupportedMediaType(detail)
+            | ApiError::NotAcceptable(detail)
+            | ApiError::PreconditionFailed(detail)
+            | ApiError::PreconditionRequired(detail)
+            | ApiError::Unauthorized(detail)
+            | ApiError::Forbidden(_, detail)
+            | ApiError::Internal(detail) => detail.clone(),
+            ApiError::InvalidCredentials => String::from("Unknown email or wrong password"),
+            ApiError::TooManyRequests(retry_after) => format!(
+                "Too many requests, retry in {} second(s)",
+                retry_after_seconds(*retry_after)
+            ),
+            ApiError::Overloaded => String::from("The server is busy, retry shortly"),
+            ApiError::Validation(errors) => {
+                format!("{} field(s) failed validation", errors.len())
+            }
+            ApiError::InvalidRows(error_count, _) => {
+                format!("{} row(s) failed validation", error_count)
+            }
+            ApiError::InvalidJson(rejection) => rejection.body_text(),
+            // Raw errors name constraints and quote SQL, so they only go to
+            // the log, see `log_internal`.
+            ApiError::Database(_) => String::from("Internal database error"),
+        }
+    }
+
+    /// Like `detail`, but with the underlying database error, for logs only.
+    pub fn internal_detail(&self) -> String {
+        match self {
+            ApiError::Database(error) => error.to_string(),
+            _ => self.detail(),
+        }
+    }
+
+    /// Logs the database error hidden from the client by `detail`.
+    pub fn log_internal(&self) {
+        if let ApiError::Database(error) = self {
+            tracing::error!(error = %error, "database error");
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        if let sqlx::Error::RowNotFound = error {
+            return ApiError::NotFound(String::from("Resource not found"));
+        }
+
+        let Some(database_error) = error.as_database_error() else {
+            return ApiError::Database(error);
+        };
+
+        let detail = database_error.message().to_string();
+        match database_error.code().as_deref() {
+            Some(UNIQUE_VIOLATION | SQLITE_CONSTRAINT_UNIQUE | SQLITE_CONSTRAINT_PRIMARYKEY) => {
+                return ApiError::UniqueViolation(detail)
+            }
+            Some(FOREIGN_KEY_VIOLATION | SQLITE_CONSTRAINT_FOREIGNKEY) => {
+                return ApiError::ForeignKeyViolation(detail)
+            }
+            Some(STRING_DATA_RIGHT_TRUNCATION) => return ApiError::ValueTooLong(detail),
+            Some(CHECK_VIOLATION | SQLITE_CONSTRAINT_CHECK) => {
+                return ApiError::CheckViolation(detail)
+            }
+            _ => return ApiError::Database(error),
+        }
+    }
+}
+
+impl From<Vec<FieldError>> for ApiError {
+    fn from(errors: Vec<FieldError>) -> Self {
+        Self::Validation(errors)
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::InvalidJson(rejection)
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::InvalidQuery(rejection.body_text())
+    }
+}
+
+/// RFC 7807 body of every error response.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    #[schema(example = "/problems/not_found")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable, machine-readable error code.
+    #[schema(example = "not_found")]
+    pub code: String,
+    /// Every failing field, for `validation_failed` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
+    /// The first bad rows, for `invalid_rows` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<Vec<RowError>>,
+    /// Why the request was denied, for `forbidden` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "admin_required")]
+    pub reason: Option<String>,
+    /// `X-Request-Id` of the failed request, to quote in bug reports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Problem details, see [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807).
+// Only describes error responses in the OpenAPI document, never built.
+#[allow(dead_code)]
+#[derive(utoipa::ToResponse)]
+#[response(content_type = "application/problem+json")]
+pub struct ProblemResponse(Problem);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        self.log_internal();
+        let status = self.status();
+        let retry_after = match &self {
+            ApiError::TooManyRequests(retry_after) => Some(retry_after_seconds(*retry_after)),
+            ApiError::Overloaded => Some(1),
+            _ => None,
+        };
+        let mut body = Problem {
+            problem_type: format!("/problems/{}", self.code()),
+            title: String::from(status.canonical_reason().unwrap_or_default()),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: String::from(self.code()),
+            errors: None,
+            rows: None,
+            reason: None,
+            request_id: current_request_id(),
+        };
+        match self {
+            ApiError::Validation(errors) => body.errors = Some(errors),
+            ApiError::InvalidRows(_, rows) => body.rows = Some(rows),
+            ApiError::Forbidden(reason, _) => body.reason = Some(String::from(reason)),
+            _ => {}
+        }
+
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(body),
+        )
+            .into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Bearer"),
+            );
+        }
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from(retry_after));
+        }
+        return response;
+    }
+}
+
+/// `Retry-After` only takes whole seconds, rounded up so a client that waits
+/// that long is not throttled again.
+fn retry_after_seconds(retry_after: Duration) -> u64 {
+    return retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+}
@@ -0,0 +1,525 @@
+//! Change events of people, fruits, salads and ingredients, pushed to clients
+//! over `/events` (server-sent events) and `/ws` (WebSocket).
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        FromRef, Query, State,
+    },
+    http::{HeaderMap, HeaderName},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+    routing::get,
+    Router,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::AppState::AppState;
+use super::Errors::{ApiError, ApiResult, ProblemResponse};
+use super::Fruit::Fruit;
+use super::Person::Person;
+use super::Repository::Repository;
+use super::Salad::FruitSalad;
+use super::SaladIngredient::SaladIngredient;
+
+/// Events kept for clients that reconnect with `Last-Event-ID`.
+const REPLAY_BUFFER_SIZE: usize = 1024;
+/// How far a subscriber may fall behind before it misses events and is sent
+/// `resync`.
+const SUBSCRIBER_BACKLOG: usize = 256;
+
+pub const LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
+/// Sent instead of the events a client missed, it should reload what it shows.
+const RESYNC_EVENT: &str = "resync";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    Person,
+    Fruit,
+    Salad,
+    Ingredient,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+}
+
+/// A change to one row. Sent as JSON, named `<resource>.<action>` in the
+/// `event` field of SSE and the `type` member of WebSocket messages.
+#[derive(serde::Serialize)]
+pub struct ChangeEvent {
+    /// Grows by one with every event, the SSE `id`.
+    pub id: u64,
+    pub resource: Resource,
+    pub action: Action,
+    pub resource_id: i64,
+    /// The salad itself, or the salad of an ingredient.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub salad_id: Option<i64>,
+    /// The row after the change, left out for deletions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub occurred_at: OffsetDateTime,
+}
+
+impl Resource {
+    pub const ALL: [Resource; 4] = [
+        Resource::Person,
+        Resource::Fruit,
+        Resource::Salad,
+        Resource::Ingredient,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resource::Person => "person",
+            Resource::Fruit => "fruit",
+            Resource::Salad => "salad",
+            Resource::Ingredient => "ingredient",
+        }
+    }
+}
+
+impl Action {
+    pub const ALL: [Action; 4] = [
+        Action::Created,
+        Action::Updated,
+        Action::Deleted,
+        Action::Restored,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Created => "created",
+            Action::Updated => "updated",
+            Action::Deleted => "deleted",
+            Action::Restored => "restored",
+        }
+    }
+}
+
+impl ChangeEvent {
+    pub fn name(&self) -> String {
+        return format!("{}.{}", self.resource.as_str(), self.action.as_str());
+    }
+}
+
+/// Whether `name` is the `<resource>.<action>` name of some event.
+pub fn is_event_name(name: &str) -> bool {
+    return Resource::ALL.iter().any(|resource| {
+        return Action::ALL
+            .iter()
+            .any(|action| name == format!("{}.{}", resource.as_str(), action.as_str()));
+    });
+}
+
+/// A row whose changes are published.
+pub trait Subject: Serialize {
+    const RESOURCE: Resource;
+
+    fn id(&self) -> i64;
+
+    fn salad_id(&self) -> Option<i64> {
+        return None;
+    }
+}
+
+impl Subject for Person {
+    const RESOURCE: Resource = Resource::Person;
+
+    fn id(&self) -> i64 {
+        return self.id;
+    }
+}
+
+impl Subject for Fruit {
+    const RESOURCE: Resource = Resource::Fruit;
+
+    fn id(&self) -> i64 {
+        return self.id;
+    }
+}
+
+impl Subject for FruitSalad {
+    const RESOURCE: Resource = Resource::Salad;
+
+    fn id(&self) -> i64 {
+        return self.id;
+    }
+
+    fn salad_id(&self) -> Option<i64> {
+        return Some(self.id);
+    }
+}
+
+impl Subject for SaladIngredient {
+    const RESOURCE: Resource = Resource::Ingredient;
+
+    fn id(&self) -> i64 {
+        return self.id;
+    }
+
+    fn salad_id(&self) -> Option<i64> {
+        return Some(self.id_salad);
+    }
+}
+
+/// Publishes change events to every subscriber of this process and keeps
+/// the last `REPLAY_BUFFER_SIZE` of them. Events are lost on restart.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<ChangeEvent>>,
+    recent: Arc<Mutex<RecentEvents>>,
+}
+
+struct RecentEvents {
+    events: VecDeque<Arc<ChangeEvent>>,
+    last_id: u64,
+}
+
+/// What a new subscriber gets before the live events.
+struct Replay {
+    events: Vec<Arc<ChangeEvent>>,
+    /// Events after `Last-Event-ID` already left the buffer.
+    missed: bool,
+}
+
+impl Default for EventBus {
+    fn default() -> EventBus {
+        let (sender, _) = broadcast::channel(SUBSCRIBER_BACKLOG);
+        return EventBus {
+            sender,
+            recent: Arc::new(Mutex::new(RecentEvents {
+                events: VecDeque::with_capacity(REPLAY_BUFFER_SIZE),
+                last_id: 0,
+            })),
+        };
+    }
+}
+
+impl EventBus {
+    /// Publishes that `row` was created, updated or restored.
+    pub fn changed<T: Subject>(&self, action: Action, row: &T) {
+        self.publish(
+            T::RESOURCE,
+            action,
+            row.id(),
+            row.salad_id(),
+            serde_json::to_value(row).ok(),
+        );
+    }
+
+    pub fn deleted(&self, resource: Resource, resource_id: i64, salad_id: Option<i64>) {
+        self.publish(resource, Action::Deleted, resource_id, salad_id, None);
+    }
+
+    fn publish(
+        &self,
+        resource: Resource,
+        action: Action,
+        resource_id: i64,
+        salad_id: Option<i64>,
+        data: Option<Value>,
+    ) {
+        let mut recent = self
+            .recent
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        recent.last_id += 1;
+        let event = Arc::new(ChangeEvent {
+            id: recent.last_id,
+            resource,
+            action,
+            resource_id,
+            salad_id,
+            data,
+            occurred_at: OffsetDateTime::now_utc(),
+        });
+        if recent.events.len() == REPLAY_BUFFER_SIZE {
+            recent.events.pop_front();
+        }
+        recent.events.push_back(event.clone());
+        // Sent under the lock, so that `subscribe` never sees an event both
+        // in the buffer and on its receiver. Fails only without subscribers.
+        let _ = self.sender.send(event);
+    }
+
+    /// A receiver of every event to come, for consumers inside the server.
+    pub fn listen(&self) -> broadcast::Receiver<Arc<ChangeEvent>> {
+        return self.sender.subscribe();
+    }
+
+    /// A receiver of the events to come and, with `last_event_id`, the
+    /// buffered events after it.
+    fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Replay, broadcast::Receiver<Arc<ChangeEvent>>) {
+        let recent = self
+            .recent
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let receiver = self.sender.subscribe();
+        let Some(last_event_id) = last_event_id else {
+            let replay = Replay {
+                events: Vec::new(),
+                missed: false,
+            };
+            return (replay, receiver);
+        };
+        // An id from before a restart is ahead of the current ones.
+        let missed = last_event_id > recent.last_id
+            || recent
+                .events
+                .front()
+                .is_some_and(|oldest| last_event_id + 1 < oldest.id);
+        let replay = Replay {
+            events: recent
+                .events
+                .iter()
+                .filter(|event| event.id > last_event_id)
+                .cloned()
+                .collect(),
+            missed,
+        };
+        return (replay, receiver);
+    }
+}
+
+impl<R> FromRef<AppState<R>> for EventBus {
+    fn from_ref(app_state: &AppState<R>) -> EventBus {
+        return app_state.events.clone();
+    }
+}
+
+/// Query parameters of `/events` and `/ws`, comma-separated lists.
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventQuery {
+    /// Only these of `person`, `fruit`, `salad` and `ingredient`, all by
+    /// default.
+    resources: Option<String>,
+    /// Only changes to these salads and their ingredients.
+    salad_ids: Option<String>,
+}
+
+/// Which events a client subscribed to, every event when both lists are
+/// empty.
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EventFilter {
+    #[serde(default)]
+    resources: Vec<Resource>,
+    #[serde(default)]
+    salad_ids: Vec<i64>,
+}
+
+impl EventFilter {
+    fn parse(query: &EventQuery) -> ApiResult<EventFilter> {
+        let items = |list: &Option<String>| -> Vec<String> {
+            return list
+                .iter()
+                .flat_map(|list| list.split(','))
+                .map(|item| String::from(item.trim()))
+                .filter(|item| !item.is_empty())
+                .collect();
+        };
+        let resources = items(&query.resources)
+            .iter()
+            .map(|resource| {
+                return serde_json::from_value(Value::String(resource.clone())).map_err(|_| {
+                    return ApiError::InvalidQuery(format!(
+                        "`{}` is not one of person, fruit, salad or ingredient",
+                        resource
+                    ));
+                });
+            })
+            .collect::<ApiResult<Vec<Resource>>>()?;
+        let salad_ids = items(&query.salad_ids)
+            .iter()
+            .map(|salad_id| {
+                return salad_id.parse().map_err(|_| {
+                    return ApiError::InvalidQuery(format!("`{}` is not a salad id", salad_id));
+                });
+            })
+            .collect::<ApiResult<Vec<i64>>>()?;
+        return Ok(EventFilter {
+            resources,
+            salad_ids,
+        });
+    }
+
+    fn matches(&self, event: &ChangeEvent) -> bool {
+        let resource_matches =
+            self.resources.is_empty() || self.resources.contains(&event.resource);
+        let salad_matches = self.salad_ids.is_empty()
+            || event
+                .salad_id
+                .is_some_and(|salad_id| self.salad_ids.contains(&salad_id));
+        return resource_matches && salad_matches;
+    }
+}
+
+/// What a subscriber receives: an event, or word that it missed some.
+enum Delivery {
+    Change(Arc<ChangeEvent>),
+    Resync,
+}
+
+/// The replay, then the live events, both filtered. Ends when the bus is
+/// dropped.
+fn deliveries(
+    replay: Replay,
+    receiver: broadcast::Receiver<Arc<ChangeEvent>>,
+    filter: EventFilter,
+) -> impl Stream<Item = Delivery> {
+    let missed = replay.missed.then_some(Delivery::Resync);
+    let replayed = replay.events.into_iter().map(Delivery::Change);
+    let live = stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => return Some((Delivery::Change(event), receiver)),
+            Err(RecvError::Lagged(_)) => return Some((Delivery::Resync, receiver)),
+            Err(RecvError::Closed) => return None,
+        }
+    });
+    return stream::iter(missed.into_iter().chain(replayed))
+        .chain(live)
+        .filter(move |delivery| {
+            let wanted = match delivery {
+                Delivery::Change(event) => filter.matches(event),
+                Delivery::Resync => true,
+            };
+            return std::future::ready(wanted);
+        });
+}
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new()
+        .route("/events", get(stream_events))
+        .route("/ws", get(open_socket));
+}
+
+/// Server-sent events. A client reconnecting with `Last-Event-ID` first gets
+/// the buffered events it missed, or `resync` when they are gone.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(
+        EventQuery,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last event received"),
+    ),
+    responses(
+        (status = 200, description = "A stream of change events", content_type = "text/event-stream", body = String),
+        (status = 400, response = ProblemResponse),
+    ),
+)]
+pub async fn stream_events(
+    State(events): State<EventBus>,
+    Query(query): Query<EventQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let filter = EventFilter::parse(&query)?;
+    // An unreadable id is treated like none.
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    let (replay, receiver) = events.subscribe(last_event_id);
+    let stream = deliveries(replay, receiver, filter).map(|delivery| {
+        let event = match delivery {
+            Delivery::Change(change) => Event::default()
+                .id(change.id.to_string())
+                .event(change.name())
+                .json_data(&*change)
+                .expect("change events serialize"),
+            Delivery::Resync => Event::default().event(RESYNC_EVENT).data("{}"),
+        };
+        return Ok(event);
+    });
+    return Ok(Sse::new(stream).keep_alive(KeepAlive::default()));
+}
+
+/// Messages of a WebSocket client, which replace the subscription given in
+/// the query parameters.
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe(EventFilter),
+}
+
+/// WebSocket messages: `{"type": "<resource>.<action>", ...}` for every
+/// event, `resync` when some were missed and `error` for a bad message.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "events",
+    params(EventQuery),
+    responses(
+        (status = 101, description = "Switched to a WebSocket of change events"),
+        (status = 400, response = ProblemResponse),
+    ),
+)]
+pub async fn open_socket(
+    State(events): State<EventBus>,
+    Query(query): Query<EventQuery>,
+    upgrade: WebSocketUpgrade,
+) -> ApiResult<Response> {
+    let filter = EventFilter::parse(&query)?;
+    return Ok(upgrade.on_upgrade(move |socket| serve_socket(socket, events, filter)));
+}
+
+async fn serve_socket(mut socket: WebSocket, events: EventBus, mut filter: EventFilter) {
+    let (_, mut receiver) = events.subscribe(None);
+    loop {
+        let outgoing = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(event) if filter.matches(&event) => socket_message(&event),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => serde_json::json!({ "type": RESYNC_EVENT }),
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(ClientMessage::Subscribe(new_filter)) => {
+                        filter = new_filter;
+                        serde_json::json!({ "type": "subscribed" })
+                    }
+                    Err(error) => serde_json::json!({ "type": "error", "detail": error.to_string() }),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if socket
+            .send(Message::Text(outgoing.to_string()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn socket_message(event: &ChangeEvent) -> Value {
+    let mut message = serde_json::json!(event);
+    message["type"] = Value::String(event.name());
+    return message;
+}
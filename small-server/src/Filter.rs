@@ -0,0 +1,358 @@
+use std::cmp::Ordering;
+
+use sqlx::{Database, Encode, Postgres, QueryBuilder, Type};
+
+use super::Errors::{ApiError, ApiResult};
+
+/// Query parameters consumed by `Pagination` and `IncludeDeleted` rather
+/// than by filters.
+const RESERVED_PARAMETERS: &[&str] = &[
+    "size",
+    "page",
+    "after",
+    "before",
+    "with_total",
+    "include_deleted",
+];
+
+pub enum FieldKind {
+    Text,
+    Integer,
+}
+
+/// A field that can be filtered and sorted on. `column` is only ever taken
+/// from these whitelists, never from the request, so it is safe to push into
+/// the SQL as is.
+pub struct FilterField {
+    pub name: &'static str,
+    pub column: &'static str,
+    /// `column` spelled for SQLite, when it uses functions Postgres lacks.
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub sqlite_column: Option<&'static str>,
+    pub kind: FieldKind,
+}
+
+impl FilterField {
+    pub const fn text(name: &'static str, column: &'static str) -> FilterField {
+        return FilterField {
+            name,
+            column,
+            sqlite_column: None,
+            kind: FieldKind::Text,
+        };
+    }
+
+    pub const fn integer(name: &'static str, column: &'static str) -> FilterField {
+        return FilterField {
+            name,
+            column,
+            sqlite_column: None,
+            kind: FieldKind::Integer,
+        };
+    }
+
+    pub const fn with_sqlite_column(self, sqlite_column: &'static str) -> FilterField {
+        return FilterField {
+            sqlite_column: Some(sqlite_column),
+            ..self
+        };
+    }
+}
+
+/// The parts of the SQL pushed by `ListQuery` that differ between databases.
+pub trait SqlDialect: Database {
+    /// Case-insensitive `LIKE`.
+    const CONTAINS: &'static str;
+
+    fn column(field: &FilterField) -> &'static str;
+}
+
+impl SqlDialect for Postgres {
+    const CONTAINS: &'static str = " ILIKE ";
+
+    fn column(field: &FilterField) -> &'static str {
+        return field.column;
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqlDialect for sqlx::Sqlite {
+    // Only ASCII letters match regardless of case, unlike `ILIKE`.
+    const CONTAINS: &'static str = " LIKE ";
+
+    fn column(field: &FilterField) -> &'static str {
+        return field.sqlite_column.unwrap_or(field.column);
+    }
+}
+
+enum Operator {
+    Equals,
+    Contains,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+}
+
+impl Operator {
+    fn sql<DB: SqlDialect>(&self) -> &'static str {
+        match self {
+            Operator::Equals => " = ",
+            Operator::Contains => DB::CONTAINS,
+            Operator::GreaterThan => " > ",
+            Operator::GreaterThanOrEqual => " >= ",
+            Operator::LessThan => " < ",
+            Operator::LessThanOrEqual => " <= ",
+        }
+    }
+}
+
+/// A value to compare against, or the value of a field of an in-memory row.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub enum FilterValue {
+    Text(String),
+    Integer(i64),
+}
+
+/// Rows that the in-memory repository filters and sorts with a `ListQuery`.
+/// `field` takes the names of the `FilterField` whitelist of the row's list
+/// endpoint and returns what its `column` would hold.
+pub trait Filterable {
+    fn field(&self, name: &str) -> Option<FilterValue>;
+    fn is_deleted(&self) -> bool;
+}
+
+struct Condition {
+    field: &'static FilterField,
+    operator: Operator,
+    value: FilterValue,
+}
+
+impl Condition {
+    fn matches(&self, value: &FilterValue) -> bool {
+        match (&self.operator, value, &self.value) {
+            (Operator::Contains, FilterValue::Text(text), FilterValue::Text(needle)) => {
+                return text.to_lowercase().contains(&needle.to_lowercase());
+            }
+            (Operator::Contains, _, _) => return false,
+            (Operator::Equals, value, expected) => return value == expected,
+            (Operator::GreaterThan, value, bound) => return value > bound,
+            (Operator::GreaterThanOrEqual, value, bound) => return value >= bound,
+            (Operator::LessThan, value, bound) => return value < bound,
+            (Operator::LessThanOrEqual, value, bound) => return value <= bound,
+        }
+    }
+}
+
+struct SortKey {
+    field: &'static FilterField,
+    descending: bool,
+}
+
+/// Filters and sort order parsed from a list endpoint's query string, e.g.
+/// `?fruit_name~=apple&fruit_weight_gte=100&sort=-fruit_weight,fruit_name`.
+///
+/// Text fields accept `field=` and `field~=` (case-insensitive contains),
+/// integer fields accept `field=`, `field_gt=`, `field_gte=`, `field_lt=` and
+/// `field_lte=`.
+#[derive(Default)]
+pub struct ListQuery {
+    conditions: Vec<Condition>,
+    sort: Vec<SortKey>,
+    /// Keeps soft-deleted rows, set from `IncludeDeleted` by the handler.
+    pub include_deleted: bool,
+}
+
+impl ListQuery {
+    pub fn parse(
+        parameters: &[(String, String)],
+        fields: &'static [FilterField],
+    ) -> ApiResult<ListQuery> {
+        let mut list_query = ListQuery::default();
+        for (key, value) in parameters {
+            if RESERVED_PARAMETERS.contains(&key.as_str()) {
+                continue;
+            }
+            if key == "sort" {
+                list_query.sort = parse_sort(value, fields)?;
+                continue;
+            }
+            list_query
+                .conditions
+                .push(parse_condition(key, value, fields)?);
+        }
+        return Ok(list_query);
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        return !self.sort.is_empty();
+    }
+
+    /// Pushes ` WHERE ...` (or ` AND ...` when `has_where` is set) for every
+    /// condition, and for `DELETED_AT` unless deleted rows are included.
+    pub fn push_where<'args, DB>(&self, query: &mut QueryBuilder<'args, DB>, has_where: bool)
+    where
+        DB: SqlDialect,
+        String: Encode<'args, DB> + Type<DB>,
+        i64: Encode<'args, DB> + Type<DB>,
+    {
+        let mut has_where = has_where;
+        if !self.include_deleted {
+            query.push(if has_where { " AND " } else { " WHERE " });
+            query.push("DELETED_AT IS NULL");
+            has_where = true;
+        }
+        for condition in &self.conditions {
+            query.push(if has_where { " AND " } else { " WHERE " });
+            has_where = true;
+            query
+                .push(DB::column(condition.field))
+                .push(condition.operator.sql::<DB>());
+            match (&condition.operator, &condition.value) {
+                (Operator::Contains, FilterValue::Text(text)) => {
+                    // SQLite has no default escape character.
+                    query.push_bind(like_pattern(text)).push(" ESCAPE '\\'");
+                }
+                (_, FilterValue::Text(text)) => {
+                    query.push_bind(text.clone());
+                }
+                (_, FilterValue::Integer(integer)) => {
+                    query.push_bind(*integer);
+                }
+            }
+        }
+    }
+
+    /// Pushes ` ORDER BY ...` with `tie_breaker` last, so offset pages are
+    /// stable even when the requested sort keys have duplicates.
+    pub fn push_order_by<DB: SqlDialect>(
+        &self,
+        query: &mut QueryBuilder<'_, DB>,
+        tie_breaker: &str,
+    ) {
+        query.push(" ORDER BY ");
+        for sort_key in &self.sort {
+            query
+                .push(DB::column(sort_key.field))
+                .push(if sort_key.descending {
+                    " DESC, "
+                } else {
+                    " ASC, "
+                });
+        }
+        query.push(tie_breaker).push(" ASC");
+    }
+
+    /// Whether `row` passes every condition, like `push_where` would.
+    pub fn matches<T: Filterable>(&self, row: &T) -> bool {
+        if !self.include_deleted && row.is_deleted() {
+            return false;
+        }
+        return self.conditions.iter().all(|condition| {
+            return row
+                .field(condition.field.name)
+                .is_some_and(|value| condition.matches(&value));
+        });
+    }
+
+    /// Orders rows by the requested sort keys only. Sorting rows already in
+    /// id order with it (the sort is stable) gives `push_order_by`'s order.
+    pub fn compare<T: Filterable>(&self, left: &T, right: &T) -> Ordering {
+        for sort_key in &self.sort {
+            let ordering = left
+                .field(sort_key.field.name)
+                .cmp(&right.field(sort_key.field.name));
+            let ordering = if sort_key.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        return Ordering::Equal;
+    }
+}
+
+/// `ILIKE` pattern matching `text` anywhere, with its wildcards escaped.
+fn like_pattern(text: &str) -> String {
+    return format!(
+        "%{}%",
+        text.replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+}
+
+fn find_field(name: &str, fields: &'static [FilterField]) -> Option<&'static FilterField> {
+    return fields.iter().find(|field| field.name == name);
+}
+
+fn parse_sort(value: &str, fields: &'static [FilterField]) -> ApiResult<Vec<SortKey>> {
+    return value
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let (descending, name) = match name.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, name),
+            };
+            let field = find_field(name, fields).ok_or_else(|| {
+                ApiError::InvalidQuery(format!("Cannot sort by unknown field `{}`", name))
+            })?;
+            return Ok(SortKey { field, descending });
+        })
+        .collect();
+}
+
+fn parse_condition(key: &str, value: &str, fields: &'static [FilterField]) -> ApiResult<Condition> {
+    let (field, operator) = split_operator(key, fields)
+        .ok_or_else(|| ApiError::InvalidQuery(format!("Unknown filter `{}`", key)))?;
+
+    let value =
+        match (&field.kind, &operator) {
+            (FieldKind::Text, Operator::Equals | Operator::Contains) => {
+                FilterValue::Text(value.to_string())
+            }
+            (FieldKind::Integer, Operator::Contains) | (FieldKind::Text, _) => {
+                return Err(ApiError::InvalidQuery(format!(
+                    "Filter `{}` is not supported on field `{}`",
+                    key, field.name
+                )));
+            }
+            (FieldKind::Integer, _) => FilterValue::Integer(value.parse().map_err(|_| {
+                ApiError::InvalidQuery(format!("Filter `{}` expects an integer", key))
+            })?),
+        };
+
+    return Ok(Condition {
+        field,
+        operator,
+        value,
+    });
+}
+
+fn split_operator(
+    key: &str,
+    fields: &'static [FilterField],
+) -> Option<(&'static FilterField, Operator)> {
+    if let Some(name) = key.strip_suffix('~') {
+        return find_field(name, fields).map(|field| (field, Operator::Contains));
+    }
+    let suffixes = [
+        ("_gte", Operator::GreaterThanOrEqual),
+        ("_gt", Operator::GreaterThan),
+        ("_lte", Operator::LessThanOrEqual),
+        ("_lt", Operator::LessThan),
+    ];
+    for (suffix, operator) in suffixes {
+        if let Some(field) = key
+            .strip_suffix(suffix)
+            .and_then(|name| find_field(name, fields))
+        {
+            return Some((field, operator));
+        }
+    }
+    return find_field(key, fields).map(|field| (field, Operator::Equals));
+}
@@ -1,15 +1,31 @@
 use axum::{
-    extract::{rejection::JsonRejection, Path, Query, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        DefaultBodyLimit, Path, Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
     routing::{get, post},
     Json, Router,
 };
 use serde_json::Value;
-use sqlx::{Pool, Postgres};
+use sqlx::types::time::OffsetDateTime;
 
-use super::Pagination::{Pagination, RowCount};
+use super::AppState::AppState;
+use super::Authorization::{Admin, IncludeDeleted};
+use super::Errors::{ApiError, ApiResult, ProblemResponse};
+use super::Events::{Action, EventBus, Resource};
+use super::Filter::{FilterField, FilterValue, Filterable, ListQuery};
+use super::Metrics::FRUITS_CREATED;
+use super::Negotiation::Representation;
+use super::Pagination::{Keyed, Pagination};
+use super::Precondition::{tagged_response, with_entity_tag, IfMatch, IfNoneMatch};
+use super::Repository::{Deletion, FruitRepository, Repository};
+use super::Transfer::{export_rows, parse_import, ExportQuery, ImportSummary, IMPORT_BODY_LIMIT};
+use super::Validation::{FieldError, Validate, Validator, MAX_VARCHAR_LENGTH};
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema, async_graphql::InputObject)]
 pub struct NewFruit {
     pub fruit_name: String,
     pub color_red: i16,
@@ -18,7 +34,24 @@ pub struct NewFruit {
     pub fruit_weight: i32,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct FruitPatch {
+    pub fruit_name: Option<String>,
+    pub color_red: Option<i16>,
+    pub color_green: Option<i16>,
+    pub color_blue: Option<i16>,
+    pub fruit_weight: Option<i32>,
+}
+
+#[derive(
+    Clone,
+    serde::Deserialize,
+    serde::Serialize,
+    sqlx::FromRow,
+    utoipa::ToSchema,
+    async_graphql::SimpleObject,
+)]
+#[graphql(complex)]
 pub struct Fruit {
     pub id: i64,
     pub fruit_name: String,
@@ -26,116 +59,430 @@ pub struct Fruit {
     pub color_green: i16,
     pub color_blue: i16,
     pub fruit_weight: i32,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: OffsetDateTime,
+    /// When the fruit was deleted. Only admins see deleted fruits, with
+    /// `include_deleted=true`.
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    #[graphql(skip)]
+    pub deleted_at: Option<OffsetDateTime>,
+    /// Bumped on every change, sent as the `ETag` of the fruit.
+    pub row_version: i64,
 }
 
-pub fn get_router() -> Router<Pool<Postgres>> {
-    return Router::new()
-        .route("/", post(insert_fruit))
-        .route("/:fruit_id", get(get_fruit_by_id))
-        .route("/", get(list_fruit));
+impl Keyed for Fruit {
+    fn key(&self) -> i64 {
+        return self.id;
+    }
 }
 
-pub async fn get_fruit_by_id(
-    Path(fruit_id): Path<i64>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let query_result = sqlx::query_as!(Fruit, "SELECT * FROM FRUIT WHERE ID = $1", fruit_id)
-        .fetch_one(&database_connection_pool)
-        .await;
-    match query_result {
-        Ok(fruit) => {
-            return (StatusCode::OK, Json(serde_json::json!(fruit)));
-        }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({ "error": error.to_string() })),
-            );
+impl Filterable for Fruit {
+    fn field(&self, name: &str) -> Option<FilterValue> {
+        match name {
+            "id" => return Some(FilterValue::Integer(self.id)),
+            "fruit_name" => return Some(FilterValue::Text(self.fruit_name.clone())),
+            "color_red" => return Some(FilterValue::Integer(i64::from(self.color_red))),
+            "color_green" => return Some(FilterValue::Integer(i64::from(self.color_green))),
+            "color_blue" => return Some(FilterValue::Integer(i64::from(self.color_blue))),
+            "fruit_weight" => return Some(FilterValue::Integer(i64::from(self.fruit_weight))),
+            _ => return None,
         }
     }
+
+    fn is_deleted(&self) -> bool {
+        return self.deleted_at.is_some();
+    }
 }
 
-pub async fn list_fruit(
-    maybe_pagination: Option<Query<Pagination>>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let pagination = maybe_pagination.unwrap_or_default();
-    let size = i64::from(pagination.size.unwrap_or(10));
-    let offset = size * pagination.page.unwrap_or(0);
-    let query_result = sqlx::query_as!(
-        Fruit,
-        "SELECT * FROM FRUIT LIMIT $1 OFFSET $2",
-        size,
-        offset,
-    )
-    .fetch_all(&database_connection_pool)
-    .await;
-
-    let row_query_result = sqlx::query_as!(RowCount, "SELECT COUNT(1) from FRUIT")
-        .fetch_one(&database_connection_pool)
-        .await;
-
-    let Ok(row_count) = row_query_result else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": row_query_result.err().unwrap().to_string()})),
-        );
-    };
+impl Validate for NewFruit {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        return Validator::new()
+            .length("fruit_name", &self.fruit_name, 1, MAX_VARCHAR_LENGTH)
+            .range("color_red", self.color_red, 0, 255)
+            .range("color_green", self.color_green, 0, 255)
+            .range("color_blue", self.color_blue, 0, 255)
+            .positive("fruit_weight", self.fruit_weight)
+            .finish();
+    }
+}
 
-    match query_result {
-        Ok(fruit_vec) => {
-            return (
-                StatusCode::OK,
-                Json(serde_json::json!({"total":row_count.count,"hits":fruit_vec})),
-            );
+impl Validate for FruitPatch {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut validator = Validator::new();
+        if let Some(fruit_name) = &self.fruit_name {
+            validator.length("fruit_name", fruit_name, 1, MAX_VARCHAR_LENGTH);
+        }
+        if let Some(color_red) = self.color_red {
+            validator.range("color_red", color_red, 0, 255);
         }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":error.to_string()})),
-            );
+        if let Some(color_green) = self.color_green {
+            validator.range("color_green", color_green, 0, 255);
         }
+        if let Some(color_blue) = self.color_blue {
+            validator.range("color_blue", color_blue, 0, 255);
+        }
+        if let Some(fruit_weight) = self.fruit_weight {
+            validator.positive("fruit_weight", fruit_weight);
+        }
+        return validator.finish();
+    }
+}
+
+/// Fields accepted by `list_fruit` for filtering and sorting.
+pub const FRUIT_FILTERS: &[FilterField] = &[
+    FilterField::integer("id", "ID"),
+    FilterField::text("fruit_name", "FRUIT_NAME"),
+    FilterField::integer("color_red", "COLOR_RED"),
+    FilterField::integer("color_green", "COLOR_GREEN"),
+    FilterField::integer("color_blue", "COLOR_BLUE"),
+    FilterField::integer("fruit_weight", "FRUIT_WEIGHT"),
+];
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new()
+        .route("/", post(insert_fruit::<R>))
+        .route("/export", get(export_fruit::<R>))
+        .route(
+            "/import",
+            post(import_fruit::<R>).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)),
+        )
+        .route(
+            "/:fruit_id",
+            get(get_fruit_by_id::<R>)
+                .put(update_fruit::<R>)
+                .patch(patch_fruit::<R>)
+                .delete(delete_fruit::<R>),
+        )
+        .route("/:fruit_id/restore", post(restore_fruit::<R>))
+        .route("/", get(list_fruit::<R>));
+}
+
+#[utoipa::path(
+    get,
+    path = "/fruit/{fruit_id}",
+    tag = "fruit",
+    params(
+        ("fruit_id" = i64, Path, description = "Fruit id"),
+        ("include_deleted" = Option<bool>, Query, description = "Also find a deleted fruit, admins only"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a copy the client already has"),
+    ),
+    responses(
+        (status = 200, description = "The fruit", body = Fruit, content_type = ["application/json", "text/csv", "application/msgpack"], headers(("ETag" = String))),
+        (status = 304, description = "The fruit still has the `If-None-Match` ETag"),
+        (status = 406, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+)]
+pub async fn get_fruit_by_id<R: FruitRepository>(
+    Path(fruit_id): Path<i64>,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    if_none_match: IfNoneMatch,
+    representation: Representation,
+    State(fruits): State<R>,
+) -> ApiResult<Response> {
+    let fruit = fruits
+        .get_fruit(fruit_id, include_deleted)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Fruit", fruit_id))?;
+    if let Some(not_modified) = if_none_match.not_modified(fruit.row_version) {
+        return Ok(not_modified);
+    }
+    let response = representation.row_response(&fruit)?;
+    return Ok(with_entity_tag(response, fruit.row_version));
+}
+
+#[utoipa::path(
+    get,
+    path = "/fruit",
+    tag = "fruit",
+    params(
+        Pagination,
+        ("include_deleted" = Option<bool>, Query, description = "Also list deleted fruits, admins only"),
+    ),
+    responses(
+        (status = 200, description = "A page of fruits", body = FruitList, content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 406, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+    ),
+)]
+pub async fn list_fruit<R: FruitRepository>(
+    pagination: Result<Query<Pagination>, QueryRejection>,
+    Query(parameters): Query<Vec<(String, String)>>,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    representation: Representation,
+    State(fruits): State<R>,
+) -> ApiResult<Response> {
+    let Query(pagination) = pagination?;
+    let mut list_query = ListQuery::parse(&parameters, FRUIT_FILTERS)?;
+    list_query.include_deleted = include_deleted;
+    let page_request = pagination.page_request()?;
+    if page_request.is_cursor() && list_query.is_sorted() {
+        return Err(ApiError::InvalidQuery(String::from(
+            "`sort` cannot be combined with cursor pagination",
+        )));
+    }
+    let page = fruits.list_fruits(&list_query, &page_request).await?;
+    let total = if pagination.with_total() {
+        Some(fruits.count_fruits(&list_query).await?)
+    } else {
+        None
+    };
+    return representation.list_response(page, total);
+}
+
+/// Streams every fruit, ordered by id.
+#[utoipa::path(
+    get,
+    path = "/fruit/export",
+    tag = "fruit",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "One row per fruit", body = [Fruit], content_type = ["text/csv", "application/x-ndjson"]),
+        (status = 400, response = ProblemResponse),
+    ),
+)]
+pub async fn export_fruit<R: FruitRepository>(
+    Query(export_query): Query<ExportQuery>,
+    State(fruits): State<R>,
+) -> Response {
+    return export_rows(fruits.export_fruits(), "fruit", export_query.format);
+}
+
+/// Inserts every row of a CSV (with a header line) or NDJSON body, all or
+/// nothing. Nothing is inserted if any row is invalid. Publishes a
+/// `fruit.created` event per row.
+#[utoipa::path(
+    post,
+    path = "/fruit/import",
+    tag = "fruit",
+    request_body(content = [NewFruit], content_type = "text/csv", description = "`NewFruit` rows as CSV with a header line, or as NDJSON with `Content-Type: application/x-ndjson`"),
+    responses(
+        (status = 201, description = "Number of imported rows", body = ImportSummary),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 413, description = "The body is larger than 64 MiB"),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn import_fruit<R: FruitRepository>(
+    _admin: Admin,
+    State(fruits): State<R>,
+    State(events): State<EventBus>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let new_fruits: Vec<NewFruit> = parse_import(&headers, &body)?;
+    let imported = fruits.import_fruits(&new_fruits).await?;
+    FRUITS_CREATED.inc_by(imported.len() as u64);
+    for fruit in &imported {
+        events.changed(Action::Created, fruit);
     }
+
+    let summary = ImportSummary {
+        imported: imported.len(),
+    };
+    return Ok((StatusCode::CREATED, Json(serde_json::json!(summary))));
 }
 
-pub async fn insert_fruit(
-    State(database_connection_pool): State<Pool<Postgres>>,
+#[utoipa::path(
+    post,
+    path = "/fruit",
+    tag = "fruit",
+    request_body = NewFruit,
+    responses(
+        (status = 201, description = "The created fruit", body = Fruit),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn insert_fruit<R: FruitRepository>(
+    _admin: Admin,
+    State(fruits): State<R>,
+    State(events): State<EventBus>,
     body: Result<Json<NewFruit>, JsonRejection>,
-) -> (StatusCode, Json<Value>) {
-    match body {
-        Ok(fruit_json) => {
-            let query_result = sqlx::query_as!(
-                Fruit,
-                r#"
-                INSERT INTO FRUIT ( FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, FRUIT_WEIGHT ) 
-                VALUES ( $1, $2, $3, $4, $5 ) 
-                RETURNING ID, FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, FRUIT_WEIGHT
-                "#,
-                fruit_json.fruit_name,
-                fruit_json.color_red,
-                fruit_json.color_green,
-                fruit_json.color_blue,
-                fruit_json.fruit_weight
-            )
-            .fetch_one(&database_connection_pool)
-            .await;
-            match query_result {
-                Ok(fruit) => {
-                    return (StatusCode::CREATED, Json(serde_json::json!(fruit)));
-                }
-                Err(json_error) => {
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(serde_json::json!({"error":json_error.to_string()})),
-                    );
-                }
-            }
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(fruit_json) = body?;
+    fruit_json.validate()?;
+    let fruit = fruits.insert_fruit(&fruit_json).await?;
+    FRUITS_CREATED.inc();
+    events.changed(Action::Created, &fruit);
+    return Ok((StatusCode::CREATED, Json(serde_json::json!(fruit))));
+}
+
+#[utoipa::path(
+    put,
+    path = "/fruit/{fruit_id}",
+    tag = "fruit",
+    params(
+        ("fruit_id" = i64, Path, description = "Fruit id"),
+        ("If-Match" = String, Header, description = "ETag of the fruit as last read"),
+    ),
+    request_body = NewFruit,
+    responses(
+        (status = 200, description = "The updated fruit", body = Fruit, headers(("ETag" = String))),
+        (status = 412, response = ProblemResponse),
+        (status = 428, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn update_fruit<R: FruitRepository>(
+    Path(fruit_id): Path<i64>,
+    _admin: Admin,
+    if_match: IfMatch,
+    State(fruits): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<NewFruit>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(fruit_json) = body?;
+    fruit_json.validate()?;
+    let current = fruits
+        .get_fruit(fruit_id, false)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Fruit", fruit_id))?;
+    if_match.check("Fruit", fruit_id, current.row_version)?;
+    let fruit = fruits
+        .update_fruit(fruit_id, &fruit_json, current.row_version)
+        .await?
+        .ok_or_else(|| ApiError::stale("Fruit", fruit_id))?;
+    events.changed(Action::Updated, &fruit);
+    return Ok(tagged_response(
+        StatusCode::OK,
+        serde_json::json!(fruit),
+        fruit.row_version,
+    ));
+}
+
+#[utoipa::path(
+    patch,
+    path = "/fruit/{fruit_id}",
+    tag = "fruit",
+    params(
+        ("fruit_id" = i64, Path, description = "Fruit id"),
+        ("If-Match" = String, Header, description = "ETag of the fruit as last read"),
+    ),
+    request_body = FruitPatch,
+    responses(
+        (status = 200, description = "The updated fruit", body = Fruit, headers(("ETag" = String))),
+        (status = 412, response = ProblemResponse),
+        (status = 428, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn patch_fruit<R: FruitRepository>(
+    Path(fruit_id): Path<i64>,
+    _admin: Admin,
+    if_match: IfMatch,
+    State(fruits): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<FruitPatch>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(fruit_json) = body?;
+    fruit_json.validate()?;
+    let current = fruits
+        .get_fruit(fruit_id, false)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Fruit", fruit_id))?;
+    if_match.check("Fruit", fruit_id, current.row_version)?;
+    let fruit = fruits
+        .patch_fruit(fruit_id, &fruit_json, current.row_version)
+        .await?
+        .ok_or_else(|| ApiError::stale("Fruit", fruit_id))?;
+    events.changed(Action::Updated, &fruit);
+    return Ok(tagged_response(
+        StatusCode::OK,
+        serde_json::json!(fruit),
+        fruit.row_version,
+    ));
+}
+
+/// Refuses to delete a fruit that is still used by a salad, since
+/// `SALAD_INGREDIENTS.ID_FRUIT` references it. Ingredients of deleted salads
+/// do not count.
+#[utoipa::path(
+    delete,
+    path = "/fruit/{fruit_id}",
+    tag = "fruit",
+    params(("fruit_id" = i64, Path, description = "Fruit id")),
+    responses(
+        (status = 204, description = "The fruit was deleted"),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_fruit<R: FruitRepository>(
+    Path(fruit_id): Path<i64>,
+    _admin: Admin,
+    State(fruits): State<R>,
+    State(events): State<EventBus>,
+) -> ApiResult<StatusCode> {
+    match fruits.delete_fruit(fruit_id).await? {
+        Deletion::Deleted => {
+            events.deleted(Resource::Fruit, fruit_id, None);
+            return Ok(StatusCode::NO_CONTENT);
         }
-        Err(json_error) => {
-            return (
-                json_error.status(),
-                Json(serde_json::json!({"error":json_error.to_string()})),
-            );
+        Deletion::NotFound => return Err(ApiError::not_found("Fruit", fruit_id)),
+        Deletion::InUse(usage_count) => {
+            return Err(ApiError::Conflict(format!(
+                "Fruit {} is used by {} salad ingredient(s)",
+                fruit_id, usage_count
+            )))
         }
     }
 }
+
+#[utoipa::path(
+    post,
+    path = "/fruit/{fruit_id}/restore",
+    tag = "fruit",
+    params(("fruit_id" = i64, Path, description = "Fruit id")),
+    responses(
+        (status = 200, description = "The restored fruit", body = Fruit),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn restore_fruit<R: FruitRepository>(
+    Path(fruit_id): Path<i64>,
+    _admin: Admin,
+    State(fruits): State<R>,
+    State(events): State<EventBus>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let fruit = fruits
+        .restore_fruit(fruit_id)
+        .await?
+        .into_result("Fruit", fruit_id)?;
+    events.changed(Action::Restored, &fruit);
+    return Ok((StatusCode::OK, Json(serde_json::json!(fruit))));
+}
@@ -0,0 +1,611 @@
+//! `/graphql`: people, fruits, salads and ingredients as one graph. Related
+//! rows are fetched through a `DataLoader` per request, so the salads of a
+//! whole page of people take one query rather than one per person.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{
+    ComplexObject, Context, EmptySubscription, ErrorExtensions, Object, OutputType, Schema,
+    SimpleObject,
+};
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRequestParts, State},
+    http::{header, request::Parts, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use super::AppState::AppState;
+use super::Auth::CurrentPerson;
+use super::Errors::{ApiError, ApiResult, ProblemResponse};
+use super::Events::{Action, EventBus};
+use super::Filter::ListQuery;
+use super::Fruit::{Fruit, NewFruit};
+use super::Metrics::{FRUITS_CREATED, INGREDIENTS_ADDED, PEOPLE_CREATED, SALADS_CREATED};
+use super::Pagination::{decode_cursor, Cursor, Page, PageRequest};
+use super::Person::{NewPerson, Person};
+use super::Repository::{
+    FruitRepository, PersonRepository, Repository, SaladIngredientRepository, SaladRepository,
+};
+use super::Salad::{FruitSalad, NewFruitSalad};
+use super::SaladIngredient::{ensure_target_salad_owner, NewSaladIngredient, SaladIngredient};
+use super::Validation::Validate;
+
+/// Deepest selection a query may have, `{ people { hits { salads { ... } } } }`
+/// is already four levels.
+const MAX_QUERY_DEPTH: usize = 8;
+/// Every field costs one, lists cost their children times their size.
+const MAX_QUERY_COMPLEXITY: usize = 2000;
+/// Assumed size of the unpaged lists between rows, like a person's salads.
+const RELATED_LIST_COMPLEXITY: usize = 5;
+
+type GraphSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+static SCHEMA: Lazy<GraphSchema> = Lazy::new(|| {
+    return Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish();
+});
+
+/// Every repository behind one trait object, since the schema is built once
+/// for all backends.
+trait Records:
+    PersonRepository + FruitRepository + SaladRepository + SaladIngredientRepository
+{
+}
+
+impl<T> Records for T where
+    T: PersonRepository + FruitRepository + SaladRepository + SaladIngredientRepository
+{
+}
+
+/// The person behind the bearer token of the request, if one was sent.
+pub struct Viewer(Option<CurrentPerson>);
+
+#[async_trait]
+impl<R: Repository> FromRequestParts<AppState<R>> for Viewer {
+    type Rejection = ApiError;
+
+    /// Reads are open to everybody, but a token that is sent must be valid.
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &AppState<R>,
+    ) -> Result<Self, Self::Rejection> {
+        if !parts.headers.contains_key(header::AUTHORIZATION) {
+            return Ok(Viewer(None));
+        }
+        let current_person = CurrentPerson::from_request_parts(parts, app_state).await?;
+        return Ok(Viewer(Some(current_person)));
+    }
+}
+
+impl Viewer {
+    fn person(&self) -> ApiResult<&CurrentPerson> {
+        return self
+            .0
+            .as_ref()
+            .ok_or_else(|| ApiError::Unauthorized(String::from("Missing bearer token")));
+    }
+
+    fn admin(&self) -> ApiResult<&CurrentPerson> {
+        let current_person = self.person()?;
+        current_person.ensure_admin()?;
+        return Ok(current_person);
+    }
+}
+
+/// Errors carry the `code` of the REST problem, and `errors` or `reason`
+/// when the problem would have them.
+impl From<ApiError> for async_graphql::Error {
+    fn from(error: ApiError) -> Self {
+        error.log_internal();
+        let details = match &error {
+            ApiError::Validation(errors) => serde_json::to_value(errors)
+                .ok()
+                .and_then(|errors| async_graphql::Value::from_json(errors).ok())
+                .map(|errors| ("errors", errors)),
+            ApiError::Forbidden(reason, _) => Some(("reason", async_graphql::Value::from(*reason))),
+            _ => None,
+        };
+        return async_graphql::Error::new(error.detail()).extend_with(|_, extensions| {
+            extensions.set("code", error.code());
+            if let Some((name, value)) = details {
+                extensions.set(name, value);
+            }
+        });
+    }
+}
+
+fn records<'a>(context: &Context<'a>) -> &'a Arc<dyn Records> {
+    return context.data_unchecked::<Arc<dyn Records>>();
+}
+
+fn loader<'a>(context: &Context<'a>) -> &'a DataLoader<RecordLoader> {
+    return context.data_unchecked::<DataLoader<RecordLoader>>();
+}
+
+fn viewer<'a>(context: &Context<'a>) -> &'a Viewer {
+    return context.data_unchecked::<Viewer>();
+}
+
+fn events<'a>(context: &Context<'a>) -> &'a EventBus {
+    return context.data_unchecked::<EventBus>();
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct PersonId(i64);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FruitId(i64);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SaladId(i64);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct IngredientId(i64);
+/// The salads created by a person.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SaladsOfPerson(i64);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct IngredientsOfSalad(i64);
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct IngredientsOfFruit(i64);
+
+/// Loads live rows by the keys above, one query per batch of keys.
+struct RecordLoader(Arc<dyn Records>);
+
+/// `rows` by the key `key_of` gives each of them, in the order they came.
+fn group_rows<K: Eq + Hash, T>(rows: Vec<T>, key_of: impl Fn(&T) -> K) -> HashMap<K, Vec<T>> {
+    let mut groups: HashMap<K, Vec<T>> = HashMap::new();
+    for row in rows {
+        groups.entry(key_of(&row)).or_default().push(row);
+    }
+    return groups;
+}
+
+fn key_ids<K: Copy>(keys: &[K], id_of: impl Fn(K) -> i64) -> Vec<i64> {
+    return keys.iter().map(|key| id_of(*key)).collect();
+}
+
+impl Loader<PersonId> for RecordLoader {
+    type Value = Person;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[PersonId]) -> Result<HashMap<PersonId, Person>, Self::Error> {
+        let people = self.0.get_people(&key_ids(keys, |key| key.0)).await?;
+        return Ok(people
+            .into_iter()
+            .map(|person| (PersonId(person.id), person))
+            .collect());
+    }
+}
+
+impl Loader<FruitId> for RecordLoader {
+    type Value = Fruit;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[FruitId]) -> Result<HashMap<FruitId, Fruit>, Self::Error> {
+        let fruits = self.0.get_fruits(&key_ids(keys, |key| key.0)).await?;
+        return Ok(fruits
+            .into_iter()
+            .map(|fruit| (FruitId(fruit.id), fruit))
+            .collect());
+    }
+}
+
+impl Loader<SaladId> for RecordLoader {
+    type Value = FruitSalad;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[SaladId]) -> Result<HashMap<SaladId, FruitSalad>, Self::Error> {
+        let salads = self.0.get_salads(&key_ids(keys, |key| key.0)).await?;
+        return Ok(salads
+            .into_iter()
+            .map(|salad| (SaladId(salad.id), salad))
+            .collect());
+    }
+}
+
+impl Loader<IngredientId> for RecordLoader {
+    type Value = SaladIngredient;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[IngredientId],
+    ) -> Result<HashMap<IngredientId, SaladIngredient>, Self::Error> {
+        let ingredients = self
+            .0
+            .get_salad_ingredients(&key_ids(keys, |key| key.0))
+            .await?;
+        return Ok(ingredients
+            .into_iter()
+            .map(|ingredient| (IngredientId(ingredient.id), ingredient))
+            .collect());
+    }
+}
+
+impl Loader<SaladsOfPerson> for RecordLoader {
+    type Value = Vec<FruitSalad>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[SaladsOfPerson],
+    ) -> Result<HashMap<SaladsOfPerson, Vec<FruitSalad>>, Self::Error> {
+        let salads = self
+            .0
+            .list_salads_of_creators(&key_ids(keys, |key| key.0))
+            .await?;
+        return Ok(group_rows(salads, |salad| SaladsOfPerson(salad.id_creator)));
+    }
+}
+
+impl Loader<IngredientsOfSalad> for RecordLoader {
+    type Value = Vec<SaladIngredient>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[IngredientsOfSalad],
+    ) -> Result<HashMap<IngredientsOfSalad, Vec<SaladIngredient>>, Self::Error> {
+        let ingredients = self
+            .0
+            .list_ingredients_of_salads(&key_ids(keys, |key| key.0))
+            .await?;
+        return Ok(group_rows(ingredients, |ingredient| {
+            return IngredientsOfSalad(ingredient.id_salad);
+        }));
+    }
+}
+
+impl Loader<IngredientsOfFruit> for RecordLoader {
+    type Value = Vec<SaladIngredient>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[IngredientsOfFruit],
+    ) -> Result<HashMap<IngredientsOfFruit, Vec<SaladIngredient>>, Self::Error> {
+        let ingredients = self
+            .0
+            .list_ingredients_of_fruits(&key_ids(keys, |key| key.0))
+            .await?;
+        return Ok(group_rows(ingredients, |ingredient| {
+            return IngredientsOfFruit(ingredient.id_fruit);
+        }));
+    }
+}
+
+/// One page of a list, continued by passing `nextCursor` as `after`.
+#[derive(SimpleObject)]
+#[graphql(concrete(name = "PersonPage", params(Person)))]
+#[graphql(concrete(name = "FruitPage", params(Fruit)))]
+#[graphql(concrete(name = "FruitSaladPage", params(FruitSalad)))]
+#[graphql(concrete(name = "SaladIngredientPage", params(SaladIngredient)))]
+struct PageOf<T: OutputType> {
+    hits: Vec<T>,
+    next_cursor: Option<String>,
+    /// Size of the whole list, only counted when selected.
+    total: Option<i64>,
+}
+
+/// The cursor page of `first` rows after the `after` cursor.
+fn page_request(first: i32, after: Option<&str>) -> ApiResult<PageRequest> {
+    let after = after
+        .filter(|after| !after.is_empty())
+        .map(decode_cursor)
+        .transpose()?;
+    return Ok(PageRequest::Cursor {
+        cursor: Cursor::After(after),
+        size: i64::from(first),
+    });
+}
+
+/// Counts the list with `count` if the query selected `total`.
+async fn page_of<T, F>(context: &Context<'_>, page: Page<T>, count: F) -> ApiResult<PageOf<T>>
+where
+    T: OutputType,
+    F: std::future::Future<Output = ApiResult<i64>>,
+{
+    let total = if context.look_ahead().field("total").exists() {
+        Some(count.await?)
+    } else {
+        None
+    };
+    match page {
+        Page::Cursor(page) => {
+            return Ok(PageOf {
+                hits: page.hits,
+                next_cursor: page.next_cursor,
+                total,
+            })
+        }
+        Page::Offset { hits } => {
+            return Ok(PageOf {
+                hits,
+                next_cursor: None,
+                total,
+            })
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    #[graphql(complexity = "first.max(0) as usize * child_complexity")]
+    async fn people(
+        &self,
+        context: &Context<'_>,
+        #[graphql(default = 10, validator(minimum = 1, maximum = 100))] first: i32,
+        after: Option<String>,
+    ) -> async_graphql::Result<PageOf<Person>> {
+        let records = records(context);
+        let list_query = ListQuery::default();
+        let page = records
+            .list_people(&list_query, &page_request(first, after.as_deref())?)
+            .await?;
+        return Ok(page_of(context, page, records.count_people(&list_query)).await?);
+    }
+
+    async fn person(
+        &self,
+        context: &Context<'_>,
+        id: i64,
+    ) -> async_graphql::Result<Option<Person>> {
+        return loader(context).load_one(PersonId(id)).await;
+    }
+
+    #[graphql(complexity = "first.max(0) as usize * child_complexity")]
+    async fn fruits(
+        &self,
+        context: &Context<'_>,
+        #[graphql(default = 10, validator(minimum = 1, maximum = 100))] first: i32,
+        after: Option<String>,
+    ) -> async_graphql::Result<PageOf<Fruit>> {
+        let records = records(context);
+        let list_query = ListQuery::default();
+        let page = records
+            .list_fruits(&list_query, &page_request(first, after.as_deref())?)
+            .await?;
+        return Ok(page_of(context, page, records.count_fruits(&list_query)).await?);
+    }
+
+    async fn fruit(&self, context: &Context<'_>, id: i64) -> async_graphql::Result<Option<Fruit>> {
+        return loader(context).load_one(FruitId(id)).await;
+    }
+
+    #[graphql(complexity = "first.max(0) as usize * child_complexity")]
+    async fn salads(
+        &self,
+        context: &Context<'_>,
+        #[graphql(default = 10, validator(minimum = 1, maximum = 100))] first: i32,
+        after: Option<String>,
+    ) -> async_graphql::Result<PageOf<FruitSalad>> {
+        let records = records(context);
+        let list_query = ListQuery::default();
+        let page = records
+            .list_salads(&list_query, &page_request(first, after.as_deref())?)
+            .await?;
+        return Ok(page_of(context, page, records.count_salads(&list_query)).await?);
+    }
+
+    async fn salad(
+        &self,
+        context: &Context<'_>,
+        id: i64,
+    ) -> async_graphql::Result<Option<FruitSalad>> {
+        return loader(context).load_one(SaladId(id)).await;
+    }
+
+    #[graphql(complexity = "first.max(0) as usize * child_complexity")]
+    async fn ingredients(
+        &self,
+        context: &Context<'_>,
+        #[graphql(default = 10, validator(minimum = 1, maximum = 100))] first: i32,
+        after: Option<String>,
+    ) -> async_graphql::Result<PageOf<SaladIngredient>> {
+        let records = records(context);
+        let page = records
+            .list_salad_ingredients(&page_request(first, after.as_deref())?, false)
+            .await?;
+        return Ok(page_of(context, page, records.count_salad_ingredients(false)).await?);
+    }
+
+    async fn ingredient(
+        &self,
+        context: &Context<'_>,
+        id: i64,
+    ) -> async_graphql::Result<Option<SaladIngredient>> {
+        return loader(context).load_one(IngredientId(id)).await;
+    }
+}
+
+pub struct MutationRoot;
+
+/// Mutations need the same role as their REST endpoint.
+#[Object]
+impl MutationRoot {
+    async fn create_person(
+        &self,
+        context: &Context<'_>,
+        input: NewPerson,
+    ) -> async_graphql::Result<Person> {
+        viewer(context).admin()?;
+        input.validate().map_err(ApiError::from)?;
+        let person = records(context).insert_person(&input).await?;
+        PEOPLE_CREATED.inc();
+        events(context).changed(Action::Created, &person);
+        return Ok(person);
+    }
+
+    async fn create_fruit(
+        &self,
+        context: &Context<'_>,
+        input: NewFruit,
+    ) -> async_graphql::Result<Fruit> {
+        viewer(context).admin()?;
+        input.validate().map_err(ApiError::from)?;
+        let fruit = records(context).insert_fruit(&input).await?;
+        FRUITS_CREATED.inc();
+        events(context).changed(Action::Created, &fruit);
+        return Ok(fruit);
+    }
+
+    /// Creates a salad of the viewer, with one whole fruit per id in
+    /// `ingredients`.
+    async fn create_salad(
+        &self,
+        context: &Context<'_>,
+        input: NewFruitSalad,
+    ) -> async_graphql::Result<FruitSalad> {
+        let current_person = viewer(context).person()?;
+        input.validate().map_err(ApiError::from)?;
+        let full_salad = records(context)
+            .insert_salad(current_person.person.id, &input)
+            .await?;
+        SALADS_CREATED.inc();
+        INGREDIENTS_ADDED.inc_by(full_salad.ingredients.len() as u64);
+        let events = events(context);
+        events.changed(Action::Created, &full_salad.salad);
+        for ingredient in &full_salad.ingredients {
+            events.changed(Action::Created, ingredient);
+        }
+        return Ok(full_salad.salad);
+    }
+
+    async fn add_salad_ingredient(
+        &self,
+        context: &Context<'_>,
+        input: NewSaladIngredient,
+    ) -> async_graphql::Result<SaladIngredient> {
+        let current_person = viewer(context).person()?;
+        input.validate().map_err(ApiError::from)?;
+        let records = records(context);
+        ensure_target_salad_owner(records.as_ref(), input.id_salad, current_person).await?;
+        let ingredient = records
+            .insert_salad_ingredient(&input)
+            .await?
+            .ok_or_else(|| {
+                ApiError::ForeignKeyViolation(format!("Fruit {} not found", input.id_fruit))
+            })?;
+        INGREDIENTS_ADDED.inc();
+        events(context).changed(Action::Created, &ingredient);
+        return Ok(ingredient);
+    }
+}
+
+#[ComplexObject]
+impl Person {
+    #[graphql(complexity = "RELATED_LIST_COMPLEXITY * child_complexity")]
+    async fn salads(&self, context: &Context<'_>) -> async_graphql::Result<Vec<FruitSalad>> {
+        let salads = loader(context).load_one(SaladsOfPerson(self.id)).await?;
+        return Ok(salads.unwrap_or_default());
+    }
+}
+
+#[ComplexObject]
+impl Fruit {
+    /// The salads with this fruit among their ingredients.
+    #[graphql(complexity = "RELATED_LIST_COMPLEXITY * child_complexity")]
+    async fn salads(&self, context: &Context<'_>) -> async_graphql::Result<Vec<FruitSalad>> {
+        let loader = loader(context);
+        let ingredients = loader.load_one(IngredientsOfFruit(self.id)).await?;
+        let salad_ids: Vec<SaladId> = ingredients
+            .unwrap_or_default()
+            .iter()
+            .map(|ingredient| SaladId(ingredient.id_salad))
+            .collect();
+        let mut salads = loader.load_many(salad_ids.iter().copied()).await?;
+        return Ok(salad_ids
+            .iter()
+            .filter_map(|salad_id| salads.remove(salad_id))
+            .collect());
+    }
+}
+
+#[ComplexObject]
+impl FruitSalad {
+    async fn creator(&self, context: &Context<'_>) -> async_graphql::Result<Option<Person>> {
+        return loader(context).load_one(PersonId(self.id_creator)).await;
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COMPLEXITY * child_complexity")]
+    async fn ingredients(
+        &self,
+        context: &Context<'_>,
+    ) -> async_graphql::Result<Vec<SaladIngredient>> {
+        let ingredients = loader(context)
+            .load_one(IngredientsOfSalad(self.id))
+            .await?;
+        return Ok(ingredients.unwrap_or_default());
+    }
+
+    /// The fruits of the salad's ingredients, in the order they were added.
+    #[graphql(complexity = "RELATED_LIST_COMPLEXITY * child_complexity")]
+    async fn fruits(&self, context: &Context<'_>) -> async_graphql::Result<Vec<Fruit>> {
+        let loader = loader(context);
+        let ingredients = loader.load_one(IngredientsOfSalad(self.id)).await?;
+        let fruit_ids: Vec<FruitId> = ingredients
+            .unwrap_or_default()
+            .iter()
+            .map(|ingredient| FruitId(ingredient.id_fruit))
+            .collect();
+        let mut fruits = loader.load_many(fruit_ids.iter().copied()).await?;
+        return Ok(fruit_ids
+            .iter()
+            .filter_map(|fruit_id| fruits.remove(fruit_id))
+            .collect());
+    }
+}
+
+#[ComplexObject]
+impl SaladIngredient {
+    async fn salad(&self, context: &Context<'_>) -> async_graphql::Result<Option<FruitSalad>> {
+        return loader(context).load_one(SaladId(self.id_salad)).await;
+    }
+
+    async fn fruit(&self, context: &Context<'_>) -> async_graphql::Result<Option<Fruit>> {
+        return loader(context).load_one(FruitId(self.id_fruit)).await;
+    }
+}
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new().route("/", post(execute::<R>));
+}
+
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(
+        content = Object,
+        description = "A GraphQL request with `query` and optional `variables` and `operationName`",
+    ),
+    responses(
+        (status = 200, description = "The GraphQL response, with `errors` if any", body = Object),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+    ),
+    security((), ("bearer" = [])),
+)]
+pub async fn execute<R: Repository>(
+    viewer: Viewer,
+    State(repository): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<async_graphql::Request>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(request) = body?;
+    let records: Arc<dyn Records> = Arc::new(repository);
+    let loader = DataLoader::new(RecordLoader(records.clone()), tokio::spawn);
+    let request = request.data(records).data(loader).data(viewer).data(events);
+    let response = SCHEMA.execute(request).await;
+    return Ok((StatusCode::OK, Json(serde_json::json!(response))));
+}
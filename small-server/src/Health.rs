@@ -0,0 +1,57 @@
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde_json::Value;
+
+use super::AppState::AppState;
+use super::Repository::Repository;
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new()
+        .route("/healthz", get(get_health))
+        .route("/readyz", get(get_readiness::<R>));
+}
+
+/// Liveness probe, answers as long as the process serves requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The process is up"))
+)]
+pub async fn get_health() -> (StatusCode, Json<Value>) {
+    return (StatusCode::OK, Json(serde_json::json!({"status": "ok"})));
+}
+
+/// Readiness probe, pings the database so a server that cannot reach it is
+/// taken out of rotation. Probes are unauthenticated, so why the ping failed
+/// is logged rather than answered.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "The database answers"),
+        (status = 503, description = "The database cannot be reached"),
+    )
+)]
+pub async fn get_readiness<R: Repository>(
+    State(repository): State<R>,
+) -> (StatusCode, Json<Value>) {
+    match repository.ping().await {
+        Ok(()) => {
+            return (
+                StatusCode::OK,
+                Json(serde_json::json!({"status": "ok", "database": "ok"})),
+            )
+        }
+        Err(error) => {
+            tracing::error!(error = %error, "readiness ping failed");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({
+                    "status": "unavailable",
+                    "database": "unavailable",
+                })),
+            );
+        }
+    }
+}
@@ -0,0 +1,1608 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use axum::async_trait;
+use futures::StreamExt;
+use serde_json::Value;
+use sqlx::types::time::OffsetDateTime;
+use sqlx::types::Json;
+
+use super::Authorization::Role;
+use super::Errors::{ApiError, ApiResult};
+use super::Filter::{Filterable, ListQuery};
+use super::Fruit::{Fruit, FruitPatch, NewFruit};
+use super::Pagination::{finish_cursor_page, Cursor, Keyed, Page, PageRequest};
+use super::Person::{NewPerson, Person, PersonPatch};
+use super::Repository::{
+    Account, Deletion, FruitRepository, PersonRepository, PoolStatus, Repository, Restoration,
+    RowStream, SaladIngredientRepository, SaladRepository, WebhookRepository,
+};
+use super::Salad::{
+    FruitSalad, FruitSaladPatch, FullFruitSalad, NewFruitSalad, SaladComponent,
+    SaladIngredientsView, SaladView,
+};
+use super::SaladIngredient::{NewSaladIngredient, SaladIngredient, SaladIngredientPatch};
+use super::Webhook::{
+    DeliveryAttempt, DueDelivery, NewWebhook, Webhook, WebhookDelivery, DELIVERY_DEAD,
+    DELIVERY_DELIVERED, DELIVERY_PENDING,
+};
+
+/// Every repository, kept in memory for tests and local experiments. It
+/// enforces the same unique and foreign key constraints as the Postgres
+/// schema and reports them with the same messages. Soft-deleted rows stay in
+/// their table with `deleted_at` set, like they do in the database.
+#[derive(Clone, Default)]
+pub struct MemoryRepository {
+    store: Arc<Mutex<MemoryStore>>,
+}
+
+#[derive(Default)]
+struct MemoryStore {
+    people: Table<PersonRow>,
+    sessions: Table<SessionRow>,
+    fruits: Table<Fruit>,
+    salads: Table<FruitSalad>,
+    ingredients: Table<SaladIngredient>,
+    webhooks: Table<WebhookRow>,
+    deliveries: Table<WebhookDelivery>,
+}
+
+/// Rows by id, so iterating a table gives them in id order. Like a
+/// `bigserial`, ids are never reused.
+struct Table<T> {
+    rows: BTreeMap<i64, T>,
+    last_id: i64,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Table<T> {
+        return Table {
+            rows: BTreeMap::new(),
+            last_id: 0,
+        };
+    }
+}
+
+impl<T> Table<T> {
+    fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        return self.last_id;
+    }
+}
+
+struct PersonRow {
+    person: Person,
+    role: Role,
+    password_hash: Option<String>,
+}
+
+struct WebhookRow {
+    webhook: Webhook,
+    secret: String,
+}
+
+struct SessionRow {
+    id_person: i64,
+    expires_at: OffsetDateTime,
+    revoked: bool,
+}
+
+fn unique_violation(constraint: &str) -> ApiError {
+    return ApiError::UniqueViolation(format!(
+        "duplicate key value violates unique constraint \"{}\"",
+        constraint
+    ));
+}
+
+fn foreign_key_violation(table: &str, constraint: &str) -> ApiError {
+    return ApiError::ForeignKeyViolation(format!(
+        "insert or update on table \"{}\" violates foreign key constraint \"{}\"",
+        table, constraint
+    ));
+}
+
+/// Rows matching `list_query`, in the order `push_order_by` would give.
+fn query_rows<'a, T, I>(rows: I, list_query: &ListQuery) -> Vec<T>
+where
+    T: Filterable + Clone + 'a,
+    I: Iterator<Item = &'a T>,
+{
+    let mut hits: Vec<T> = rows
+        .filter(|row| list_query.matches(*row))
+        .cloned()
+        .collect();
+    hits.sort_by(|left, right| list_query.compare(left, right));
+    return hits;
+}
+
+/// Cuts the page asked for out of `rows`. In cursor mode `rows` must be in
+/// id order.
+fn page_rows<T: Keyed>(mut rows: Vec<T>, page_request: &PageRequest) -> Page<T> {
+    match page_request {
+        PageRequest::Offset { size, offset } => {
+            let hits = rows
+                .into_iter()
+                .skip((*offset).max(0) as usize)
+                .take((*size).max(0) as usize)
+                .collect();
+            return Page::Offset { hits };
+        }
+        PageRequest::Cursor { cursor, size } => {
+            let beyond_cursor = match cursor {
+                Cursor::After(Some(after)) => {
+                    let beyond_cursor = rows.iter().any(|row| row.key() <= *after);
+                    rows.retain(|row| row.key() > *after);
+                    beyond_cursor
+                }
+                Cursor::After(None) => false,
+                Cursor::Before(before) => {
+                    let beyond_cursor = rows.iter().any(|row| row.key() >= *before);
+                    rows.retain(|row| row.key() < *before);
+                    rows.reverse();
+                    beyond_cursor
+                }
+            };
+            rows.truncate((*size + 1).max(0) as usize);
+            return Page::Cursor(finish_cursor_page(rows, cursor, *size, beyond_cursor));
+        }
+    }
+}
+
+fn stream_rows<T: Send + 'static>(rows: Vec<T>) -> RowStream<T> {
+    return futures::stream::iter(rows.into_iter().map(Ok)).boxed();
+}
+
+impl MemoryRepository {
+    fn store(&self) -> MutexGuard<'_, MemoryStore> {
+        // A panic while holding the lock cannot leave a table half-written,
+        // every method checks its constraints before changing anything.
+        return self
+            .store
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+}
+
+impl MemoryStore {
+    /// Enforces `PERSON_LOGIN_EMAIL_UNIQUE` for a person who can log in.
+    fn check_login_email(&self, email: &str, person_id: Option<i64>) -> ApiResult<()> {
+        let email = email.to_lowercase();
+        let taken = self.people.rows.values().any(|row| {
+            return row.password_hash.is_some()
+                && row.person.deleted_at.is_none()
+                && Some(row.person.id) != person_id
+                && row.person.email.to_lowercase() == email;
+        });
+        if taken {
+            return Err(unique_violation("person_login_email_unique"));
+        }
+        return Ok(());
+    }
+
+    /// Enforces the foreign keys and `SALAD_INGREDIENTS_SALAD_FRUIT_UNIQUE`
+    /// for the live `ingredient`, which replaces the row with the same id if
+    /// any.
+    fn check_ingredient(&self, ingredient: &SaladIngredient) -> ApiResult<()> {
+        if !self.salads.rows.contains_key(&ingredient.id_salad) {
+            return Err(foreign_key_violation(
+                "salad_ingredients",
+                "salad_ingredients_id_salad_fkey",
+            ));
+        }
+        if !self.fruits.rows.contains_key(&ingredient.id_fruit) {
+            return Err(foreign_key_violation(
+                "salad_ingredients",
+                "salad_ingredients_id_fruit_fkey",
+            ));
+        }
+        let duplicate = self.ingredients.rows.values().any(|row| {
+            return row.id != ingredient.id
+                && row.deleted_at.is_none()
+                && row.id_salad == ingredient.id_salad
+                && row.id_fruit == ingredient.id_fruit;
+        });
+        if duplicate {
+            return Err(unique_violation("salad_ingredients_salad_fruit_unique"));
+        }
+        return Ok(());
+    }
+
+    fn set_person(&mut self, person: Person) -> ApiResult<Person> {
+        let row = self.people.rows.get(&person.id).expect("person exists");
+        if row.password_hash.is_some() {
+            self.check_login_email(&person.email, Some(person.id))?;
+        }
+        self.people
+            .rows
+            .get_mut(&person.id)
+            .expect("person exists")
+            .person = person.clone();
+        return Ok(person);
+    }
+
+    fn set_ingredient(&mut self, ingredient: SaladIngredient) -> ApiResult<SaladIngredient> {
+        self.check_ingredient(&ingredient)?;
+        self.ingredients
+            .rows
+            .insert(ingredient.id, ingredient.clone());
+        return Ok(ingredient);
+    }
+}
+
+#[async_trait]
+impl PersonRepository for MemoryRepository {
+    async fn get_person(&self, person_id: i64, include_deleted: bool) -> ApiResult<Option<Person>> {
+        let store = self.store();
+        return Ok(store
+            .people
+            .rows
+            .get(&person_id)
+            .filter(|row| include_deleted || row.person.deleted_at.is_none())
+            .map(|row| row.person.clone()));
+    }
+
+    async fn list_people(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<Person>> {
+        let store = self.store();
+        let people = store.people.rows.values().map(|row| &row.person);
+        return Ok(page_rows(query_rows(people, list_query), page_request));
+    }
+
+    async fn count_people(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let store = self.store();
+        let count = store
+            .people
+            .rows
+            .values()
+            .filter(|row| list_query.matches(&row.person))
+            .count();
+        return Ok(count as i64);
+    }
+
+    async fn get_people(&self, person_ids: &[i64]) -> ApiResult<Vec<Person>> {
+        let store = self.store();
+        let people = store
+            .people
+            .rows
+            .values()
+            .filter(|row| row.person.deleted_at.is_none() && person_ids.contains(&row.person.id))
+            .map(|row| row.person.clone());
+        return Ok(people.collect());
+    }
+
+    fn export_people(&self) -> RowStream<Person> {
+        let store = self.store();
+        let people = store
+            .people
+            .rows
+            .values()
+            .filter(|row| row.person.deleted_at.is_none())
+            .map(|row| row.person.clone());
+        return stream_rows(people.collect());
+    }
+
+    async fn import_people(&self, people: &[NewPerson]) -> ApiResult<Vec<Person>> {
+        let mut store = self.store();
+        let now = OffsetDateTime::now_utc();
+        let mut imported = Vec::with_capacity(people.len());
+        for new_person in people {
+            let id = store.people.next_id();
+            let person = Person {
+                id,
+                person_name: new_person.person_name.clone(),
+                age: new_person.age,
+                email: new_person.email.clone(),
+                created_at: now,
+                updated_at: now,
+                deleted_at: None,
+                row_version: 1,
+            };
+            imported.push(person.clone());
+            store.people.rows.insert(
+                id,
+                PersonRow {
+                    person,
+                    role: Role::User,
+                    password_hash: None,
+                },
+            );
+        }
+        return Ok(imported);
+    }
+
+    async fn insert_person(&self, new_person: &NewPerson) -> ApiResult<Person> {
+        let mut store = self.store();
+        let id = store.people.next_id();
+        let now = OffsetDateTime::now_utc();
+        let person = Person {
+            id,
+            person_name: new_person.person_name.clone(),
+            age: new_person.age,
+            email: new_person.email.clone(),
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            row_version: 1,
+        };
+        store.people.rows.insert(
+            id,
+            PersonRow {
+                person: person.clone(),
+                role: Role::User,
+                password_hash: None,
+            },
+        );
+        return Ok(person);
+    }
+
+    async fn update_person(
+        &self,
+        person_id: i64,
+        person: &NewPerson,
+        row_version: i64,
+    ) -> ApiResult<Option<Person>> {
+        let mut store = self.store();
+        let Some(row) = store.people.rows.get(&person_id) else {
+            return Ok(None);
+        };
+        if row.person.deleted_at.is_some() || row.person.row_version != row_version {
+            return Ok(None);
+        }
+        let person = Person {
+            id: person_id,
+            person_name: person.person_name.clone(),
+            age: person.age,
+            email: person.email.clone(),
+            created_at: row.person.created_at,
+            updated_at: OffsetDateTime::now_utc(),
+            deleted_at: None,
+            row_version: row.person.row_version + 1,
+        };
+        return store.set_person(person).map(Some);
+    }
+
+    async fn patch_person(
+        &self,
+        person_id: i64,
+        patch: &PersonPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<Person>> {
+        let mut store = self.store();
+        let Some(row) = store.people.rows.get(&person_id) else {
+            return Ok(None);
+        };
+        let current = &row.person;
+        if current.deleted_at.is_some() || current.row_version != row_version {
+            return Ok(None);
+        }
+        let person = Person {
+            id: person_id,
+            person_name: patch
+                .person_name
+                .clone()
+                .unwrap_or_else(|| current.person_name.clone()),
+            age: patch.age.unwrap_or(current.age),
+            email: patch.email.clone().unwrap_or_else(|| current.email.clone()),
+            created_at: current.created_at,
+            updated_at: OffsetDateTime::now_utc(),
+            deleted_at: None,
+            row_version: current.row_version + 1,
+        };
+        return store.set_person(person).map(Some);
+    }
+
+    async fn delete_person(&self, person_id: i64) -> ApiResult<Deletion> {
+        let mut store = self.store();
+        let usage_count = store
+            .salads
+            .rows
+            .values()
+            .filter(|salad| salad.id_creator == person_id && salad.deleted_at.is_none())
+            .count();
+        if usage_count > 0 {
+            return Ok(Deletion::InUse(usage_count as i64));
+        }
+        let Some(row) = store.people.rows.get_mut(&person_id) else {
+            return Ok(Deletion::NotFound);
+        };
+        if row.person.deleted_at.is_some() {
+            return Ok(Deletion::NotFound);
+        }
+        let now = OffsetDateTime::now_utc();
+        row.person.deleted_at = Some(now);
+        row.person.updated_at = now;
+        row.person.row_version += 1;
+        for session in store.sessions.rows.values_mut() {
+            if session.id_person == person_id {
+                session.revoked = true;
+            }
+        }
+        return Ok(Deletion::Deleted);
+    }
+
+    async fn restore_person(&self, person_id: i64) -> ApiResult<Restoration<Person>> {
+        let mut store = self.store();
+        let Some(row) = store.people.rows.get(&person_id) else {
+            return Ok(Restoration::NotFound);
+        };
+        if row.person.deleted_at.is_none() {
+            return Ok(Restoration::Restored(row.person.clone()));
+        }
+        if row.password_hash.is_some() {
+            store.check_login_email(&row.person.email, Some(person_id))?;
+        }
+        let row = store
+            .people
+            .rows
+            .get_mut(&person_id)
+            .expect("person exists");
+        row.person.deleted_at = None;
+        row.person.updated_at = OffsetDateTime::now_utc();
+        row.person.row_version += 1;
+        return Ok(Restoration::Restored(row.person.clone()));
+    }
+
+    async fn set_person_role(&self, person_id: i64, role: Role) -> ApiResult<Option<Role>> {
+        let mut store = self.store();
+        let Some(row) = store.people.rows.get_mut(&person_id) else {
+            return Ok(None);
+        };
+        if row.person.deleted_at.is_some() {
+            return Ok(None);
+        }
+        row.role = role;
+        row.person.updated_at = OffsetDateTime::now_utc();
+        row.person.row_version += 1;
+        return Ok(Some(role));
+    }
+
+    async fn register_person(
+        &self,
+        new_person: &NewPerson,
+        password_hash: &str,
+    ) -> ApiResult<Person> {
+        let mut store = self.store();
+        store.check_login_email(&new_person.email, None)?;
+        let id = store.people.next_id();
+        let now = OffsetDateTime::now_utc();
+        let person = Person {
+            id,
+            person_name: new_person.person_name.clone(),
+            age: new_person.age,
+            email: new_person.email.clone(),
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            row_version: 1,
+        };
+        store.people.rows.insert(
+            id,
+            PersonRow {
+                person: person.clone(),
+                role: Role::User,
+                password_hash: Some(String::from(password_hash)),
+            },
+        );
+        return Ok(person);
+    }
+
+    async fn find_account(&self, email: &str) -> ApiResult<Option<Account>> {
+        let store = self.store();
+        let email = email.to_lowercase();
+        let account = store.people.rows.values().find_map(|row| {
+            let password_hash = row.password_hash.as_ref()?;
+            if row.person.deleted_at.is_some() || row.person.email.to_lowercase() != email {
+                return None;
+            }
+            return Some(Account {
+                person: row.person.clone(),
+                role: row.role,
+                password_hash: password_hash.clone(),
+            });
+        });
+        return Ok(account);
+    }
+
+    async fn create_session(&self, person_id: i64, expires_at: OffsetDateTime) -> ApiResult<i64> {
+        let mut store = self.store();
+        if !store.people.rows.contains_key(&person_id) {
+            return Err(foreign_key_violation(
+                "auth_session",
+                "auth_session_id_person_fkey",
+            ));
+        }
+        let id = store.sessions.next_id();
+        store.sessions.rows.insert(
+            id,
+            SessionRow {
+                id_person: person_id,
+                expires_at,
+                revoked: false,
+            },
+        );
+        return Ok(id);
+    }
+
+    async fn find_session(&self, session_id: i64) -> ApiResult<Option<(Person, Role)>> {
+        let store = self.store();
+        let Some(session) = store.sessions.rows.get(&session_id) else {
+            return Ok(None);
+        };
+        if session.revoked || session.expires_at <= OffsetDateTime::now_utc() {
+            return Ok(None);
+        }
+        return Ok(store
+            .people
+            .rows
+            .get(&session.id_person)
+            .filter(|row| row.person.deleted_at.is_none())
+            .map(|row| (row.person.clone(), row.role)));
+    }
+
+    async fn revoke_session(&self, session_id: i64) -> ApiResult<()> {
+        let mut store = self.store();
+        if let Some(session) = store.sessions.rows.get_mut(&session_id) {
+            session.revoked = true;
+        }
+        return Ok(());
+    }
+}
+
+#[async_trait]
+impl FruitRepository for MemoryRepository {
+    async fn get_fruit(&self, fruit_id: i64, include_deleted: bool) -> ApiResult<Option<Fruit>> {
+        let store = self.store();
+        return Ok(store
+            .fruits
+            .rows
+            .get(&fruit_id)
+            .filter(|fruit| include_deleted || fruit.deleted_at.is_none())
+            .cloned());
+    }
+
+    async fn list_fruits(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<Fruit>> {
+        let store = self.store();
+        let fruits = store.fruits.rows.values();
+        return Ok(page_rows(query_rows(fruits, list_query), page_request));
+    }
+
+    async fn count_fruits(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let store = self.store();
+        let count = store
+            .fruits
+            .rows
+            .values()
+            .filter(|fruit| list_query.matches(*fruit))
+            .count();
+        return Ok(count as i64);
+    }
+
+    async fn get_fruits(&self, fruit_ids: &[i64]) -> ApiResult<Vec<Fruit>> {
+        let store = self.store();
+        let fruits = store
+            .fruits
+            .rows
+            .values()
+            .filter(|fruit| fruit.deleted_at.is_none() && fruit_ids.contains(&fruit.id))
+            .cloned();
+        return Ok(fruits.collect());
+    }
+
+    fn export_fruits(&self) -> RowStream<Fruit> {
+        let store = self.store();
+        let fruits = store
+            .fruits
+            .rows
+            .values()
+            .filter(|fruit| fruit.deleted_at.is_none())
+            .cloned();
+        return stream_rows(fruits.collect());
+    }
+
+    async fn import_fruits(&self, fruits: &[NewFruit]) -> ApiResult<Vec<Fruit>> {
+        let mut store = self.store();
+        let now = OffsetDateTime::now_utc();
+        let mut imported = Vec::with_capacity(fruits.len());
+        for new_fruit in fruits {
+            let id = store.fruits.next_id();
+            let fruit = Fruit {
+                id,
+                fruit_name: new_fruit.fruit_name.clone(),
+                color_red: new_fruit.color_red,
+                color_green: new_fruit.color_green,
+                color_blue: new_fruit.color_blue,
+                fruit_weight: new_fruit.fruit_weight,
+                created_at: now,
+                updated_at: now,
+                deleted_at: None,
+                row_version: 1,
+            };
+            imported.push(fruit.clone());
+            store.fruits.rows.insert(id, fruit);
+        }
+        return Ok(imported);
+    }
+
+    async fn insert_fruit(&self, new_fruit: &NewFruit) -> ApiResult<Fruit> {
+        let mut store = self.store();
+        let id = store.fruits.next_id();
+        let now = OffsetDateTime::now_utc();
+        let fruit = Fruit {
+            id,
+            fruit_name: new_fruit.fruit_name.clone(),
+            color_red: new_fruit.color_red,
+            color_green: new_fruit.color_green,
+            color_blue: new_fruit.color_blue,
+            fruit_weight: new_fruit.fruit_weight,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            row_version: 1,
+        };
+        store.fruits.rows.insert(id, fruit.clone());
+        return Ok(fruit);
+    }
+
+    async fn update_fruit(
+        &self,
+        fruit_id: i64,
+        fruit: &NewFruit,
+        row_version: i64,
+    ) -> ApiResult<Option<Fruit>> {
+        let mut store = self.store();
+        let Some(row) = store.fruits.rows.get_mut(&fruit_id) else {
+            return Ok(None);
+        };
+        if row.deleted_at.is_some() || row.row_version != row_version {
+            return Ok(None);
+        }
+        *row = Fruit {
+            id: fruit_id,
+            fruit_name: fruit.fruit_name.clone(),
+            color_red: fruit.color_red,
+            color_green: fruit.color_green,
+            color_blue: fruit.color_blue,
+            fruit_weight: fruit.fruit_weight,
+            created_at: row.created_at,
+            updated_at: OffsetDateTime::now_utc(),
+            deleted_at: None,
+            row_version: row.row_version + 1,
+        };
+        return Ok(Some(row.clone()));
+    }
+
+    async fn patch_fruit(
+        &self,
+        fruit_id: i64,
+        patch: &FruitPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<Fruit>> {
+        let mut store = self.store();
+        let Some(row) = store.fruits.rows.get_mut(&fruit_id) else {
+            return Ok(None);
+        };
+        if row.deleted_at.is_some() || row.row_version != row_version {
+            return Ok(None);
+        }
+        if let Some(fruit_name) = &patch.fruit_name {
+            row.fruit_name = fruit_name.clone();
+        }
+        row.color_red = patch.color_red.unwrap_or(row.color_red);
+        row.color_green = patch.color_green.unwrap_or(row.color_green);
+        row.color_blue = patch.color_blue.unwrap_or(row.color_blue);
+        row.fruit_weight = patch.fruit_weight.unwrap_or(row.fruit_weight);
+        row.updated_at = OffsetDateTime::now_utc();
+        row.row_version += 1;
+        return Ok(Some(row.clone()));
+    }
+
+    async fn delete_fruit(&self, fruit_id: i64) -> ApiResult<Deletion> {
+        let mut store = self.store();
+        let usage_count = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| {
+                return ingredient.id_fruit == fruit_id && ingredient.deleted_at.is_none();
+            })
+            .count();
+        if usage_count > 0 {
+            return Ok(Deletion::InUse(usage_count as i64));
+        }
+        let Some(fruit) = store.fruits.rows.get_mut(&fruit_id) else {
+            return Ok(Deletion::NotFound);
+        };
+        if fruit.deleted_at.is_some() {
+            return Ok(Deletion::NotFound);
+        }
+        let now = OffsetDateTime::now_utc();
+        fruit.deleted_at = Some(now);
+        fruit.updated_at = now;
+        fruit.row_version += 1;
+        return Ok(Deletion::Deleted);
+    }
+
+    async fn restore_fruit(&self, fruit_id: i64) -> ApiResult<Restoration<Fruit>> {
+        let mut store = self.store();
+        let Some(fruit) = store.fruits.rows.get_mut(&fruit_id) else {
+            return Ok(Restoration::NotFound);
+        };
+        if fruit.deleted_at.is_some() {
+            fruit.deleted_at = None;
+            fruit.updated_at = OffsetDateTime::now_utc();
+            fruit.row_version += 1;
+        }
+        return Ok(Restoration::Restored(fruit.clone()));
+    }
+}
+
+#[async_trait]
+impl SaladRepository for MemoryRepository {
+    async fn get_salad(
+        &self,
+        salad_id: i64,
+        include_deleted: bool,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let store = self.store();
+        return Ok(store
+            .salads
+            .rows
+            .get(&salad_id)
+            .filter(|salad| include_deleted || salad.deleted_at.is_none())
+            .cloned());
+    }
+
+    async fn list_salads(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<FruitSalad>> {
+        let store = self.store();
+        let salads = store.salads.rows.values();
+        return Ok(page_rows(query_rows(salads, list_query), page_request));
+    }
+
+    async fn count_salads(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let store = self.store();
+        let count = store
+            .salads
+            .rows
+            .values()
+            .filter(|salad| list_query.matches(*salad))
+            .count();
+        return Ok(count as i64);
+    }
+
+    async fn get_salads(&self, salad_ids: &[i64]) -> ApiResult<Vec<FruitSalad>> {
+        let store = self.store();
+        let salads = store
+            .salads
+            .rows
+            .values()
+            .filter(|salad| salad.deleted_at.is_none() && salad_ids.contains(&salad.id))
+            .cloned();
+        return Ok(salads.collect());
+    }
+
+    async fn list_salads_of_creators(&self, creator_ids: &[i64]) -> ApiResult<Vec<FruitSalad>> {
+        let store = self.store();
+        let salads = store
+            .salads
+            .rows
+            .values()
+            .filter(|salad| salad.deleted_at.is_none() && creator_ids.contains(&salad.id_creator))
+            .cloned();
+        return Ok(salads.collect());
+    }
+
+    async fn list_salads_by_creator(
+        &self,
+        creator_id: i64,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<SaladView>> {
+        let store = self.store();
+        let Some(creator) = store.people.rows.get(&creator_id) else {
+            return Ok(page_rows(Vec::new(), page_request));
+        };
+        let salads = store
+            .salads
+            .rows
+            .values()
+            .filter(|salad| salad.id_creator == creator_id && salad.deleted_at.is_none())
+            .map(|salad| SaladView {
+                id: salad.id,
+                person_name: creator.person.person_name.clone(),
+                salad_name: salad.salad_name.clone(),
+            })
+            .collect();
+        return Ok(page_rows(salads, page_request));
+    }
+
+    async fn count_salads_by_creator(&self, creator_id: i64) -> ApiResult<i64> {
+        let store = self.store();
+        let count = store
+            .salads
+            .rows
+            .values()
+            .filter(|salad| salad.id_creator == creator_id && salad.deleted_at.is_none())
+            .count();
+        return Ok(count as i64);
+    }
+
+    async fn list_salad_ingredient_views(
+        &self,
+        salad_id: i64,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<SaladIngredientsView>> {
+        let store = self.store();
+        let Some(salad) = store
+            .salads
+            .rows
+            .get(&salad_id)
+            .filter(|salad| salad.deleted_at.is_none())
+        else {
+            return Ok(page_rows(Vec::new(), page_request));
+        };
+        let person_name = &store.people.rows[&salad.id_creator].person.person_name;
+        let views = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| {
+                return ingredient.id_salad == salad_id && ingredient.deleted_at.is_none();
+            })
+            .map(|ingredient| SaladIngredientsView {
+                id: ingredient.id,
+                person_name: person_name.clone(),
+                salad_name: salad.salad_name.clone(),
+                fruit_name: store.fruits.rows[&ingredient.id_fruit].fruit_name.clone(),
+                quantity_grams: ingredient.quantity_grams,
+            })
+            .collect();
+        return Ok(page_rows(views, page_request));
+    }
+
+    async fn count_salad_ingredient_views(&self, salad_id: i64) -> ApiResult<i64> {
+        let store = self.store();
+        // Ingredients of a deleted salad were deleted along with it.
+        let count = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| {
+                return ingredient.id_salad == salad_id && ingredient.deleted_at.is_none();
+            })
+            .count();
+        return Ok(count as i64);
+    }
+
+    async fn salad_components(&self, salad_id: i64) -> ApiResult<Vec<SaladComponent>> {
+        let store = self.store();
+        let mut components: Vec<SaladComponent> = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| {
+                return ingredient.id_salad == salad_id && ingredient.deleted_at.is_none();
+            })
+            .map(|ingredient| {
+                let fruit = &store.fruits.rows[&ingredient.id_fruit];
+                return SaladComponent {
+                    id_fruit: fruit.id,
+                    fruit_name: fruit.fruit_name.clone(),
+                    color_red: fruit.color_red,
+                    color_green: fruit.color_green,
+                    color_blue: fruit.color_blue,
+                    quantity_grams: ingredient.quantity_grams,
+                };
+            })
+            .collect();
+        components.sort_by(|left, right| {
+            return right
+                .quantity_grams
+                .cmp(&left.quantity_grams)
+                .then(left.id_fruit.cmp(&right.id_fruit));
+        });
+        return Ok(components);
+    }
+
+    fn export_salads(&self) -> RowStream<FruitSalad> {
+        let store = self.store();
+        let salads = store
+            .salads
+            .rows
+            .values()
+            .filter(|salad| salad.deleted_at.is_none())
+            .cloned();
+        return stream_rows(salads.collect());
+    }
+
+    async fn insert_salad(
+        &self,
+        creator_id: i64,
+        new_salad: &NewFruitSalad,
+    ) -> ApiResult<FullFruitSalad> {
+        let mut store = self.store();
+        if !store.people.rows.contains_key(&creator_id) {
+            return Err(foreign_key_violation(
+                "fruit_salad",
+                "fruit_salad_id_creator_fkey",
+            ));
+        }
+
+        let mut missing_fruits: Vec<i64> = new_salad
+            .ingredients
+            .iter()
+            .copied()
+            .filter(|fruit_id| {
+                return store
+                    .fruits
+                    .rows
+                    .get(fruit_id)
+                    .is_none_or(|fruit| fruit.deleted_at.is_some());
+            })
+            .collect();
+        if !missing_fruits.is_empty() {
+            missing_fruits.sort_unstable();
+            missing_fruits.dedup();
+            return Err(ApiError::ForeignKeyViolation(format!(
+                "Fruit(s) {:?} not found",
+                missing_fruits
+            )));
+        }
+        let mut fruit_ids = new_salad.ingredients.clone();
+        fruit_ids.sort_unstable();
+        if fruit_ids.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(unique_violation("salad_ingredients_salad_fruit_unique"));
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let salad = FruitSalad {
+            id: store.salads.next_id(),
+            id_creator: creator_id,
+            salad_name: new_salad.salad_name.clone(),
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            row_version: 1,
+        };
+        store.salads.rows.insert(salad.id, salad.clone());
+
+        let mut ingredients = Vec::new();
+        for fruit_id in &new_salad.ingredients {
+            let ingredient = SaladIngredient {
+                id: store.ingredients.next_id(),
+                id_salad: salad.id,
+                id_fruit: *fruit_id,
+                quantity_grams: store.fruits.rows[fruit_id].fruit_weight,
+                created_at: now,
+                updated_at: now,
+                deleted_at: None,
+            };
+            store
+                .ingredients
+                .rows
+                .insert(ingredient.id, ingredient.clone());
+            ingredients.push(ingredient);
+        }
+        return Ok(FullFruitSalad { salad, ingredients });
+    }
+
+    async fn update_salad(
+        &self,
+        salad_id: i64,
+        salad: &NewFruitSalad,
+        row_version: i64,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let mut store = self.store();
+        let Some(row) = store.salads.rows.get_mut(&salad_id) else {
+            return Ok(None);
+        };
+        if row.deleted_at.is_some() || row.row_version != row_version {
+            return Ok(None);
+        }
+        row.salad_name = salad.salad_name.clone();
+        row.updated_at = OffsetDateTime::now_utc();
+        row.row_version += 1;
+        return Ok(Some(row.clone()));
+    }
+
+    async fn patch_salad(
+        &self,
+        salad_id: i64,
+        patch: &FruitSaladPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let mut store = self.store();
+        let Some(row) = store.salads.rows.get_mut(&salad_id) else {
+            return Ok(None);
+        };
+        if row.deleted_at.is_some() || row.row_version != row_version {
+            return Ok(None);
+        }
+        if let Some(salad_name) = &patch.salad_name {
+            row.salad_name = salad_name.clone();
+        }
+        row.updated_at = OffsetDateTime::now_utc();
+        row.row_version += 1;
+        return Ok(Some(row.clone()));
+    }
+
+    async fn delete_salad(&self, salad_id: i64) -> ApiResult<Option<Vec<i64>>> {
+        let mut store = self.store();
+        let Some(salad) = store.salads.rows.get_mut(&salad_id) else {
+            return Ok(None);
+        };
+        if salad.deleted_at.is_some() {
+            return Ok(None);
+        }
+        let now = OffsetDateTime::now_utc();
+        salad.deleted_at = Some(now);
+        salad.updated_at = now;
+        salad.row_version += 1;
+        let mut ingredient_ids = Vec::new();
+        for ingredient in store.ingredients.rows.values_mut() {
+            if ingredient.id_salad == salad_id && ingredient.deleted_at.is_none() {
+                ingredient.deleted_at = Some(now);
+                ingredient.updated_at = now;
+                ingredient_ids.push(ingredient.id);
+            }
+        }
+        return Ok(Some(ingredient_ids));
+    }
+
+    async fn restore_salad(
+        &self,
+        salad_id: i64,
+    ) -> ApiResult<Restoration<(FruitSalad, Vec<SaladIngredient>)>> {
+        let mut store = self.store();
+        let Some(salad) = store.salads.rows.get(&salad_id) else {
+            return Ok(Restoration::NotFound);
+        };
+        let Some(deleted_at) = salad.deleted_at else {
+            return Ok(Restoration::Restored((salad.clone(), Vec::new())));
+        };
+
+        let creator_deleted = store.people.rows[&salad.id_creator]
+            .person
+            .deleted_at
+            .is_some();
+        if creator_deleted {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Person {}",
+                salad.id_creator
+            )));
+        }
+        let deleted_fruit = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| {
+                return ingredient.id_salad == salad_id
+                    && ingredient.deleted_at == Some(deleted_at);
+            })
+            .map(|ingredient| ingredient.id_fruit)
+            .filter(|fruit_id| store.fruits.rows[fruit_id].deleted_at.is_some())
+            .min();
+        if let Some(fruit_id) = deleted_fruit {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Fruit {}",
+                fruit_id
+            )));
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let mut ingredients = Vec::new();
+        for ingredient in store.ingredients.rows.values_mut() {
+            if ingredient.id_salad == salad_id && ingredient.deleted_at == Some(deleted_at) {
+                ingredient.deleted_at = None;
+                ingredient.updated_at = now;
+                ingredients.push(ingredient.clone());
+            }
+        }
+        let salad = store.salads.rows.get_mut(&salad_id).expect("salad exists");
+        salad.deleted_at = None;
+        salad.updated_at = now;
+        salad.row_version += 1;
+        return Ok(Restoration::Restored((salad.clone(), ingredients)));
+    }
+}
+
+#[async_trait]
+impl SaladIngredientRepository for MemoryRepository {
+    async fn get_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        include_deleted: bool,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let store = self.store();
+        return Ok(store
+            .ingredients
+            .rows
+            .get(&ingredient_id)
+            .filter(|ingredient| include_deleted || ingredient.deleted_at.is_none())
+            .cloned());
+    }
+
+    async fn get_salad_ingredient_owner(&self, ingredient_id: i64) -> ApiResult<Option<i64>> {
+        let store = self.store();
+        let owner = store
+            .ingredients
+            .rows
+            .get(&ingredient_id)
+            .filter(|ingredient| ingredient.deleted_at.is_none())
+            .and_then(|ingredient| store.salads.rows.get(&ingredient.id_salad))
+            .map(|salad| salad.id_creator);
+        return Ok(owner);
+    }
+
+    async fn list_salad_ingredients(
+        &self,
+        page_request: &PageRequest,
+        include_deleted: bool,
+    ) -> ApiResult<Page<SaladIngredient>> {
+        let store = self.store();
+        let ingredients = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| include_deleted || ingredient.deleted_at.is_none())
+            .cloned()
+            .collect();
+        return Ok(page_rows(ingredients, page_request));
+    }
+
+    async fn count_salad_ingredients(&self, include_deleted: bool) -> ApiResult<i64> {
+        let store = self.store();
+        let count = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| include_deleted || ingredient.deleted_at.is_none())
+            .count();
+        return Ok(count as i64);
+    }
+
+    async fn get_salad_ingredients(
+        &self,
+        ingredient_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let store = self.store();
+        let ingredients = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| {
+                return ingredient.deleted_at.is_none() && ingredient_ids.contains(&ingredient.id);
+            })
+            .cloned();
+        return Ok(ingredients.collect());
+    }
+
+    async fn list_ingredients_of_salads(
+        &self,
+        salad_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let store = self.store();
+        let ingredients = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| {
+                return ingredient.deleted_at.is_none() && salad_ids.contains(&ingredient.id_salad);
+            })
+            .cloned();
+        return Ok(ingredients.collect());
+    }
+
+    async fn list_ingredients_of_fruits(
+        &self,
+        fruit_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let store = self.store();
+        let ingredients = store
+            .ingredients
+            .rows
+            .values()
+            .filter(|ingredient| {
+                return ingredient.deleted_at.is_none() && fruit_ids.contains(&ingredient.id_fruit);
+            })
+            .cloned();
+        return Ok(ingredients.collect());
+    }
+
+    async fn insert_salad_ingredient(
+        &self,
+        new_ingredient: &NewSaladIngredient,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let mut store = self.store();
+        let Some(fruit) = store
+            .fruits
+            .rows
+            .get(&new_ingredient.id_fruit)
+            .filter(|fruit| fruit.deleted_at.is_none())
+        else {
+            return Ok(None);
+        };
+        let quantity_grams = new_ingredient.quantity_grams.unwrap_or(fruit.fruit_weight);
+        let now = OffsetDateTime::now_utc();
+        // The id is only taken once the row is known to be valid.
+        let mut ingredient = SaladIngredient {
+            id: 0,
+            id_salad: new_ingredient.id_salad,
+            id_fruit: new_ingredient.id_fruit,
+            quantity_grams,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        };
+        store.check_ingredient(&ingredient)?;
+        ingredient.id = store.ingredients.next_id();
+        return store.set_ingredient(ingredient).map(Some);
+    }
+
+    async fn update_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        ingredient: &NewSaladIngredient,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let mut store = self.store();
+        let Some(current) = store.ingredients.rows.get(&ingredient_id) else {
+            return Ok(None);
+        };
+        if current.deleted_at.is_some() {
+            return Ok(None);
+        }
+        let ingredient = SaladIngredient {
+            id: ingredient_id,
+            id_salad: ingredient.id_salad,
+            id_fruit: ingredient.id_fruit,
+            quantity_grams: ingredient.quantity_grams.unwrap_or(current.quantity_grams),
+            created_at: current.created_at,
+            updated_at: OffsetDateTime::now_utc(),
+            deleted_at: None,
+        };
+        return store.set_ingredient(ingredient).map(Some);
+    }
+
+    async fn patch_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        patch: &SaladIngredientPatch,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let mut store = self.store();
+        let Some(current) = store.ingredients.rows.get(&ingredient_id) else {
+            return Ok(None);
+        };
+        if current.deleted_at.is_some() {
+            return Ok(None);
+        }
+        let ingredient = SaladIngredient {
+            id: ingredient_id,
+            id_salad: patch.id_salad.unwrap_or(current.id_salad),
+            id_fruit: patch.id_fruit.unwrap_or(current.id_fruit),
+            quantity_grams: patch.quantity_grams.unwrap_or(current.quantity_grams),
+            created_at: current.created_at,
+            updated_at: OffsetDateTime::now_utc(),
+            deleted_at: None,
+        };
+        return store.set_ingredient(ingredient).map(Some);
+    }
+
+    async fn delete_salad_ingredient(&self, ingredient_id: i64) -> ApiResult<bool> {
+        let mut store = self.store();
+        let Some(ingredient) = store.ingredients.rows.get_mut(&ingredient_id) else {
+            return Ok(false);
+        };
+        if ingredient.deleted_at.is_some() {
+            return Ok(false);
+        }
+        let now = OffsetDateTime::now_utc();
+        ingredient.deleted_at = Some(now);
+        ingredient.updated_at = now;
+        return Ok(true);
+    }
+
+    async fn restore_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+    ) -> ApiResult<Restoration<SaladIngredient>> {
+        let mut store = self.store();
+        let Some(current) = store.ingredients.rows.get(&ingredient_id) else {
+            return Ok(Restoration::NotFound);
+        };
+        if current.deleted_at.is_none() {
+            return Ok(Restoration::Restored(current.clone()));
+        }
+        if store.salads.rows[&current.id_salad].deleted_at.is_some() {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Salad {}",
+                current.id_salad
+            )));
+        }
+        if store.fruits.rows[&current.id_fruit].deleted_at.is_some() {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Fruit {}",
+                current.id_fruit
+            )));
+        }
+        let ingredient = SaladIngredient {
+            updated_at: OffsetDateTime::now_utc(),
+            deleted_at: None,
+            ..current.clone()
+        };
+        return store.set_ingredient(ingredient).map(Restoration::Restored);
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for MemoryRepository {
+    async fn get_webhook(&self, webhook_id: i64) -> ApiResult<Option<Webhook>> {
+        let store = self.store();
+        return Ok(store
+            .webhooks
+            .rows
+            .get(&webhook_id)
+            .map(|row| row.webhook.clone()));
+    }
+
+    async fn list_webhooks(&self, page_request: &PageRequest) -> ApiResult<Page<Webhook>> {
+        let store = self.store();
+        let webhooks = store
+            .webhooks
+            .rows
+            .values()
+            .map(|row| row.webhook.clone())
+            .collect();
+        return Ok(page_rows(webhooks, page_request));
+    }
+
+    async fn count_webhooks(&self) -> ApiResult<i64> {
+        let store = self.store();
+        return Ok(store.webhooks.rows.len() as i64);
+    }
+
+    async fn insert_webhook(&self, new_webhook: &NewWebhook, secret: &str) -> ApiResult<Webhook> {
+        let mut store = self.store();
+        let now = OffsetDateTime::now_utc();
+        let webhook = Webhook {
+            id: store.webhooks.next_id(),
+            target_url: new_webhook.target_url.clone(),
+            event_types: Json(new_webhook.event_types.clone()),
+            active: new_webhook.active.unwrap_or(true),
+            created_at: now,
+            updated_at: now,
+        };
+        let row = WebhookRow {
+            webhook: webhook.clone(),
+            secret: String::from(secret),
+        };
+        store.webhooks.rows.insert(webhook.id, row);
+        return Ok(webhook);
+    }
+
+    async fn update_webhook(
+        &self,
+        webhook_id: i64,
+        webhook: &NewWebhook,
+    ) -> ApiResult<Option<Webhook>> {
+        let mut store = self.store();
+        let Some(row) = store.webhooks.rows.get_mut(&webhook_id) else {
+            return Ok(None);
+        };
+        row.webhook.target_url = webhook.target_url.clone();
+        row.webhook.event_types = Json(webhook.event_types.clone());
+        row.webhook.active = webhook.active.unwrap_or(true);
+        row.webhook.updated_at = OffsetDateTime::now_utc();
+        return Ok(Some(row.webhook.clone()));
+    }
+
+    async fn delete_webhook(&self, webhook_id: i64) -> ApiResult<bool> {
+        let mut store = self.store();
+        if store.webhooks.rows.remove(&webhook_id).is_none() {
+            return Ok(false);
+        }
+        store
+            .deliveries
+            .rows
+            .retain(|_, delivery| delivery.id_webhook != webhook_id);
+        return Ok(true);
+    }
+
+    async fn get_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+    ) -> ApiResult<Option<WebhookDelivery>> {
+        let store = self.store();
+        return Ok(store
+            .deliveries
+            .rows
+            .get(&delivery_id)
+            .filter(|delivery| delivery.id_webhook == webhook_id)
+            .cloned());
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<&str>,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<WebhookDelivery>> {
+        let store = self.store();
+        let deliveries = store
+            .deliveries
+            .rows
+            .values()
+            .filter(|delivery| {
+                return delivery.id_webhook == webhook_id
+                    && status.is_none_or(|status| delivery.status == status);
+            })
+            .cloned()
+            .collect();
+        return Ok(page_rows(deliveries, page_request));
+    }
+
+    async fn count_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<&str>,
+    ) -> ApiResult<i64> {
+        let store = self.store();
+        let count = store
+            .deliveries
+            .rows
+            .values()
+            .filter(|delivery| {
+                return delivery.id_webhook == webhook_id
+                    && status.is_none_or(|status| delivery.status == status);
+            })
+            .count();
+        return Ok(count as i64);
+    }
+
+    async fn enqueue_webhook_deliveries(
+        &self,
+        event_type: &str,
+        payload: &Value,
+    ) -> ApiResult<u64> {
+        let mut store = self.store();
+        let webhook_ids: Vec<i64> = store
+            .webhooks
+            .rows
+            .values()
+            .filter(|row| {
+                return row.webhook.active
+                    && row
+                        .webhook
+                        .event_types
+                        .iter()
+                        .any(|name| name == event_type);
+            })
+            .map(|row| row.webhook.id)
+            .collect();
+        let now = OffsetDateTime::now_utc();
+        for webhook_id in &webhook_ids {
+            let delivery = WebhookDelivery {
+                id: store.deliveries.next_id(),
+                id_webhook: *webhook_id,
+                event_type: String::from(event_type),
+                payload: Json(payload.clone()),
+                status: String::from(DELIVERY_PENDING),
+                attempts: 0,
+                next_attempt_at: now,
+                last_attempt_at: None,
+                last_status_code: None,
+                last_error: None,
+                created_at: now,
+                delivered_at: None,
+            };
+            store.deliveries.rows.insert(delivery.id, delivery);
+        }
+        return Ok(webhook_ids.len() as u64);
+    }
+
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease_until: OffsetDateTime,
+    ) -> ApiResult<Vec<DueDelivery>> {
+        let mut store = self.store();
+        let store = &mut *store;
+        let now = OffsetDateTime::now_utc();
+        let mut due: Vec<&mut WebhookDelivery> = store
+            .deliveries
+            .rows
+            .values_mut()
+            .filter(|delivery| {
+                return delivery.status == DELIVERY_PENDING
+                    && delivery.next_attempt_at <= now
+                    && store
+                        .webhooks
+                        .rows
+                        .get(&delivery.id_webhook)
+                        .is_some_and(|row| row.webhook.active);
+            })
+            .collect();
+        due.sort_by_key(|delivery| delivery.next_attempt_at);
+        due.truncate(limit.max(0) as usize);
+
+        let mut claimed = Vec::new();
+        for delivery in due {
+            delivery.next_attempt_at = lease_until;
+            let webhook = &store.webhooks.rows[&delivery.id_webhook];
+            claimed.push(DueDelivery {
+                id: delivery.id,
+                event_type: delivery.event_type.clone(),
+                payload: delivery.payload.clone(),
+                attempts: delivery.attempts,
+                target_url: webhook.webhook.target_url.clone(),
+                secret: webhook.secret.clone(),
+            });
+        }
+        return Ok(claimed);
+    }
+
+    async fn finish_webhook_attempt(
+        &self,
+        delivery_id: i64,
+        attempt: &DeliveryAttempt,
+    ) -> ApiResult<()> {
+        let mut store = self.store();
+        let Some(delivery) = store.deliveries.rows.get_mut(&delivery_id) else {
+            return Ok(());
+        };
+        let now = OffsetDateTime::now_utc();
+        delivery.status = String::from(attempt.status);
+        delivery.attempts += 1;
+        delivery.last_attempt_at = Some(now);
+        delivery.last_status_code = attempt.status_code;
+        delivery.last_error = attempt.error.clone();
+        delivery.next_attempt_at = attempt.next_attempt_at;
+        delivery.delivered_at = (attempt.status == DELIVERY_DELIVERED).then_some(now);
+        return Ok(());
+    }
+
+    async fn retry_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+    ) -> ApiResult<Option<WebhookDelivery>> {
+        let mut store = self.store();
+        let Some(delivery) = store
+            .deliveries
+            .rows
+            .get_mut(&delivery_id)
+            .filter(|delivery| {
+                delivery.id_webhook == webhook_id && delivery.status == DELIVERY_DEAD
+            })
+        else {
+            return Ok(None);
+        };
+        delivery.status = String::from(DELIVERY_PENDING);
+        delivery.attempts = 0;
+        delivery.next_attempt_at = OffsetDateTime::now_utc();
+        return Ok(Some(delivery.clone()));
+    }
+}
+
+#[async_trait]
+impl Repository for MemoryRepository {
+    // The body is a lone `return`, which trips this lint through
+    // `async_trait`'s expansion.
+    #[allow(clippy::diverging_sub_expression)]
+    async fn ping(&self) -> Result<(), String> {
+        return Ok(());
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        return None;
+    }
+}
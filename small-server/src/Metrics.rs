@@ -0,0 +1,231 @@
+use std::time::Instant;
+
+use super::AppState::AppState;
+use super::Repository::Repository;
+use axum::{
+    body::Body,
+    extract::{MatchedPath, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::pool::PoolConnection;
+use sqlx::{Database, Pool};
+
+/// Every metric below is registered here and nowhere else, so `/metrics`
+/// only shows what this server defines.
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    return metric;
+}
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    return register(
+        IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled requests"),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+    );
+});
+static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    return register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time from receiving a request to sending the response head",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+    );
+});
+static HTTP_REQUESTS_IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    return register(IntGauge::new("http_requests_in_flight", "Requests being handled").unwrap());
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    return register(
+        IntGauge::new("db_pool_connections", "Open connections, idle or in use").unwrap(),
+    );
+});
+static DB_POOL_IDLE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    return register(
+        IntGauge::new("db_pool_idle_connections", "Open connections not in use").unwrap(),
+    );
+});
+static DB_POOL_WAITING_ACQUIRES: Lazy<IntGauge> = Lazy::new(|| {
+    return register(
+        IntGauge::new(
+            "db_pool_waiting_acquires",
+            "Queries waiting for a connection from the pool",
+        )
+        .unwrap(),
+    );
+});
+static DB_POOL_MAX_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    return register(
+        IntGauge::new("db_pool_max_connections", "Configured size of the pool").unwrap(),
+    );
+});
+
+pub static PEOPLE_CREATED: Lazy<IntCounter> = Lazy::new(|| {
+    return register(
+        IntCounter::new(
+            "people_created_total",
+            "People registered, inserted or imported",
+        )
+        .unwrap(),
+    );
+});
+pub static FRUITS_CREATED: Lazy<IntCounter> = Lazy::new(|| {
+    return register(
+        IntCounter::new("fruits_created_total", "Fruits inserted or imported").unwrap(),
+    );
+});
+pub static SALADS_CREATED: Lazy<IntCounter> = Lazy::new(|| {
+    return register(IntCounter::new("salads_created_total", "Salads created").unwrap());
+});
+pub static INGREDIENTS_ADDED: Lazy<IntCounter> = Lazy::new(|| {
+    return register(
+        IntCounter::new(
+            "ingredients_added_total",
+            "Fruits added to salads, on creation or afterwards",
+        )
+        .unwrap(),
+    );
+});
+pub static WEBHOOK_ATTEMPTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    return register(
+        IntCounterVec::new(
+            Opts::new(
+                "webhook_attempts_total",
+                "Webhook deliveries attempted, by the status they were left in",
+            ),
+            &["status"],
+        )
+        .unwrap(),
+    );
+});
+
+/// Registers every metric up front, so counters are exported as zero before
+/// they are first incremented.
+pub fn init_metrics() {
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_REQUEST_DURATION);
+    Lazy::force(&HTTP_REQUESTS_IN_FLIGHT);
+    Lazy::force(&DB_POOL_CONNECTIONS);
+    Lazy::force(&DB_POOL_IDLE_CONNECTIONS);
+    Lazy::force(&DB_POOL_WAITING_ACQUIRES);
+    Lazy::force(&DB_POOL_MAX_CONNECTIONS);
+    Lazy::force(&PEOPLE_CREATED);
+    Lazy::force(&FRUITS_CREATED);
+    Lazy::force(&SALADS_CREATED);
+    Lazy::force(&INGREDIENTS_ADDED);
+    Lazy::force(&WEBHOOK_ATTEMPTS);
+}
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new().route("/metrics", get(get_metrics::<R>));
+}
+
+/// Prometheus text exposition of every metric. The pool gauges are sampled
+/// on each scrape, and stay at zero for backends without a pool.
+pub async fn get_metrics<R: Repository>(State(repository): State<R>) -> Response {
+    if let Some(pool_status) = repository.pool_status() {
+        DB_POOL_CONNECTIONS.set(i64::from(pool_status.connections));
+        DB_POOL_IDLE_CONNECTIONS.set(pool_status.idle_connections as i64);
+        DB_POOL_MAX_CONNECTIONS.set(i64::from(pool_status.max_connections));
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(error) = encoder.encode(&REGISTRY.gather(), &mut body) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response();
+    }
+    return (
+        [(header::CONTENT_TYPE, String::from(encoder.format_type()))],
+        body,
+    )
+        .into_response();
+}
+
+/// Counts and times every route of `app`, labelled by route template so the
+/// number of series stays bounded; unmatched paths share one label.
+pub fn track_requests(app: Router) -> Router {
+    return app.layer(axum::middleware::from_fn(record_request));
+}
+
+/// Counts a request as in flight until dropped, also when the client goes
+/// away and the request future is dropped before it completes.
+struct InFlight;
+
+impl InFlight {
+    fn start() -> InFlight {
+        HTTP_REQUESTS_IN_FLIGHT.inc();
+        return InFlight;
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        HTTP_REQUESTS_IN_FLIGHT.dec();
+    }
+}
+
+/// Counts an acquire as waiting until dropped, like `InFlight`.
+struct WaitingAcquire;
+
+impl WaitingAcquire {
+    fn start() -> WaitingAcquire {
+        DB_POOL_WAITING_ACQUIRES.inc();
+        return WaitingAcquire;
+    }
+}
+
+impl Drop for WaitingAcquire {
+    fn drop(&mut self) {
+        DB_POOL_WAITING_ACQUIRES.dec();
+    }
+}
+
+/// Acquires a connection from `pool`, counted in `db_pool_waiting_acquires`
+/// until the pool hands one out or gives up. Repositories take every
+/// connection through here rather than passing the pool to sqlx.
+pub async fn acquire<DB: Database>(pool: &Pool<DB>) -> Result<PoolConnection<DB>, sqlx::Error> {
+    let _waiting = WaitingAcquire::start();
+    return pool.acquire().await;
+}
+
+async fn record_request(request: Request<Body>, next: Next<Body>) -> Response {
+    let started_at = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_string())
+        .unwrap_or_else(|| String::from("unmatched"));
+
+    let in_flight = InFlight::start();
+    let response = next.run(request).await;
+    drop(in_flight);
+
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(started_at.elapsed().as_secs_f64());
+    return response;
+}
@@ -0,0 +1,136 @@
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{Database, Pool, Postgres};
+
+/// Every migration in `migrations/`, embedded at compile time. Each one has
+/// an `.up.sql` and a `.down.sql` script.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The SQLite variant of `MIGRATOR`, from `migrations/sqlite/`.
+#[cfg(feature = "sqlite")]
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// A database with migrations of its own.
+pub trait MigratedDatabase: Database {
+    fn migrator() -> &'static Migrator;
+}
+
+impl MigratedDatabase for Postgres {
+    fn migrator() -> &'static Migrator {
+        return &MIGRATOR;
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl MigratedDatabase for sqlx::Sqlite {
+    fn migrator() -> &'static Migrator {
+        return &SQLITE_MIGRATOR;
+    }
+}
+
+pub enum MigrateCommand {
+    Up,
+    /// Reverts every migration newer than `to`, or only the latest one.
+    Down {
+        to: Option<i64>,
+    },
+    Status,
+}
+
+/// Applies every pending migration, used on startup.
+pub async fn run_pending<DB>(database_connection_pool: &Pool<DB>) -> Result<(), MigrateError>
+where
+    DB: MigratedDatabase,
+    DB::Connection: Migrate,
+{
+    return DB::migrator().run(database_connection_pool).await;
+}
+
+pub async fn run_command<DB>(
+    database_connection_pool: &Pool<DB>,
+    command: MigrateCommand,
+) -> Result<(), MigrateError>
+where
+    DB: MigratedDatabase,
+    DB::Connection: Migrate,
+{
+    match command {
+        MigrateCommand::Up => {
+            run_pending(database_connection_pool).await?;
+            println!("Database is up to date");
+        }
+        MigrateCommand::Down { to } => {
+            let mut applied_versions = applied_versions(database_connection_pool).await?;
+            let target = match to {
+                Some(target) => target,
+                None => {
+                    if applied_versions.pop().is_none() {
+                        println!("No migration to revert");
+                        return Ok(());
+                    }
+                    applied_versions.pop().unwrap_or(0)
+                }
+            };
+            DB::migrator()
+                .undo(database_connection_pool, target)
+                .await?;
+            println!("Reverted every migration newer than {}", target);
+        }
+        MigrateCommand::Status => print_status(database_connection_pool).await?,
+    }
+    return Ok(());
+}
+
+/// Versions of the applied migrations, oldest first.
+async fn applied_versions<DB>(database_connection_pool: &Pool<DB>) -> Result<Vec<i64>, MigrateError>
+where
+    DB: Database,
+    DB::Connection: Migrate,
+{
+    let mut connection = database_connection_pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    let mut versions: Vec<i64> = connection
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+    versions.sort_unstable();
+    return Ok(versions);
+}
+
+async fn print_status<DB>(database_connection_pool: &Pool<DB>) -> Result<(), MigrateError>
+where
+    DB: MigratedDatabase,
+    DB::Connection: Migrate,
+{
+    let mut connection = database_connection_pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    let applied_migrations = connection.list_applied_migrations().await?;
+
+    for migration in DB::migrator()
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+    {
+        let applied_migration = applied_migrations
+            .iter()
+            .find(|applied_migration| applied_migration.version == migration.version);
+        let state = match applied_migration {
+            None => "pending",
+            Some(applied_migration) if applied_migration.checksum != migration.checksum => {
+                "changed since applied"
+            }
+            Some(_) => "applied",
+        };
+        println!(
+            "{}  {:<8}  {}",
+            migration.version, state, migration.description
+        );
+    }
+    if let Some(version) = connection.dirty_version().await? {
+        println!(
+            "Migration {} failed halfway and must be fixed by hand",
+            version
+        );
+    }
+    return Ok(());
+}
@@ -0,0 +1,218 @@
+//! Picks the body format of list and get responses from the `Accept` header.
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use super::Errors::{ApiError, ApiResult};
+use super::Pagination::Page;
+use super::Transfer::CSV_CONTENT_TYPE;
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+/// Older names of `application/msgpack`, still sent by some clients.
+const MSGPACK_ALIASES: &[&str] = &["application/x-msgpack", "application/vnd.msgpack"];
+
+pub const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+pub const X_NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+pub const X_PREV_CURSOR: HeaderName = HeaderName::from_static("x-prev-cursor");
+
+/// Body format of a list or get response. Requests without `Accept` get
+/// JSON, and JSON wins ties between equally preferred formats.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Representation {
+    Json,
+    Csv,
+    MessagePack,
+}
+
+/// A list response before encoding, with the members of the JSON body.
+#[derive(serde::Serialize)]
+struct ListBody<T> {
+    #[serde(flatten)]
+    page: Page<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<i64>,
+}
+
+impl Representation {
+    const ALL: [Representation; 3] = [
+        Representation::Json,
+        Representation::Csv,
+        Representation::MessagePack,
+    ];
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Representation::Json => JSON_CONTENT_TYPE,
+            Representation::Csv => CSV_CONTENT_TYPE,
+            Representation::MessagePack => MSGPACK_CONTENT_TYPE,
+        }
+    }
+
+    /// How specifically `media_range` names this format: 2 for its own
+    /// type, 1 for `type/*`, 0 for `*/*` and `None` when it does not match.
+    fn specificity(self, media_range: &str) -> Option<u8> {
+        if media_range == "*/*" {
+            return Some(0);
+        }
+        let main_type = match self {
+            Representation::Csv => "text/*",
+            Representation::Json | Representation::MessagePack => "application/*",
+        };
+        if media_range == main_type {
+            return Some(1);
+        }
+        let named = media_range == self.content_type()
+            || (self == Representation::MessagePack && MSGPACK_ALIASES.contains(&media_range));
+        return named.then_some(2);
+    }
+
+    /// The format the `Accept` headers prefer, `None` when they rule out
+    /// every format. Each format takes the quality of the most specific
+    /// media range that matches it.
+    fn negotiate(headers: &HeaderMap) -> Option<Representation> {
+        let mut media_ranges = Vec::new();
+        for value in headers.get_all(header::ACCEPT) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for media_range in value.split(',').filter(|item| !item.trim().is_empty()) {
+                let mut parameters = media_range.split(';').map(str::trim);
+                let mime = parameters.next().unwrap_or_default().to_ascii_lowercase();
+                let quality = parameters
+                    .find_map(|parameter| parameter.strip_prefix("q="))
+                    .map_or(Some(1.0), |quality| quality.parse::<f32>().ok())
+                    .unwrap_or(0.0);
+                media_ranges.push((mime, quality));
+            }
+        }
+        if media_ranges.is_empty() {
+            return Some(Representation::Json);
+        }
+
+        let mut best: Option<(Representation, f32)> = None;
+        for representation in Representation::ALL {
+            let quality = media_ranges
+                .iter()
+                .filter_map(|(mime, quality)| {
+                    return representation
+                        .specificity(mime)
+                        .map(|specificity| (specificity, *quality));
+                })
+                .max_by_key(|(specificity, _)| *specificity)
+                .map_or(0.0, |(_, quality)| quality);
+            if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+                best = Some((representation, quality));
+            }
+        }
+        return best.map(|(representation, _)| representation);
+    }
+
+    /// `body` in this format. A CSV body is `body` as its only record.
+    fn encode<T: Serialize>(self, body: &T) -> ApiResult<Vec<u8>> {
+        let encoded = match self {
+            Representation::Json => serde_json::to_vec(body).map_err(|error| error.to_string()),
+            Representation::MessagePack => {
+                rmp_serde::to_vec_named(body).map_err(|error| error.to_string())
+            }
+            Representation::Csv => return csv_records(std::slice::from_ref(body)),
+        };
+        return encoded.map_err(|error| encoding_error(self, error));
+    }
+
+    fn response(self, body: Vec<u8>) -> Response {
+        let headers = [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(self.content_type()),
+            ),
+            (header::VARY, HeaderValue::from_static("accept")),
+        ];
+        return (headers, body).into_response();
+    }
+
+    /// `row` alone, which in CSV is a header line and one record.
+    pub fn row_response<T: Serialize>(self, row: &T) -> ApiResult<Response> {
+        return Ok(self.response(self.encode(row)?));
+    }
+
+    /// A page of a list and, when it was counted, the size of the whole list.
+    /// CSV bodies only hold the rows, so `total` and the cursors are sent as
+    /// `X-Total-Count`, `X-Next-Cursor` and `X-Prev-Cursor` instead.
+    pub fn list_response<T: Serialize>(
+        self,
+        page: Page<T>,
+        total: Option<i64>,
+    ) -> ApiResult<Response> {
+        if self != Representation::Csv {
+            let body = self.encode(&ListBody { page, total })?;
+            return Ok(self.response(body));
+        }
+
+        let (rows, next_cursor, prev_cursor) = match page {
+            Page::Offset { hits } => (hits, None, None),
+            Page::Cursor(page) => (page.hits, page.next_cursor, page.prev_cursor),
+        };
+        let body = csv_records(&rows)?;
+        let mut response = self.response(body);
+        let headers = response.headers_mut();
+        if let Some(total) = total {
+            headers.insert(X_TOTAL_COUNT, HeaderValue::from(total));
+        }
+        for (name, cursor) in [(X_NEXT_CURSOR, next_cursor), (X_PREV_CURSOR, prev_cursor)] {
+            // Cursors are URL-safe base64, always a valid header value.
+            if let Some(value) = cursor.and_then(|cursor| HeaderValue::try_from(cursor).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        return Ok(response);
+    }
+}
+
+/// `rows` as CSV with a header line, or an empty body without rows.
+fn csv_records<T: Serialize>(rows: &[T]) -> ApiResult<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer
+            .serialize(row)
+            .map_err(|error| encoding_error(Representation::Csv, error.to_string()))?;
+    }
+    return writer.into_inner().map_err(|error| {
+        return encoding_error(Representation::Csv, error.into_error().to_string());
+    });
+}
+
+fn encoding_error(representation: Representation, error: String) -> ApiError {
+    return ApiError::Internal(format!(
+        "Failed to encode the response as {}: {}",
+        representation.content_type(),
+        error
+    ));
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Representation {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let representation = Representation::negotiate(&parts.headers);
+        return representation.ok_or_else(|| {
+            let accept = parts
+                .headers
+                .get_all(header::ACCEPT)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return ApiError::NotAcceptable(format!(
+                "Can answer with `{}`, `{}` or `{}`, not `{}`",
+                JSON_CONTENT_TYPE, CSV_CONTENT_TYPE, MSGPACK_CONTENT_TYPE, accept
+            ));
+        });
+    }
+}
@@ -1,19 +1,290 @@
-#[derive(serde::Deserialize, std::fmt::Debug)]
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sqlx::{
+    postgres::PgRow, ColumnIndex, Database, Decode, Encode, FromRow, PgConnection, Postgres,
+    QueryBuilder, Row, Type,
+};
+
+use super::Errors::{ApiError, ApiResult};
+
+/// Largest `size` a list request can ask for.
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Query parameters shared by every list endpoint.
+///
+/// Without `after`/`before` the list is paged with `size`/`page` (offset mode).
+/// Passing `after` (possibly empty, to start from the beginning) or `before`
+/// switches to cursor mode, keyed on the `ID` column. `with_total=false` skips
+/// the `COUNT(1)` query in either mode.
+#[derive(serde::Deserialize, std::fmt::Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Pagination {
-    pub size: Option<i8>,
+    /// Page size, 10 by default and at most 100.
+    #[param(minimum = 1, maximum = 100)]
+    pub size: Option<i64>,
+    /// Zero-based page number in offset mode.
+    #[param(minimum = 0)]
     pub page: Option<i64>,
+    /// Cursor to continue after; pass it empty to start from the beginning.
+    pub after: Option<String>,
+    /// Cursor to continue before.
+    pub before: Option<String>,
+    /// Whether to include `total`, true by default.
+    pub with_total: Option<bool>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, sqlx::FromRow)]
 pub struct RowCount {
     pub count: Option<i64>,
 }
 
-impl Default for Pagination {
-    fn default() -> Pagination {
-        return Pagination {
-            size: Some(10),
-            page: Some(0),
+pub enum Cursor {
+    After(Option<i64>),
+    Before(i64),
+}
+
+/// Which rows of a list the request asks for, see `Pagination`.
+pub enum PageRequest {
+    Offset { size: i64, offset: i64 },
+    Cursor { cursor: Cursor, size: i64 },
+}
+
+impl PageRequest {
+    pub fn is_cursor(&self) -> bool {
+        return matches!(self, PageRequest::Cursor { .. });
+    }
+}
+
+impl Pagination {
+    pub fn size(&self) -> i64 {
+        return self.size.unwrap_or(10);
+    }
+
+    pub fn offset(&self) -> i64 {
+        return self.size().saturating_mul(self.page.unwrap_or(0));
+    }
+
+    /// Refuses sizes and pages that would turn into a zero or negative
+    /// `LIMIT` or a negative `OFFSET`.
+    fn check_bounds(&self) -> ApiResult<()> {
+        if let Some(size) = self.size.filter(|size| !(1..=MAX_PAGE_SIZE).contains(size)) {
+            return Err(ApiError::InvalidQuery(format!(
+                "`size` must be between 1 and {}, not {}",
+                MAX_PAGE_SIZE, size
+            )));
+        }
+        if let Some(page) = self.page.filter(|page| *page < 0) {
+            return Err(ApiError::InvalidQuery(format!(
+                "`page` cannot be negative, not {}",
+                page
+            )));
+        }
+        return Ok(());
+    }
+
+    pub fn with_total(&self) -> bool {
+        return self.with_total.unwrap_or(true);
+    }
+
+    pub fn cursor(&self) -> ApiResult<Option<Cursor>> {
+        match (&self.after, &self.before) {
+            (Some(_), Some(_)) => {
+                return Err(ApiError::InvalidCursor(String::from(
+                    "Only one of `after` and `before` can be given",
+                )));
+            }
+            (Some(after), None) if after.is_empty() => return Ok(Some(Cursor::After(None))),
+            (Some(after), None) => return Ok(Some(Cursor::After(Some(decode_cursor(after)?)))),
+            (None, Some(before)) => return Ok(Some(Cursor::Before(decode_cursor(before)?))),
+            (None, None) => return Ok(None),
+        }
+    }
+
+    pub fn page_request(&self) -> ApiResult<PageRequest> {
+        self.check_bounds()?;
+        let page_request = match self.cursor()? {
+            Some(cursor) => PageRequest::Cursor {
+                cursor,
+                size: self.size(),
+            },
+            None => PageRequest::Offset {
+                size: self.size(),
+                offset: self.offset(),
+            },
         };
+        return Ok(page_request);
     }
 }
+
+pub fn encode_cursor(id: i64) -> String {
+    return URL_SAFE_NO_PAD.encode(id.to_string());
+}
+
+pub fn decode_cursor(cursor: &str) -> ApiResult<i64> {
+    let invalid_cursor = || ApiError::InvalidCursor(format!("`{}` is not a valid cursor", cursor));
+    let bytes = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|_| invalid_cursor())?;
+    let id = String::from_utf8(bytes)
+        .ok()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(invalid_cursor)?;
+    return Ok(id);
+}
+
+/// Rows that can be paged through with a cursor expose their `ID`.
+pub trait Keyed {
+    fn key(&self) -> i64;
+}
+
+#[derive(serde::Serialize)]
+pub struct CursorPage<T> {
+    pub hits: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+/// Rows returned by a repository for a `PageRequest`, serialized as the body
+/// of a list endpoint before `total` is added.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+pub enum Page<T> {
+    Offset { hits: Vec<T> },
+    Cursor(CursorPage<T>),
+}
+
+/// Wraps the query pushed by `push_base_query` in a query for the `size + 1`
+/// rows that follow or precede `cursor`, to be passed to `finish_cursor_rows`.
+/// The subquery must select an `id` column and no duplicate column names. It
+/// is pushed a second time to tell, in a `beyond_cursor` column, whether the
+/// list has rows on the other side of the cursor.
+pub fn cursor_page_query<'args, DB, F>(
+    push_base_query: F,
+    cursor: &Cursor,
+    size: i64,
+) -> QueryBuilder<'args, DB>
+where
+    DB: Database,
+    i64: Encode<'args, DB> + Type<DB>,
+    F: Fn(&mut QueryBuilder<'args, DB>),
+{
+    let mut query = QueryBuilder::new("SELECT PAGE.*, ");
+    let (beyond_cursor, cursor_id) = match cursor {
+        Cursor::After(None) => (None, None),
+        Cursor::After(Some(after)) => (Some(" WHERE BEYOND.ID <= "), Some(*after)),
+        Cursor::Before(before) => (Some(" WHERE BEYOND.ID >= "), Some(*before)),
+    };
+    match beyond_cursor.zip(cursor_id) {
+        Some((condition, cursor_id)) => {
+            query.push("EXISTS (SELECT 1 FROM (");
+            push_base_query(&mut query);
+            query
+                .push(") AS BEYOND")
+                .push(condition)
+                .push_bind(cursor_id)
+                .push(")");
+        }
+        None => {
+            query.push("FALSE");
+        }
+    }
+    query.push(" AS beyond_cursor FROM (");
+    push_base_query(&mut query);
+    query.push(") AS PAGE");
+
+    match cursor {
+        Cursor::After(Some(after)) => {
+            query.push(" WHERE PAGE.ID > ").push_bind(*after);
+        }
+        Cursor::After(None) => {}
+        Cursor::Before(before) => {
+            query.push(" WHERE PAGE.ID < ").push_bind(*before);
+        }
+    }
+    query
+        .push(if matches!(cursor, Cursor::Before(_)) {
+            " ORDER BY PAGE.ID DESC"
+        } else {
+            " ORDER BY PAGE.ID ASC"
+        })
+        .push(" LIMIT ")
+        .push_bind(size + 1);
+    return query;
+}
+
+/// Runs the query pushed by `push_base_query` as a subquery and returns the
+/// `size` rows that follow or precede `cursor`. The subquery must select an
+/// `id` column and no duplicate column names.
+pub async fn fetch_cursor_page<'args, T, F>(
+    connection: &mut PgConnection,
+    push_base_query: F,
+    cursor: &Cursor,
+    size: i64,
+) -> ApiResult<CursorPage<T>>
+where
+    T: for<'row> FromRow<'row, PgRow> + Keyed + Send + Unpin,
+    F: Fn(&mut QueryBuilder<'args, Postgres>),
+{
+    let mut query = cursor_page_query(push_base_query, cursor, size);
+    let rows = query.build().fetch_all(connection).await?;
+    return finish_cursor_rows(rows, cursor, size);
+}
+
+/// Decodes the rows of a `cursor_page_query` and builds the page from them.
+pub fn finish_cursor_rows<R, T>(
+    rows: Vec<R>,
+    cursor: &Cursor,
+    size: i64,
+) -> ApiResult<CursorPage<T>>
+where
+    R: Row,
+    T: for<'row> FromRow<'row, R> + Keyed,
+    bool: for<'row> Decode<'row, R::Database> + Type<R::Database>,
+    for<'name> &'name str: ColumnIndex<R>,
+{
+    let beyond_cursor = match rows.first() {
+        Some(row) => row.try_get::<bool, _>("beyond_cursor")?,
+        None => false,
+    };
+    let hits = rows
+        .iter()
+        .map(T::from_row)
+        .collect::<Result<Vec<T>, sqlx::Error>>()?;
+    return Ok(finish_cursor_page(hits, cursor, size, beyond_cursor));
+}
+
+/// Builds the page from up to `size + 1` rows following `cursor`, ordered
+/// away from it (by descending id for `Before`). The extra row only tells
+/// whether there is more in that direction, and `beyond_cursor` whether there
+/// are rows in the other one.
+pub fn finish_cursor_page<T: Keyed>(
+    mut hits: Vec<T>,
+    cursor: &Cursor,
+    size: i64,
+    beyond_cursor: bool,
+) -> CursorPage<T> {
+    let has_more = hits.len() as i64 > size;
+    hits.truncate(size.max(0) as usize);
+    if matches!(cursor, Cursor::Before(_)) {
+        hits.reverse();
+    }
+
+    let first_cursor = hits.first().map(|hit| encode_cursor(hit.key()));
+    let last_cursor = hits.last().map(|hit| encode_cursor(hit.key()));
+    let (next_cursor, prev_cursor) = match cursor {
+        Cursor::After(None) => (last_cursor.filter(|_| has_more), None),
+        Cursor::After(Some(_)) => (
+            last_cursor.filter(|_| has_more),
+            first_cursor.filter(|_| beyond_cursor),
+        ),
+        Cursor::Before(_) => (
+            last_cursor.filter(|_| beyond_cursor),
+            first_cursor.filter(|_| has_more),
+        ),
+    };
+
+    return CursorPage {
+        hits,
+        next_cursor,
+        prev_cursor,
+    };
+}
@@ -1,123 +1,549 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    routing::{get, post},
+    body::Bytes,
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        DefaultBodyLimit, Path, Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::{get, post, put},
     Json, Router,
 };
 use serde_json::Value;
-use sqlx::{Pool, Postgres};
+use sqlx::types::time::OffsetDateTime;
 
-use super::Pagination::{Pagination, RowCount};
+use super::AppState::AppState;
+use super::Auth::CurrentPerson;
+use super::Authorization::{Admin, IncludeDeleted, Role};
+use super::Errors::{ApiError, ApiResult, ProblemResponse};
+use super::Events::{Action, EventBus, Resource};
+use super::Filter::{FilterField, FilterValue, Filterable, ListQuery};
+use super::Metrics::PEOPLE_CREATED;
+use super::Negotiation::Representation;
+use super::Pagination::{Keyed, Pagination};
+use super::Precondition::{tagged_response, with_entity_tag, IfMatch, IfNoneMatch};
+use super::Repository::{Deletion, PersonRepository, Repository};
+use super::Transfer::{export_rows, parse_import, ExportQuery, ImportSummary, IMPORT_BODY_LIMIT};
+use super::Validation::{FieldError, Validate, Validator, MAX_VARCHAR_LENGTH};
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, utoipa::ToSchema, async_graphql::InputObject)]
 pub struct NewPerson {
     pub person_name: String,
     pub age: i32,
     pub email: String,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct PersonPatch {
+    pub person_name: Option<String>,
+    pub age: Option<i32>,
+    pub email: Option<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RoleChange {
+    pub role: Role,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct PersonRole {
+    pub id: i64,
+    pub role: Role,
+}
+
+#[derive(
+    Clone,
+    serde::Deserialize,
+    serde::Serialize,
+    sqlx::FromRow,
+    utoipa::ToSchema,
+    async_graphql::SimpleObject,
+)]
+#[graphql(complex)]
 pub struct Person {
-    id: i64,
-    person_name: String,
-    age: i32,
-    email: String,
+    pub id: i64,
+    pub person_name: String,
+    pub age: i32,
+    pub email: String,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: OffsetDateTime,
+    /// When the person was deleted, which also ended their sessions.
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    #[graphql(skip)]
+    pub deleted_at: Option<OffsetDateTime>,
+    /// Starts at 1 and grows with every change to the person. Updates must
+    /// quote it in `If-Match`.
+    pub row_version: i64,
 }
 
-pub fn get_router() -> Router<Pool<Postgres>> {
-    return Router::new()
-        .route("/", post(insert_person))
-        .route("/:user_id", get(get_person_by_id))
-        .route("/:user_id/salad", get(crate::Salad::list_salads_by_user_id))
-        .route("/", get(list_person));
+impl Keyed for Person {
+    fn key(&self) -> i64 {
+        return self.id;
+    }
 }
 
-pub async fn get_person_by_id(
-    Path(user_id): Path<i64>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let query_result = sqlx::query_as!(Person, "SELECT * FROM PERSON WHERE ID = $1", user_id)
-        .fetch_one(&database_connection_pool)
-        .await;
-    match query_result {
-        Ok(person) => {
-            return (StatusCode::OK, Json(serde_json::json!(person)));
-        }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({ "error": error.to_string() })),
-            );
+impl Filterable for Person {
+    fn field(&self, name: &str) -> Option<FilterValue> {
+        match name {
+            "id" => return Some(FilterValue::Integer(self.id)),
+            "person_name" => return Some(FilterValue::Text(self.person_name.clone())),
+            "age" => return Some(FilterValue::Integer(i64::from(self.age))),
+            "email" => return Some(FilterValue::Text(self.email.clone())),
+            "email_domain" => {
+                let domain = self.email.split('@').nth(1).unwrap_or_default();
+                return Some(FilterValue::Text(domain.to_lowercase()));
+            }
+            _ => return None,
         }
     }
+
+    fn is_deleted(&self) -> bool {
+        return self.deleted_at.is_some();
+    }
 }
 
-pub async fn list_person(
-    maybe_pagination: Option<Query<Pagination>>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let Query(pagination) = maybe_pagination.unwrap_or_default();
-    let size = i64::from(pagination.size.unwrap_or(10));
-    let offset = size * pagination.page.unwrap_or(0);
-    let query_result = sqlx::query_as!(
-        Person,
-        "SELECT * FROM PERSON LIMIT $1 OFFSET $2",
-        size,
-        offset,
-    )
-    .fetch_all(&database_connection_pool)
-    .await;
-
-    let row_query_result = sqlx::query_as!(RowCount, "SELECT COUNT(1) from PERSON")
-        .fetch_one(&database_connection_pool)
-        .await;
-
-    match (query_result, row_query_result) {
-        (Ok(person_vec), Ok(row_query)) => {
-            return (
-                StatusCode::OK,
-                Json(
-                    serde_json::json!({"total":row_query.count.unwrap_or_default(), "hits":person_vec}),
-                ),
-            );
+impl Validate for NewPerson {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        return Validator::new()
+            .length("person_name", &self.person_name, 1, MAX_VARCHAR_LENGTH)
+            .positive("age", self.age)
+            .length("email", &self.email, 1, MAX_VARCHAR_LENGTH)
+            .email("email", &self.email)
+            .finish();
+    }
+}
+
+impl Validate for PersonPatch {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut validator = Validator::new();
+        if let Some(person_name) = &self.person_name {
+            validator.length("person_name", person_name, 1, MAX_VARCHAR_LENGTH);
         }
-        (Err(error), Ok(_)) | (Ok(_), Err(error)) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":error.to_string()})),
-            );
+        if let Some(age) = self.age {
+            validator.positive("age", age);
         }
-        (Err(error), Err(error_count)) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"errors":[error.to_string(), error_count.to_string()]})),
-            );
+        if let Some(email) = &self.email {
+            validator
+                .length("email", email, 1, MAX_VARCHAR_LENGTH)
+                .email("email", email);
         }
+        return validator.finish();
+    }
+}
+
+/// Fields accepted by `list_person` for filtering and sorting.
+pub const PERSON_FILTERS: &[FilterField] = &[
+    FilterField::integer("id", "ID"),
+    FilterField::text("person_name", "PERSON_NAME"),
+    FilterField::integer("age", "AGE"),
+    FilterField::text("email", "EMAIL"),
+    FilterField::text("email_domain", "LOWER(SPLIT_PART(EMAIL, '@', 2))")
+        .with_sqlite_column("LOWER(SUBSTR(EMAIL, INSTR(EMAIL, '@') + 1))"),
+];
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new()
+        .route("/", post(insert_person::<R>))
+        .route("/export", get(export_person::<R>))
+        .route(
+            "/import",
+            post(import_person::<R>).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)),
+        )
+        .route(
+            "/:user_id",
+            get(get_person_by_id::<R>)
+                .put(update_person::<R>)
+                .patch(patch_person::<R>)
+                .delete(delete_person::<R>),
+        )
+        .route(
+            "/:user_id/salad",
+            get(crate::Salad::list_salads_by_user_id::<R>),
+        )
+        .route("/:user_id/role", put(update_person_role::<R>))
+        .route("/:user_id/restore", post(restore_person::<R>))
+        .route("/", get(list_person::<R>));
+}
+
+#[utoipa::path(
+    get,
+    path = "/person/{user_id}",
+    tag = "person",
+    params(
+        ("user_id" = i64, Path, description = "Person id"),
+        ("include_deleted" = Option<bool>, Query, description = "Also find a deleted person, admins only"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a copy the client already has"),
+    ),
+    responses(
+        (status = 200, description = "The person", body = Person, content_type = ["application/json", "text/csv", "application/msgpack"], headers(("ETag" = String))),
+        (status = 304, description = "The person still has the `If-None-Match` ETag"),
+        (status = 406, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+)]
+pub async fn get_person_by_id<R: PersonRepository>(
+    Path(user_id): Path<i64>,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    if_none_match: IfNoneMatch,
+    representation: Representation,
+    State(people): State<R>,
+) -> ApiResult<Response> {
+    let person = people
+        .get_person(user_id, include_deleted)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Person", user_id))?;
+    if let Some(not_modified) = if_none_match.not_modified(person.row_version) {
+        return Ok(not_modified);
     }
+    let response = representation.row_response(&person)?;
+    return Ok(with_entity_tag(response, person.row_version));
 }
 
-pub async fn insert_person(
-    State(database_connection_pool): State<Pool<Postgres>>,
-    Json(new_person_json): Json<NewPerson>,
-) -> (StatusCode, Json<Value>) {
-    let query_result = sqlx::query_as!(
-                Person,
-                "INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL ) VALUES ( $1, $2, $3 ) RETURNING ID, PERSON_NAME, AGE, EMAIL",
-                new_person_json.person_name,
-                new_person_json.age,
-                new_person_json.email
-            )
-            .fetch_one(&database_connection_pool)
-            .await;
-    match query_result {
-        Ok(person) => {
-            return (StatusCode::CREATED, Json(serde_json::json!(person)));
+#[utoipa::path(
+    get,
+    path = "/person",
+    tag = "person",
+    params(
+        Pagination,
+        ("include_deleted" = Option<bool>, Query, description = "Also list deleted people, admins only"),
+    ),
+    responses(
+        (status = 200, description = "A page of people", body = PersonList, content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 406, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+    ),
+)]
+pub async fn list_person<R: PersonRepository>(
+    pagination: Result<Query<Pagination>, QueryRejection>,
+    Query(parameters): Query<Vec<(String, String)>>,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    representation: Representation,
+    State(people): State<R>,
+) -> ApiResult<Response> {
+    let Query(pagination) = pagination?;
+    let mut list_query = ListQuery::parse(&parameters, PERSON_FILTERS)?;
+    list_query.include_deleted = include_deleted;
+    let page_request = pagination.page_request()?;
+    if page_request.is_cursor() && list_query.is_sorted() {
+        return Err(ApiError::InvalidQuery(String::from(
+            "`sort` cannot be combined with cursor pagination",
+        )));
+    }
+    let page = people.list_people(&list_query, &page_request).await?;
+    let total = if pagination.with_total() {
+        Some(people.count_people(&list_query).await?)
+    } else {
+        None
+    };
+    return representation.list_response(page, total);
+}
+
+/// Streams every person, ordered by id. Admins only, like the import, as
+/// the rows hold everyone's email.
+#[utoipa::path(
+    get,
+    path = "/person/export",
+    tag = "person",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "One row per person", body = [Person], content_type = ["text/csv", "application/x-ndjson"]),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn export_person<R: PersonRepository>(
+    _admin: Admin,
+    Query(export_query): Query<ExportQuery>,
+    State(people): State<R>,
+) -> Response {
+    return export_rows(people.export_people(), "person", export_query.format);
+}
+
+/// Inserts every row of a CSV (with a header line) or NDJSON body, all or
+/// nothing. Nothing is inserted if any row is invalid. Imported people have
+/// no password, like those created by `insert_person`. Publishes a
+/// `person.created` event per row.
+#[utoipa::path(
+    post,
+    path = "/person/import",
+    tag = "person",
+    request_body(content = [NewPerson], content_type = "text/csv", description = "`NewPerson` rows as CSV with a header line, or as NDJSON with `Content-Type: application/x-ndjson`"),
+    responses(
+        (status = 201, description = "Number of imported rows", body = ImportSummary),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 413, description = "The body is larger than 64 MiB"),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn import_person<R: PersonRepository>(
+    _admin: Admin,
+    State(people): State<R>,
+    State(events): State<EventBus>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let new_people: Vec<NewPerson> = parse_import(&headers, &body)?;
+    let imported = people.import_people(&new_people).await?;
+    PEOPLE_CREATED.inc_by(imported.len() as u64);
+    for person in &imported {
+        events.changed(Action::Created, person);
+    }
+
+    let summary = ImportSummary {
+        imported: imported.len(),
+    };
+    return Ok((StatusCode::CREATED, Json(serde_json::json!(summary))));
+}
+
+/// Creates a person without a password, who cannot log in. People sign up
+/// through `/auth/register` instead, so this is reserved to admins.
+#[utoipa::path(
+    post,
+    path = "/person",
+    tag = "person",
+    request_body = NewPerson,
+    responses(
+        (status = 201, description = "The created person", body = Person),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn insert_person<R: PersonRepository>(
+    _admin: Admin,
+    State(people): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<NewPerson>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(new_person_json) = body?;
+    new_person_json.validate()?;
+    let person = people.insert_person(&new_person_json).await?;
+    PEOPLE_CREATED.inc();
+    events.changed(Action::Created, &person);
+    return Ok((StatusCode::CREATED, Json(serde_json::json!(person))));
+}
+
+#[utoipa::path(
+    put,
+    path = "/person/{user_id}",
+    tag = "person",
+    params(
+        ("user_id" = i64, Path, description = "Person id"),
+        ("If-Match" = String, Header, description = "ETag of the person as last read"),
+    ),
+    request_body = NewPerson,
+    responses(
+        (status = 200, description = "The updated person", body = Person, headers(("ETag" = String))),
+        (status = 412, response = ProblemResponse),
+        (status = 428, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn update_person<R: PersonRepository>(
+    Path(user_id): Path<i64>,
+    current_person: CurrentPerson,
+    if_match: IfMatch,
+    State(people): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<NewPerson>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(person_json) = body?;
+    person_json.validate()?;
+    current_person.ensure_owner_or_admin(user_id, &format!("Person {}", user_id))?;
+    let current = people
+        .get_person(user_id, false)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Person", user_id))?;
+    if_match.check("Person", user_id, current.row_version)?;
+    let person = people
+        .update_person(user_id, &person_json, current.row_version)
+        .await?
+        .ok_or_else(|| ApiError::stale("Person", user_id))?;
+    events.changed(Action::Updated, &person);
+    return Ok(tagged_response(
+        StatusCode::OK,
+        serde_json::json!(person),
+        person.row_version,
+    ));
+}
+
+#[utoipa::path(
+    patch,
+    path = "/person/{user_id}",
+    tag = "person",
+    params(
+        ("user_id" = i64, Path, description = "Person id"),
+        ("If-Match" = String, Header, description = "ETag of the person as last read"),
+    ),
+    request_body = PersonPatch,
+    responses(
+        (status = 200, description = "The updated person", body = Person, headers(("ETag" = String))),
+        (status = 412, response = ProblemResponse),
+        (status = 428, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn patch_person<R: PersonRepository>(
+    Path(user_id): Path<i64>,
+    current_person: CurrentPerson,
+    if_match: IfMatch,
+    State(people): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<PersonPatch>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(person_json) = body?;
+    person_json.validate()?;
+    current_person.ensure_owner_or_admin(user_id, &format!("Person {}", user_id))?;
+    let current = people
+        .get_person(user_id, false)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Person", user_id))?;
+    if_match.check("Person", user_id, current.row_version)?;
+    let person = people
+        .patch_person(user_id, &person_json, current.row_version)
+        .await?
+        .ok_or_else(|| ApiError::stale("Person", user_id))?;
+    events.changed(Action::Updated, &person);
+    return Ok(tagged_response(
+        StatusCode::OK,
+        serde_json::json!(person),
+        person.row_version,
+    ));
+}
+
+/// Refuses to delete a person who still owns salads, since
+/// `FRUIT_SALAD.ID_CREATOR` references it. The person's login sessions are
+/// revoked, and stay so if the person is restored.
+#[utoipa::path(
+    delete,
+    path = "/person/{user_id}",
+    tag = "person",
+    params(("user_id" = i64, Path, description = "Person id")),
+    responses(
+        (status = 204, description = "The person was deleted"),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_person<R: PersonRepository>(
+    Path(user_id): Path<i64>,
+    current_person: CurrentPerson,
+    State(people): State<R>,
+    State(events): State<EventBus>,
+) -> ApiResult<StatusCode> {
+    current_person.ensure_owner_or_admin(user_id, &format!("Person {}", user_id))?;
+    match people.delete_person(user_id).await? {
+        Deletion::Deleted => {
+            events.deleted(Resource::Person, user_id, None);
+            return Ok(StatusCode::NO_CONTENT);
         }
-        Err(json_error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":json_error.to_string()})),
-            );
+        Deletion::NotFound => return Err(ApiError::not_found("Person", user_id)),
+        Deletion::InUse(usage_count) => {
+            return Err(ApiError::Conflict(format!(
+                "Person {} still owns {} salad(s)",
+                user_id, usage_count
+            )))
         }
     }
 }
+
+/// Undoes `delete_person`. Fails with 409 when somebody else registered with
+/// the same email in the meantime.
+#[utoipa::path(
+    post,
+    path = "/person/{user_id}/restore",
+    tag = "person",
+    params(("user_id" = i64, Path, description = "Person id")),
+    responses(
+        (status = 200, description = "The restored person", body = Person),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn restore_person<R: PersonRepository>(
+    Path(user_id): Path<i64>,
+    _admin: Admin,
+    State(people): State<R>,
+    State(events): State<EventBus>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let person = people
+        .restore_person(user_id)
+        .await?
+        .into_result("Person", user_id)?;
+    events.changed(Action::Restored, &person);
+    return Ok((StatusCode::OK, Json(serde_json::json!(person))));
+}
+
+/// Promotes or demotes a person. Admins cannot demote themselves, so there is
+/// always at least one admin left once the first one exists.
+#[utoipa::path(
+    put,
+    path = "/person/{user_id}/role",
+    tag = "person",
+    params(("user_id" = i64, Path, description = "Person id")),
+    request_body = RoleChange,
+    responses(
+        (status = 200, description = "The new role", body = PersonRole),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn update_person_role<R: PersonRepository>(
+    Path(user_id): Path<i64>,
+    Admin(admin): Admin,
+    State(people): State<R>,
+    body: Result<Json<RoleChange>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(role_change) = body?;
+    if admin.person.id == user_id {
+        return Err(ApiError::Conflict(String::from(
+            "Admins cannot change their own role",
+        )));
+    }
+    let role = people
+        .set_person_role(user_id, role_change.role)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Person", user_id))?;
+    let person_role = PersonRole { id: user_id, role };
+    return Ok((StatusCode::OK, Json(serde_json::json!(person_role))));
+}
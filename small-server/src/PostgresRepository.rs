@@ -0,0 +1,1776 @@
+use axum::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgRow;
+use sqlx::types::time::OffsetDateTime;
+use sqlx::types::Json;
+use sqlx::{Connection, FromRow, Pool, Postgres, QueryBuilder};
+use tracing::Instrument;
+
+use super::Authorization::Role;
+use super::Errors::{ApiError, ApiResult};
+use super::Filter::ListQuery;
+use super::Fruit::{Fruit, FruitPatch, NewFruit};
+use super::Metrics::acquire;
+use super::Pagination::{fetch_cursor_page, Keyed, Page, PageRequest, RowCount};
+use super::Person::{NewPerson, Person, PersonPatch};
+use super::Repository::{
+    Account, Deletion, FruitRepository, PersonRepository, PoolStatus, Repository, Restoration,
+    RowStream, SaladIngredientRepository, SaladRepository, WebhookRepository,
+};
+use super::Salad::{
+    FruitSalad, FruitSaladPatch, FullFruitSalad, NewFruitSalad, SaladComponent,
+    SaladIngredientsView, SaladView,
+};
+use super::SaladIngredient::{NewSaladIngredient, SaladIngredient, SaladIngredientPatch};
+use super::Transfer::IMPORT_BATCH_SIZE;
+use super::Webhook::{DeliveryAttempt, DueDelivery, NewWebhook, Webhook, WebhookDelivery};
+
+/// Every repository, backed by the tables of `migrations/`.
+#[derive(Clone)]
+pub struct PostgresRepository {
+    database_connection_pool: Pool<Postgres>,
+    /// Not readable from the pool itself, so it is taken from the config.
+    max_connections: u32,
+}
+
+impl PostgresRepository {
+    pub fn new(
+        database_connection_pool: Pool<Postgres>,
+        max_connections: u32,
+    ) -> PostgresRepository {
+        return PostgresRepository {
+            database_connection_pool,
+            max_connections,
+        };
+    }
+
+    /// A connection of the pool, counted while the query waits for it.
+    async fn connection(&self) -> ApiResult<PoolConnection<Postgres>> {
+        return Ok(acquire(&self.database_connection_pool).await?);
+    }
+
+    /// Lists the rows of `base_query`, a `SELECT` without a `WHERE` clause,
+    /// that match `list_query`.
+    async fn list_rows<T>(
+        &self,
+        base_query: &'static str,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<T>>
+    where
+        T: for<'row> FromRow<'row, PgRow> + Keyed + Send + Unpin,
+    {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = fetch_cursor_page::<T, _>(
+                    &mut *self.connection().await?,
+                    |query| {
+                        query.push(base_query);
+                        list_query.push_where(query, false);
+                    },
+                    cursor,
+                    *size,
+                )
+                .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let mut query = QueryBuilder::new(base_query);
+                list_query.push_where(&mut query, false);
+                list_query.push_order_by(&mut query, "ID");
+                query
+                    .push(" LIMIT ")
+                    .push_bind(*size)
+                    .push(" OFFSET ")
+                    .push_bind(*offset);
+                let hits: Vec<T> = query
+                    .build_query_as()
+                    .fetch_all(&mut self.connection().await?)
+                    .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_rows(&self, table: &'static str, list_query: &ListQuery) -> ApiResult<i64> {
+        let mut query = QueryBuilder::new("SELECT COUNT(1) FROM ");
+        query.push(table);
+        list_query.push_where(&mut query, false);
+        let row_count: RowCount = query
+            .build_query_as()
+            .fetch_one(&mut self.connection().await?)
+            .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    /// Streams the rows of `sql` from a task of their own, since the stream
+    /// of a query borrows the pool.
+    fn export_rows<T>(&self, sql: &'static str) -> RowStream<T>
+    where
+        T: for<'row> FromRow<'row, PgRow> + Send + Unpin + 'static,
+    {
+        let database_connection_pool = self.database_connection_pool.clone();
+        let (mut sender, receiver) = futures::channel::mpsc::channel(16);
+        let export = async move {
+            let mut connection = match acquire(&database_connection_pool).await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    let _ = sender.send(Err(error)).await;
+                    return;
+                }
+            };
+            let mut rows = sqlx::query_as::<_, T>(sql).fetch(&mut *connection);
+            while let Some(row) = rows.next().await {
+                let failed = row.is_err();
+                if sender.send(row).await.is_err() || failed {
+                    break;
+                }
+            }
+        };
+        // Keeps the query logs of the export in the span of its request.
+        tokio::spawn(export.instrument(tracing::Span::current()));
+        return receiver.boxed();
+    }
+}
+
+#[async_trait]
+impl PersonRepository for PostgresRepository {
+    async fn get_person(&self, person_id: i64, include_deleted: bool) -> ApiResult<Option<Person>> {
+        let person = sqlx::query_as!(
+            Person,
+            r#"
+            SELECT ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            FROM PERSON
+            WHERE ID = $1 AND ($2 OR DELETED_AT IS NULL)
+            "#,
+            person_id,
+            include_deleted
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn list_people(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<Person>> {
+        let page = self
+            .list_rows(
+                r#"
+                SELECT ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+                FROM PERSON
+                "#,
+                list_query,
+                page_request,
+            )
+            .await?;
+        return Ok(page);
+    }
+
+    async fn count_people(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let count = self.count_rows("PERSON", list_query).await?;
+        return Ok(count);
+    }
+
+    async fn get_people(&self, person_ids: &[i64]) -> ApiResult<Vec<Person>> {
+        let people = sqlx::query_as!(
+            Person,
+            r#"
+            SELECT ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            FROM PERSON
+            WHERE ID = ANY($1) AND DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+            person_ids
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(people);
+    }
+
+    fn export_people(&self) -> RowStream<Person> {
+        return self.export_rows(
+            r#"
+            SELECT ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            FROM PERSON
+            WHERE DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+        );
+    }
+
+    async fn import_people(&self, people: &[NewPerson]) -> ApiResult<Vec<Person>> {
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let mut imported = Vec::with_capacity(people.len());
+        for batch in people.chunks(IMPORT_BATCH_SIZE) {
+            let mut query = QueryBuilder::new("INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL ) ");
+            query.push_values(batch, |mut row, person| {
+                row.push_bind(&person.person_name);
+                row.push_bind(person.age);
+                row.push_bind(&person.email);
+            });
+            query.push(
+                " RETURNING ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION",
+            );
+            imported.extend(
+                query
+                    .build_query_as::<Person>()
+                    .fetch_all(&mut transaction)
+                    .await?,
+            );
+        }
+        transaction.commit().await?;
+        imported.sort_by_key(|person| person.id);
+        return Ok(imported);
+    }
+
+    async fn insert_person(&self, new_person: &NewPerson) -> ApiResult<Person> {
+        let person = sqlx::query_as!(
+            Person,
+            r#"
+            INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL ) VALUES ( $1, $2, $3 )
+            RETURNING ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            "#,
+            new_person.person_name,
+            new_person.age,
+            new_person.email
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn update_person(
+        &self,
+        person_id: i64,
+        person: &NewPerson,
+        row_version: i64,
+    ) -> ApiResult<Option<Person>> {
+        let person = sqlx::query_as!(
+            Person,
+            r#"
+            UPDATE PERSON
+            SET PERSON_NAME = $2, AGE = $3, EMAIL = $4, UPDATED_AT = NOW(),
+                ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $5
+            RETURNING ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            "#,
+            person_id,
+            person.person_name,
+            person.age,
+            person.email,
+            row_version
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn patch_person(
+        &self,
+        person_id: i64,
+        patch: &PersonPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<Person>> {
+        let person = sqlx::query_as!(
+            Person,
+            r#"
+            UPDATE PERSON
+            SET PERSON_NAME = COALESCE($2, PERSON_NAME),
+                AGE = COALESCE($3, AGE),
+                EMAIL = COALESCE($4, EMAIL),
+                UPDATED_AT = NOW(),
+                ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $5
+            RETURNING ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            "#,
+            person_id,
+            patch.person_name,
+            patch.age,
+            patch.email,
+            row_version
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn delete_person(&self, person_id: i64) -> ApiResult<Deletion> {
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        // Salad inserts and restores share-lock their creator, see `delete_fruit`.
+        let person = sqlx::query!(
+            "SELECT ID FROM PERSON WHERE ID = $1 AND DELETED_AT IS NULL FOR UPDATE",
+            person_id
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        if person.is_none() {
+            return Ok(Deletion::NotFound);
+        }
+
+        let usage = sqlx::query_as!(
+            RowCount,
+            "SELECT COUNT(1) FROM FRUIT_SALAD WHERE ID_CREATOR = $1 AND DELETED_AT IS NULL",
+            person_id
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+
+        let usage_count = usage.count.unwrap_or_default();
+        if usage_count > 0 {
+            return Ok(Deletion::InUse(usage_count));
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE PERSON SET DELETED_AT = NOW(), UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1
+            "#,
+            person_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE AUTH_SESSION SET REVOKED_AT = NOW() WHERE ID_PERSON = $1 AND REVOKED_AT IS NULL",
+            person_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        return Ok(Deletion::Deleted);
+    }
+
+    async fn restore_person(&self, person_id: i64) -> ApiResult<Restoration<Person>> {
+        match self.get_person(person_id, true).await? {
+            None => return Ok(Restoration::NotFound),
+            Some(person) if person.deleted_at.is_none() => {
+                return Ok(Restoration::Restored(person))
+            }
+            Some(_) => {}
+        }
+
+        let person = sqlx::query_as!(
+            Person,
+            r#"
+            UPDATE PERSON SET DELETED_AT = NULL, UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1
+            RETURNING ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            "#,
+            person_id
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(Restoration::Restored(person));
+    }
+
+    async fn set_person_role(&self, person_id: i64, role: Role) -> ApiResult<Option<Role>> {
+        let person = sqlx::query!(
+            r#"
+            UPDATE PERSON
+            SET PERSON_ROLE = $2, UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            RETURNING PERSON_ROLE
+            "#,
+            person_id,
+            role.as_str()
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(person.map(|person| Role::parse(&person.person_role)));
+    }
+
+    async fn register_person(
+        &self,
+        new_person: &NewPerson,
+        password_hash: &str,
+    ) -> ApiResult<Person> {
+        let person = sqlx::query_as!(
+            Person,
+            r#"
+            INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL, PASSWORD_HASH ) VALUES ( $1, $2, $3, $4 )
+            RETURNING ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            "#,
+            new_person.person_name,
+            new_person.age,
+            new_person.email,
+            password_hash
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn find_account(&self, email: &str) -> ApiResult<Option<Account>> {
+        let account = sqlx::query!(
+            r#"
+            SELECT ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION,
+                   PERSON_ROLE, PASSWORD_HASH AS "password_hash!"
+            FROM PERSON
+            WHERE LOWER(EMAIL) = LOWER($1) AND PASSWORD_HASH IS NOT NULL AND DELETED_AT IS NULL
+            "#,
+            email
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+
+        return Ok(account.map(|account| Account {
+            person: Person {
+                id: account.id,
+                person_name: account.person_name,
+                age: account.age,
+                email: account.email,
+                created_at: account.created_at,
+                updated_at: account.updated_at,
+                deleted_at: account.deleted_at,
+                row_version: account.row_version,
+            },
+            role: Role::parse(&account.person_role),
+            password_hash: account.password_hash,
+        }));
+    }
+
+    async fn create_session(&self, person_id: i64, expires_at: OffsetDateTime) -> ApiResult<i64> {
+        let session = sqlx::query!(
+            "INSERT INTO AUTH_SESSION ( ID_PERSON, EXPIRES_AT ) VALUES ( $1, $2 ) RETURNING ID",
+            person_id,
+            expires_at
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(session.id);
+    }
+
+    async fn find_session(&self, session_id: i64) -> ApiResult<Option<(Person, Role)>> {
+        let account = sqlx::query!(
+            r#"
+            SELECT PERSON.ID, PERSON_NAME, AGE, EMAIL, PERSON.CREATED_AT, PERSON.UPDATED_AT,
+                   PERSON.DELETED_AT, PERSON.ROW_VERSION, PERSON_ROLE
+            FROM AUTH_SESSION
+            JOIN PERSON ON ID_PERSON = PERSON.ID
+            WHERE AUTH_SESSION.ID = $1 AND REVOKED_AT IS NULL AND EXPIRES_AT > NOW()
+              AND PERSON.DELETED_AT IS NULL
+            "#,
+            session_id
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+
+        return Ok(account.map(|account| {
+            let person = Person {
+                id: account.id,
+                person_name: account.person_name,
+                age: account.age,
+                email: account.email,
+                created_at: account.created_at,
+                updated_at: account.updated_at,
+                deleted_at: account.deleted_at,
+                row_version: account.row_version,
+            };
+            return (person, Role::parse(&account.person_role));
+        }));
+    }
+
+    async fn revoke_session(&self, session_id: i64) -> ApiResult<()> {
+        sqlx::query!(
+            "UPDATE AUTH_SESSION SET REVOKED_AT = NOW() WHERE ID = $1",
+            session_id
+        )
+        .execute(&mut self.connection().await?)
+        .await?;
+        return Ok(());
+    }
+}
+
+#[async_trait]
+impl FruitRepository for PostgresRepository {
+    async fn get_fruit(&self, fruit_id: i64, include_deleted: bool) -> ApiResult<Option<Fruit>> {
+        let fruit = sqlx::query_as!(
+            Fruit,
+            "SELECT * FROM FRUIT WHERE ID = $1 AND ($2 OR DELETED_AT IS NULL)",
+            fruit_id,
+            include_deleted
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(fruit);
+    }
+
+    async fn list_fruits(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<Fruit>> {
+        let page = self
+            .list_rows("SELECT * FROM FRUIT", list_query, page_request)
+            .await?;
+        return Ok(page);
+    }
+
+    async fn count_fruits(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let count = self.count_rows("FRUIT", list_query).await?;
+        return Ok(count);
+    }
+
+    async fn get_fruits(&self, fruit_ids: &[i64]) -> ApiResult<Vec<Fruit>> {
+        let fruits = sqlx::query_as!(
+            Fruit,
+            "SELECT * FROM FRUIT WHERE ID = ANY($1) AND DELETED_AT IS NULL ORDER BY ID",
+            fruit_ids
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(fruits);
+    }
+
+    fn export_fruits(&self) -> RowStream<Fruit> {
+        return self.export_rows(
+            r#"
+            SELECT ID, FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, FRUIT_WEIGHT, CREATED_AT,
+                   UPDATED_AT, DELETED_AT, ROW_VERSION
+            FROM FRUIT
+            WHERE DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+        );
+    }
+
+    async fn import_fruits(&self, fruits: &[NewFruit]) -> ApiResult<Vec<Fruit>> {
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let mut imported = Vec::with_capacity(fruits.len());
+        for batch in fruits.chunks(IMPORT_BATCH_SIZE) {
+            let mut query = QueryBuilder::new(
+                "INSERT INTO FRUIT ( FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, FRUIT_WEIGHT ) ",
+            );
+            query.push_values(batch, |mut row, fruit| {
+                row.push_bind(&fruit.fruit_name);
+                row.push_bind(fruit.color_red);
+                row.push_bind(fruit.color_green);
+                row.push_bind(fruit.color_blue);
+                row.push_bind(fruit.fruit_weight);
+            });
+            query.push(" RETURNING *");
+            imported.extend(
+                query
+                    .build_query_as::<Fruit>()
+                    .fetch_all(&mut transaction)
+                    .await?,
+            );
+        }
+        transaction.commit().await?;
+        imported.sort_by_key(|fruit| fruit.id);
+        return Ok(imported);
+    }
+
+    async fn insert_fruit(&self, new_fruit: &NewFruit) -> ApiResult<Fruit> {
+        let fruit = sqlx::query_as!(
+            Fruit,
+            r#"
+            INSERT INTO FRUIT ( FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, FRUIT_WEIGHT )
+            VALUES ( $1, $2, $3, $4, $5 )
+            RETURNING *
+            "#,
+            new_fruit.fruit_name,
+            new_fruit.color_red,
+            new_fruit.color_green,
+            new_fruit.color_blue,
+            new_fruit.fruit_weight
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(fruit);
+    }
+
+    async fn update_fruit(
+        &self,
+        fruit_id: i64,
+        fruit: &NewFruit,
+        row_version: i64,
+    ) -> ApiResult<Option<Fruit>> {
+        let fruit = sqlx::query_as!(
+            Fruit,
+            r#"
+            UPDATE FRUIT
+            SET FRUIT_NAME = $2, COLOR_RED = $3, COLOR_GREEN = $4, COLOR_BLUE = $5, FRUIT_WEIGHT = $6,
+                UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $7
+            RETURNING *
+            "#,
+            fruit_id,
+            fruit.fruit_name,
+            fruit.color_red,
+            fruit.color_green,
+            fruit.color_blue,
+            fruit.fruit_weight,
+            row_version
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(fruit);
+    }
+
+    async fn patch_fruit(
+        &self,
+        fruit_id: i64,
+        patch: &FruitPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<Fruit>> {
+        let fruit = sqlx::query_as!(
+            Fruit,
+            r#"
+            UPDATE FRUIT
+            SET FRUIT_NAME = COALESCE($2, FRUIT_NAME),
+                COLOR_RED = COALESCE($3, COLOR_RED),
+                COLOR_GREEN = COALESCE($4, COLOR_GREEN),
+                COLOR_BLUE = COALESCE($5, COLOR_BLUE),
+                FRUIT_WEIGHT = COALESCE($6, FRUIT_WEIGHT),
+                UPDATED_AT = NOW(),
+                ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $7
+            RETURNING *
+            "#,
+            fruit_id,
+            patch.fruit_name,
+            patch.color_red,
+            patch.color_green,
+            patch.color_blue,
+            patch.fruit_weight,
+            row_version
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(fruit);
+    }
+
+    async fn delete_fruit(&self, fruit_id: i64) -> ApiResult<Deletion> {
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        // Ingredient inserts share-lock the fruit they add, so the lock waits
+        // for those in progress and keeps new ones out until the fruit is gone.
+        let fruit = sqlx::query!(
+            "SELECT ID FROM FRUIT WHERE ID = $1 AND DELETED_AT IS NULL FOR UPDATE",
+            fruit_id
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        if fruit.is_none() {
+            return Ok(Deletion::NotFound);
+        }
+
+        let usage = sqlx::query_as!(
+            RowCount,
+            "SELECT COUNT(1) FROM SALAD_INGREDIENTS WHERE ID_FRUIT = $1 AND DELETED_AT IS NULL",
+            fruit_id
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+
+        let usage_count = usage.count.unwrap_or_default();
+        if usage_count > 0 {
+            return Ok(Deletion::InUse(usage_count));
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE FRUIT SET DELETED_AT = NOW(), UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1
+            "#,
+            fruit_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        return Ok(Deletion::Deleted);
+    }
+
+    async fn restore_fruit(&self, fruit_id: i64) -> ApiResult<Restoration<Fruit>> {
+        match self.get_fruit(fruit_id, true).await? {
+            None => return Ok(Restoration::NotFound),
+            Some(fruit) if fruit.deleted_at.is_none() => return Ok(Restoration::Restored(fruit)),
+            Some(_) => {}
+        }
+
+        let fruit = sqlx::query_as!(
+            Fruit,
+            r#"
+            UPDATE FRUIT SET DELETED_AT = NULL, UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1
+            RETURNING *
+            "#,
+            fruit_id
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(Restoration::Restored(fruit));
+    }
+}
+
+#[async_trait]
+impl SaladRepository for PostgresRepository {
+    async fn get_salad(
+        &self,
+        salad_id: i64,
+        include_deleted: bool,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let salad = sqlx::query_as!(
+            FruitSalad,
+            "SELECT * FROM FRUIT_SALAD WHERE ID = $1 AND ($2 OR DELETED_AT IS NULL)",
+            salad_id,
+            include_deleted
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(salad);
+    }
+
+    async fn list_salads(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<FruitSalad>> {
+        let page = self
+            .list_rows("SELECT * FROM FRUIT_SALAD", list_query, page_request)
+            .await?;
+        return Ok(page);
+    }
+
+    async fn count_salads(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let count = self.count_rows("FRUIT_SALAD", list_query).await?;
+        return Ok(count);
+    }
+
+    async fn get_salads(&self, salad_ids: &[i64]) -> ApiResult<Vec<FruitSalad>> {
+        let salads = sqlx::query_as!(
+            FruitSalad,
+            "SELECT * FROM FRUIT_SALAD WHERE ID = ANY($1) AND DELETED_AT IS NULL ORDER BY ID",
+            salad_ids
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(salads);
+    }
+
+    async fn list_salads_of_creators(&self, creator_ids: &[i64]) -> ApiResult<Vec<FruitSalad>> {
+        let salads = sqlx::query_as!(
+            FruitSalad,
+            r#"
+            SELECT * FROM FRUIT_SALAD
+            WHERE ID_CREATOR = ANY($1) AND DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+            creator_ids
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(salads);
+    }
+
+    async fn list_salads_by_creator(
+        &self,
+        creator_id: i64,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<SaladView>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = fetch_cursor_page::<SaladView, _>(
+                    &mut *self.connection().await?,
+                    |query| {
+                        query
+                            .push(
+                                r#"
+                                SELECT FRUIT_SALAD.id, person_name, salad_name FROM FRUIT_SALAD
+                                JOIN PERSON ON ID_CREATOR = PERSON.ID
+                                where FRUIT_SALAD.DELETED_AT IS NULL AND ID_CREATOR = "#,
+                            )
+                            .push_bind(creator_id);
+                    },
+                    cursor,
+                    *size,
+                )
+                .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as!(
+                    SaladView,
+                    r#"
+                    SELECT FRUIT_SALAD.id, person_name, salad_name FROM FRUIT_SALAD
+                    JOIN PERSON ON ID_CREATOR = PERSON.ID
+                    where FRUIT_SALAD.DELETED_AT IS NULL AND ID_CREATOR = $3
+                    LIMIT $1 OFFSET $2
+                    "#,
+                    size,
+                    offset,
+                    creator_id
+                )
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_salads_by_creator(&self, creator_id: i64) -> ApiResult<i64> {
+        let row_count = sqlx::query_as!(
+            RowCount,
+            "SELECT COUNT(1) from FRUIT_SALAD where DELETED_AT IS NULL AND ID_CREATOR = $1",
+            creator_id
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    async fn list_salad_ingredient_views(
+        &self,
+        salad_id: i64,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<SaladIngredientsView>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = fetch_cursor_page::<SaladIngredientsView, _>(
+                    &mut *self.connection().await?,
+                    |query| {
+                        query
+                            .push(
+                                r#"
+                                SELECT SALAD_INGREDIENTS.ID AS id, person_name, salad_name, fruit_name,
+                                quantity_grams
+                                FROM FRUIT_SALAD
+                                JOIN PERSON ON ID_CREATOR = PERSON.ID
+                                JOIN SALAD_INGREDIENTS ON ID_SALAD = FRUIT_SALAD.ID
+                                JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+                                where FRUIT_SALAD.DELETED_AT IS NULL
+                                AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+                                AND FRUIT_SALAD.ID = "#,
+                            )
+                            .push_bind(salad_id);
+                    },
+                    cursor,
+                    *size,
+                )
+                .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as!(
+                    SaladIngredientsView,
+                    r#"
+                    SELECT SALAD_INGREDIENTS.ID AS id, person_name, salad_name, fruit_name, quantity_grams
+                    FROM FRUIT_SALAD
+                    JOIN PERSON ON ID_CREATOR = PERSON.ID
+                    JOIN SALAD_INGREDIENTS ON ID_SALAD = FRUIT_SALAD.ID
+                    JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+                    where FRUIT_SALAD.DELETED_AT IS NULL
+                    AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+                    AND FRUIT_SALAD.ID = $3
+                    LIMIT $1 OFFSET $2
+                    "#,
+                    size,
+                    offset,
+                    salad_id
+                )
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_salad_ingredient_views(&self, salad_id: i64) -> ApiResult<i64> {
+        let row_count = sqlx::query_as!(
+            RowCount,
+            r#"
+            SELECT COUNT(1) from FRUIT_SALAD
+            JOIN PERSON ON ID_CREATOR = PERSON.ID
+            JOIN SALAD_INGREDIENTS ON ID_SALAD = FRUIT_SALAD.ID
+            JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+            where FRUIT_SALAD.DELETED_AT IS NULL
+            AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+            AND FRUIT_SALAD.ID = $1
+            "#,
+            salad_id
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    async fn salad_components(&self, salad_id: i64) -> ApiResult<Vec<SaladComponent>> {
+        let components = sqlx::query_as!(
+            SaladComponent,
+            r#"
+            SELECT FRUIT.ID AS id_fruit, FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, QUANTITY_GRAMS
+            FROM SALAD_INGREDIENTS
+            JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+            WHERE ID_SALAD = $1 AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+            ORDER BY QUANTITY_GRAMS DESC, FRUIT.ID
+            "#,
+            salad_id
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(components);
+    }
+
+    fn export_salads(&self) -> RowStream<FruitSalad> {
+        return self.export_rows(
+            r#"
+            SELECT ID, ID_CREATOR, SALAD_NAME, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION
+            FROM FRUIT_SALAD
+            WHERE DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+        );
+    }
+
+    async fn insert_salad(
+        &self,
+        creator_id: i64,
+        new_salad: &NewFruitSalad,
+    ) -> ApiResult<FullFruitSalad> {
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let creator = sqlx::query!(
+            "SELECT ID FROM PERSON WHERE ID = $1 AND DELETED_AT IS NULL FOR SHARE",
+            creator_id
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        if creator.is_none() {
+            return Err(ApiError::ForeignKeyViolation(format!(
+                "Person {} not found",
+                creator_id
+            )));
+        }
+
+        let salad = sqlx::query_as!(
+            FruitSalad,
+            r#"
+            INSERT INTO FRUIT_SALAD ( ID_CREATOR, SALAD_NAME )
+            VALUES ( $1, $2 )
+            RETURNING *
+            "#,
+            creator_id,
+            new_salad.salad_name
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+
+        let mut ingredients = Vec::new();
+        if !new_salad.ingredients.is_empty() {
+            let existing_fruits = sqlx::query!(
+                "SELECT ID FROM FRUIT WHERE ID = ANY($1) AND DELETED_AT IS NULL FOR SHARE",
+                &new_salad.ingredients
+            )
+            .fetch_all(&mut transaction)
+            .await?;
+
+            let mut missing_fruits: Vec<i64> = new_salad
+                .ingredients
+                .iter()
+                .copied()
+                .filter(|fruit_id| !existing_fruits.iter().any(|fruit| fruit.id == *fruit_id))
+                .collect();
+            if !missing_fruits.is_empty() {
+                missing_fruits.sort_unstable();
+                missing_fruits.dedup();
+                return Err(ApiError::ForeignKeyViolation(format!(
+                    "Fruit(s) {:?} not found",
+                    missing_fruits
+                )));
+            }
+
+            ingredients = sqlx::query_as!(
+                SaladIngredient,
+                r#"
+                INSERT INTO SALAD_INGREDIENTS ( ID_SALAD, ID_FRUIT, QUANTITY_GRAMS )
+                SELECT $1, FRUIT.ID, FRUIT.FRUIT_WEIGHT
+                FROM UNNEST($2::BIGINT[]) AS REQUESTED(ID_FRUIT)
+                JOIN FRUIT ON FRUIT.ID = REQUESTED.ID_FRUIT
+                RETURNING *
+                "#,
+                salad.id,
+                &new_salad.ingredients
+            )
+            .fetch_all(&mut transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        return Ok(FullFruitSalad { salad, ingredients });
+    }
+
+    async fn update_salad(
+        &self,
+        salad_id: i64,
+        salad: &NewFruitSalad,
+        row_version: i64,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let salad = sqlx::query_as!(
+            FruitSalad,
+            r#"
+            UPDATE FRUIT_SALAD
+            SET SALAD_NAME = $2, UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $3
+            RETURNING *
+            "#,
+            salad_id,
+            salad.salad_name,
+            row_version
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(salad);
+    }
+
+    async fn patch_salad(
+        &self,
+        salad_id: i64,
+        patch: &FruitSaladPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let salad = sqlx::query_as!(
+            FruitSalad,
+            r#"
+            UPDATE FRUIT_SALAD
+            SET SALAD_NAME = COALESCE($2, SALAD_NAME),
+                UPDATED_AT = NOW(),
+                ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $3
+            RETURNING *
+            "#,
+            salad_id,
+            patch.salad_name,
+            row_version
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(salad);
+    }
+
+    async fn delete_salad(&self, salad_id: i64) -> ApiResult<Option<Vec<i64>>> {
+        // `NOW()` is the start of the transaction, so the salad and its
+        // ingredients get the same `DELETED_AT`.
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+
+        let delete_result = sqlx::query!(
+            r#"
+            UPDATE FRUIT_SALAD SET DELETED_AT = NOW(), UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            "#,
+            salad_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        if delete_result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let ingredients = sqlx::query!(
+            r#"
+            UPDATE SALAD_INGREDIENTS SET DELETED_AT = NOW(), UPDATED_AT = NOW()
+            WHERE ID_SALAD = $1 AND DELETED_AT IS NULL
+            RETURNING ID
+            "#,
+            salad_id
+        )
+        .fetch_all(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        let mut ingredient_ids: Vec<i64> = ingredients.into_iter().map(|row| row.id).collect();
+        ingredient_ids.sort_unstable();
+        return Ok(Some(ingredient_ids));
+    }
+
+    async fn restore_salad(
+        &self,
+        salad_id: i64,
+    ) -> ApiResult<Restoration<(FruitSalad, Vec<SaladIngredient>)>> {
+        let salad = match self.get_salad(salad_id, true).await? {
+            None => return Ok(Restoration::NotFound),
+            Some(salad) => salad,
+        };
+        let Some(deleted_at) = salad.deleted_at else {
+            return Ok(Restoration::Restored((salad, Vec::new())));
+        };
+
+        // The creator and fruits are share-locked, so they cannot be deleted
+        // while the salad comes back.
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let creator = sqlx::query!(
+            "SELECT ID FROM PERSON WHERE ID = $1 AND DELETED_AT IS NULL FOR SHARE",
+            salad.id_creator
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        if creator.is_none() {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Person {}",
+                salad.id_creator
+            )));
+        }
+
+        let fruits = sqlx::query!(
+            r#"
+            SELECT FRUIT.ID, FRUIT.DELETED_AT FROM SALAD_INGREDIENTS
+            JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+            WHERE ID_SALAD = $1 AND SALAD_INGREDIENTS.DELETED_AT = $2
+            ORDER BY FRUIT.ID
+            FOR SHARE OF FRUIT
+            "#,
+            salad_id,
+            deleted_at
+        )
+        .fetch_all(&mut transaction)
+        .await?;
+        if let Some(fruit) = fruits.iter().find(|fruit| fruit.deleted_at.is_some()) {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Fruit {}",
+                fruit.id
+            )));
+        }
+
+        let mut ingredients = sqlx::query_as!(
+            SaladIngredient,
+            r#"
+            UPDATE SALAD_INGREDIENTS SET DELETED_AT = NULL, UPDATED_AT = NOW()
+            WHERE ID_SALAD = $1 AND DELETED_AT = $2
+            RETURNING *
+            "#,
+            salad_id,
+            deleted_at
+        )
+        .fetch_all(&mut transaction)
+        .await?;
+
+        let salad = sqlx::query_as!(
+            FruitSalad,
+            r#"
+            UPDATE FRUIT_SALAD SET DELETED_AT = NULL, UPDATED_AT = NOW(), ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1
+            RETURNING *
+            "#,
+            salad_id
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        ingredients.sort_by_key(|ingredient| ingredient.id);
+        return Ok(Restoration::Restored((salad, ingredients)));
+    }
+}
+
+#[async_trait]
+impl SaladIngredientRepository for PostgresRepository {
+    async fn get_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        include_deleted: bool,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let ingredient = sqlx::query_as!(
+            SaladIngredient,
+            "SELECT * FROM SALAD_INGREDIENTS WHERE ID = $1 AND ($2 OR DELETED_AT IS NULL)",
+            ingredient_id,
+            include_deleted
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient);
+    }
+
+    async fn get_salad_ingredient_owner(&self, ingredient_id: i64) -> ApiResult<Option<i64>> {
+        let ingredient = sqlx::query!(
+            r#"
+            SELECT ID_CREATOR FROM SALAD_INGREDIENTS
+            JOIN FRUIT_SALAD ON ID_SALAD = FRUIT_SALAD.ID
+            WHERE SALAD_INGREDIENTS.ID = $1 AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+            "#,
+            ingredient_id
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient.map(|ingredient| ingredient.id_creator));
+    }
+
+    async fn list_salad_ingredients(
+        &self,
+        page_request: &PageRequest,
+        include_deleted: bool,
+    ) -> ApiResult<Page<SaladIngredient>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = fetch_cursor_page::<SaladIngredient, _>(
+                    &mut *self.connection().await?,
+                    |query| {
+                        query.push("SELECT * FROM SALAD_INGREDIENTS");
+                        if !include_deleted {
+                            query.push(" WHERE DELETED_AT IS NULL");
+                        }
+                    },
+                    cursor,
+                    *size,
+                )
+                .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as!(
+                    SaladIngredient,
+                    r#"
+                    SELECT * FROM SALAD_INGREDIENTS
+                    WHERE $3 OR DELETED_AT IS NULL
+                    LIMIT $1 OFFSET $2
+                    "#,
+                    size,
+                    offset,
+                    include_deleted,
+                )
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_salad_ingredients(&self, include_deleted: bool) -> ApiResult<i64> {
+        let row_count = sqlx::query_as!(
+            RowCount,
+            "SELECT COUNT(1) from SALAD_INGREDIENTS WHERE $1 OR DELETED_AT IS NULL",
+            include_deleted
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    async fn get_salad_ingredients(
+        &self,
+        ingredient_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let ingredients = sqlx::query_as!(
+            SaladIngredient,
+            "SELECT * FROM SALAD_INGREDIENTS WHERE ID = ANY($1) AND DELETED_AT IS NULL ORDER BY ID",
+            ingredient_ids
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredients);
+    }
+
+    async fn list_ingredients_of_salads(
+        &self,
+        salad_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let ingredients = sqlx::query_as!(
+            SaladIngredient,
+            r#"
+            SELECT * FROM SALAD_INGREDIENTS
+            WHERE ID_SALAD = ANY($1) AND DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+            salad_ids
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredients);
+    }
+
+    async fn list_ingredients_of_fruits(
+        &self,
+        fruit_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let ingredients = sqlx::query_as!(
+            SaladIngredient,
+            r#"
+            SELECT * FROM SALAD_INGREDIENTS
+            WHERE ID_FRUIT = ANY($1) AND DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+            fruit_ids
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredients);
+    }
+
+    async fn insert_salad_ingredient(
+        &self,
+        new_ingredient: &NewSaladIngredient,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let ingredient = sqlx::query_as!(
+            SaladIngredient,
+            r#"
+            INSERT INTO SALAD_INGREDIENTS ( ID_SALAD, ID_FRUIT, QUANTITY_GRAMS )
+            SELECT $1, FRUIT.ID, COALESCE($3, FRUIT.FRUIT_WEIGHT) FROM FRUIT
+            WHERE FRUIT.ID = $2 AND FRUIT.DELETED_AT IS NULL
+            FOR SHARE
+            RETURNING *
+            "#,
+            new_ingredient.id_salad,
+            new_ingredient.id_fruit,
+            new_ingredient.quantity_grams,
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient);
+    }
+
+    async fn update_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        ingredient: &NewSaladIngredient,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let ingredient = sqlx::query_as!(
+            SaladIngredient,
+            r#"
+            UPDATE SALAD_INGREDIENTS
+            SET ID_SALAD = $2, ID_FRUIT = $3, QUANTITY_GRAMS = COALESCE($4, QUANTITY_GRAMS),
+                UPDATED_AT = NOW()
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            RETURNING *
+            "#,
+            ingredient_id,
+            ingredient.id_salad,
+            ingredient.id_fruit,
+            ingredient.quantity_grams
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient);
+    }
+
+    async fn patch_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        patch: &SaladIngredientPatch,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let ingredient = sqlx::query_as!(
+            SaladIngredient,
+            r#"
+            UPDATE SALAD_INGREDIENTS
+            SET ID_SALAD = COALESCE($2, ID_SALAD),
+                ID_FRUIT = COALESCE($3, ID_FRUIT),
+                QUANTITY_GRAMS = COALESCE($4, QUANTITY_GRAMS),
+                UPDATED_AT = NOW()
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            RETURNING *
+            "#,
+            ingredient_id,
+            patch.id_salad,
+            patch.id_fruit,
+            patch.quantity_grams
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient);
+    }
+
+    async fn delete_salad_ingredient(&self, ingredient_id: i64) -> ApiResult<bool> {
+        let delete_result = sqlx::query!(
+            r#"
+            UPDATE SALAD_INGREDIENTS SET DELETED_AT = NOW(), UPDATED_AT = NOW()
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            "#,
+            ingredient_id
+        )
+        .execute(&mut self.connection().await?)
+        .await?;
+        return Ok(delete_result.rows_affected() > 0);
+    }
+
+    async fn restore_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+    ) -> ApiResult<Restoration<SaladIngredient>> {
+        let ingredient = match self.get_salad_ingredient(ingredient_id, true).await? {
+            None => return Ok(Restoration::NotFound),
+            Some(ingredient) if ingredient.deleted_at.is_none() => {
+                return Ok(Restoration::Restored(ingredient))
+            }
+            Some(ingredient) => ingredient,
+        };
+
+        // Share-locked like in `restore_salad`.
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let salad = sqlx::query!(
+            "SELECT ID FROM FRUIT_SALAD WHERE ID = $1 AND DELETED_AT IS NULL FOR SHARE",
+            ingredient.id_salad
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        if salad.is_none() {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Salad {}",
+                ingredient.id_salad
+            )));
+        }
+        let fruit = sqlx::query!(
+            "SELECT ID FROM FRUIT WHERE ID = $1 AND DELETED_AT IS NULL FOR SHARE",
+            ingredient.id_fruit
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        if fruit.is_none() {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Fruit {}",
+                ingredient.id_fruit
+            )));
+        }
+
+        let ingredient = sqlx::query_as!(
+            SaladIngredient,
+            r#"
+            UPDATE SALAD_INGREDIENTS SET DELETED_AT = NULL, UPDATED_AT = NOW()
+            WHERE ID = $1
+            RETURNING *
+            "#,
+            ingredient_id
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        return Ok(Restoration::Restored(ingredient));
+    }
+}
+
+#[async_trait]
+impl Repository for PostgresRepository {
+    async fn ping(&self) -> Result<(), String> {
+        let ping_result = match acquire(&self.database_connection_pool).await {
+            Ok(mut connection) => connection.ping().await,
+            Err(error) => Err(error),
+        };
+        return ping_result.map_err(|error| error.to_string());
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        return Some(PoolStatus {
+            connections: self.database_connection_pool.size(),
+            idle_connections: self.database_connection_pool.num_idle(),
+            max_connections: self.max_connections,
+        });
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for PostgresRepository {
+    async fn get_webhook(&self, webhook_id: i64) -> ApiResult<Option<Webhook>> {
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT ID, TARGET_URL, EVENT_TYPES AS "event_types: Json<Vec<String>>", ACTIVE,
+                   CREATED_AT, UPDATED_AT
+            FROM WEBHOOK
+            WHERE ID = $1
+            "#,
+            webhook_id
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(webhook);
+    }
+
+    async fn list_webhooks(&self, page_request: &PageRequest) -> ApiResult<Page<Webhook>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = fetch_cursor_page::<Webhook, _>(
+                    &mut *self.connection().await?,
+                    |query| {
+                        query.push(
+                            r#"
+                            SELECT ID, TARGET_URL, EVENT_TYPES, ACTIVE, CREATED_AT, UPDATED_AT
+                            FROM WEBHOOK
+                            "#,
+                        );
+                    },
+                    cursor,
+                    *size,
+                )
+                .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as!(
+                    Webhook,
+                    r#"
+                    SELECT ID, TARGET_URL, EVENT_TYPES AS "event_types: Json<Vec<String>>", ACTIVE,
+                           CREATED_AT, UPDATED_AT
+                    FROM WEBHOOK
+                    ORDER BY ID
+                    LIMIT $1 OFFSET $2
+                    "#,
+                    size,
+                    offset,
+                )
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_webhooks(&self) -> ApiResult<i64> {
+        let row_count = sqlx::query_as!(RowCount, "SELECT COUNT(1) FROM WEBHOOK")
+            .fetch_one(&mut self.connection().await?)
+            .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    async fn insert_webhook(&self, new_webhook: &NewWebhook, secret: &str) -> ApiResult<Webhook> {
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            INSERT INTO WEBHOOK ( TARGET_URL, EVENT_TYPES, SECRET, ACTIVE )
+            VALUES ( $1, $2, $3, $4 )
+            RETURNING ID, TARGET_URL, EVENT_TYPES AS "event_types: Json<Vec<String>>", ACTIVE,
+                      CREATED_AT, UPDATED_AT
+            "#,
+            new_webhook.target_url,
+            serde_json::json!(new_webhook.event_types),
+            secret,
+            new_webhook.active.unwrap_or(true),
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(webhook);
+    }
+
+    async fn update_webhook(
+        &self,
+        webhook_id: i64,
+        webhook: &NewWebhook,
+    ) -> ApiResult<Option<Webhook>> {
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            UPDATE WEBHOOK
+            SET TARGET_URL = $2, EVENT_TYPES = $3, ACTIVE = $4, UPDATED_AT = NOW()
+            WHERE ID = $1
+            RETURNING ID, TARGET_URL, EVENT_TYPES AS "event_types: Json<Vec<String>>", ACTIVE,
+                      CREATED_AT, UPDATED_AT
+            "#,
+            webhook_id,
+            webhook.target_url,
+            serde_json::json!(webhook.event_types),
+            webhook.active.unwrap_or(true),
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(webhook);
+    }
+
+    async fn delete_webhook(&self, webhook_id: i64) -> ApiResult<bool> {
+        let deletion = sqlx::query!("DELETE FROM WEBHOOK WHERE ID = $1", webhook_id)
+            .execute(&mut self.connection().await?)
+            .await?;
+        return Ok(deletion.rows_affected() > 0);
+    }
+
+    async fn get_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+    ) -> ApiResult<Option<WebhookDelivery>> {
+        let delivery = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+            SELECT ID, ID_WEBHOOK, EVENT_TYPE, PAYLOAD AS "payload: Json<Value>", STATUS, ATTEMPTS,
+                   NEXT_ATTEMPT_AT, LAST_ATTEMPT_AT, LAST_STATUS_CODE, LAST_ERROR, CREATED_AT,
+                   DELIVERED_AT
+            FROM WEBHOOK_DELIVERY
+            WHERE ID_WEBHOOK = $1 AND ID = $2
+            "#,
+            webhook_id,
+            delivery_id
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(delivery);
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<&str>,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<WebhookDelivery>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = fetch_cursor_page::<WebhookDelivery, _>(
+                    &mut *self.connection().await?,
+                    |query| {
+                        query
+                            .push("SELECT * FROM WEBHOOK_DELIVERY WHERE ID_WEBHOOK = ")
+                            .push_bind(webhook_id);
+                        if let Some(status) = status {
+                            query.push(" AND STATUS = ").push_bind(status.to_string());
+                        }
+                    },
+                    cursor,
+                    *size,
+                )
+                .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as!(
+                    WebhookDelivery,
+                    r#"
+                    SELECT ID, ID_WEBHOOK, EVENT_TYPE, PAYLOAD AS "payload: Json<Value>", STATUS,
+                           ATTEMPTS, NEXT_ATTEMPT_AT, LAST_ATTEMPT_AT, LAST_STATUS_CODE,
+                           LAST_ERROR, CREATED_AT, DELIVERED_AT
+                    FROM WEBHOOK_DELIVERY
+                    WHERE ID_WEBHOOK = $1 AND ($2::VARCHAR IS NULL OR STATUS = $2)
+                    ORDER BY ID
+                    LIMIT $3 OFFSET $4
+                    "#,
+                    webhook_id,
+                    status,
+                    size,
+                    offset,
+                )
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<&str>,
+    ) -> ApiResult<i64> {
+        let row_count = sqlx::query_as!(
+            RowCount,
+            r#"
+            SELECT COUNT(1) FROM WEBHOOK_DELIVERY
+            WHERE ID_WEBHOOK = $1 AND ($2::VARCHAR IS NULL OR STATUS = $2)
+            "#,
+            webhook_id,
+            status
+        )
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    async fn enqueue_webhook_deliveries(
+        &self,
+        event_type: &str,
+        payload: &Value,
+    ) -> ApiResult<u64> {
+        let insertion = sqlx::query!(
+            r#"
+            INSERT INTO WEBHOOK_DELIVERY ( ID_WEBHOOK, EVENT_TYPE, PAYLOAD )
+            SELECT ID, $1::VARCHAR, $2 FROM WEBHOOK
+            WHERE ACTIVE AND EVENT_TYPES ? $1::VARCHAR
+            "#,
+            event_type,
+            payload
+        )
+        .execute(&mut self.connection().await?)
+        .await?;
+        return Ok(insertion.rows_affected());
+    }
+
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease_until: OffsetDateTime,
+    ) -> ApiResult<Vec<DueDelivery>> {
+        // `SKIP LOCKED` leaves the rows another worker is claiming to it.
+        let due = sqlx::query_as!(
+            DueDelivery,
+            r#"
+            WITH DUE AS (
+                SELECT WEBHOOK_DELIVERY.ID FROM WEBHOOK_DELIVERY
+                JOIN WEBHOOK ON WEBHOOK.ID = ID_WEBHOOK
+                WHERE STATUS = 'pending' AND NEXT_ATTEMPT_AT <= NOW() AND ACTIVE
+                ORDER BY NEXT_ATTEMPT_AT
+                LIMIT $1
+                FOR UPDATE OF WEBHOOK_DELIVERY SKIP LOCKED
+            ), CLAIMED AS (
+                UPDATE WEBHOOK_DELIVERY SET NEXT_ATTEMPT_AT = $2
+                FROM DUE
+                WHERE WEBHOOK_DELIVERY.ID = DUE.ID
+                RETURNING WEBHOOK_DELIVERY.ID, ID_WEBHOOK, EVENT_TYPE, PAYLOAD, ATTEMPTS
+            )
+            SELECT CLAIMED.ID AS "id!", EVENT_TYPE AS "event_type!",
+                   PAYLOAD AS "payload!: Json<Value>", ATTEMPTS AS "attempts!",
+                   TARGET_URL AS "target_url!", SECRET AS "secret!"
+            FROM CLAIMED
+            JOIN WEBHOOK ON WEBHOOK.ID = CLAIMED.ID_WEBHOOK
+            ORDER BY CLAIMED.ID
+            "#,
+            limit,
+            lease_until
+        )
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(due);
+    }
+
+    async fn finish_webhook_attempt(
+        &self,
+        delivery_id: i64,
+        attempt: &DeliveryAttempt,
+    ) -> ApiResult<()> {
+        sqlx::query!(
+            r#"
+            UPDATE WEBHOOK_DELIVERY
+            SET STATUS = $2::VARCHAR, ATTEMPTS = ATTEMPTS + 1, LAST_ATTEMPT_AT = NOW(),
+                LAST_STATUS_CODE = $3, LAST_ERROR = $4, NEXT_ATTEMPT_AT = $5,
+                DELIVERED_AT = CASE WHEN $2::VARCHAR = 'delivered' THEN NOW() END
+            WHERE ID = $1
+            "#,
+            delivery_id,
+            attempt.status,
+            attempt.status_code,
+            attempt.error,
+            attempt.next_attempt_at
+        )
+        .execute(&mut self.connection().await?)
+        .await?;
+        return Ok(());
+    }
+
+    async fn retry_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+    ) -> ApiResult<Option<WebhookDelivery>> {
+        let delivery = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+            UPDATE WEBHOOK_DELIVERY
+            SET STATUS = 'pending', ATTEMPTS = 0, NEXT_ATTEMPT_AT = NOW()
+            WHERE ID_WEBHOOK = $1 AND ID = $2 AND STATUS = 'dead'
+            RETURNING ID, ID_WEBHOOK, EVENT_TYPE, PAYLOAD AS "payload: Json<Value>", STATUS,
+                      ATTEMPTS, NEXT_ATTEMPT_AT, LAST_ATTEMPT_AT, LAST_STATUS_CODE, LAST_ERROR,
+                      CREATED_AT, DELIVERED_AT
+            "#,
+            webhook_id,
+            delivery_id
+        )
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(delivery);
+    }
+}
@@ -0,0 +1,140 @@
+//! Conditional requests on fruits, people and salads. The `ETag` of a row is
+//! its `row_version` in quotes, so it changes whenever the row does.
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::Value;
+
+use super::Errors::{ApiError, ApiResult};
+
+/// The strong entity tag of a row at `row_version`.
+pub fn entity_tag(row_version: i64) -> HeaderValue {
+    return HeaderValue::from_str(&format!("\"{}\"", row_version))
+        .expect("a quoted number is a valid header value");
+}
+
+/// `body` with the `ETag` of the row it describes.
+pub fn tagged_response(status: StatusCode, body: Value, row_version: i64) -> Response {
+    return with_entity_tag((status, Json(body)).into_response(), row_version);
+}
+
+/// Adds the `ETag` of the row at `row_version` to an encoded `response`.
+pub fn with_entity_tag(mut response: Response, row_version: i64) -> Response {
+    response
+        .headers_mut()
+        .insert(header::ETAG, entity_tag(row_version));
+    return response;
+}
+
+/// Entity tags of an `If-Match` or `If-None-Match` header.
+enum EntityTags {
+    Any,
+    /// Each tag without its quotes, and whether it was weak.
+    List(Vec<(String, bool)>),
+}
+
+impl EntityTags {
+    /// `None` when the header is missing. Unreadable values match nothing.
+    fn parse(headers: &HeaderMap, name: header::HeaderName) -> Option<EntityTags> {
+        let mut tags = Vec::new();
+        for value in headers.get_all(&name) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for tag in value.split(',').map(str::trim) {
+                if tag == "*" {
+                    return Some(EntityTags::Any);
+                }
+                let (weak, tag) = match tag.strip_prefix("W/") {
+                    Some(tag) => (true, tag),
+                    None => (false, tag),
+                };
+                if let Some(tag) = tag.strip_prefix('"').and_then(|tag| tag.strip_suffix('"')) {
+                    tags.push((String::from(tag), weak));
+                }
+            }
+        }
+        if tags.is_empty() && !headers.contains_key(&name) {
+            return None;
+        }
+        return Some(EntityTags::List(tags));
+    }
+
+    /// Weak comparison ignores the `W/` prefix, strong comparison never
+    /// matches a weak tag.
+    fn matches(&self, row_version: i64, weak_comparison: bool) -> bool {
+        match self {
+            EntityTags::Any => return true,
+            EntityTags::List(tags) => {
+                let version = row_version.to_string();
+                return tags
+                    .iter()
+                    .any(|(tag, weak)| *tag == version && (weak_comparison || !weak));
+            }
+        }
+    }
+}
+
+/// The `If-Match` header of an update. Updates without one are refused with
+/// 428, and updates of a row that has changed since with 412.
+pub struct IfMatch(Option<EntityTags>);
+
+impl IfMatch {
+    /// Checks `If-Match` against the current version of `resource` `id`.
+    pub fn check(&self, resource: &str, id: i64, row_version: i64) -> ApiResult<()> {
+        let Some(tags) = &self.0 else {
+            return Err(ApiError::PreconditionRequired(format!(
+                "Updating {} {} requires an If-Match header with its ETag",
+                resource, id
+            )));
+        };
+        if !tags.matches(row_version, false) {
+            return Err(ApiError::stale(resource, id));
+        }
+        return Ok(());
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let tags = EntityTags::parse(&parts.headers, header::IF_MATCH);
+        return Ok(IfMatch(tags));
+    }
+}
+
+/// The `If-None-Match` header of a read, answered with 304 when the client
+/// already has the current version.
+pub struct IfNoneMatch(Option<EntityTags>);
+
+impl IfNoneMatch {
+    /// The 304 to send instead of a row at `row_version`, if any.
+    pub fn not_modified(&self, row_version: i64) -> Option<Response> {
+        let tags = self.0.as_ref()?;
+        if !tags.matches(row_version, true) {
+            return None;
+        }
+        let response = (
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, entity_tag(row_version))],
+        );
+        return Some(response.into_response());
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfNoneMatch {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let tags = EntityTags::parse(&parts.headers, header::IF_NONE_MATCH);
+        return Ok(IfNoneMatch(tags));
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{Body, BoxBody, Bytes, HttpBody},
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+    Router,
+};
+use http_body::SizeHint;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::Auth::{bearer_token, AuthKeys};
+use super::Errors::{ApiError, ApiResult};
+
+/// Buckets that have refilled completely are dropped once a limiter tracks
+/// more clients than this.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A token bucket holding `requests` tokens that refills completely over
+/// `period`, written as e.g. `60/min`, or `off`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RateLimit {
+    Off,
+    PerPeriod { requests: u32, period: Duration },
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(limit: &str) -> Result<RateLimit, String> {
+        let error = || String::from("expected `off` or `<requests>/<s|min|h>`, e.g. `60/min`");
+        if limit.eq_ignore_ascii_case("off") {
+            return Ok(RateLimit::Off);
+        }
+        let (requests, period) = limit.split_once('/').ok_or_else(error)?;
+        let requests: u32 = requests.trim().parse().map_err(|_| error())?;
+        let period = match period.trim() {
+            "s" => Duration::from_secs(1),
+            "min" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            _ => return Err(error()),
+        };
+        if requests == 0 {
+            return Err(error());
+        }
+        return Ok(RateLimit::PerPeriod { requests, period });
+    }
+}
+
+/// Who a bucket belongs to. Requests with a valid bearer token share the
+/// bucket of their person whatever address they come from.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum ClientKey {
+    Person(i64),
+    Address(IpAddr),
+    Unknown,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+#[derive(Clone)]
+struct RateLimiter {
+    capacity: f64,
+    /// Tokens added per second.
+    refill_rate: f64,
+    buckets: Arc<Mutex<HashMap<ClientKey, Bucket>>>,
+    /// Verifies bearer tokens, or `None` to key every request by address.
+    auth_keys: Option<AuthKeys>,
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket of `client`, or returns how long until
+    /// the next one is available.
+    fn acquire(&self, client: ClientKey) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+                return bucket.tokens + elapsed * self.refill_rate < self.capacity;
+            });
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.capacity,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_rate).min(self.capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_rate,
+            ));
+        }
+        bucket.tokens -= 1.0;
+        return Ok(());
+    }
+
+    fn client_key(&self, request: &Request<Body>) -> ClientKey {
+        let claims = self.auth_keys.as_ref().and_then(|auth_keys| {
+            return bearer_token(request.headers()).and_then(|token| auth_keys.verify(token));
+        });
+        if let Some(claims) = claims {
+            return ClientKey::Person(claims.person_id);
+        }
+        return match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(address)) => ClientKey::Address(address.ip()),
+            None => ClientKey::Unknown,
+        };
+    }
+}
+
+/// Applies `limit` to every route of `router`. Each router gets buckets of its
+/// own, so a client throttled on `/salad` can still use `/fruit`.
+pub fn limit_rate<S: Clone + Send + Sync + 'static>(
+    router: Router<S>,
+    limit: RateLimit,
+    auth_keys: AuthKeys,
+) -> Router<S> {
+    return layer_rate_limiter(router, limit, Some(auth_keys));
+}
+
+/// Applies `limit` to every route of `router` per client address, whether
+/// or not the request carries a token. For routes that hand out tokens.
+pub fn limit_rate_by_address<S: Clone + Send + Sync + 'static>(
+    router: Router<S>,
+    limit: RateLimit,
+) -> Router<S> {
+    return layer_rate_limiter(router, limit, None);
+}
+
+fn layer_rate_limiter<S: Clone + Send + Sync + 'static>(
+    router: Router<S>,
+    limit: RateLimit,
+    auth_keys: Option<AuthKeys>,
+) -> Router<S> {
+    let RateLimit::PerPeriod { requests, period } = limit else {
+        return router;
+    };
+    let rate_limiter = RateLimiter {
+        capacity: f64::from(requests),
+        refill_rate: f64::from(requests) / period.as_secs_f64(),
+        buckets: Arc::new(Mutex::new(HashMap::new())),
+        auth_keys,
+    };
+    return router.layer(axum::middleware::from_fn_with_state(
+        rate_limiter,
+        check_rate_limit,
+    ));
+}
+
+async fn check_rate_limit(
+    State(rate_limiter): State<RateLimiter>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> ApiResult<Response> {
+    let client = rate_limiter.client_key(&request);
+    rate_limiter
+        .acquire(client)
+        .map_err(ApiError::TooManyRequests)?;
+    return Ok(next.run(request).await);
+}
+
+/// Rejects requests with 503 while `max_in_flight` others are being handled,
+/// instead of queueing them. `0` disables the cap.
+pub fn limit_in_flight(app: Router, max_in_flight: usize) -> Router {
+    if max_in_flight == 0 {
+        return app;
+    }
+    let permits = Arc::new(Semaphore::new(max_in_flight));
+    return app.layer(axum::middleware::from_fn_with_state(
+        permits,
+        check_in_flight,
+    ));
+}
+
+async fn check_in_flight(
+    State(permits): State<Arc<Semaphore>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> ApiResult<Response> {
+    let Ok(permit) = permits.try_acquire_owned() else {
+        return Err(ApiError::Overloaded);
+    };
+    let response = next.run(request).await;
+    return Ok(response.map(|body| {
+        return axum::body::boxed(PermitBody {
+            body,
+            _permit: permit,
+        });
+    }));
+}
+
+/// A response body that holds its in-flight permit until the body has been
+/// sent or dropped, not just until the handler returned the head.
+struct PermitBody {
+    body: BoxBody,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl HttpBody for PermitBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        return Pin::new(&mut self.get_mut().body).poll_data(context);
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        return Pin::new(&mut self.get_mut().body).poll_trailers(context);
+    }
+
+    fn is_end_stream(&self) -> bool {
+        return self.body.is_end_stream();
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        return self.body.size_hint();
+    }
+}
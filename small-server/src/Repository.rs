@@ -0,0 +1,358 @@
+use axum::{async_trait, extract::FromRef};
+use futures::stream::BoxStream;
+use serde_json::Value;
+use sqlx::types::time::OffsetDateTime;
+
+use super::AppState::AppState;
+use super::Authorization::Role;
+use super::Errors::{ApiError, ApiResult};
+use super::Filter::ListQuery;
+use super::Fruit::{Fruit, FruitPatch, NewFruit};
+use super::Pagination::{Page, PageRequest};
+use super::Person::{NewPerson, Person, PersonPatch};
+use super::Salad::{
+    FruitSalad, FruitSaladPatch, FullFruitSalad, NewFruitSalad, SaladComponent,
+    SaladIngredientsView, SaladView,
+};
+use super::SaladIngredient::{NewSaladIngredient, SaladIngredient, SaladIngredientPatch};
+use super::Webhook::{DeliveryAttempt, DueDelivery, NewWebhook, Webhook, WebhookDelivery};
+
+/// Rows of an export in id order, produced as they are read.
+pub type RowStream<T> = BoxStream<'static, Result<T, sqlx::Error>>;
+
+/// Outcome of deleting a row that other rows may still reference. Deletes
+/// only set `DELETED_AT`, and reads skip such rows unless asked for them.
+pub enum Deletion {
+    Deleted,
+    NotFound,
+    /// Nothing was deleted, this many rows still reference it.
+    InUse(i64),
+}
+
+/// Outcome of restoring a soft-deleted row. Restoring a row that was not
+/// deleted returns it unchanged.
+pub enum Restoration<T> {
+    Restored(T),
+    NotFound,
+    /// Nothing was restored because the row depends on another deleted row,
+    /// described here.
+    DependencyDeleted(String),
+}
+
+impl<T> Restoration<T> {
+    /// The restored row, or the 404 or 409 for `resource` `id`.
+    pub fn into_result(self, resource: &str, id: i64) -> ApiResult<T> {
+        match self {
+            Restoration::Restored(row) => return Ok(row),
+            Restoration::NotFound => return Err(ApiError::not_found(resource, id)),
+            Restoration::DependencyDeleted(dependency) => {
+                return Err(ApiError::Conflict(format!(
+                    "{} {} cannot be restored while {} is deleted",
+                    resource, id, dependency
+                )))
+            }
+        }
+    }
+}
+
+/// A person who can log in, as found by their email.
+pub struct Account {
+    pub person: Person,
+    pub role: Role,
+    pub password_hash: String,
+}
+
+/// Connections of a backend with a connection pool, for `/metrics`.
+pub struct PoolStatus {
+    pub connections: u32,
+    pub idle_connections: usize,
+    pub max_connections: u32,
+}
+
+/// People, their credentials and their login sessions.
+#[async_trait]
+pub trait PersonRepository: Send + Sync {
+    async fn get_person(&self, person_id: i64, include_deleted: bool) -> ApiResult<Option<Person>>;
+    async fn list_people(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<Person>>;
+    async fn count_people(&self, list_query: &ListQuery) -> ApiResult<i64>;
+    /// The live people among `person_ids` in id order, for batched loads.
+    async fn get_people(&self, person_ids: &[i64]) -> ApiResult<Vec<Person>>;
+    fn export_people(&self) -> RowStream<Person>;
+    /// Inserts every person or, if any insert fails, none of them. Returns
+    /// the inserted people in id order.
+    async fn import_people(&self, people: &[NewPerson]) -> ApiResult<Vec<Person>>;
+    async fn insert_person(&self, new_person: &NewPerson) -> ApiResult<Person>;
+    /// Updates and patches only apply to a live row still at `row_version`,
+    /// and return `None` otherwise. Every change bumps the version.
+    async fn update_person(
+        &self,
+        person_id: i64,
+        person: &NewPerson,
+        row_version: i64,
+    ) -> ApiResult<Option<Person>>;
+    async fn patch_person(
+        &self,
+        person_id: i64,
+        patch: &PersonPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<Person>>;
+    /// Refused while the person owns salads. Their sessions are revoked.
+    async fn delete_person(&self, person_id: i64) -> ApiResult<Deletion>;
+    async fn restore_person(&self, person_id: i64) -> ApiResult<Restoration<Person>>;
+    async fn set_person_role(&self, person_id: i64, role: Role) -> ApiResult<Option<Role>>;
+
+    /// Inserts a person who can log in. Emails of such people are unique,
+    /// ignoring case.
+    async fn register_person(
+        &self,
+        new_person: &NewPerson,
+        password_hash: &str,
+    ) -> ApiResult<Person>;
+    async fn find_account(&self, email: &str) -> ApiResult<Option<Account>>;
+    /// Returns the id of the new session.
+    async fn create_session(&self, person_id: i64, expires_at: OffsetDateTime) -> ApiResult<i64>;
+    /// The person behind a session that has neither expired nor been revoked.
+    async fn find_session(&self, session_id: i64) -> ApiResult<Option<(Person, Role)>>;
+    async fn revoke_session(&self, session_id: i64) -> ApiResult<()>;
+}
+
+/// The fruit catalogue.
+#[async_trait]
+pub trait FruitRepository: Send + Sync {
+    async fn get_fruit(&self, fruit_id: i64, include_deleted: bool) -> ApiResult<Option<Fruit>>;
+    async fn list_fruits(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<Fruit>>;
+    async fn count_fruits(&self, list_query: &ListQuery) -> ApiResult<i64>;
+    /// The live fruits among `fruit_ids` in id order.
+    async fn get_fruits(&self, fruit_ids: &[i64]) -> ApiResult<Vec<Fruit>>;
+    fn export_fruits(&self) -> RowStream<Fruit>;
+    /// Inserts every fruit or, if any insert fails, none of them. Returns the
+    /// inserted fruits in id order.
+    async fn import_fruits(&self, fruits: &[NewFruit]) -> ApiResult<Vec<Fruit>>;
+    async fn insert_fruit(&self, new_fruit: &NewFruit) -> ApiResult<Fruit>;
+    /// `None` unless the fruit is live and still at `row_version`.
+    async fn update_fruit(
+        &self,
+        fruit_id: i64,
+        fruit: &NewFruit,
+        row_version: i64,
+    ) -> ApiResult<Option<Fruit>>;
+    async fn patch_fruit(
+        &self,
+        fruit_id: i64,
+        patch: &FruitPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<Fruit>>;
+    /// Refused while salad ingredients use the fruit.
+    async fn delete_fruit(&self, fruit_id: i64) -> ApiResult<Deletion>;
+    async fn restore_fruit(&self, fruit_id: i64) -> ApiResult<Restoration<Fruit>>;
+}
+
+/// Salads, and the views of them joined with their creator and fruits.
+#[async_trait]
+pub trait SaladRepository: Send + Sync {
+    async fn get_salad(
+        &self,
+        salad_id: i64,
+        include_deleted: bool,
+    ) -> ApiResult<Option<FruitSalad>>;
+    async fn list_salads(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<FruitSalad>>;
+    async fn count_salads(&self, list_query: &ListQuery) -> ApiResult<i64>;
+    /// The live salads among `salad_ids` in id order.
+    async fn get_salads(&self, salad_ids: &[i64]) -> ApiResult<Vec<FruitSalad>>;
+    /// Every live salad of any of `creator_ids`, in id order.
+    async fn list_salads_of_creators(&self, creator_ids: &[i64]) -> ApiResult<Vec<FruitSalad>>;
+    async fn list_salads_by_creator(
+        &self,
+        creator_id: i64,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<SaladView>>;
+    async fn count_salads_by_creator(&self, creator_id: i64) -> ApiResult<i64>;
+    async fn list_salad_ingredient_views(
+        &self,
+        salad_id: i64,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<SaladIngredientsView>>;
+    async fn count_salad_ingredient_views(&self, salad_id: i64) -> ApiResult<i64>;
+    /// The salad's fruits, heaviest first, for `summarize_salad`.
+    async fn salad_components(&self, salad_id: i64) -> ApiResult<Vec<SaladComponent>>;
+    fn export_salads(&self) -> RowStream<FruitSalad>;
+    /// Inserts the salad and one whole fruit per id of `ingredients`, or
+    /// nothing when one of the fruits does not exist.
+    async fn insert_salad(
+        &self,
+        creator_id: i64,
+        new_salad: &NewFruitSalad,
+    ) -> ApiResult<FullFruitSalad>;
+    /// Renames the salad if it is live and still at `row_version`.
+    async fn update_salad(
+        &self,
+        salad_id: i64,
+        salad: &NewFruitSalad,
+        row_version: i64,
+    ) -> ApiResult<Option<FruitSalad>>;
+    async fn patch_salad(
+        &self,
+        salad_id: i64,
+        patch: &FruitSaladPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<FruitSalad>>;
+    /// Deletes the salad together with its ingredients. Returns the ids of
+    /// the ingredients deleted with it, or `None` if the salad did not exist.
+    async fn delete_salad(&self, salad_id: i64) -> ApiResult<Option<Vec<i64>>>;
+    /// Restores the salad and the ingredients that were deleted with it, and
+    /// returns both. Refused while its creator or one of those fruits is
+    /// deleted.
+    async fn restore_salad(
+        &self,
+        salad_id: i64,
+    ) -> ApiResult<Restoration<(FruitSalad, Vec<SaladIngredient>)>>;
+}
+
+/// Fruits added to salads.
+#[async_trait]
+pub trait SaladIngredientRepository: Send + Sync {
+    async fn get_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        include_deleted: bool,
+    ) -> ApiResult<Option<SaladIngredient>>;
+    /// Creator of the salad the ingredient belongs to.
+    async fn get_salad_ingredient_owner(&self, ingredient_id: i64) -> ApiResult<Option<i64>>;
+    async fn list_salad_ingredients(
+        &self,
+        page_request: &PageRequest,
+        include_deleted: bool,
+    ) -> ApiResult<Page<SaladIngredient>>;
+    async fn count_salad_ingredients(&self, include_deleted: bool) -> ApiResult<i64>;
+    /// The live ingredients among `ingredient_ids` in id order.
+    async fn get_salad_ingredients(
+        &self,
+        ingredient_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>>;
+    /// Every live ingredient of any of `salad_ids`, in id order.
+    async fn list_ingredients_of_salads(
+        &self,
+        salad_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>>;
+    /// Every live ingredient made of any of `fruit_ids`, in id order.
+    async fn list_ingredients_of_fruits(
+        &self,
+        fruit_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>>;
+    /// `None` when the fruit does not exist or was deleted. The quantity defaults to the
+    /// fruit's weight.
+    async fn insert_salad_ingredient(
+        &self,
+        new_ingredient: &NewSaladIngredient,
+    ) -> ApiResult<Option<SaladIngredient>>;
+    async fn update_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        ingredient: &NewSaladIngredient,
+    ) -> ApiResult<Option<SaladIngredient>>;
+    async fn patch_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        patch: &SaladIngredientPatch,
+    ) -> ApiResult<Option<SaladIngredient>>;
+    /// Returns whether the ingredient existed.
+    async fn delete_salad_ingredient(&self, ingredient_id: i64) -> ApiResult<bool>;
+    /// Refused while its salad or fruit is deleted.
+    async fn restore_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+    ) -> ApiResult<Restoration<SaladIngredient>>;
+}
+
+/// Webhook subscriptions and the queue and log of their deliveries.
+#[async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn get_webhook(&self, webhook_id: i64) -> ApiResult<Option<Webhook>>;
+    async fn list_webhooks(&self, page_request: &PageRequest) -> ApiResult<Page<Webhook>>;
+    async fn count_webhooks(&self) -> ApiResult<i64>;
+    async fn insert_webhook(&self, new_webhook: &NewWebhook, secret: &str) -> ApiResult<Webhook>;
+    /// Keeps the secret.
+    async fn update_webhook(
+        &self,
+        webhook_id: i64,
+        webhook: &NewWebhook,
+    ) -> ApiResult<Option<Webhook>>;
+    /// Deletes the subscription together with its deliveries. Returns
+    /// whether it existed.
+    async fn delete_webhook(&self, webhook_id: i64) -> ApiResult<bool>;
+    async fn get_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+    ) -> ApiResult<Option<WebhookDelivery>>;
+    /// Deliveries of the subscription in id order, only those in `status`
+    /// if given.
+    async fn list_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<&str>,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<WebhookDelivery>>;
+    async fn count_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<&str>,
+    ) -> ApiResult<i64>;
+    /// Queues `payload` for every active subscription to `event_type`.
+    /// Returns how many deliveries were queued.
+    async fn enqueue_webhook_deliveries(&self, event_type: &str, payload: &Value)
+        -> ApiResult<u64>;
+    /// Up to `limit` pending deliveries of active subscriptions that are due,
+    /// oldest first. They are not due again before `lease_until`, so no other
+    /// worker takes them meanwhile.
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease_until: OffsetDateTime,
+    ) -> ApiResult<Vec<DueDelivery>>;
+    /// Counts the attempt and records its outcome.
+    async fn finish_webhook_attempt(
+        &self,
+        delivery_id: i64,
+        attempt: &DeliveryAttempt,
+    ) -> ApiResult<()>;
+    /// Makes a dead delivery pending and due again, with no attempts.
+    /// `None` unless the subscription has such a dead delivery.
+    async fn retry_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+    ) -> ApiResult<Option<WebhookDelivery>>;
+}
+
+/// A storage backend for every router. Routers are generic over it and
+/// take it out of `AppState` with `State<R>`, hence the `FromRef` bound that
+/// each backend implements in `AppState`.
+#[async_trait]
+pub trait Repository:
+    PersonRepository
+    + FruitRepository
+    + SaladRepository
+    + SaladIngredientRepository
+    + WebhookRepository
+    + FromRef<AppState<Self>>
+    + Clone
+    + 'static
+{
+    /// Fails with the reason when the backend cannot serve queries.
+    async fn ping(&self) -> Result<(), String>;
+    /// `None` for backends without a connection pool.
+    fn pool_status(&self) -> Option<PoolStatus>;
+}
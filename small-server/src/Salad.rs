@@ -1,266 +1,676 @@
 use axum::{
-    extract::{rejection::JsonRejection, Path, Query, State},
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        Path, Query, State,
+    },
     http::StatusCode,
+    response::Response,
     routing::{get, post},
     Json, Router,
 };
 use serde_json::Value;
-use sqlx::{Pool, Postgres};
+use sqlx::types::time::OffsetDateTime;
 
-use super::Pagination::{Pagination, RowCount};
+use super::AppState::AppState;
+use super::Auth::CurrentPerson;
+use super::Authorization::{Admin, IncludeDeleted};
+use super::Errors::{ApiError, ApiResult, ProblemResponse};
+use super::Events::{Action, EventBus, Resource};
+use super::Filter::{FilterField, FilterValue, Filterable, ListQuery};
+use super::Metrics::{INGREDIENTS_ADDED, SALADS_CREATED};
+use super::Negotiation::Representation;
+use super::Pagination::{Keyed, Pagination};
+use super::Precondition::{tagged_response, with_entity_tag, IfMatch, IfNoneMatch};
+use super::Repository::{Repository, SaladRepository};
+use super::SaladIngredient::SaladIngredient;
+use super::Transfer::{export_rows, ExportQuery};
+use super::Validation::{FieldError, Validate, Validator, MAX_VARCHAR_LENGTH};
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, utoipa::ToSchema, async_graphql::InputObject)]
 pub struct NewFruitSalad {
-    pub id_creator: i64,
     pub salad_name: String,
+    /// Fruit ids added as `SALAD_INGREDIENTS` in the same transaction as the
+    /// salad, one whole fruit each. Only accepted when creating a salad.
+    #[serde(default)]
+    #[graphql(default)]
+    pub ingredients: Vec<i64>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct FruitSaladPatch {
+    pub salad_name: Option<String>,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(
+    Clone,
+    serde::Deserialize,
+    serde::Serialize,
+    sqlx::FromRow,
+    utoipa::ToSchema,
+    async_graphql::SimpleObject,
+)]
+#[graphql(complex)]
 pub struct FruitSalad {
     pub id: i64,
     pub id_creator: i64,
     pub salad_name: String,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: OffsetDateTime,
+    /// When the salad was deleted, together with its ingredients.
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    #[graphql(skip)]
+    pub deleted_at: Option<OffsetDateTime>,
+    /// Version of the salad row, see `Precondition`.
+    pub row_version: i64,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct FullFruitSalad {
+    #[serde(flatten)]
+    pub salad: FruitSalad,
+    pub ingredients: Vec<SaladIngredient>,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
 pub struct SaladView {
     pub id: i64,
     pub person_name: String,
     pub salad_name: String,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
 pub struct SaladIngredientsView {
+    pub id: i64,
     pub person_name: String,
     pub salad_name: String,
     pub fruit_name: String,
+    pub quantity_grams: i32,
 }
 
-pub fn get_router() -> Router<Pool<Postgres>> {
-    return Router::new()
-        .route("/", post(insert_salad))
-        .route("/", get(list_salad))
-        .route("/:salad_id", get(get_salad_by_id))
-        .route("/:salad_id/ingredients", get(list_salad_all_ingredients));
+#[derive(sqlx::FromRow)]
+pub struct SaladComponent {
+    pub id_fruit: i64,
+    pub fruit_name: String,
+    pub color_red: i16,
+    pub color_green: i16,
+    pub color_blue: i16,
+    pub quantity_grams: i32,
 }
 
-pub async fn get_salad_by_id(
-    Path(salad_id): Path<i64>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let query_result = sqlx::query_as!(
-        FruitSalad,
-        "SELECT * FROM FRUIT_SALAD WHERE ID = $1",
-        salad_id
-    )
-    .fetch_one(&database_connection_pool)
-    .await;
-    match query_result {
-        Ok(salad) => {
-            return (StatusCode::OK, Json(serde_json::json!(salad)));
-        }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({ "error": error.to_string() })),
-            );
-        }
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Color {
+    pub red: i16,
+    pub green: i16,
+    pub blue: i16,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct FruitShare {
+    pub id_fruit: i64,
+    pub fruit_name: String,
+    pub quantity_grams: i32,
+    /// Fraction of the salad's total weight, between 0 and 1.
+    pub share: f64,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct SaladSummary {
+    pub id: i64,
+    pub salad_name: String,
+    pub total_weight_grams: i64,
+    /// Average of the fruits' colours weighted by their quantity, or `None`
+    /// for a salad without ingredients.
+    pub average_color: Option<Color>,
+    pub fruits: Vec<FruitShare>,
+}
+
+impl Keyed for FruitSalad {
+    fn key(&self) -> i64 {
+        return self.id;
     }
 }
 
-pub async fn list_salads_by_user_id(
-    maybe_pagination: Option<Query<Pagination>>,
-    Path(user_id): Path<i64>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let pagination = maybe_pagination.unwrap_or_default();
-    let size = i64::from(pagination.size.unwrap_or(10));
-    let offset = size * pagination.page.unwrap_or(0);
-    let query_result = sqlx::query_as!(
-        SaladView,
-        r#"
-        SELECT FRUIT_SALAD.id, person_name, salad_name FROM FRUIT_SALAD 
-        JOIN PERSON ON ID_CREATOR = PERSON.ID 
-        where ID_CREATOR = $3
-        LIMIT $1 OFFSET $2
-        "#,
-        size,
-        offset,
-        user_id
-    )
-    .fetch_all(&database_connection_pool)
-    .await;
-
-    let row_query_result = sqlx::query_as!(RowCount, "SELECT COUNT(1) from FRUIT")
-        .fetch_one(&database_connection_pool)
-        .await;
-
-    let Ok(row_count) = row_query_result else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": row_query_result.err().unwrap().to_string()})),
-        );
-    };
+impl Keyed for SaladView {
+    fn key(&self) -> i64 {
+        return self.id;
+    }
+}
+
+impl Keyed for SaladIngredientsView {
+    fn key(&self) -> i64 {
+        return self.id;
+    }
+}
 
-    match query_result {
-        Ok(salad_vec) => {
-            return (
-                StatusCode::OK,
-                Json(serde_json::json!({"total":row_count.count,"hits":salad_vec})),
-            );
+impl Filterable for FruitSalad {
+    fn field(&self, name: &str) -> Option<FilterValue> {
+        match name {
+            "id" => return Some(FilterValue::Integer(self.id)),
+            "id_creator" => return Some(FilterValue::Integer(self.id_creator)),
+            "salad_name" => return Some(FilterValue::Text(self.salad_name.clone())),
+            _ => return None,
         }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":error.to_string()})),
-            );
+    }
+
+    fn is_deleted(&self) -> bool {
+        return self.deleted_at.is_some();
+    }
+}
+
+impl Validate for NewFruitSalad {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        return Validator::new()
+            .length("salad_name", &self.salad_name, 1, MAX_VARCHAR_LENGTH)
+            .check(
+                "ingredients",
+                self.ingredients.iter().all(|fruit_id| *fruit_id > 0),
+                "positive",
+                "every fruit id must be greater than zero",
+            )
+            .check(
+                "ingredients",
+                !has_duplicates(&self.ingredients),
+                "unique",
+                "a fruit can only be added once to a salad",
+            )
+            .finish();
+    }
+}
+
+impl Validate for FruitSaladPatch {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut validator = Validator::new();
+        if let Some(salad_name) = &self.salad_name {
+            validator.length("salad_name", salad_name, 1, MAX_VARCHAR_LENGTH);
         }
+        return validator.finish();
+    }
+}
+
+/// Fields accepted by `list_salad` for filtering and sorting.
+pub const FRUIT_SALAD_FILTERS: &[FilterField] = &[
+    FilterField::integer("id", "ID"),
+    FilterField::integer("id_creator", "ID_CREATOR"),
+    FilterField::text("salad_name", "SALAD_NAME"),
+];
+
+/// Fails with 404 when the salad does not exist and with 403 when it belongs
+/// to someone other than `current_person`, unless they are an admin. Returns
+/// the salad otherwise.
+pub async fn ensure_salad_owner<R: SaladRepository + ?Sized>(
+    salads: &R,
+    salad_id: i64,
+    current_person: &CurrentPerson,
+) -> ApiResult<FruitSalad> {
+    let salad = salads
+        .get_salad(salad_id, false)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Salad", salad_id))?;
+
+    current_person.ensure_owner_or_admin(salad.id_creator, &format!("Salad {}", salad_id))?;
+    return Ok(salad);
+}
+
+fn has_duplicates(fruit_ids: &[i64]) -> bool {
+    let mut sorted_ids = fruit_ids.to_vec();
+    sorted_ids.sort_unstable();
+    return sorted_ids.windows(2).any(|pair| pair[0] == pair[1]);
+}
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new()
+        .route("/", post(insert_salad::<R>))
+        .route("/export", get(export_salad::<R>))
+        .route("/", get(list_salad::<R>))
+        .route(
+            "/:salad_id",
+            get(get_salad_by_id::<R>)
+                .put(update_salad::<R>)
+                .patch(patch_salad::<R>)
+                .delete(delete_salad::<R>),
+        )
+        .route(
+            "/:salad_id/ingredients",
+            get(list_salad_all_ingredients::<R>),
+        )
+        .route("/:salad_id/summary", get(get_salad_summary::<R>))
+        .route("/:salad_id/restore", post(restore_salad::<R>));
+}
+
+#[utoipa::path(
+    get,
+    path = "/salad/{salad_id}",
+    tag = "salad",
+    params(
+        ("salad_id" = i64, Path, description = "Salad id"),
+        ("include_deleted" = Option<bool>, Query, description = "Also find a deleted salad, admins only"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a copy the client already has"),
+    ),
+    responses(
+        (status = 200, description = "The salad", body = FruitSalad, content_type = ["application/json", "text/csv", "application/msgpack"], headers(("ETag" = String))),
+        (status = 304, description = "The salad still has the `If-None-Match` ETag"),
+        (status = 406, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+)]
+pub async fn get_salad_by_id<R: SaladRepository>(
+    Path(salad_id): Path<i64>,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    if_none_match: IfNoneMatch,
+    representation: Representation,
+    State(salads): State<R>,
+) -> ApiResult<Response> {
+    let salad = salads
+        .get_salad(salad_id, include_deleted)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Salad", salad_id))?;
+    if let Some(not_modified) = if_none_match.not_modified(salad.row_version) {
+        return Ok(not_modified);
     }
+    let response = representation.row_response(&salad)?;
+    return Ok(with_entity_tag(response, salad.row_version));
 }
 
-pub async fn list_salad(
-    maybe_pagination: Option<Query<Pagination>>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let pagination = maybe_pagination.unwrap_or_default();
-    let size = i64::from(pagination.size.unwrap_or(10));
-    let offset = size * pagination.page.unwrap_or(0);
-    let query_result = sqlx::query_as!(
-        FruitSalad,
-        r#"
-        SELECT * FROM FRUIT_SALAD
-        LIMIT $1 OFFSET $2
-        "#,
-        size,
-        offset,
-    )
-    .fetch_all(&database_connection_pool)
-    .await;
-
-    let row_query_result = sqlx::query_as!(RowCount, "SELECT COUNT(1) from FRUIT_SALAD")
-        .fetch_one(&database_connection_pool)
-        .await;
-
-    let Ok(row_count) = row_query_result else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": row_query_result.err().unwrap().to_string()})),
-        );
+#[utoipa::path(
+    get,
+    path = "/person/{user_id}/salad",
+    tag = "salad",
+    params(("user_id" = i64, Path, description = "Person id"), Pagination),
+    responses(
+        (status = 200, description = "A page of the person's salads", body = SaladViewList, content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 406, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+    ),
+)]
+pub async fn list_salads_by_user_id<R: SaladRepository>(
+    pagination: Result<Query<Pagination>, QueryRejection>,
+    Path(user_id): Path<i64>,
+    representation: Representation,
+    State(salads): State<R>,
+) -> ApiResult<Response> {
+    let Query(pagination) = pagination?;
+    let page_request = pagination.page_request()?;
+    let page = salads
+        .list_salads_by_creator(user_id, &page_request)
+        .await?;
+    let total = if pagination.with_total() {
+        Some(salads.count_salads_by_creator(user_id).await?)
+    } else {
+        None
     };
+    return representation.list_response(page, total);
+}
 
-    match query_result {
-        Ok(salad_vec) => {
-            return (
-                StatusCode::OK,
-                Json(serde_json::json!({"total":row_count.count,"hits":salad_vec})),
-            );
-        }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":error.to_string()})),
-            );
-        }
+#[utoipa::path(
+    get,
+    path = "/salad",
+    tag = "salad",
+    params(
+        Pagination,
+        ("include_deleted" = Option<bool>, Query, description = "Also list deleted salads, admins only"),
+    ),
+    responses(
+        (status = 200, description = "A page of salads", body = FruitSaladList, content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 406, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+    ),
+)]
+pub async fn list_salad<R: SaladRepository>(
+    pagination: Result<Query<Pagination>, QueryRejection>,
+    Query(parameters): Query<Vec<(String, String)>>,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    representation: Representation,
+    State(salads): State<R>,
+) -> ApiResult<Response> {
+    let Query(pagination) = pagination?;
+    let mut list_query = ListQuery::parse(&parameters, FRUIT_SALAD_FILTERS)?;
+    list_query.include_deleted = include_deleted;
+    let page_request = pagination.page_request()?;
+    if page_request.is_cursor() && list_query.is_sorted() {
+        return Err(ApiError::InvalidQuery(String::from(
+            "`sort` cannot be combined with cursor pagination",
+        )));
     }
+    let page = salads.list_salads(&list_query, &page_request).await?;
+    let total = if pagination.with_total() {
+        Some(salads.count_salads(&list_query).await?)
+    } else {
+        None
+    };
+    return representation.list_response(page, total);
 }
 
-pub async fn list_salad_all_ingredients(
-    maybe_pagination: Option<Query<Pagination>>,
+#[utoipa::path(
+    get,
+    path = "/salad/{salad_id}/ingredients",
+    tag = "salad",
+    params(("salad_id" = i64, Path, description = "Salad id"), Pagination),
+    responses(
+        (status = 200, description = "A page of the salad's ingredients", body = SaladIngredientsViewList, content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 406, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+    ),
+)]
+pub async fn list_salad_all_ingredients<R: SaladRepository>(
+    pagination: Result<Query<Pagination>, QueryRejection>,
     Path(salad_id): Path<i64>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let pagination = maybe_pagination.unwrap_or_default();
-    let size = i64::from(pagination.size.unwrap_or(10));
-    let offset = size * pagination.page.unwrap_or(0);
-    let query_result = sqlx::query_as!(
-        SaladIngredientsView,
-        r#"
-        SELECT person_name, salad_name, fruit_name FROM FRUIT_SALAD 
-        JOIN PERSON ON ID_CREATOR = PERSON.ID 
-        JOIN SALAD_INGREDIENTS ON ID_SALAD = FRUIT_SALAD.ID
-        JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
-        where FRUIT_SALAD.ID = $3
-        LIMIT $1 OFFSET $2
-        "#,
-        size,
-        offset,
-        salad_id
-    )
-    .fetch_all(&database_connection_pool)
-    .await;
-
-    let row_query_result = sqlx::query_as!(
-        RowCount,
-        r#"
-        SELECT COUNT(1) from FRUIT_SALAD 
-        JOIN PERSON ON ID_CREATOR = PERSON.ID 
-        JOIN SALAD_INGREDIENTS ON ID_SALAD = FRUIT_SALAD.ID
-        JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID 
-        where FRUIT_SALAD.ID = $1
-        "#,
-        salad_id
-    )
-    .fetch_one(&database_connection_pool)
-    .await;
-
-    let Ok(row_count) = row_query_result else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": row_query_result.err().unwrap().to_string()})),
-        );
+    representation: Representation,
+    State(salads): State<R>,
+) -> ApiResult<Response> {
+    let Query(pagination) = pagination?;
+    let page_request = pagination.page_request()?;
+    let page = salads
+        .list_salad_ingredient_views(salad_id, &page_request)
+        .await?;
+    let total = if pagination.with_total() {
+        Some(salads.count_salad_ingredient_views(salad_id).await?)
+    } else {
+        None
     };
+    return representation.list_response(page, total);
+}
 
-    match query_result {
-        Ok(salad_vec) => {
-            return (
-                StatusCode::OK,
-                Json(serde_json::json!({"total":row_count.count,"hits":salad_vec})),
-            );
-        }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":error.to_string()})),
-            );
-        }
+#[utoipa::path(
+    get,
+    path = "/salad/{salad_id}/summary",
+    tag = "salad",
+    params(("salad_id" = i64, Path, description = "Salad id")),
+    responses(
+        (status = 200, description = "Weight, colour and composition of the salad", body = SaladSummary),
+        (status = 404, response = ProblemResponse),
+    ),
+)]
+pub async fn get_salad_summary<R: SaladRepository>(
+    Path(salad_id): Path<i64>,
+    State(salads): State<R>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let salad = salads
+        .get_salad(salad_id, false)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Salad", salad_id))?;
+    let components = salads.salad_components(salad_id).await?;
+
+    return Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(summarize_salad(salad, components))),
+    ));
+}
+
+pub fn summarize_salad(salad: FruitSalad, components: Vec<SaladComponent>) -> SaladSummary {
+    let total_weight_grams: i64 = components
+        .iter()
+        .map(|component| i64::from(component.quantity_grams))
+        .sum();
+
+    let average_color = if total_weight_grams > 0 {
+        let weighted_channel = |channel: fn(&SaladComponent) -> i16| {
+            let weighted_sum: i64 = components
+                .iter()
+                .map(|component| {
+                    i64::from(channel(component)) * i64::from(component.quantity_grams)
+                })
+                .sum();
+            return (weighted_sum as f64 / total_weight_grams as f64).round() as i16;
+        };
+        Some(Color {
+            red: weighted_channel(|component| component.color_red),
+            green: weighted_channel(|component| component.color_green),
+            blue: weighted_channel(|component| component.color_blue),
+        })
+    } else {
+        None
+    };
+
+    let fruits = components
+        .into_iter()
+        .map(|component| FruitShare {
+            share: if total_weight_grams > 0 {
+                f64::from(component.quantity_grams) / total_weight_grams as f64
+            } else {
+                0.0
+            },
+            id_fruit: component.id_fruit,
+            fruit_name: component.fruit_name,
+            quantity_grams: component.quantity_grams,
+        })
+        .collect();
+
+    return SaladSummary {
+        id: salad.id,
+        salad_name: salad.salad_name,
+        total_weight_grams,
+        average_color,
+        fruits,
+    };
+}
+
+/// Streams every salad, without its ingredients, ordered by id.
+#[utoipa::path(
+    get,
+    path = "/salad/export",
+    tag = "salad",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "One row per salad", body = [FruitSalad], content_type = ["text/csv", "application/x-ndjson"]),
+        (status = 400, response = ProblemResponse),
+    ),
+)]
+pub async fn export_salad<R: SaladRepository>(
+    Query(export_query): Query<ExportQuery>,
+    State(salads): State<R>,
+) -> Response {
+    return export_rows(salads.export_salads(), "salad", export_query.format);
+}
+
+/// Creates a salad owned by the authenticated person and, when `ingredients`
+/// is given, one `SALAD_INGREDIENTS` row per fruit id, all in one transaction.
+/// Nothing is written if any of the fruits does not exist.
+#[utoipa::path(
+    post,
+    path = "/salad",
+    tag = "salad",
+    request_body = NewFruitSalad,
+    responses(
+        (status = 201, description = "The created salad with its ingredients", body = FullFruitSalad),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn insert_salad<R: SaladRepository>(
+    current_person: CurrentPerson,
+    State(salads): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<NewFruitSalad>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(salad_json) = body?;
+    salad_json.validate()?;
+    let full_salad = salads
+        .insert_salad(current_person.person.id, &salad_json)
+        .await?;
+    SALADS_CREATED.inc();
+    INGREDIENTS_ADDED.inc_by(full_salad.ingredients.len() as u64);
+    events.changed(Action::Created, &full_salad.salad);
+    for ingredient in &full_salad.ingredients {
+        events.changed(Action::Created, ingredient);
     }
+    return Ok((StatusCode::CREATED, Json(serde_json::json!(full_salad))));
 }
 
-pub async fn insert_salad(
-    State(database_connection_pool): State<Pool<Postgres>>,
+#[utoipa::path(
+    put,
+    path = "/salad/{salad_id}",
+    tag = "salad",
+    params(
+        ("salad_id" = i64, Path, description = "Salad id"),
+        ("If-Match" = String, Header, description = "ETag of the salad as last read"),
+    ),
+    request_body = NewFruitSalad,
+    responses(
+        (status = 200, description = "The updated salad", body = FruitSalad, headers(("ETag" = String))),
+        (status = 412, response = ProblemResponse),
+        (status = 428, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn update_salad<R: SaladRepository>(
+    Path(salad_id): Path<i64>,
+    current_person: CurrentPerson,
+    if_match: IfMatch,
+    State(salads): State<R>,
+    State(events): State<EventBus>,
     body: Result<Json<NewFruitSalad>, JsonRejection>,
-) -> (StatusCode, Json<Value>) {
-    match body {
-        Ok(salad_json) => {
-            let query_result = sqlx::query_as!(
-                FruitSalad,
-                r#"
-                INSERT INTO FRUIT_SALAD ( ID_CREATOR, SALAD_NAME ) 
-                VALUES ( $1, $2 ) 
-                RETURNING ID, ID_CREATOR, SALAD_NAME
-                "#,
-                salad_json.id_creator,
-                salad_json.salad_name
-            )
-            .fetch_one(&database_connection_pool)
-            .await;
-            match query_result {
-                Ok(salad) => {
-                    return (StatusCode::CREATED, Json(serde_json::json!(salad)));
-                }
-                Err(json_error) => {
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(serde_json::json!({"error":json_error.to_string()})),
-                    );
-                }
-            }
-        }
-        Err(json_error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":json_error.to_string()})),
-            );
-        }
+) -> ApiResult<Response> {
+    let Json(salad_json) = body?;
+    salad_json.validate()?;
+    if !salad_json.ingredients.is_empty() {
+        return Err(ApiError::Validation(vec![FieldError {
+            field: "ingredients",
+            code: "read_only",
+            message: String::from("can only be given when creating a salad"),
+        }]));
+    }
+    let current = ensure_salad_owner(&salads, salad_id, &current_person).await?;
+    if_match.check("Salad", salad_id, current.row_version)?;
+    let salad = salads
+        .update_salad(salad_id, &salad_json, current.row_version)
+        .await?
+        .ok_or_else(|| ApiError::stale("Salad", salad_id))?;
+    events.changed(Action::Updated, &salad);
+    return Ok(tagged_response(
+        StatusCode::OK,
+        serde_json::json!(salad),
+        salad.row_version,
+    ));
+}
+
+#[utoipa::path(
+    patch,
+    path = "/salad/{salad_id}",
+    tag = "salad",
+    params(
+        ("salad_id" = i64, Path, description = "Salad id"),
+        ("If-Match" = String, Header, description = "ETag of the salad as last read"),
+    ),
+    request_body = FruitSaladPatch,
+    responses(
+        (status = 200, description = "The updated salad", body = FruitSalad, headers(("ETag" = String))),
+        (status = 412, response = ProblemResponse),
+        (status = 428, response = ProblemResponse),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn patch_salad<R: SaladRepository>(
+    Path(salad_id): Path<i64>,
+    current_person: CurrentPerson,
+    if_match: IfMatch,
+    State(salads): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<FruitSaladPatch>, JsonRejection>,
+) -> ApiResult<Response> {
+    let Json(salad_json) = body?;
+    salad_json.validate()?;
+    let current = ensure_salad_owner(&salads, salad_id, &current_person).await?;
+    if_match.check("Salad", salad_id, current.row_version)?;
+    let salad = salads
+        .patch_salad(salad_id, &salad_json, current.row_version)
+        .await?
+        .ok_or_else(|| ApiError::stale("Salad", salad_id))?;
+    events.changed(Action::Updated, &salad);
+    return Ok(tagged_response(
+        StatusCode::OK,
+        serde_json::json!(salad),
+        salad.row_version,
+    ));
+}
+
+/// Deletes the salad together with its `SALAD_INGREDIENTS` rows in a single
+/// transaction, since the ingredients cannot outlive the salad they belong to.
+/// Both get the same `deleted_at`, which is how `restore_salad` finds the
+/// ingredients to bring back. Each ingredient gets an `ingredient.deleted`
+/// event before the `salad.deleted` one.
+#[utoipa::path(
+    delete,
+    path = "/salad/{salad_id}",
+    tag = "salad",
+    params(("salad_id" = i64, Path, description = "Salad id")),
+    responses(
+        (status = 204, description = "The salad and its ingredients were deleted"),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_salad<R: SaladRepository>(
+    Path(salad_id): Path<i64>,
+    current_person: CurrentPerson,
+    State(salads): State<R>,
+    State(events): State<EventBus>,
+) -> ApiResult<StatusCode> {
+    ensure_salad_owner(&salads, salad_id, &current_person).await?;
+    let Some(ingredient_ids) = salads.delete_salad(salad_id).await? else {
+        return Err(ApiError::not_found("Salad", salad_id));
+    };
+    for ingredient_id in ingredient_ids {
+        events.deleted(Resource::Ingredient, ingredient_id, Some(salad_id));
+    }
+    events.deleted(Resource::Salad, salad_id, Some(salad_id));
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+/// Undoes `delete_salad`, ingredients included. Ingredients deleted on their
+/// own before the salad stay deleted.
+#[utoipa::path(
+    post,
+    path = "/salad/{salad_id}/restore",
+    tag = "salad",
+    params(("salad_id" = i64, Path, description = "Salad id")),
+    responses(
+        (status = 200, description = "The restored salad", body = FruitSalad),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn restore_salad<R: SaladRepository>(
+    Path(salad_id): Path<i64>,
+    _admin: Admin,
+    State(salads): State<R>,
+    State(events): State<EventBus>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let (salad, ingredients) = salads
+        .restore_salad(salad_id)
+        .await?
+        .into_result("Salad", salad_id)?;
+    events.changed(Action::Restored, &salad);
+    for ingredient in &ingredients {
+        events.changed(Action::Restored, ingredient);
     }
+    return Ok((StatusCode::OK, Json(serde_json::json!(salad))));
 }
@@ -1,140 +1,416 @@
 use axum::{
-    extract::{rejection::JsonRejection, Path, Query, State},
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        Path, Query, State,
+    },
     http::StatusCode,
     routing::{get, post},
     Json, Router,
 };
 use serde_json::Value;
-use sqlx::{Pool, Postgres};
+use sqlx::types::time::OffsetDateTime;
 
-use super::Pagination::{Pagination, RowCount};
+use super::AppState::AppState;
+use super::Auth::CurrentPerson;
+use super::Authorization::{Admin, IncludeDeleted};
+use super::Errors::{ApiError, ApiResult, ProblemResponse};
+use super::Events::{Action, EventBus, Resource};
+use super::Metrics::INGREDIENTS_ADDED;
+use super::Pagination::{Keyed, Pagination};
+use super::Repository::{FruitRepository, Repository, SaladIngredientRepository, SaladRepository};
+use super::Salad::ensure_salad_owner;
+use super::Validation::{FieldError, Validate, Validator};
 
-pub fn getRouter() -> Router<Pool<Postgres>> {
+impl Keyed for SaladIngredient {
+    fn key(&self) -> i64 {
+        return self.id;
+    }
+}
+
+impl Validate for NewSaladIngredient {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        return Validator::new()
+            .positive("id_salad", self.id_salad)
+            .positive("id_fruit", self.id_fruit)
+            .check(
+                "quantity_grams",
+                self.quantity_grams.is_none_or(|quantity| quantity > 0),
+                "positive",
+                "must be greater than zero",
+            )
+            .finish();
+    }
+}
+
+impl Validate for SaladIngredientPatch {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut validator = Validator::new();
+        if let Some(id_salad) = self.id_salad {
+            validator.positive("id_salad", id_salad);
+        }
+        if let Some(id_fruit) = self.id_fruit {
+            validator.positive("id_fruit", id_fruit);
+        }
+        if let Some(quantity_grams) = self.quantity_grams {
+            validator.positive("quantity_grams", quantity_grams);
+        }
+        return validator.finish();
+    }
+}
+
+pub fn getRouter<R: Repository>() -> Router<AppState<R>> {
     return Router::new()
-        .route("/", post(insert_salad_ingredient))
-        .route("/", get(list_salad_ingredients))
-        .route("/:ingredient_id", get(get_salad_ingredient_by_id));
+        .route("/", post(insert_salad_ingredient::<R>))
+        .route("/", get(list_salad_ingredients::<R>))
+        .route(
+            "/:ingredient_id",
+            get(get_salad_ingredient_by_id::<R>)
+                .put(update_salad_ingredient::<R>)
+                .patch(patch_salad_ingredient::<R>)
+                .delete(delete_salad_ingredient::<R>),
+        )
+        .route(
+            "/:ingredient_id/restore",
+            post(restore_salad_ingredient::<R>),
+        );
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, utoipa::ToSchema, async_graphql::InputObject)]
 pub struct NewSaladIngredient {
     pub id_salad: i64,
     pub id_fruit: i64,
+    /// Defaults to the fruit's `FRUIT_WEIGHT` on insert and to the current
+    /// quantity on update.
+    pub quantity_grams: Option<i32>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct SaladIngredientPatch {
+    pub id_salad: Option<i64>,
+    pub id_fruit: Option<i64>,
+    pub quantity_grams: Option<i32>,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(
+    Clone,
+    serde::Deserialize,
+    serde::Serialize,
+    sqlx::FromRow,
+    utoipa::ToSchema,
+    async_graphql::SimpleObject,
+)]
+#[graphql(complex)]
 pub struct SaladIngredient {
     pub id: i64,
     pub id_salad: i64,
     pub id_fruit: i64,
+    pub quantity_grams: i32,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: OffsetDateTime,
+    /// When the ingredient, or the salad it belongs to, was deleted.
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    #[graphql(skip)]
+    pub deleted_at: Option<OffsetDateTime>,
 }
 
-pub async fn get_salad_ingredient_by_id(
-    Path(salad_ingredient_id): Path<i64>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let query_result = sqlx::query_as!(
-        SaladIngredient,
-        "SELECT * FROM SALAD_INGREDIENTS WHERE ID = $1",
-        salad_ingredient_id
-    )
-    .fetch_one(&database_connection_pool)
-    .await;
-
-    match query_result {
-        Ok(salad) => {
-            return (StatusCode::OK, Json(serde_json::json!(salad)));
-        }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({ "error": error.to_string() })),
-            );
-        }
+/// Fails with 404 when the ingredient does not exist and with 403 when its
+/// salad belongs to someone other than `current_person`, unless they are an
+/// admin.
+async fn ensure_ingredient_owner<R: SaladIngredientRepository>(
+    ingredients: &R,
+    salad_ingredient_id: i64,
+    current_person: &CurrentPerson,
+) -> ApiResult<()> {
+    let id_creator = ingredients
+        .get_salad_ingredient_owner(salad_ingredient_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Salad ingredient", salad_ingredient_id))?;
+
+    return current_person.ensure_owner_or_admin(
+        id_creator,
+        &format!("Salad ingredient {}", salad_ingredient_id),
+    );
+}
+
+/// Like `ensure_salad_owner`, but for a salad referenced from the request
+/// body, where a missing salad is a 422 rather than a 404.
+pub async fn ensure_target_salad_owner<R: SaladRepository + ?Sized>(
+    salads: &R,
+    salad_id: i64,
+    current_person: &CurrentPerson,
+) -> ApiResult<()> {
+    match ensure_salad_owner(salads, salad_id, current_person).await {
+        Ok(_) => return Ok(()),
+        Err(ApiError::NotFound(detail)) => return Err(ApiError::ForeignKeyViolation(detail)),
+        Err(error) => return Err(error),
     }
 }
 
-pub async fn list_salad_ingredients(
-    maybe_pagination: Option<Query<Pagination>>,
-    State(database_connection_pool): State<Pool<Postgres>>,
-) -> (StatusCode, Json<Value>) {
-    let pagination = maybe_pagination.unwrap_or_default();
-    let size = i64::from(pagination.size.unwrap_or(10));
-    let offset = size * pagination.page.unwrap_or(0);
-    let query_result = sqlx::query_as!(
-        SaladIngredient,
-        r#"
-        SELECT * FROM SALAD_INGREDIENTS 
-        LIMIT $1 OFFSET $2
-        "#,
-        size,
-        offset,
-    )
-    .fetch_all(&database_connection_pool)
-    .await;
-
-    let row_query_result = sqlx::query_as!(RowCount, "SELECT COUNT(1) from SALAD_INGREDIENTS")
-        .fetch_one(&database_connection_pool)
-        .await;
-
-    let Ok(row_count) = row_query_result else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": row_query_result.err().unwrap().to_string()})),
-        );
-    };
-
-    match query_result {
-        Ok(salad_vec) => {
-            return (
-                StatusCode::OK,
-                Json(serde_json::json!({"total":row_count.count,"hits":salad_vec})),
-            );
-        }
-        Err(error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":error.to_string()})),
-            );
-        }
+/// Fails with 422 when the fruit referenced from the request body does not
+/// exist or was deleted, which the foreign key alone does not catch.
+async fn ensure_target_fruit<R: FruitRepository>(fruits: &R, fruit_id: i64) -> ApiResult<()> {
+    if fruits.get_fruit(fruit_id, false).await?.is_none() {
+        return Err(ApiError::ForeignKeyViolation(format!(
+            "Fruit {} not found",
+            fruit_id
+        )));
     }
+    return Ok(());
 }
 
-pub async fn insert_salad_ingredient(
-    State(database_connection_pool): State<Pool<Postgres>>,
+#[utoipa::path(
+    get,
+    path = "/ingredient/{ingredient_id}",
+    tag = "ingredient",
+    params(
+        ("ingredient_id" = i64, Path, description = "Salad ingredient id"),
+        ("include_deleted" = Option<bool>, Query, description = "Also find a deleted ingredient, admins only"),
+    ),
+    responses(
+        (status = 200, description = "The salad ingredient", body = SaladIngredient),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+)]
+pub async fn get_salad_ingredient_by_id<R: SaladIngredientRepository>(
+    Path(salad_ingredient_id): Path<i64>,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    State(ingredients): State<R>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let ingredient = ingredients
+        .get_salad_ingredient(salad_ingredient_id, include_deleted)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Salad ingredient", salad_ingredient_id))?;
+    return Ok((StatusCode::OK, Json(serde_json::json!(ingredient))));
+}
+
+#[utoipa::path(
+    get,
+    path = "/ingredient",
+    tag = "ingredient",
+    params(
+        Pagination,
+        ("include_deleted" = Option<bool>, Query, description = "Also list deleted ingredients, admins only"),
+    ),
+    responses(
+        (status = 200, description = "A page of salad ingredients", body = SaladIngredientList),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+    ),
+)]
+pub async fn list_salad_ingredients<R: SaladIngredientRepository>(
+    pagination: Result<Query<Pagination>, QueryRejection>,
+    IncludeDeleted(include_deleted): IncludeDeleted,
+    State(ingredients): State<R>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Query(pagination) = pagination?;
+    let page_request = pagination.page_request()?;
+    let mut response = serde_json::json!(
+        ingredients
+            .list_salad_ingredients(&page_request, include_deleted)
+            .await?
+    );
+
+    if pagination.with_total() {
+        response["total"] =
+            serde_json::json!(ingredients.count_salad_ingredients(include_deleted).await?);
+    }
+
+    return Ok((StatusCode::OK, Json(response)));
+}
+
+#[utoipa::path(
+    post,
+    path = "/ingredient",
+    tag = "ingredient",
+    request_body = NewSaladIngredient,
+    responses(
+        (status = 201, description = "The created salad ingredient", body = SaladIngredient),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn insert_salad_ingredient<R: SaladIngredientRepository + SaladRepository>(
+    current_person: CurrentPerson,
+    State(repository): State<R>,
+    State(events): State<EventBus>,
     body: Result<Json<NewSaladIngredient>, JsonRejection>,
-) -> (StatusCode, Json<Value>) {
-    match body {
-        Ok(ingredient_json) => {
-            let query_result = sqlx::query_as!(
-                SaladIngredient,
-                r#"
-                INSERT INTO SALAD_INGREDIENTS ( ID_SALAD, ID_FRUIT ) 
-                VALUES ( $1, $2 ) 
-                RETURNING ID, ID_SALAD, ID_FRUIT
-                "#,
-                ingredient_json.id_salad,
-                ingredient_json.id_fruit,
-            )
-            .fetch_one(&database_connection_pool)
-            .await;
-            match query_result {
-                Ok(salad) => {
-                    return (StatusCode::CREATED, Json(serde_json::json!(salad)));
-                }
-                Err(json_error) => {
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(serde_json::json!({"error":json_error.to_string()})),
-                    );
-                }
-            }
-        }
-        Err(json_error) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error":json_error.to_string()})),
-            );
-        }
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(ingredient_json) = body?;
+    ingredient_json.validate()?;
+    ensure_target_salad_owner(&repository, ingredient_json.id_salad, &current_person).await?;
+    let ingredient = repository
+        .insert_salad_ingredient(&ingredient_json)
+        .await?
+        .ok_or_else(|| {
+            ApiError::ForeignKeyViolation(format!("Fruit {} not found", ingredient_json.id_fruit))
+        })?;
+    INGREDIENTS_ADDED.inc();
+    events.changed(Action::Created, &ingredient);
+    return Ok((StatusCode::CREATED, Json(serde_json::json!(ingredient))));
+}
+
+#[utoipa::path(
+    put,
+    path = "/ingredient/{ingredient_id}",
+    tag = "ingredient",
+    params(("ingredient_id" = i64, Path, description = "Salad ingredient id")),
+    request_body = NewSaladIngredient,
+    responses(
+        (status = 200, description = "The updated salad ingredient", body = SaladIngredient),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn update_salad_ingredient<
+    R: SaladIngredientRepository + SaladRepository + FruitRepository,
+>(
+    Path(salad_ingredient_id): Path<i64>,
+    current_person: CurrentPerson,
+    State(repository): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<NewSaladIngredient>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(ingredient_json) = body?;
+    ingredient_json.validate()?;
+    ensure_ingredient_owner(&repository, salad_ingredient_id, &current_person).await?;
+    ensure_target_salad_owner(&repository, ingredient_json.id_salad, &current_person).await?;
+    ensure_target_fruit(&repository, ingredient_json.id_fruit).await?;
+    let ingredient = repository
+        .update_salad_ingredient(salad_ingredient_id, &ingredient_json)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Salad ingredient", salad_ingredient_id))?;
+    events.changed(Action::Updated, &ingredient);
+    return Ok((StatusCode::OK, Json(serde_json::json!(ingredient))));
+}
+
+#[utoipa::path(
+    patch,
+    path = "/ingredient/{ingredient_id}",
+    tag = "ingredient",
+    params(("ingredient_id" = i64, Path, description = "Salad ingredient id")),
+    request_body = SaladIngredientPatch,
+    responses(
+        (status = 200, description = "The updated salad ingredient", body = SaladIngredient),
+        (status = 400, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn patch_salad_ingredient<
+    R: SaladIngredientRepository + SaladRepository + FruitRepository,
+>(
+    Path(salad_ingredient_id): Path<i64>,
+    current_person: CurrentPerson,
+    State(repository): State<R>,
+    State(events): State<EventBus>,
+    body: Result<Json<SaladIngredientPatch>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(ingredient_json) = body?;
+    ingredient_json.validate()?;
+    ensure_ingredient_owner(&repository, salad_ingredient_id, &current_person).await?;
+    if let Some(id_salad) = ingredient_json.id_salad {
+        ensure_target_salad_owner(&repository, id_salad, &current_person).await?;
+    }
+    if let Some(id_fruit) = ingredient_json.id_fruit {
+        ensure_target_fruit(&repository, id_fruit).await?;
+    }
+    let ingredient = repository
+        .patch_salad_ingredient(salad_ingredient_id, &ingredient_json)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Salad ingredient", salad_ingredient_id))?;
+    events.changed(Action::Updated, &ingredient);
+    return Ok((StatusCode::OK, Json(serde_json::json!(ingredient))));
+}
+
+#[utoipa::path(
+    delete,
+    path = "/ingredient/{ingredient_id}",
+    tag = "ingredient",
+    params(("ingredient_id" = i64, Path, description = "Salad ingredient id")),
+    responses(
+        (status = 204, description = "The salad ingredient was deleted"),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_salad_ingredient<R: SaladIngredientRepository>(
+    Path(salad_ingredient_id): Path<i64>,
+    current_person: CurrentPerson,
+    State(ingredients): State<R>,
+    State(events): State<EventBus>,
+) -> ApiResult<StatusCode> {
+    ensure_ingredient_owner(&ingredients, salad_ingredient_id, &current_person).await?;
+    // Read first for the salad id of the event.
+    let ingredient = ingredients
+        .get_salad_ingredient(salad_ingredient_id, false)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Salad ingredient", salad_ingredient_id))?;
+    if !ingredients
+        .delete_salad_ingredient(salad_ingredient_id)
+        .await?
+    {
+        return Err(ApiError::not_found("Salad ingredient", salad_ingredient_id));
     }
+    events.deleted(
+        Resource::Ingredient,
+        salad_ingredient_id,
+        Some(ingredient.id_salad),
+    );
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+/// Undoes `delete_salad_ingredient`. Fails with 409 while the salad or the
+/// fruit is deleted, or when the fruit was added to the salad again since.
+#[utoipa::path(
+    post,
+    path = "/ingredient/{ingredient_id}/restore",
+    tag = "ingredient",
+    params(("ingredient_id" = i64, Path, description = "Salad ingredient id")),
+    responses(
+        (status = 200, description = "The restored salad ingredient", body = SaladIngredient),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn restore_salad_ingredient<R: SaladIngredientRepository>(
+    Path(salad_ingredient_id): Path<i64>,
+    _admin: Admin,
+    State(ingredients): State<R>,
+    State(events): State<EventBus>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let ingredient = ingredients
+        .restore_salad_ingredient(salad_ingredient_id)
+        .await?
+        .into_result("Salad ingredient", salad_ingredient_id)?;
+    events.changed(Action::Restored, &ingredient);
+    return Ok((StatusCode::OK, Json(serde_json::json!(ingredient))));
 }
@@ -0,0 +1,1917 @@
+use axum::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::sqlite::SqliteRow;
+use sqlx::types::time::OffsetDateTime;
+use sqlx::types::Json;
+use sqlx::{Connection, FromRow, Pool, QueryBuilder, Sqlite};
+use tracing::Instrument;
+
+use super::Authorization::Role;
+use super::Errors::{ApiError, ApiResult};
+use super::Filter::ListQuery;
+use super::Fruit::{Fruit, FruitPatch, NewFruit};
+use super::Metrics::acquire;
+use super::Pagination::{
+    cursor_page_query, finish_cursor_rows, Cursor, CursorPage, Keyed, Page, PageRequest, RowCount,
+};
+use super::Person::{NewPerson, Person, PersonPatch};
+use super::Repository::{
+    Account, Deletion, FruitRepository, PersonRepository, PoolStatus, Repository, Restoration,
+    RowStream, SaladIngredientRepository, SaladRepository, WebhookRepository,
+};
+use super::Salad::{
+    FruitSalad, FruitSaladPatch, FullFruitSalad, NewFruitSalad, SaladComponent,
+    SaladIngredientsView, SaladView,
+};
+use super::SaladIngredient::{NewSaladIngredient, SaladIngredient, SaladIngredientPatch};
+use super::Transfer::IMPORT_BATCH_SIZE;
+use super::Webhook::{DeliveryAttempt, DueDelivery, NewWebhook, Webhook, WebhookDelivery};
+
+// `FromRow` matches column names case-sensitively. SQLite names a column
+// read straight from a table as it is declared, in lowercase in
+// `migrations/sqlite/`, and any other column as it is spelled in the query,
+// so the select lists below are spelled in lowercase too.
+
+/// A person with their role and password hash, as read by `find_account`.
+type AccountRow = (
+    i64,
+    String,
+    i32,
+    String,
+    OffsetDateTime,
+    OffsetDateTime,
+    Option<OffsetDateTime>,
+    i64,
+    String,
+    String,
+);
+
+/// A person with their role, as read by `find_session`.
+type SessionRow = (
+    i64,
+    String,
+    i32,
+    String,
+    OffsetDateTime,
+    OffsetDateTime,
+    Option<OffsetDateTime>,
+    i64,
+    String,
+);
+
+/// Every repository, backed by the tables of `migrations/sqlite/`. The
+/// queries are checked at runtime only, `query!` needs a Postgres database
+/// to build.
+#[derive(Clone)]
+pub struct SqliteRepository {
+    database_connection_pool: Pool<Sqlite>,
+    /// Not readable from the pool itself, so it is taken from the config.
+    max_connections: u32,
+}
+
+impl SqliteRepository {
+    pub fn new(database_connection_pool: Pool<Sqlite>, max_connections: u32) -> SqliteRepository {
+        return SqliteRepository {
+            database_connection_pool,
+            max_connections,
+        };
+    }
+
+    /// A connection of the pool, counted while the query waits for it.
+    async fn connection(&self) -> ApiResult<PoolConnection<Sqlite>> {
+        return Ok(acquire(&self.database_connection_pool).await?);
+    }
+
+    /// Runs the query pushed by `push_base_query` as a subquery and returns
+    /// the `size` rows that follow or precede `cursor`.
+    async fn fetch_cursor_page<'args, T, F>(
+        &self,
+        push_base_query: F,
+        cursor: &Cursor,
+        size: i64,
+    ) -> ApiResult<CursorPage<T>>
+    where
+        T: for<'row> FromRow<'row, SqliteRow> + Keyed + Send + Unpin,
+        F: Fn(&mut QueryBuilder<'args, Sqlite>),
+    {
+        let mut query = cursor_page_query(push_base_query, cursor, size);
+        let rows = query
+            .build()
+            .fetch_all(&mut self.connection().await?)
+            .await?;
+        return finish_cursor_rows(rows, cursor, size);
+    }
+
+    /// Lists the rows of `base_query`, a `SELECT` without a `WHERE` clause,
+    /// that match `list_query`.
+    async fn list_rows<T>(
+        &self,
+        base_query: &'static str,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<T>>
+    where
+        T: for<'row> FromRow<'row, SqliteRow> + Keyed + Send + Unpin,
+    {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = self
+                    .fetch_cursor_page(
+                        |query| {
+                            query.push(base_query);
+                            list_query.push_where(query, false);
+                        },
+                        cursor,
+                        *size,
+                    )
+                    .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let mut query = QueryBuilder::new(base_query);
+                list_query.push_where(&mut query, false);
+                list_query.push_order_by(&mut query, "ID");
+                query
+                    .push(" LIMIT ")
+                    .push_bind(*size)
+                    .push(" OFFSET ")
+                    .push_bind(*offset);
+                let hits: Vec<T> = query
+                    .build_query_as()
+                    .fetch_all(&mut self.connection().await?)
+                    .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_rows(&self, table: &'static str, list_query: &ListQuery) -> ApiResult<i64> {
+        let mut query = QueryBuilder::new("SELECT COUNT(1) AS count FROM ");
+        query.push(table);
+        list_query.push_where(&mut query, false);
+        let row_count: RowCount = query
+            .build_query_as()
+            .fetch_one(&mut self.connection().await?)
+            .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    /// Runs `sql`, a `SELECT COUNT(1) AS count` of the rows related to `id`.
+    async fn count_related(&self, sql: &'static str, id: i64) -> ApiResult<i64> {
+        let row_count = sqlx::query_as::<_, RowCount>(sql)
+            .bind(id)
+            .fetch_one(&mut self.connection().await?)
+            .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    /// The live rows of `base_query`, a `SELECT` without a `WHERE` clause,
+    /// whose `column` is one of `ids`, in id order.
+    async fn fetch_rows_in<T>(
+        &self,
+        base_query: &'static str,
+        column: &'static str,
+        ids: &[i64],
+    ) -> ApiResult<Vec<T>>
+    where
+        T: for<'row> FromRow<'row, SqliteRow> + Send + Unpin,
+    {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        // SQLite has no array parameters, so the ids are bound one by one.
+        let mut query = QueryBuilder::new(base_query);
+        query
+            .push(" WHERE DELETED_AT IS NULL AND ")
+            .push(column)
+            .push(" IN (");
+        let mut bound_ids = query.separated(", ");
+        for id in ids {
+            bound_ids.push_bind(*id);
+        }
+        query.push(") ORDER BY ID");
+        let rows: Vec<T> = query
+            .build_query_as()
+            .fetch_all(&mut self.connection().await?)
+            .await?;
+        return Ok(rows);
+    }
+
+    /// Streams the rows of `sql` from a task of their own, since the stream
+    /// of a query borrows the pool.
+    fn export_rows<T>(&self, sql: &'static str) -> RowStream<T>
+    where
+        T: for<'row> FromRow<'row, SqliteRow> + Send + Unpin + 'static,
+    {
+        let database_connection_pool = self.database_connection_pool.clone();
+        let (mut sender, receiver) = futures::channel::mpsc::channel(16);
+        let export = async move {
+            let mut connection = match acquire(&database_connection_pool).await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    let _ = sender.send(Err(error)).await;
+                    return;
+                }
+            };
+            let mut rows = sqlx::query_as::<_, T>(sql).fetch(&mut *connection);
+            while let Some(row) = rows.next().await {
+                let failed = row.is_err();
+                if sender.send(row).await.is_err() || failed {
+                    break;
+                }
+            }
+        };
+        // Keeps the query logs of the export in the span of its request.
+        tokio::spawn(export.instrument(tracing::Span::current()));
+        return receiver.boxed();
+    }
+}
+
+#[async_trait]
+impl PersonRepository for SqliteRepository {
+    async fn get_person(&self, person_id: i64, include_deleted: bool) -> ApiResult<Option<Person>> {
+        let person = sqlx::query_as::<_, Person>(
+            r#"
+            SELECT id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+            FROM PERSON
+            WHERE ID = $1 AND ($2 OR DELETED_AT IS NULL)
+            "#,
+        )
+        .bind(person_id)
+        .bind(include_deleted)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn list_people(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<Person>> {
+        let page = self
+            .list_rows(
+                r#"
+                SELECT id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+                FROM PERSON
+                "#,
+                list_query,
+                page_request,
+            )
+            .await?;
+        return Ok(page);
+    }
+
+    async fn count_people(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let count = self.count_rows("PERSON", list_query).await?;
+        return Ok(count);
+    }
+
+    async fn get_people(&self, person_ids: &[i64]) -> ApiResult<Vec<Person>> {
+        let people = self
+            .fetch_rows_in(
+                r#"
+                SELECT id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+                FROM PERSON
+                "#,
+                "ID",
+                person_ids,
+            )
+            .await?;
+        return Ok(people);
+    }
+
+    fn export_people(&self) -> RowStream<Person> {
+        return self.export_rows(
+            r#"
+            SELECT id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+            FROM PERSON
+            WHERE DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+        );
+    }
+
+    async fn import_people(&self, people: &[NewPerson]) -> ApiResult<Vec<Person>> {
+        let now = OffsetDateTime::now_utc();
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let mut imported = Vec::with_capacity(people.len());
+        for batch in people.chunks(IMPORT_BATCH_SIZE) {
+            let mut query = QueryBuilder::new(
+                "INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT ) ",
+            );
+            query.push_values(batch, |mut row, person| {
+                row.push_bind(&person.person_name);
+                row.push_bind(person.age);
+                row.push_bind(&person.email);
+                row.push_bind(now);
+                row.push_bind(now);
+            });
+            query.push(
+                " RETURNING id, person_name, age, email, created_at, updated_at, deleted_at, row_version",
+            );
+            imported.extend(
+                query
+                    .build_query_as::<Person>()
+                    .fetch_all(&mut transaction)
+                    .await?,
+            );
+        }
+        transaction.commit().await?;
+        imported.sort_by_key(|person| person.id);
+        return Ok(imported);
+    }
+
+    async fn insert_person(&self, new_person: &NewPerson) -> ApiResult<Person> {
+        let person = sqlx::query_as::<_, Person>(
+            r#"
+            INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT )
+            VALUES ( $1, $2, $3, $4, $4 )
+            RETURNING id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(&new_person.person_name)
+        .bind(new_person.age)
+        .bind(&new_person.email)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn update_person(
+        &self,
+        person_id: i64,
+        person: &NewPerson,
+        row_version: i64,
+    ) -> ApiResult<Option<Person>> {
+        let person = sqlx::query_as::<_, Person>(
+            r#"
+            UPDATE PERSON
+            SET PERSON_NAME = $2, AGE = $3, EMAIL = $4, UPDATED_AT = $5,
+                ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $6
+            RETURNING id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(person_id)
+        .bind(&person.person_name)
+        .bind(person.age)
+        .bind(&person.email)
+        .bind(OffsetDateTime::now_utc())
+        .bind(row_version)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn patch_person(
+        &self,
+        person_id: i64,
+        patch: &PersonPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<Person>> {
+        let person = sqlx::query_as::<_, Person>(
+            r#"
+            UPDATE PERSON
+            SET PERSON_NAME = COALESCE($2, PERSON_NAME),
+                AGE = COALESCE($3, AGE),
+                EMAIL = COALESCE($4, EMAIL),
+                UPDATED_AT = $5,
+                ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $6
+            RETURNING id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(person_id)
+        .bind(&patch.person_name)
+        .bind(patch.age)
+        .bind(&patch.email)
+        .bind(OffsetDateTime::now_utc())
+        .bind(row_version)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn delete_person(&self, person_id: i64) -> ApiResult<Deletion> {
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        // Same single-statement check as `delete_fruit`.
+        let delete_result = sqlx::query(
+            r#"
+            UPDATE PERSON SET DELETED_AT = $2, UPDATED_AT = $2, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL
+                  AND NOT EXISTS (SELECT 1 FROM FRUIT_SALAD
+                                  WHERE ID_CREATOR = $1 AND DELETED_AT IS NULL)
+            "#,
+        )
+        .bind(person_id)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&mut transaction)
+        .await?;
+
+        if delete_result.rows_affected() == 0 {
+            let usage = sqlx::query_as::<_, RowCount>(
+                "SELECT COUNT(1) AS count FROM FRUIT_SALAD WHERE ID_CREATOR = $1 AND DELETED_AT IS NULL",
+            )
+            .bind(person_id)
+            .fetch_one(&mut transaction)
+            .await?;
+            let usage_count = usage.count.unwrap_or_default();
+            if usage_count > 0 {
+                return Ok(Deletion::InUse(usage_count));
+            }
+            return Ok(Deletion::NotFound);
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE AUTH_SESSION SET REVOKED_AT = STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now')
+            WHERE ID_PERSON = $1 AND REVOKED_AT IS NULL
+            "#,
+        )
+        .bind(person_id)
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        return Ok(Deletion::Deleted);
+    }
+
+    async fn restore_person(&self, person_id: i64) -> ApiResult<Restoration<Person>> {
+        match self.get_person(person_id, true).await? {
+            None => return Ok(Restoration::NotFound),
+            Some(person) if person.deleted_at.is_none() => {
+                return Ok(Restoration::Restored(person))
+            }
+            Some(_) => {}
+        }
+
+        let person = sqlx::query_as::<_, Person>(
+            r#"
+            UPDATE PERSON SET DELETED_AT = NULL, UPDATED_AT = $2, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1
+            RETURNING id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(person_id)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(Restoration::Restored(person));
+    }
+
+    async fn set_person_role(&self, person_id: i64, role: Role) -> ApiResult<Option<Role>> {
+        let person_role = sqlx::query_as::<_, (String,)>(
+            r#"
+            UPDATE PERSON SET PERSON_ROLE = $2, UPDATED_AT = $3, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            RETURNING PERSON_ROLE
+            "#,
+        )
+        .bind(person_id)
+        .bind(role.as_str())
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(person_role.map(|(person_role,)| Role::parse(&person_role)));
+    }
+
+    async fn register_person(
+        &self,
+        new_person: &NewPerson,
+        password_hash: &str,
+    ) -> ApiResult<Person> {
+        let person = sqlx::query_as::<_, Person>(
+            r#"
+            INSERT INTO PERSON ( PERSON_NAME, AGE, EMAIL, PASSWORD_HASH, CREATED_AT, UPDATED_AT )
+            VALUES ( $1, $2, $3, $4, $5, $5 )
+            RETURNING id, person_name, age, email, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(&new_person.person_name)
+        .bind(new_person.age)
+        .bind(&new_person.email)
+        .bind(password_hash)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(person);
+    }
+
+    async fn find_account(&self, email: &str) -> ApiResult<Option<Account>> {
+        let account = sqlx::query_as::<_, AccountRow>(
+            r#"
+            SELECT ID, PERSON_NAME, AGE, EMAIL, CREATED_AT, UPDATED_AT, DELETED_AT, ROW_VERSION,
+                   PERSON_ROLE, PASSWORD_HASH
+            FROM PERSON
+            WHERE LOWER(EMAIL) = LOWER($1) AND PASSWORD_HASH IS NOT NULL AND DELETED_AT IS NULL
+            "#,
+        )
+        .bind(email)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+
+        return Ok(account.map(
+            |(
+                id,
+                person_name,
+                age,
+                email,
+                created_at,
+                updated_at,
+                deleted_at,
+                row_version,
+                person_role,
+                password_hash,
+            )| {
+                return Account {
+                    person: Person {
+                        id,
+                        person_name,
+                        age,
+                        email,
+                        created_at,
+                        updated_at,
+                        deleted_at,
+                        row_version,
+                    },
+                    role: Role::parse(&person_role),
+                    password_hash,
+                };
+            },
+        ));
+    }
+
+    async fn create_session(&self, person_id: i64, expires_at: OffsetDateTime) -> ApiResult<i64> {
+        let (session_id,) = sqlx::query_as::<_, (i64,)>(
+            "INSERT INTO AUTH_SESSION ( ID_PERSON, EXPIRES_AT ) VALUES ( $1, $2 ) RETURNING ID",
+        )
+        .bind(person_id)
+        .bind(expires_at)
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(session_id);
+    }
+
+    async fn find_session(&self, session_id: i64) -> ApiResult<Option<(Person, Role)>> {
+        let account = sqlx::query_as::<_, SessionRow>(
+            r#"
+            SELECT PERSON.ID, PERSON_NAME, AGE, EMAIL, PERSON.CREATED_AT, PERSON.UPDATED_AT,
+                   PERSON.DELETED_AT, ROW_VERSION, PERSON_ROLE
+            FROM AUTH_SESSION
+            JOIN PERSON ON ID_PERSON = PERSON.ID
+            WHERE AUTH_SESSION.ID = $1 AND REVOKED_AT IS NULL
+                AND JULIANDAY(EXPIRES_AT) > JULIANDAY('now')
+                AND PERSON.DELETED_AT IS NULL
+            "#,
+        )
+        .bind(session_id)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+
+        return Ok(account.map(
+            |(
+                id,
+                person_name,
+                age,
+                email,
+                created_at,
+                updated_at,
+                deleted_at,
+                row_version,
+                person_role,
+            )| {
+                let person = Person {
+                    id,
+                    person_name,
+                    age,
+                    email,
+                    created_at,
+                    updated_at,
+                    deleted_at,
+                    row_version,
+                };
+                return (person, Role::parse(&person_role));
+            },
+        ));
+    }
+
+    async fn revoke_session(&self, session_id: i64) -> ApiResult<()> {
+        sqlx::query(
+            "UPDATE AUTH_SESSION SET REVOKED_AT = STRFTIME('%Y-%m-%dT%H:%M:%SZ', 'now') WHERE ID = $1",
+        )
+        .bind(session_id)
+        .execute(&mut self.connection().await?)
+        .await?;
+        return Ok(());
+    }
+}
+
+#[async_trait]
+impl FruitRepository for SqliteRepository {
+    async fn get_fruit(&self, fruit_id: i64, include_deleted: bool) -> ApiResult<Option<Fruit>> {
+        let fruit = sqlx::query_as::<_, Fruit>(
+            r#"
+            SELECT id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                   updated_at, deleted_at, row_version
+            FROM FRUIT
+            WHERE ID = $1 AND ($2 OR DELETED_AT IS NULL)
+            "#,
+        )
+        .bind(fruit_id)
+        .bind(include_deleted)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(fruit);
+    }
+
+    async fn list_fruits(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<Fruit>> {
+        let page = self
+            .list_rows(
+                r#"
+                SELECT id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                       updated_at, deleted_at, row_version
+                FROM FRUIT
+                "#,
+                list_query,
+                page_request,
+            )
+            .await?;
+        return Ok(page);
+    }
+
+    async fn count_fruits(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let count = self.count_rows("FRUIT", list_query).await?;
+        return Ok(count);
+    }
+
+    async fn get_fruits(&self, fruit_ids: &[i64]) -> ApiResult<Vec<Fruit>> {
+        let fruits = self
+            .fetch_rows_in(
+                r#"
+                SELECT id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                       updated_at, deleted_at, row_version
+                FROM FRUIT
+                "#,
+                "ID",
+                fruit_ids,
+            )
+            .await?;
+        return Ok(fruits);
+    }
+
+    fn export_fruits(&self) -> RowStream<Fruit> {
+        return self.export_rows(
+            r#"
+            SELECT id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                   updated_at, deleted_at, row_version
+            FROM FRUIT
+            WHERE DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+        );
+    }
+
+    async fn import_fruits(&self, fruits: &[NewFruit]) -> ApiResult<Vec<Fruit>> {
+        let now = OffsetDateTime::now_utc();
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let mut imported = Vec::with_capacity(fruits.len());
+        for batch in fruits.chunks(IMPORT_BATCH_SIZE) {
+            let mut query = QueryBuilder::new(
+                r#"
+                INSERT INTO FRUIT ( FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, FRUIT_WEIGHT,
+                                    CREATED_AT, UPDATED_AT )
+                "#,
+            );
+            query.push_values(batch, |mut row, fruit| {
+                row.push_bind(&fruit.fruit_name);
+                row.push_bind(fruit.color_red);
+                row.push_bind(fruit.color_green);
+                row.push_bind(fruit.color_blue);
+                row.push_bind(fruit.fruit_weight);
+                row.push_bind(now);
+                row.push_bind(now);
+            });
+            query.push(
+                r#"
+                RETURNING id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                          updated_at, deleted_at, row_version
+                "#,
+            );
+            imported.extend(
+                query
+                    .build_query_as::<Fruit>()
+                    .fetch_all(&mut transaction)
+                    .await?,
+            );
+        }
+        transaction.commit().await?;
+        imported.sort_by_key(|fruit| fruit.id);
+        return Ok(imported);
+    }
+
+    async fn insert_fruit(&self, new_fruit: &NewFruit) -> ApiResult<Fruit> {
+        let fruit = sqlx::query_as::<_, Fruit>(
+            r#"
+            INSERT INTO FRUIT ( FRUIT_NAME, COLOR_RED, COLOR_GREEN, COLOR_BLUE, FRUIT_WEIGHT,
+                                CREATED_AT, UPDATED_AT )
+            VALUES ( $1, $2, $3, $4, $5, $6, $6 )
+            RETURNING id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                      updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(&new_fruit.fruit_name)
+        .bind(new_fruit.color_red)
+        .bind(new_fruit.color_green)
+        .bind(new_fruit.color_blue)
+        .bind(new_fruit.fruit_weight)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(fruit);
+    }
+
+    async fn update_fruit(
+        &self,
+        fruit_id: i64,
+        fruit: &NewFruit,
+        row_version: i64,
+    ) -> ApiResult<Option<Fruit>> {
+        let fruit = sqlx::query_as::<_, Fruit>(r#"
+            UPDATE FRUIT
+            SET FRUIT_NAME = $2, COLOR_RED = $3, COLOR_GREEN = $4, COLOR_BLUE = $5, FRUIT_WEIGHT = $6,
+                UPDATED_AT = $7, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $8
+            RETURNING id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                      updated_at, deleted_at, row_version
+            "#)
+        .bind(fruit_id)
+        .bind(&fruit.fruit_name)
+        .bind(fruit.color_red)
+        .bind(fruit.color_green)
+        .bind(fruit.color_blue)
+        .bind(fruit.fruit_weight)
+        .bind(OffsetDateTime::now_utc())
+        .bind(row_version)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(fruit);
+    }
+
+    async fn patch_fruit(
+        &self,
+        fruit_id: i64,
+        patch: &FruitPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<Fruit>> {
+        let fruit = sqlx::query_as::<_, Fruit>(
+            r#"
+            UPDATE FRUIT
+            SET FRUIT_NAME = COALESCE($2, FRUIT_NAME),
+                COLOR_RED = COALESCE($3, COLOR_RED),
+                COLOR_GREEN = COALESCE($4, COLOR_GREEN),
+                COLOR_BLUE = COALESCE($5, COLOR_BLUE),
+                FRUIT_WEIGHT = COALESCE($6, FRUIT_WEIGHT),
+                UPDATED_AT = $7, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $8
+            RETURNING id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                      updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(fruit_id)
+        .bind(&patch.fruit_name)
+        .bind(patch.color_red)
+        .bind(patch.color_green)
+        .bind(patch.color_blue)
+        .bind(patch.fruit_weight)
+        .bind(OffsetDateTime::now_utc())
+        .bind(row_version)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(fruit);
+    }
+
+    async fn delete_fruit(&self, fruit_id: i64) -> ApiResult<Deletion> {
+        // One statement, so no ingredient can be added between the check and
+        // the delete. Usage is only counted to report why nothing changed.
+        let delete_result = sqlx::query(
+            r#"
+            UPDATE FRUIT SET DELETED_AT = $2, UPDATED_AT = $2, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL
+                  AND NOT EXISTS (SELECT 1 FROM SALAD_INGREDIENTS
+                                  WHERE ID_FRUIT = $1 AND DELETED_AT IS NULL)
+            "#,
+        )
+        .bind(fruit_id)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&mut self.connection().await?)
+        .await?;
+        if delete_result.rows_affected() > 0 {
+            return Ok(Deletion::Deleted);
+        }
+
+        let usage_count = self
+            .count_related(
+                r#"
+                SELECT COUNT(1) AS count FROM SALAD_INGREDIENTS
+                WHERE ID_FRUIT = $1 AND DELETED_AT IS NULL
+                "#,
+                fruit_id,
+            )
+            .await?;
+        if usage_count > 0 {
+            return Ok(Deletion::InUse(usage_count));
+        }
+        return Ok(Deletion::NotFound);
+    }
+
+    async fn restore_fruit(&self, fruit_id: i64) -> ApiResult<Restoration<Fruit>> {
+        match self.get_fruit(fruit_id, true).await? {
+            None => return Ok(Restoration::NotFound),
+            Some(fruit) if fruit.deleted_at.is_none() => return Ok(Restoration::Restored(fruit)),
+            Some(_) => {}
+        }
+
+        let fruit = sqlx::query_as::<_, Fruit>(
+            r#"
+            UPDATE FRUIT SET DELETED_AT = NULL, UPDATED_AT = $2, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1
+            RETURNING id, fruit_name, color_red, color_green, color_blue, fruit_weight, created_at,
+                      updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(fruit_id)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(Restoration::Restored(fruit));
+    }
+}
+
+#[async_trait]
+impl SaladRepository for SqliteRepository {
+    async fn get_salad(
+        &self,
+        salad_id: i64,
+        include_deleted: bool,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let salad = sqlx::query_as::<_, FruitSalad>(
+            r#"
+            SELECT id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+            FROM FRUIT_SALAD
+            WHERE ID = $1 AND ($2 OR DELETED_AT IS NULL)
+            "#,
+        )
+        .bind(salad_id)
+        .bind(include_deleted)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(salad);
+    }
+
+    async fn list_salads(
+        &self,
+        list_query: &ListQuery,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<FruitSalad>> {
+        let page = self
+            .list_rows(
+                r#"
+                SELECT id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+                FROM FRUIT_SALAD
+                "#,
+                list_query,
+                page_request,
+            )
+            .await?;
+        return Ok(page);
+    }
+
+    async fn count_salads(&self, list_query: &ListQuery) -> ApiResult<i64> {
+        let count = self.count_rows("FRUIT_SALAD", list_query).await?;
+        return Ok(count);
+    }
+
+    async fn get_salads(&self, salad_ids: &[i64]) -> ApiResult<Vec<FruitSalad>> {
+        let salads = self
+            .fetch_rows_in(
+                r#"
+                SELECT id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+                FROM FRUIT_SALAD
+                "#,
+                "ID",
+                salad_ids,
+            )
+            .await?;
+        return Ok(salads);
+    }
+
+    async fn list_salads_of_creators(&self, creator_ids: &[i64]) -> ApiResult<Vec<FruitSalad>> {
+        let salads = self
+            .fetch_rows_in(
+                r#"
+                SELECT id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+                FROM FRUIT_SALAD
+                "#,
+                "ID_CREATOR",
+                creator_ids,
+            )
+            .await?;
+        return Ok(salads);
+    }
+
+    async fn list_salads_by_creator(
+        &self,
+        creator_id: i64,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<SaladView>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = self
+                    .fetch_cursor_page(
+                        |query| {
+                            query
+                                .push(
+                                    r#"
+                                    SELECT FRUIT_SALAD.ID AS id, person_name, salad_name FROM FRUIT_SALAD
+                                    JOIN PERSON ON ID_CREATOR = PERSON.ID
+                                    where FRUIT_SALAD.DELETED_AT IS NULL AND ID_CREATOR = "#,
+                                )
+                                .push_bind(creator_id);
+                        },
+                        cursor,
+                        *size,
+                    )
+                    .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as::<_, SaladView>(
+                    r#"
+                    SELECT FRUIT_SALAD.ID AS id, person_name, salad_name FROM FRUIT_SALAD
+                    JOIN PERSON ON ID_CREATOR = PERSON.ID
+                    where FRUIT_SALAD.DELETED_AT IS NULL AND ID_CREATOR = $3
+                    LIMIT $1 OFFSET $2
+                    "#,
+                )
+                .bind(size)
+                .bind(offset)
+                .bind(creator_id)
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_salads_by_creator(&self, creator_id: i64) -> ApiResult<i64> {
+        let count = self
+            .count_related(
+                r#"
+                SELECT COUNT(1) AS count from FRUIT_SALAD
+                where DELETED_AT IS NULL AND ID_CREATOR = $1
+                "#,
+                creator_id,
+            )
+            .await?;
+        return Ok(count);
+    }
+
+    async fn list_salad_ingredient_views(
+        &self,
+        salad_id: i64,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<SaladIngredientsView>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = self
+                    .fetch_cursor_page(
+                        |query| {
+                            query
+                                .push(
+                                    r#"
+                                    SELECT SALAD_INGREDIENTS.ID AS id, person_name, salad_name,
+                                    fruit_name, quantity_grams
+                                    FROM FRUIT_SALAD
+                                    JOIN PERSON ON ID_CREATOR = PERSON.ID
+                                    JOIN SALAD_INGREDIENTS ON ID_SALAD = FRUIT_SALAD.ID
+                                    JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+                                    where FRUIT_SALAD.DELETED_AT IS NULL
+                                    AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+                                    AND FRUIT_SALAD.ID = "#,
+                                )
+                                .push_bind(salad_id);
+                        },
+                        cursor,
+                        *size,
+                    )
+                    .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as::<_, SaladIngredientsView>(
+                    r#"
+                    SELECT SALAD_INGREDIENTS.ID AS id, person_name, salad_name, fruit_name, quantity_grams
+                    FROM FRUIT_SALAD
+                    JOIN PERSON ON ID_CREATOR = PERSON.ID
+                    JOIN SALAD_INGREDIENTS ON ID_SALAD = FRUIT_SALAD.ID
+                    JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+                    where FRUIT_SALAD.DELETED_AT IS NULL
+                    AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+                    AND FRUIT_SALAD.ID = $3
+                    LIMIT $1 OFFSET $2
+                    "#,
+                )
+                .bind(size)
+                .bind(offset)
+                .bind(salad_id)
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_salad_ingredient_views(&self, salad_id: i64) -> ApiResult<i64> {
+        let count = self
+            .count_related(
+                r#"
+                SELECT COUNT(1) AS count from FRUIT_SALAD
+                JOIN PERSON ON ID_CREATOR = PERSON.ID
+                JOIN SALAD_INGREDIENTS ON ID_SALAD = FRUIT_SALAD.ID
+                JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+                where FRUIT_SALAD.DELETED_AT IS NULL
+                AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+                AND FRUIT_SALAD.ID = $1
+                "#,
+                salad_id,
+            )
+            .await?;
+        return Ok(count);
+    }
+
+    async fn salad_components(&self, salad_id: i64) -> ApiResult<Vec<SaladComponent>> {
+        let components = sqlx::query_as::<_, SaladComponent>(
+            r#"
+            SELECT FRUIT.ID AS id_fruit, fruit_name, color_red, color_green, color_blue, quantity_grams
+            FROM SALAD_INGREDIENTS
+            JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+            WHERE ID_SALAD = $1 AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+            ORDER BY QUANTITY_GRAMS DESC, FRUIT.ID
+            "#,
+        )
+        .bind(salad_id)
+        .fetch_all(&mut self.connection().await?)
+        .await?;
+        return Ok(components);
+    }
+
+    fn export_salads(&self) -> RowStream<FruitSalad> {
+        return self.export_rows(
+            r#"
+            SELECT id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+            FROM FRUIT_SALAD
+            WHERE DELETED_AT IS NULL
+            ORDER BY ID
+            "#,
+        );
+    }
+
+    async fn insert_salad(
+        &self,
+        creator_id: i64,
+        new_salad: &NewFruitSalad,
+    ) -> ApiResult<FullFruitSalad> {
+        let now = OffsetDateTime::now_utc();
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let salad = sqlx::query_as::<_, FruitSalad>(
+            r#"
+            INSERT INTO FRUIT_SALAD ( ID_CREATOR, SALAD_NAME, CREATED_AT, UPDATED_AT )
+            VALUES ( $1, $2, $3, $3 )
+            RETURNING id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(creator_id)
+        .bind(&new_salad.salad_name)
+        .bind(now)
+        .fetch_one(&mut transaction)
+        .await?;
+
+        let mut ingredients = Vec::new();
+        if !new_salad.ingredients.is_empty() {
+            // SQLite has no array parameters, so the ids are bound one by one.
+            let mut query =
+                QueryBuilder::new("SELECT ID FROM FRUIT WHERE DELETED_AT IS NULL AND ID IN (");
+            let mut fruit_ids = query.separated(", ");
+            for fruit_id in &new_salad.ingredients {
+                fruit_ids.push_bind(*fruit_id);
+            }
+            query.push(")");
+            let existing_fruits: Vec<(i64,)> =
+                query.build_query_as().fetch_all(&mut transaction).await?;
+
+            let mut missing_fruits: Vec<i64> = new_salad
+                .ingredients
+                .iter()
+                .copied()
+                .filter(|fruit_id| !existing_fruits.iter().any(|(id,)| id == fruit_id))
+                .collect();
+            if !missing_fruits.is_empty() {
+                missing_fruits.sort_unstable();
+                missing_fruits.dedup();
+                return Err(ApiError::ForeignKeyViolation(format!(
+                    "Fruit(s) {:?} not found",
+                    missing_fruits
+                )));
+            }
+
+            for fruit_id in &new_salad.ingredients {
+                let ingredient = sqlx::query_as::<_, SaladIngredient>(
+                    r#"
+                    INSERT INTO SALAD_INGREDIENTS ( ID_SALAD, ID_FRUIT, QUANTITY_GRAMS, CREATED_AT,
+                                                    UPDATED_AT )
+                    SELECT $1, FRUIT.ID, FRUIT.FRUIT_WEIGHT, $3, $3 FROM FRUIT WHERE FRUIT.ID = $2
+                    RETURNING id, id_salad, id_fruit, quantity_grams, created_at, updated_at,
+                              deleted_at
+                    "#,
+                )
+                .bind(salad.id)
+                .bind(fruit_id)
+                .bind(now)
+                .fetch_one(&mut transaction)
+                .await?;
+                ingredients.push(ingredient);
+            }
+        }
+
+        transaction.commit().await?;
+        return Ok(FullFruitSalad { salad, ingredients });
+    }
+
+    async fn update_salad(
+        &self,
+        salad_id: i64,
+        salad: &NewFruitSalad,
+        row_version: i64,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let salad = sqlx::query_as::<_, FruitSalad>(
+            r#"
+            UPDATE FRUIT_SALAD
+            SET SALAD_NAME = $2, UPDATED_AT = $3, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $4
+            RETURNING id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(salad_id)
+        .bind(&salad.salad_name)
+        .bind(OffsetDateTime::now_utc())
+        .bind(row_version)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(salad);
+    }
+
+    async fn patch_salad(
+        &self,
+        salad_id: i64,
+        patch: &FruitSaladPatch,
+        row_version: i64,
+    ) -> ApiResult<Option<FruitSalad>> {
+        let salad = sqlx::query_as::<_, FruitSalad>(
+            r#"
+            UPDATE FRUIT_SALAD
+            SET SALAD_NAME = COALESCE($2, SALAD_NAME),
+                UPDATED_AT = $3,
+                ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL AND ROW_VERSION = $4
+            RETURNING id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(salad_id)
+        .bind(&patch.salad_name)
+        .bind(OffsetDateTime::now_utc())
+        .bind(row_version)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(salad);
+    }
+
+    async fn delete_salad(&self, salad_id: i64) -> ApiResult<Option<Vec<i64>>> {
+        // The salad and its ingredients share `deleted_at`, which is how a
+        // restore finds the ingredients deleted along with the salad.
+        let now = OffsetDateTime::now_utc();
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+
+        let delete_result = sqlx::query(
+            r#"
+            UPDATE FRUIT_SALAD SET DELETED_AT = $2, UPDATED_AT = $2, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            "#,
+        )
+        .bind(salad_id)
+        .bind(now)
+        .execute(&mut transaction)
+        .await?;
+
+        if delete_result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let mut ingredient_ids = sqlx::query_as::<_, (i64,)>(
+            r#"
+            UPDATE SALAD_INGREDIENTS SET DELETED_AT = $2, UPDATED_AT = $2
+            WHERE ID_SALAD = $1 AND DELETED_AT IS NULL
+            RETURNING id
+            "#,
+        )
+        .bind(salad_id)
+        .bind(now)
+        .fetch_all(&mut transaction)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect::<Vec<i64>>();
+
+        transaction.commit().await?;
+        ingredient_ids.sort_unstable();
+        return Ok(Some(ingredient_ids));
+    }
+
+    async fn restore_salad(
+        &self,
+        salad_id: i64,
+    ) -> ApiResult<Restoration<(FruitSalad, Vec<SaladIngredient>)>> {
+        let salad = match self.get_salad(salad_id, true).await? {
+            None => return Ok(Restoration::NotFound),
+            Some(salad) => salad,
+        };
+        let Some(deleted_at) = salad.deleted_at else {
+            return Ok(Restoration::Restored((salad, Vec::new())));
+        };
+
+        if self.get_person(salad.id_creator, false).await?.is_none() {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Person {}",
+                salad.id_creator
+            )));
+        }
+
+        let (deleted_fruit,) = sqlx::query_as::<_, (Option<i64>,)>(
+            r#"
+            SELECT MIN(FRUIT.ID) FROM SALAD_INGREDIENTS
+            JOIN FRUIT ON SALAD_INGREDIENTS.ID_FRUIT = FRUIT.ID
+            WHERE ID_SALAD = $1 AND SALAD_INGREDIENTS.DELETED_AT = $2
+              AND FRUIT.DELETED_AT IS NOT NULL
+            "#,
+        )
+        .bind(salad_id)
+        .bind(deleted_at)
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        if let Some(fruit_id) = deleted_fruit {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Fruit {}",
+                fruit_id
+            )));
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let mut ingredients = sqlx::query_as::<_, SaladIngredient>(
+            r#"
+            UPDATE SALAD_INGREDIENTS SET DELETED_AT = NULL, UPDATED_AT = $3
+            WHERE ID_SALAD = $1 AND DELETED_AT = $2
+            RETURNING id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(salad_id)
+        .bind(deleted_at)
+        .bind(now)
+        .fetch_all(&mut transaction)
+        .await?;
+
+        let salad = sqlx::query_as::<_, FruitSalad>(
+            r#"
+            UPDATE FRUIT_SALAD
+            SET DELETED_AT = NULL, UPDATED_AT = $2, ROW_VERSION = ROW_VERSION + 1
+            WHERE ID = $1
+            RETURNING id, id_creator, salad_name, created_at, updated_at, deleted_at, row_version
+            "#,
+        )
+        .bind(salad_id)
+        .bind(now)
+        .fetch_one(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+        ingredients.sort_by_key(|ingredient| ingredient.id);
+        return Ok(Restoration::Restored((salad, ingredients)));
+    }
+}
+
+#[async_trait]
+impl SaladIngredientRepository for SqliteRepository {
+    async fn get_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        include_deleted: bool,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let ingredient = sqlx::query_as::<_, SaladIngredient>(
+            r#"
+            SELECT id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+            FROM SALAD_INGREDIENTS
+            WHERE ID = $1 AND ($2 OR DELETED_AT IS NULL)
+            "#,
+        )
+        .bind(ingredient_id)
+        .bind(include_deleted)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient);
+    }
+
+    async fn get_salad_ingredient_owner(&self, ingredient_id: i64) -> ApiResult<Option<i64>> {
+        let owner = sqlx::query_as::<_, (i64,)>(
+            r#"
+            SELECT ID_CREATOR FROM SALAD_INGREDIENTS
+            JOIN FRUIT_SALAD ON ID_SALAD = FRUIT_SALAD.ID
+            WHERE SALAD_INGREDIENTS.ID = $1 AND SALAD_INGREDIENTS.DELETED_AT IS NULL
+            "#,
+        )
+        .bind(ingredient_id)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(owner.map(|(id_creator,)| id_creator));
+    }
+
+    async fn list_salad_ingredients(
+        &self,
+        page_request: &PageRequest,
+        include_deleted: bool,
+    ) -> ApiResult<Page<SaladIngredient>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = self
+                    .fetch_cursor_page(
+                        |query| {
+                            query.push(
+                                r#"
+                                SELECT id, id_salad, id_fruit, quantity_grams, created_at,
+                                       updated_at, deleted_at
+                                FROM SALAD_INGREDIENTS
+                                "#,
+                            );
+                            if !include_deleted {
+                                query.push(" WHERE DELETED_AT IS NULL");
+                            }
+                        },
+                        cursor,
+                        *size,
+                    )
+                    .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as::<_, SaladIngredient>(
+                    r#"
+                    SELECT id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+                    FROM SALAD_INGREDIENTS
+                    WHERE $3 OR DELETED_AT IS NULL
+                    LIMIT $1 OFFSET $2
+                    "#,
+                )
+                .bind(size)
+                .bind(offset)
+                .bind(include_deleted)
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_salad_ingredients(&self, include_deleted: bool) -> ApiResult<i64> {
+        let row_count = sqlx::query_as::<_, RowCount>(
+            "SELECT COUNT(1) AS count from SALAD_INGREDIENTS WHERE $1 OR DELETED_AT IS NULL",
+        )
+        .bind(include_deleted)
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    async fn get_salad_ingredients(
+        &self,
+        ingredient_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let ingredients = self
+            .fetch_rows_in(
+                r#"
+                SELECT id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+                FROM SALAD_INGREDIENTS
+                "#,
+                "ID",
+                ingredient_ids,
+            )
+            .await?;
+        return Ok(ingredients);
+    }
+
+    async fn list_ingredients_of_salads(
+        &self,
+        salad_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let ingredients = self
+            .fetch_rows_in(
+                r#"
+                SELECT id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+                FROM SALAD_INGREDIENTS
+                "#,
+                "ID_SALAD",
+                salad_ids,
+            )
+            .await?;
+        return Ok(ingredients);
+    }
+
+    async fn list_ingredients_of_fruits(
+        &self,
+        fruit_ids: &[i64],
+    ) -> ApiResult<Vec<SaladIngredient>> {
+        let ingredients = self
+            .fetch_rows_in(
+                r#"
+                SELECT id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+                FROM SALAD_INGREDIENTS
+                "#,
+                "ID_FRUIT",
+                fruit_ids,
+            )
+            .await?;
+        return Ok(ingredients);
+    }
+
+    async fn insert_salad_ingredient(
+        &self,
+        new_ingredient: &NewSaladIngredient,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let ingredient = sqlx::query_as::<_, SaladIngredient>(
+            r#"
+            INSERT INTO SALAD_INGREDIENTS ( ID_SALAD, ID_FRUIT, QUANTITY_GRAMS, CREATED_AT,
+                                            UPDATED_AT )
+            SELECT $1, FRUIT.ID, COALESCE($3, FRUIT.FRUIT_WEIGHT), $4, $4 FROM FRUIT
+            WHERE FRUIT.ID = $2 AND FRUIT.DELETED_AT IS NULL
+            RETURNING id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(new_ingredient.id_salad)
+        .bind(new_ingredient.id_fruit)
+        .bind(new_ingredient.quantity_grams)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient);
+    }
+
+    async fn update_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        ingredient: &NewSaladIngredient,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let ingredient = sqlx::query_as::<_, SaladIngredient>(
+            r#"
+            UPDATE SALAD_INGREDIENTS
+            SET ID_SALAD = $2, ID_FRUIT = $3, QUANTITY_GRAMS = COALESCE($4, QUANTITY_GRAMS),
+                UPDATED_AT = $5
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            RETURNING id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(ingredient_id)
+        .bind(ingredient.id_salad)
+        .bind(ingredient.id_fruit)
+        .bind(ingredient.quantity_grams)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient);
+    }
+
+    async fn patch_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+        patch: &SaladIngredientPatch,
+    ) -> ApiResult<Option<SaladIngredient>> {
+        let ingredient = sqlx::query_as::<_, SaladIngredient>(
+            r#"
+            UPDATE SALAD_INGREDIENTS
+            SET ID_SALAD = COALESCE($2, ID_SALAD),
+                ID_FRUIT = COALESCE($3, ID_FRUIT),
+                QUANTITY_GRAMS = COALESCE($4, QUANTITY_GRAMS),
+                UPDATED_AT = $5
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            RETURNING id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(ingredient_id)
+        .bind(patch.id_salad)
+        .bind(patch.id_fruit)
+        .bind(patch.quantity_grams)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(ingredient);
+    }
+
+    async fn delete_salad_ingredient(&self, ingredient_id: i64) -> ApiResult<bool> {
+        let delete_result = sqlx::query(
+            r#"
+            UPDATE SALAD_INGREDIENTS SET DELETED_AT = $2, UPDATED_AT = $2
+            WHERE ID = $1 AND DELETED_AT IS NULL
+            "#,
+        )
+        .bind(ingredient_id)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&mut self.connection().await?)
+        .await?;
+        return Ok(delete_result.rows_affected() > 0);
+    }
+
+    async fn restore_salad_ingredient(
+        &self,
+        ingredient_id: i64,
+    ) -> ApiResult<Restoration<SaladIngredient>> {
+        let ingredient = match self.get_salad_ingredient(ingredient_id, true).await? {
+            None => return Ok(Restoration::NotFound),
+            Some(ingredient) if ingredient.deleted_at.is_none() => {
+                return Ok(Restoration::Restored(ingredient))
+            }
+            Some(ingredient) => ingredient,
+        };
+
+        if self.get_salad(ingredient.id_salad, false).await?.is_none() {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Salad {}",
+                ingredient.id_salad
+            )));
+        }
+        if self.get_fruit(ingredient.id_fruit, false).await?.is_none() {
+            return Ok(Restoration::DependencyDeleted(format!(
+                "Fruit {}",
+                ingredient.id_fruit
+            )));
+        }
+
+        let ingredient = sqlx::query_as::<_, SaladIngredient>(
+            r#"
+            UPDATE SALAD_INGREDIENTS SET DELETED_AT = NULL, UPDATED_AT = $2
+            WHERE ID = $1
+            RETURNING id, id_salad, id_fruit, quantity_grams, created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(ingredient_id)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(Restoration::Restored(ingredient));
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for SqliteRepository {
+    async fn get_webhook(&self, webhook_id: i64) -> ApiResult<Option<Webhook>> {
+        let webhook = sqlx::query_as::<_, Webhook>(
+            r#"
+            SELECT id, target_url, event_types, active, created_at, updated_at
+            FROM WEBHOOK
+            WHERE ID = $1
+            "#,
+        )
+        .bind(webhook_id)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(webhook);
+    }
+
+    async fn list_webhooks(&self, page_request: &PageRequest) -> ApiResult<Page<Webhook>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = self
+                    .fetch_cursor_page(
+                        |query| {
+                            query.push(
+                                r#"
+                                SELECT id, target_url, event_types, active, created_at, updated_at
+                                FROM WEBHOOK
+                                "#,
+                            );
+                        },
+                        cursor,
+                        *size,
+                    )
+                    .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as::<_, Webhook>(
+                    r#"
+                    SELECT id, target_url, event_types, active, created_at, updated_at
+                    FROM WEBHOOK
+                    ORDER BY ID
+                    LIMIT $1 OFFSET $2
+                    "#,
+                )
+                .bind(size)
+                .bind(offset)
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_webhooks(&self) -> ApiResult<i64> {
+        let row_count = sqlx::query_as::<_, RowCount>("SELECT COUNT(1) AS count FROM WEBHOOK")
+            .fetch_one(&mut self.connection().await?)
+            .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    async fn insert_webhook(&self, new_webhook: &NewWebhook, secret: &str) -> ApiResult<Webhook> {
+        let webhook = sqlx::query_as::<_, Webhook>(
+            r#"
+            INSERT INTO WEBHOOK ( TARGET_URL, EVENT_TYPES, SECRET, ACTIVE, CREATED_AT, UPDATED_AT )
+            VALUES ( $1, $2, $3, $4, $5, $5 )
+            RETURNING id, target_url, event_types, active, created_at, updated_at
+            "#,
+        )
+        .bind(&new_webhook.target_url)
+        .bind(Json(&new_webhook.event_types))
+        .bind(secret)
+        .bind(new_webhook.active.unwrap_or(true))
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(webhook);
+    }
+
+    async fn update_webhook(
+        &self,
+        webhook_id: i64,
+        webhook: &NewWebhook,
+    ) -> ApiResult<Option<Webhook>> {
+        let webhook = sqlx::query_as::<_, Webhook>(
+            r#"
+            UPDATE WEBHOOK
+            SET TARGET_URL = $2, EVENT_TYPES = $3, ACTIVE = $4, UPDATED_AT = $5
+            WHERE ID = $1
+            RETURNING id, target_url, event_types, active, created_at, updated_at
+            "#,
+        )
+        .bind(webhook_id)
+        .bind(&webhook.target_url)
+        .bind(Json(&webhook.event_types))
+        .bind(webhook.active.unwrap_or(true))
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(webhook);
+    }
+
+    async fn delete_webhook(&self, webhook_id: i64) -> ApiResult<bool> {
+        let deletion = sqlx::query("DELETE FROM WEBHOOK WHERE ID = $1")
+            .bind(webhook_id)
+            .execute(&mut self.connection().await?)
+            .await?;
+        return Ok(deletion.rows_affected() > 0);
+    }
+
+    async fn get_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+    ) -> ApiResult<Option<WebhookDelivery>> {
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(
+            r#"
+            SELECT id, id_webhook, event_type, payload, status, attempts, next_attempt_at,
+                   last_attempt_at, last_status_code, last_error, created_at, delivered_at
+            FROM WEBHOOK_DELIVERY
+            WHERE ID_WEBHOOK = $1 AND ID = $2
+            "#,
+        )
+        .bind(webhook_id)
+        .bind(delivery_id)
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(delivery);
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<&str>,
+        page_request: &PageRequest,
+    ) -> ApiResult<Page<WebhookDelivery>> {
+        match page_request {
+            PageRequest::Cursor { cursor, size } => {
+                let page = self
+                    .fetch_cursor_page(
+                        |query| {
+                            query
+                                .push(
+                                    r#"
+                                    SELECT id, id_webhook, event_type, payload, status, attempts,
+                                           next_attempt_at, last_attempt_at, last_status_code,
+                                           last_error, created_at, delivered_at
+                                    FROM WEBHOOK_DELIVERY
+                                    WHERE ID_WEBHOOK = "#,
+                                )
+                                .push_bind(webhook_id);
+                            if let Some(status) = status {
+                                query.push(" AND STATUS = ").push_bind(status.to_string());
+                            }
+                        },
+                        cursor,
+                        *size,
+                    )
+                    .await?;
+                return Ok(Page::Cursor(page));
+            }
+            PageRequest::Offset { size, offset } => {
+                let hits = sqlx::query_as::<_, WebhookDelivery>(
+                    r#"
+                    SELECT id, id_webhook, event_type, payload, status, attempts, next_attempt_at,
+                           last_attempt_at, last_status_code, last_error, created_at, delivered_at
+                    FROM WEBHOOK_DELIVERY
+                    WHERE ID_WEBHOOK = $1 AND ($2 IS NULL OR STATUS = $2)
+                    ORDER BY ID
+                    LIMIT $3 OFFSET $4
+                    "#,
+                )
+                .bind(webhook_id)
+                .bind(status)
+                .bind(size)
+                .bind(offset)
+                .fetch_all(&mut self.connection().await?)
+                .await?;
+                return Ok(Page::Offset { hits });
+            }
+        }
+    }
+
+    async fn count_webhook_deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<&str>,
+    ) -> ApiResult<i64> {
+        let row_count = sqlx::query_as::<_, RowCount>(
+            r#"
+            SELECT COUNT(1) AS count FROM WEBHOOK_DELIVERY
+            WHERE ID_WEBHOOK = $1 AND ($2 IS NULL OR STATUS = $2)
+            "#,
+        )
+        .bind(webhook_id)
+        .bind(status)
+        .fetch_one(&mut self.connection().await?)
+        .await?;
+        return Ok(row_count.count.unwrap_or_default());
+    }
+
+    async fn enqueue_webhook_deliveries(
+        &self,
+        event_type: &str,
+        payload: &Value,
+    ) -> ApiResult<u64> {
+        let insertion = sqlx::query(
+            r#"
+            INSERT INTO WEBHOOK_DELIVERY
+                ( ID_WEBHOOK, EVENT_TYPE, PAYLOAD, NEXT_ATTEMPT_AT, CREATED_AT )
+            SELECT ID, $1, $2, $3, $3 FROM WEBHOOK
+            WHERE ACTIVE AND EXISTS (SELECT 1 FROM json_each(EVENT_TYPES) WHERE value = $1)
+            "#,
+        )
+        .bind(event_type)
+        .bind(Json(payload))
+        .bind(OffsetDateTime::now_utc())
+        .execute(&mut self.connection().await?)
+        .await?;
+        return Ok(insertion.rows_affected());
+    }
+
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease_until: OffsetDateTime,
+    ) -> ApiResult<Vec<DueDelivery>> {
+        // Timestamps are RFC 3339 text, compared as dates by `julianday()`.
+        let mut connection = self.connection().await?;
+        let mut transaction = connection.begin().await?;
+        let due = sqlx::query_as::<_, DueDelivery>(
+            r#"
+            SELECT WEBHOOK_DELIVERY.id, event_type, payload, attempts, target_url, secret
+            FROM WEBHOOK_DELIVERY
+            JOIN WEBHOOK ON WEBHOOK.ID = ID_WEBHOOK
+            WHERE STATUS = 'pending' AND ACTIVE
+                  AND julianday(NEXT_ATTEMPT_AT) <= julianday($2)
+            ORDER BY julianday(NEXT_ATTEMPT_AT), WEBHOOK_DELIVERY.ID
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_all(&mut transaction)
+        .await?;
+
+        if !due.is_empty() {
+            let mut query = QueryBuilder::new("UPDATE WEBHOOK_DELIVERY SET NEXT_ATTEMPT_AT = ");
+            query.push_bind(lease_until).push(" WHERE ID IN (");
+            let mut delivery_ids = query.separated(", ");
+            for delivery in &due {
+                delivery_ids.push_bind(delivery.id);
+            }
+            query.push(")");
+            query.build().execute(&mut transaction).await?;
+        }
+        transaction.commit().await?;
+        return Ok(due);
+    }
+
+    async fn finish_webhook_attempt(
+        &self,
+        delivery_id: i64,
+        attempt: &DeliveryAttempt,
+    ) -> ApiResult<()> {
+        sqlx::query(
+            r#"
+            UPDATE WEBHOOK_DELIVERY
+            SET STATUS = $2, ATTEMPTS = ATTEMPTS + 1, LAST_ATTEMPT_AT = $6,
+                LAST_STATUS_CODE = $3, LAST_ERROR = $4, NEXT_ATTEMPT_AT = $5,
+                DELIVERED_AT = CASE WHEN $2 = 'delivered' THEN $6 END
+            WHERE ID = $1
+            "#,
+        )
+        .bind(delivery_id)
+        .bind(attempt.status)
+        .bind(attempt.status_code)
+        .bind(&attempt.error)
+        .bind(attempt.next_attempt_at)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&mut self.connection().await?)
+        .await?;
+        return Ok(());
+    }
+
+    async fn retry_webhook_delivery(
+        &self,
+        webhook_id: i64,
+        delivery_id: i64,
+    ) -> ApiResult<Option<WebhookDelivery>> {
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(
+            r#"
+            UPDATE WEBHOOK_DELIVERY
+            SET STATUS = 'pending', ATTEMPTS = 0, NEXT_ATTEMPT_AT = $3
+            WHERE ID_WEBHOOK = $1 AND ID = $2 AND STATUS = 'dead'
+            RETURNING id, id_webhook, event_type, payload, status, attempts, next_attempt_at,
+                      last_attempt_at, last_status_code, last_error, created_at, delivered_at
+            "#,
+        )
+        .bind(webhook_id)
+        .bind(delivery_id)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(&mut self.connection().await?)
+        .await?;
+        return Ok(delivery);
+    }
+}
+
+#[async_trait]
+impl Repository for SqliteRepository {
+    async fn ping(&self) -> Result<(), String> {
+        let ping_result = match acquire(&self.database_connection_pool).await {
+            Ok(mut connection) => connection.ping().await,
+            Err(error) => Err(error),
+        };
+        return ping_result.map_err(|error| error.to_string());
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        return Some(PoolStatus {
+            connections: self.database_connection_pool.size(),
+            idle_connections: self.database_connection_pool.num_idle(),
+            max_connections: self.max_connections,
+        });
+    }
+}
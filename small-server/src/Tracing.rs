@@ -0,0 +1,132 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::{
+    body::{Body, BoxBody},
+    extract::MatchedPath,
+    http::{Request, Response},
+    middleware::Next,
+    Router,
+};
+use tower::ServiceBuilder;
+use tower_http::request_id::{
+    MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer,
+};
+use tower_http::trace::{DefaultOnFailure, DefaultOnRequest, TraceLayer};
+use tracing::{field, Level, Span};
+use tracing_subscriber::filter::LevelFilter;
+
+use super::Config::{Config, LogLevel};
+
+tokio::task_local! {
+    /// `X-Request-Id` of the request being handled, read by error responses.
+    static REQUEST_ID: String;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<LogFormat, String> {
+        match format.to_ascii_lowercase().as_str() {
+            "pretty" => return Ok(LogFormat::Pretty),
+            "json" => return Ok(LogFormat::Json),
+            _ => return Err(String::from("expected pretty or json")),
+        }
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> LevelFilter {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Installs the global subscriber. It also receives the `log` records of
+/// sqlx, so query timings end up in the span of the request that ran them.
+pub fn init_tracing(config: &Config) {
+    let builder = tracing_subscriber::fmt().with_max_level(LevelFilter::from(config.log_level));
+    match config.log_format {
+        LogFormat::Pretty => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).init(),
+    }
+}
+
+/// Wraps every route of `app` so each request gets an `X-Request-Id`, taken
+/// from the request or generated, echoed in the response, and a span named
+/// after the route template rather than the concrete path so `/fruit/1` and
+/// `/fruit/2` are grouped together.
+pub fn trace_requests(app: Router) -> Router {
+    // Layers run top to bottom: the id must exist before the span is made.
+    return app.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(make_request_span)
+                    .on_request(DefaultOnRequest::new().level(Level::DEBUG))
+                    .on_response(record_response)
+                    .on_failure(DefaultOnFailure::new().level(Level::ERROR)),
+            )
+            .layer(axum::middleware::from_fn(scope_request_id)),
+    );
+}
+
+fn make_request_span(request: &Request<Body>) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str())
+        .unwrap_or("unmatched");
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|request_id| request_id.header_value().to_str().ok())
+        .unwrap_or_default();
+    return tracing::info_span!(
+        "request",
+        method = %request.method(),
+        route,
+        request_id,
+        status = field::Empty,
+        latency_ms = field::Empty,
+    );
+}
+
+fn record_response(response: &Response<BoxBody>, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    tracing::info!("finished processing request");
+}
+
+/// Makes the request id available to `current_request_id` while the request
+/// is handled.
+async fn scope_request_id(request: Request<Body>, next: Next<Body>) -> axum::response::Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|request_id| request_id.header_value().to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    return REQUEST_ID.scope(request_id, next.run(request)).await;
+}
+
+/// Id of the request being handled, `None` outside of a request.
+pub fn current_request_id() -> Option<String> {
+    return REQUEST_ID
+        .try_with(|request_id| request_id.clone())
+        .ok()
+        .filter(|request_id| !request_id.is_empty());
+}
@@ -0,0 +1,224 @@
+use axum::{
+    body::{Bytes, StreamBody},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::Errors::{ApiError, ApiResult};
+use super::Repository::RowStream;
+use super::Validation::{FieldError, Validate};
+
+/// Imports are inserted this many rows per `INSERT` statement.
+pub const IMPORT_BATCH_SIZE: usize = 1000;
+/// Largest import body accepted, the default axum limit is only 2 MB.
+pub const IMPORT_BODY_LIMIT: usize = 64 * 1024 * 1024;
+/// Import errors are reported for at most this many rows.
+const MAX_REPORTED_ROWS: usize = 100;
+
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+#[derive(Clone, Copy, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferFormat {
+    Csv,
+    Ndjson,
+}
+
+impl TransferFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            TransferFormat::Csv => CSV_CONTENT_TYPE,
+            TransferFormat::Ndjson => NDJSON_CONTENT_TYPE,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            TransferFormat::Csv => "csv",
+            TransferFormat::Ndjson => "ndjson",
+        }
+    }
+
+    /// Accepts `text/csv`, `application/x-ndjson` and `application/ndjson`,
+    /// ignoring parameters such as `charset`.
+    fn from_content_type(headers: &HeaderMap) -> ApiResult<TransferFormat> {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        match mime {
+            CSV_CONTENT_TYPE => return Ok(TransferFormat::Csv),
+            NDJSON_CONTENT_TYPE | "application/ndjson" => return Ok(TransferFormat::Ndjson),
+            _ => {
+                return Err(ApiError::UnsupportedMediaType(format!(
+                    "Expected `{}` or `{}`, got `{}`",
+                    CSV_CONTENT_TYPE, NDJSON_CONTENT_TYPE, content_type
+                )))
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    pub format: TransferFormat,
+}
+
+/// Errors of one row of an import, `line` is 1-based and counts the CSV
+/// header.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct RowError {
+    pub line: u64,
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ImportSummary {
+    pub imported: usize,
+}
+
+enum RowEncoder {
+    /// Whether the header line still has to be written.
+    Csv(bool),
+    Ndjson,
+}
+
+impl RowEncoder {
+    fn new(format: TransferFormat) -> RowEncoder {
+        match format {
+            TransferFormat::Csv => return RowEncoder::Csv(true),
+            TransferFormat::Ndjson => return RowEncoder::Ndjson,
+        }
+    }
+
+    /// Returns the bytes of `row`, preceded by the header line for the first
+    /// CSV row.
+    fn encode<T: Serialize>(&mut self, row: &T) -> Result<Bytes, std::io::Error> {
+        match self {
+            RowEncoder::Csv(needs_header) => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(*needs_header)
+                    .from_writer(Vec::new());
+                writer.serialize(row)?;
+                *needs_header = false;
+                let line = writer.into_inner().map_err(|error| error.into_error())?;
+                return Ok(Bytes::from(line));
+            }
+            RowEncoder::Ndjson => {
+                let mut line = serde_json::to_vec(row)?;
+                line.push(b'\n');
+                return Ok(Bytes::from(line));
+            }
+        }
+    }
+}
+
+/// Encodes `rows` as they come out of the repository, so exports never hold
+/// the whole table in memory. The body ends at the first error.
+pub fn export_rows<T>(rows: RowStream<T>, name: &str, format: TransferFormat) -> Response
+where
+    T: Serialize + Send + 'static,
+{
+    let mut encoder = RowEncoder::new(format);
+    let chunks = rows.map(move |row| {
+        return row
+            .map_err(std::io::Error::other)
+            .and_then(|row| encoder.encode(&row));
+    });
+
+    return (
+        [
+            (header::CONTENT_TYPE, String::from(format.content_type())),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.{}\"", name, format.extension()),
+            ),
+        ],
+        StreamBody::new(chunks),
+    )
+        .into_response();
+}
+
+/// Parses and validates every row of an import body. Fails with the errors
+/// of every bad row, so nothing is inserted unless the whole body is valid.
+pub fn parse_import<T>(headers: &HeaderMap, body: &[u8]) -> ApiResult<Vec<T>>
+where
+    T: DeserializeOwned + Validate,
+{
+    let mut rows = Vec::new();
+    let mut row_errors = Vec::new();
+    let mut error_count = 0;
+    let mut record_row = |line: u64, row: Result<T, String>| {
+        let errors = match row {
+            Ok(row) => match row.validate() {
+                Ok(()) => {
+                    rows.push(row);
+                    return;
+                }
+                Err(errors) => errors,
+            },
+            Err(message) => vec![FieldError {
+                field: "row",
+                code: "malformed",
+                message,
+            }],
+        };
+        error_count += 1;
+        if row_errors.len() < MAX_REPORTED_ROWS {
+            row_errors.push(RowError { line, errors });
+        }
+    };
+
+    match TransferFormat::from_content_type(headers)? {
+        TransferFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(body);
+            let header_record = match reader.headers() {
+                Ok(header_record) => header_record.clone(),
+                Err(error) => {
+                    record_row(1, Err(error.to_string()));
+                    csv::StringRecord::new()
+                }
+            };
+            let mut record = csv::StringRecord::new();
+            loop {
+                match reader.read_record(&mut record) {
+                    Ok(false) => break,
+                    Ok(true) => {
+                        let line = record.position().map(|position| position.line());
+                        let row = record
+                            .deserialize(Some(&header_record))
+                            .map_err(|error| error.to_string());
+                        record_row(line.unwrap_or_default(), row);
+                    }
+                    Err(error) => {
+                        let line = error.position().map(|position| position.line());
+                        let is_io_error = matches!(error.kind(), csv::ErrorKind::Io(_));
+                        record_row(line.unwrap_or_default(), Err(error.to_string()));
+                        if is_io_error {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        TransferFormat::Ndjson => {
+            for (index, line) in body.split(|byte| *byte == b'\n').enumerate() {
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let row = serde_json::from_slice::<T>(line).map_err(|error| error.to_string());
+                record_row(index as u64 + 1, row);
+            }
+        }
+    }
+
+    if error_count > 0 {
+        return Err(ApiError::InvalidRows(error_count, row_errors));
+    }
+    return Ok(rows);
+}
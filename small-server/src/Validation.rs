@@ -0,0 +1,107 @@
+/// Maximum length of the `VARCHAR(100)` columns in the initial migration.
+pub const MAX_VARCHAR_LENGTH: usize = 100;
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Implemented by request bodies so handlers can reject them before any SQL
+/// runs. Every failing field is reported, not only the first one.
+pub trait Validate {
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        return Validator::default();
+    }
+
+    pub fn length(
+        &mut self,
+        field: &'static str,
+        value: &str,
+        min: usize,
+        max: usize,
+    ) -> &mut Self {
+        let length = value.chars().count();
+        if length < min || length > max {
+            self.errors.push(FieldError {
+                field,
+                code: "length",
+                message: format!("must be between {} and {} characters long", min, max),
+            });
+        }
+        return self;
+    }
+
+    pub fn range<T>(&mut self, field: &'static str, value: T, min: T, max: T) -> &mut Self
+    where
+        T: PartialOrd + std::fmt::Display,
+    {
+        if value < min || value > max {
+            self.errors.push(FieldError {
+                field,
+                code: "range",
+                message: format!("must be between {} and {}", min, max),
+            });
+        }
+        return self;
+    }
+
+    pub fn positive<T>(&mut self, field: &'static str, value: T) -> &mut Self
+    where
+        T: PartialOrd + Default,
+    {
+        if value <= T::default() {
+            self.errors.push(FieldError {
+                field,
+                code: "positive",
+                message: String::from("must be greater than zero"),
+            });
+        }
+        return self;
+    }
+
+    pub fn email(&mut self, field: &'static str, value: &str) -> &mut Self {
+        if !email_address::EmailAddress::is_valid(value) {
+            self.errors.push(FieldError {
+                field,
+                code: "email",
+                message: String::from("must be a valid email address"),
+            });
+        }
+        return self;
+    }
+
+    pub fn check(
+        &mut self,
+        field: &'static str,
+        is_valid: bool,
+        code: &'static str,
+        message: &str,
+    ) -> &mut Self {
+        if !is_valid {
+            self.errors.push(FieldError {
+                field,
+                code,
+                message: message.to_string(),
+            });
+        }
+        return self;
+    }
+
+    pub fn finish(&mut self) -> Result<(), Vec<FieldError>> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        return Err(std::mem::take(&mut self.errors));
+    }
+}
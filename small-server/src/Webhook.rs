@@ -0,0 +1,686 @@
+//! Webhooks: partner systems subscribe a URL to change events, and a worker
+//! in this process POSTs every matching event to it, retrying with
+//! exponential backoff until it is delivered or, after the last attempt,
+//! dead.
+//!
+//! Every attempt carries `X-Webhook-Event`, `X-Webhook-Delivery` (the same
+//! on each attempt, to drop duplicates), `X-Webhook-Timestamp` in Unix
+//! seconds and `X-Webhook-Signature: sha256=<hex>`, the HMAC-SHA256 of
+//! `<timestamp>.<body>` keyed with the subscription's secret.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::{header, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Notify,
+};
+
+use super::AppState::AppState;
+use super::Authorization::Admin;
+use super::Config::WebhookSettings;
+use super::Errors::{ApiError, ApiResult, ProblemResponse};
+use super::Events::{is_event_name, ChangeEvent, EventBus};
+use super::Metrics::WEBHOOK_ATTEMPTS;
+use super::Pagination::{Keyed, Pagination};
+use super::Repository::{Repository, WebhookRepository};
+use super::Validation::{FieldError, Validate, Validator};
+
+/// Values of `WEBHOOK_DELIVERY.STATUS`.
+pub const DELIVERY_PENDING: &str = "pending";
+pub const DELIVERY_DELIVERED: &str = "delivered";
+pub const DELIVERY_DEAD: &str = "dead";
+
+const MAX_TARGET_URL_LENGTH: usize = 500;
+/// Longest wait between two attempts, however many failed before.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+/// Deliveries the worker sends at once.
+const CLAIM_BATCH_SIZE: i64 = 16;
+/// How often the worker looks for due retries while no new events come in.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Keyed for Webhook {
+    fn key(&self) -> i64 {
+        return self.id;
+    }
+}
+
+impl Keyed for WebhookDelivery {
+    fn key(&self) -> i64 {
+        return self.id;
+    }
+}
+
+impl Validate for NewWebhook {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let scheme_known = reqwest::Url::parse(&self.target_url)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+        return Validator::new()
+            .length("target_url", &self.target_url, 1, MAX_TARGET_URL_LENGTH)
+            .check(
+                "target_url",
+                scheme_known,
+                "url",
+                "must be an http or https URL",
+            )
+            .check(
+                "event_types",
+                !self.event_types.is_empty(),
+                "length",
+                "must name at least one event",
+            )
+            .check(
+                "event_types",
+                self.event_types.iter().all(|name| is_event_name(name)),
+                "event",
+                "must be person, fruit, salad or ingredient followed by .created, .updated, .deleted or .restored",
+            )
+            .finish();
+    }
+}
+
+pub fn get_router<R: Repository>() -> Router<AppState<R>> {
+    return Router::new()
+        .route("/", post(insert_webhook::<R>))
+        .route("/", get(list_webhooks::<R>))
+        .route(
+            "/:webhook_id",
+            get(get_webhook_by_id::<R>)
+                .put(update_webhook::<R>)
+                .delete(delete_webhook::<R>),
+        )
+        .route("/:webhook_id/deliveries", get(list_webhook_deliveries::<R>))
+        .route(
+            "/:webhook_id/deliveries/:delivery_id",
+            get(get_webhook_delivery_by_id::<R>),
+        )
+        .route(
+            "/:webhook_id/deliveries/:delivery_id/retry",
+            post(retry_webhook_delivery::<R>),
+        );
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewWebhook {
+    /// Receives a POST for every event.
+    #[schema(example = "https://partner.example.com/hooks/salads")]
+    pub target_url: String,
+    #[schema(example = json!(["salad.created"]))]
+    pub event_types: Vec<String>,
+    /// An inactive subscription gets no new deliveries and its pending ones
+    /// wait until it is active again. True by default.
+    pub active: Option<bool>,
+}
+
+#[derive(Clone, serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Webhook {
+    pub id: i64,
+    pub target_url: String,
+    #[schema(value_type = Vec<String>)]
+    pub event_types: sqlx::types::Json<Vec<String>>,
+    pub active: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub updated_at: OffsetDateTime,
+}
+
+/// A new subscription and the secret its deliveries are signed with, which
+/// is never shown again.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    #[schema(example = "whsec_3q2-7wEjc4cJ8oUu4vZz0nDqk0zA4qgC1w6Ta9mF8bE")]
+    pub secret: String,
+}
+
+/// An event sent, or still to be sent, to a subscription.
+#[derive(Clone, serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub id_webhook: i64,
+    #[schema(example = "salad.created")]
+    pub event_type: String,
+    /// The body that is POSTed.
+    #[schema(value_type = Object)]
+    pub payload: sqlx::types::Json<Value>,
+    /// `pending`, `delivered` or `dead`.
+    #[schema(example = "pending")]
+    pub status: String,
+    pub attempts: i32,
+    /// When a pending delivery is attempted next.
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub next_attempt_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub last_attempt_at: Option<OffsetDateTime>,
+    /// Status of the last response, missing when there was none.
+    pub last_status_code: Option<i32>,
+    /// Why the last attempt failed.
+    pub last_error: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub delivered_at: Option<OffsetDateTime>,
+}
+
+/// A pending delivery claimed by the worker, with where to send it.
+#[derive(sqlx::FromRow)]
+pub struct DueDelivery {
+    pub id: i64,
+    pub event_type: String,
+    pub payload: sqlx::types::Json<Value>,
+    pub attempts: i32,
+    pub target_url: String,
+    pub secret: String,
+}
+
+/// Outcome of one attempt, written back by `finish_webhook_attempt`.
+pub struct DeliveryAttempt {
+    pub status: &'static str,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub next_attempt_at: OffsetDateTime,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveryFilter {
+    /// Only deliveries that are `pending`, `delivered` or `dead`.
+    status: Option<String>,
+}
+
+impl DeliveryFilter {
+    fn status(&self) -> ApiResult<Option<&str>> {
+        let Some(status) = self.status.as_deref() else {
+            return Ok(None);
+        };
+        if ![DELIVERY_PENDING, DELIVERY_DELIVERED, DELIVERY_DEAD].contains(&status) {
+            return Err(ApiError::InvalidQuery(format!(
+                "`{}` is not one of pending, delivered or dead",
+                status
+            )));
+        }
+        return Ok(Some(status));
+    }
+}
+
+fn new_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    return format!("whsec_{}", URL_SAFE_NO_PAD.encode(bytes));
+}
+
+/// `sha256=<hex>` of the HMAC-SHA256 of `<timestamp>.<body>`, sent as
+/// `X-Webhook-Signature`.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    return format!("sha256={}", hex);
+}
+
+async fn ensure_webhook<R: WebhookRepository>(webhooks: &R, webhook_id: i64) -> ApiResult<()> {
+    if webhooks.get_webhook(webhook_id).await?.is_none() {
+        return Err(ApiError::not_found("Webhook", webhook_id));
+    }
+    return Ok(());
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    params(Pagination),
+    responses(
+        (status = 200, description = "A page of webhook subscriptions", body = WebhookList),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list_webhooks<R: WebhookRepository>(
+    _admin: Admin,
+    pagination: Result<Query<Pagination>, QueryRejection>,
+    State(webhooks): State<R>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Query(pagination) = pagination?;
+    let page_request = pagination.page_request()?;
+    let mut response = serde_json::json!(webhooks.list_webhooks(&page_request).await?);
+
+    if pagination.with_total() {
+        response["total"] = serde_json::json!(webhooks.count_webhooks().await?);
+    }
+
+    return Ok((StatusCode::OK, Json(response)));
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{webhook_id}",
+    tag = "webhooks",
+    params(("webhook_id" = i64, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "The webhook subscription", body = Webhook),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_webhook_by_id<R: WebhookRepository>(
+    _admin: Admin,
+    Path(webhook_id): Path<i64>,
+    State(webhooks): State<R>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let webhook = webhooks
+        .get_webhook(webhook_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Webhook", webhook_id))?;
+    return Ok((StatusCode::OK, Json(serde_json::json!(webhook))));
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = NewWebhook,
+    responses(
+        (status = 201, description = "The created subscription with its secret", body = CreatedWebhook),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn insert_webhook<R: WebhookRepository>(
+    _admin: Admin,
+    State(webhooks): State<R>,
+    body: Result<Json<NewWebhook>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(webhook_json) = body?;
+    webhook_json.validate()?;
+    let secret = new_secret();
+    let webhook = webhooks.insert_webhook(&webhook_json, &secret).await?;
+    let created = CreatedWebhook { webhook, secret };
+    return Ok((StatusCode::CREATED, Json(serde_json::json!(created))));
+}
+
+/// Replaces the URL, events and state of a subscription, but not its secret.
+#[utoipa::path(
+    put,
+    path = "/webhooks/{webhook_id}",
+    tag = "webhooks",
+    params(("webhook_id" = i64, Path, description = "Webhook id")),
+    request_body = NewWebhook,
+    responses(
+        (status = 200, description = "The updated subscription", body = Webhook),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 415, response = ProblemResponse),
+        (status = 422, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn update_webhook<R: WebhookRepository>(
+    _admin: Admin,
+    Path(webhook_id): Path<i64>,
+    State(webhooks): State<R>,
+    body: Result<Json<NewWebhook>, JsonRejection>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Json(webhook_json) = body?;
+    webhook_json.validate()?;
+    let webhook = webhooks
+        .update_webhook(webhook_id, &webhook_json)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Webhook", webhook_id))?;
+    return Ok((StatusCode::OK, Json(serde_json::json!(webhook))));
+}
+
+/// Deletes the subscription and its delivery log, pending deliveries are
+/// dropped.
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{webhook_id}",
+    tag = "webhooks",
+    params(("webhook_id" = i64, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "The subscription was deleted"),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_webhook<R: WebhookRepository>(
+    _admin: Admin,
+    Path(webhook_id): Path<i64>,
+    State(webhooks): State<R>,
+) -> ApiResult<StatusCode> {
+    if !webhooks.delete_webhook(webhook_id).await? {
+        return Err(ApiError::not_found("Webhook", webhook_id));
+    }
+    return Ok(StatusCode::NO_CONTENT);
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{webhook_id}/deliveries",
+    tag = "webhooks",
+    params(
+        ("webhook_id" = i64, Path, description = "Webhook id"),
+        DeliveryFilter,
+        Pagination,
+    ),
+    responses(
+        (status = 200, description = "A page of the subscription's delivery log", body = WebhookDeliveryList),
+        (status = 400, response = ProblemResponse),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list_webhook_deliveries<R: WebhookRepository>(
+    _admin: Admin,
+    Path(webhook_id): Path<i64>,
+    Query(filter): Query<DeliveryFilter>,
+    pagination: Result<Query<Pagination>, QueryRejection>,
+    State(webhooks): State<R>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let Query(pagination) = pagination?;
+    let page_request = pagination.page_request()?;
+    let status = filter.status()?;
+    ensure_webhook(&webhooks, webhook_id).await?;
+    let mut response = serde_json::json!(
+        webhooks
+            .list_webhook_deliveries(webhook_id, status, &page_request)
+            .await?
+    );
+
+    if pagination.with_total() {
+        response["total"] = serde_json::json!(
+            webhooks
+                .count_webhook_deliveries(webhook_id, status)
+                .await?
+        );
+    }
+
+    return Ok((StatusCode::OK, Json(response)));
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{webhook_id}/deliveries/{delivery_id}",
+    tag = "webhooks",
+    params(
+        ("webhook_id" = i64, Path, description = "Webhook id"),
+        ("delivery_id" = i64, Path, description = "Delivery id"),
+    ),
+    responses(
+        (status = 200, description = "The delivery", body = WebhookDelivery),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_webhook_delivery_by_id<R: WebhookRepository>(
+    _admin: Admin,
+    Path((webhook_id, delivery_id)): Path<(i64, i64)>,
+    State(webhooks): State<R>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    let delivery = webhooks
+        .get_webhook_delivery(webhook_id, delivery_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Webhook delivery", delivery_id))?;
+    return Ok((StatusCode::OK, Json(serde_json::json!(delivery))));
+}
+
+/// Queues a dead delivery again, with as many attempts as a new one.
+#[utoipa::path(
+    post,
+    path = "/webhooks/{webhook_id}/deliveries/{delivery_id}/retry",
+    tag = "webhooks",
+    params(
+        ("webhook_id" = i64, Path, description = "Webhook id"),
+        ("delivery_id" = i64, Path, description = "Delivery id"),
+    ),
+    responses(
+        (status = 200, description = "The delivery, pending again", body = WebhookDelivery),
+        (status = 401, response = ProblemResponse),
+        (status = 403, response = ProblemResponse),
+        (status = 404, response = ProblemResponse),
+        (status = 409, response = ProblemResponse),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn retry_webhook_delivery<R: WebhookRepository>(
+    _admin: Admin,
+    Path((webhook_id, delivery_id)): Path<(i64, i64)>,
+    State(webhooks): State<R>,
+) -> ApiResult<(StatusCode, Json<Value>)> {
+    if let Some(delivery) = webhooks
+        .retry_webhook_delivery(webhook_id, delivery_id)
+        .await?
+    {
+        return Ok((StatusCode::OK, Json(serde_json::json!(delivery))));
+    }
+    let delivery = webhooks
+        .get_webhook_delivery(webhook_id, delivery_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Webhook delivery", delivery_id))?;
+    return Err(ApiError::Conflict(format!(
+        "Webhook delivery {} is {}, only dead deliveries can be retried",
+        delivery_id, delivery.status
+    )));
+}
+
+/// Starts the worker: every event on `events` is queued for the active
+/// subscriptions to it, and due deliveries are sent until the process ends.
+/// Deliveries are claimed for twice the timeout, so those of a worker that
+/// stopped mid-attempt are sent again afterwards.
+pub fn spawn_worker<R: WebhookRepository + Clone + 'static>(
+    repository: R,
+    events: &EventBus,
+    settings: WebhookSettings,
+) {
+    let wake = Arc::new(Notify::new());
+    tokio::spawn(queue_deliveries(
+        repository.clone(),
+        events.listen(),
+        wake.clone(),
+    ));
+    tokio::spawn(send_deliveries(repository, settings, wake));
+}
+
+async fn queue_deliveries<R: WebhookRepository>(
+    webhooks: R,
+    mut receiver: broadcast::Receiver<Arc<ChangeEvent>>,
+    wake: Arc<Notify>,
+) {
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                tracing::error!(missed, "webhook worker fell behind, events were not queued");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let event_type = event.name();
+        match webhooks
+            .enqueue_webhook_deliveries(&event_type, &delivery_payload(&event))
+            .await
+        {
+            Ok(0) => {}
+            Ok(_) => wake.notify_one(),
+            Err(error) => {
+                tracing::error!(
+                    event_type,
+                    error = error.internal_detail(),
+                    "failed to queue webhook deliveries"
+                );
+            }
+        }
+    }
+}
+
+/// The body of a delivery: the event as sent over `/ws`, but without the id
+/// it has on `/events`, which starts over with every restart.
+fn delivery_payload(event: &ChangeEvent) -> Value {
+    let mut payload = serde_json::json!(event);
+    payload["type"] = Value::String(event.name());
+    if let Some(members) = payload.as_object_mut() {
+        members.remove("id");
+    }
+    return payload;
+}
+
+async fn send_deliveries<R: WebhookRepository>(
+    webhooks: R,
+    settings: WebhookSettings,
+    wake: Arc<Notify>,
+) {
+    // Redirects are not followed, a subscription names its final URL.
+    let client = reqwest::Client::builder()
+        .timeout(settings.timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("the webhook HTTP client builds");
+    let idle_wait = settings.retry_delay.min(POLL_INTERVAL);
+    loop {
+        let lease_until = OffsetDateTime::now_utc() + settings.timeout * 2;
+        let due = match webhooks
+            .claim_webhook_deliveries(CLAIM_BATCH_SIZE, lease_until)
+            .await
+        {
+            Ok(due) => due,
+            Err(error) => {
+                tracing::error!(
+                    error = error.internal_detail(),
+                    "failed to claim webhook deliveries"
+                );
+                Vec::new()
+            }
+        };
+        let batch_full = due.len() as i64 == CLAIM_BATCH_SIZE;
+        let attempts = due
+            .into_iter()
+            .map(|delivery| attempt_delivery(&client, &webhooks, &settings, delivery));
+        futures::future::join_all(attempts).await;
+        if batch_full {
+            continue;
+        }
+        tokio::select! {
+            _ = wake.notified() => {},
+            _ = tokio::time::sleep(idle_wait) => {},
+        }
+    }
+}
+
+async fn attempt_delivery<R: WebhookRepository>(
+    client: &reqwest::Client,
+    webhooks: &R,
+    settings: &WebhookSettings,
+    delivery: DueDelivery,
+) {
+    let body = delivery.payload.to_string();
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+    let response = client
+        .post(&delivery.target_url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("x-webhook-event", &delivery.event_type)
+        .header("x-webhook-delivery", delivery.id)
+        .header("x-webhook-timestamp", timestamp)
+        .header(
+            "x-webhook-signature",
+            signature(&delivery.secret, timestamp, &body),
+        )
+        .body(body)
+        .send()
+        .await;
+    let (status_code, error) = match response {
+        Ok(response) => {
+            let status = response.status();
+            let error = (!status.is_success()).then(|| format!("Responded with {}", status));
+            (Some(i32::from(status.as_u16())), error)
+        }
+        Err(error) => (None, Some(error.to_string())),
+    };
+
+    let attempts = delivery.attempts + 1;
+    let now = OffsetDateTime::now_utc();
+    let (status, next_attempt_at) = if error.is_none() {
+        (DELIVERY_DELIVERED, now)
+    } else if attempts >= settings.max_attempts {
+        (DELIVERY_DEAD, now)
+    } else {
+        (
+            DELIVERY_PENDING,
+            now + retry_delay(settings.retry_delay, attempts),
+        )
+    };
+    WEBHOOK_ATTEMPTS.with_label_values(&[status]).inc();
+    if status == DELIVERY_DEAD {
+        tracing::warn!(
+            delivery_id = delivery.id,
+            attempts,
+            "webhook delivery failed for the last time"
+        );
+    }
+    let attempt = DeliveryAttempt {
+        status,
+        status_code,
+        error,
+        next_attempt_at,
+    };
+    if let Err(error) = webhooks.finish_webhook_attempt(delivery.id, &attempt).await {
+        tracing::error!(
+            delivery_id = delivery.id,
+            error = error.internal_detail(),
+            "failed to record a webhook attempt"
+        );
+    }
+}
+
+/// Wait after `failed_attempts`: `first_delay`, doubled for every failed
+/// attempt before the last one, at most `MAX_RETRY_DELAY`.
+fn retry_delay(first_delay: Duration, failed_attempts: i32) -> Duration {
+    let doublings = (failed_attempts - 1).clamp(0, 20) as u32;
+    return first_delay
+        .saturating_mul(1 << doublings)
+        .min(MAX_RETRY_DELAY);
+}
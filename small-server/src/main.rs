@@ -1,35 +1,121 @@
+#![allow(clippy::needless_return)]
+
+use crate::Auth::AuthKeys;
+use crate::Config::{CorsOrigins, RateLimits};
 use crate::Errors::{DatabaseConnectionError, UnwrapPrint};
+use crate::Migrations::MigratedDatabase;
+use axum::http::{header, HeaderName};
 use axum::Router;
-use sqlx::postgres::PgPoolOptions;
-use sqlx::{Pool, Postgres};
+use sqlx::migrate::Migrate;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Pool, Postgres};
 use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::Notify;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 
+#[allow(non_snake_case)]
+mod ApiDoc;
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod ApiTests;
+#[allow(non_snake_case)]
+mod AppState;
+#[allow(non_snake_case)]
+mod Auth;
+#[allow(non_snake_case)]
+mod Authorization;
+#[allow(non_snake_case)]
+mod Config;
 #[allow(non_snake_case)]
 mod Errors;
 #[allow(non_snake_case)]
+mod Events;
+#[allow(non_snake_case)]
+mod Filter;
+#[allow(non_snake_case)]
 mod Fruit;
 #[allow(non_snake_case)]
+mod GraphQL;
+#[allow(non_snake_case)]
+mod Health;
+#[allow(non_snake_case)]
+mod MemoryRepository;
+#[allow(non_snake_case)]
+mod Metrics;
+#[allow(non_snake_case)]
+mod Migrations;
+#[allow(non_snake_case)]
+mod Negotiation;
+#[allow(non_snake_case)]
 mod Pagination;
 #[allow(non_snake_case)]
 mod Person;
 #[allow(non_snake_case)]
+mod PostgresRepository;
+#[allow(non_snake_case)]
+mod Precondition;
+#[allow(non_snake_case)]
+mod RateLimit;
+#[allow(non_snake_case)]
+mod Repository;
+#[allow(non_snake_case)]
 mod Salad;
 #[allow(non_snake_case)]
 mod SaladIngredient;
+#[cfg(feature = "sqlite")]
+#[allow(non_snake_case)]
+mod SqliteRepository;
+#[allow(non_snake_case)]
+mod Tracing;
+#[allow(non_snake_case)]
+mod Transfer;
+#[allow(non_snake_case)]
+mod Validation;
+#[allow(non_snake_case)]
+mod Webhook;
 
-async fn get_postgres_connection_pool() -> Result<Pool<Postgres>, Errors::DatabaseConnectionError> {
-    let database_url_result = std::env::var("DATABASE_URL");
+async fn get_postgres_connection_pool(
+    config: &Config::Config,
+) -> Result<Pool<Postgres>, Errors::DatabaseConnectionError> {
+    // Every statement is logged with its duration at debug level, slow ones
+    // are also logged at warn level.
+    let mut connect_options = PgConnectOptions::from_str(&config.database_url)?;
+    connect_options
+        .log_statements(log::LevelFilter::Debug)
+        .log_slow_statements(log::LevelFilter::Warn, Duration::from_secs(1));
+    let connection_result = PgPoolOptions::new()
+        .max_connections(config.database_max_connections)
+        .acquire_timeout(config.database_acquire_timeout)
+        .idle_timeout(config.database_idle_timeout)
+        .connect_with(connect_options)
+        .await;
 
-    let database_url = match database_url_result {
-        Ok(database_url) => database_url,
-        Err(error) => return Err(DatabaseConnectionError::VarError(error)),
-    };
+    match connection_result {
+        Ok(connection_pool) => return Ok(connection_pool),
+        Err(error) => return Err(DatabaseConnectionError::ConnectionError(error)),
+    }
+}
 
-    let connection_result = PgPoolOptions::new()
-        .max_connections(10)
-        .acquire_timeout(Duration::from_secs(10))
-        .connect(&database_url)
+#[cfg(feature = "sqlite")]
+async fn get_sqlite_connection_pool(
+    config: &Config::Config,
+) -> Result<Pool<sqlx::Sqlite>, Errors::DatabaseConnectionError> {
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+    let mut connect_options =
+        SqliteConnectOptions::from_str(&config.database_url)?.create_if_missing(true);
+    connect_options
+        .log_statements(log::LevelFilter::Debug)
+        .log_slow_statements(log::LevelFilter::Warn, Duration::from_secs(1));
+    let connection_result = SqlitePoolOptions::new()
+        .max_connections(config.database_max_connections)
+        .acquire_timeout(config.database_acquire_timeout)
+        .idle_timeout(config.database_idle_timeout)
+        .connect_with(connect_options)
         .await;
 
     match connection_result {
@@ -38,38 +124,246 @@ async fn get_postgres_connection_pool() -> Result<Pool<Postgres>, Errors::Databa
     }
 }
 
-fn get_server_socket_addr() -> Result<SocketAddr, std::env::VarError> {
-    let address_result = std::env::var("SOCKET_ADDRESS");
-    let address = match address_result {
-        Ok(address) => address,
-        Err(error) => return Err(error),
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+fn get_cors_layer(config: &Config::Config) -> Option<CorsLayer> {
+    let allowed_origins = match &config.cors_allowed_origins {
+        CorsOrigins::Disabled => return None,
+        CorsOrigins::Any => AllowOrigin::any(),
+        CorsOrigins::List(origins) => AllowOrigin::list(origins.clone()),
+    };
+    return Some(
+        CorsLayer::new()
+            .allow_origin(allowed_origins)
+            .allow_methods(Any)
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_MATCH,
+                header::IF_NONE_MATCH,
+                crate::Events::LAST_EVENT_ID,
+                X_REQUEST_ID,
+            ])
+            .expose_headers([
+                header::ETAG,
+                X_REQUEST_ID,
+                crate::Negotiation::X_TOTAL_COUNT,
+                crate::Negotiation::X_NEXT_CURSOR,
+                crate::Negotiation::X_PREV_CURSOR,
+            ]),
+    );
+}
+
+/// Resolves on the first SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
     };
 
-    let address_result: Result<SocketAddr, _> = address.parse();
-    let address = match address_result {
-        Ok(address) => address,
-        Err(_) => return Err(std::env::VarError::NotPresent),
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
     };
-    return Ok(address);
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
 }
 
+/// Every route of the server with its rate limits, metrics and tracing, but
+/// without CORS.
+fn get_app<R: Repository::Repository>(
+    app_state: AppState::AppState<R>,
+    rate_limits: &RateLimits,
+    max_in_flight_requests: usize,
+) -> Router {
+    let auth_keys = app_state.auth_keys.clone();
+    let limit_rate = |router, limit| crate::RateLimit::limit_rate(router, limit, auth_keys.clone());
+
+    let api = Router::new()
+        .merge(crate::ApiDoc::get_router())
+        .nest(
+            "/auth",
+            crate::RateLimit::limit_rate_by_address(crate::Auth::get_router(), rate_limits.auth),
+        )
+        .nest(
+            "/person",
+            limit_rate(crate::Person::get_router(), rate_limits.person),
+        )
+        .nest(
+            "/fruit",
+            limit_rate(crate::Fruit::get_router(), rate_limits.fruit),
+        )
+        .nest(
+            "/salad",
+            limit_rate(crate::Salad::get_router(), rate_limits.salad),
+        )
+        .nest(
+            "/ingredient",
+            limit_rate(crate::SaladIngredient::getRouter(), rate_limits.ingredient),
+        )
+        .nest(
+            "/graphql",
+            limit_rate(crate::GraphQL::get_router(), rate_limits.graphql),
+        )
+        .nest("/webhooks", crate::Webhook::get_router())
+        .with_state(app_state.clone());
+    // Probes and scrapes are not shed, an overloaded server is still alive.
+    // Event streams stay open for as long as the client listens, so they
+    // would hold their in-flight permit forever.
+    let app = Router::new()
+        .merge(crate::Health::get_router())
+        .merge(crate::Metrics::get_router())
+        .merge(crate::Events::get_router())
+        .with_state(app_state)
+        .merge(crate::RateLimit::limit_in_flight(
+            api,
+            max_in_flight_requests,
+        ));
+    // Gzip or brotli, whichever `Accept-Encoding` prefers.
+    let app = app.layer(CompressionLayer::new());
+    let app = crate::Metrics::track_requests(app);
+    return crate::Tracing::trace_requests(app);
+}
+
+/// Serves `repository` until a shutdown signal and the drain that follows.
+async fn serve<R: Repository::Repository>(config: &Config::Config, repository: R) {
+    crate::Metrics::init_metrics();
+    let app_state = AppState::AppState {
+        repository,
+        auth_keys: AuthKeys::new(config.auth_secret.as_bytes(), config.auth_token_ttl),
+        events: Events::EventBus::default(),
+    };
+    if let Some(admin_email) = &config.admin_email {
+        crate::Authorization::promote_admin(&app_state.repository, admin_email)
+            .await
+            .map_err(|error| error.internal_detail())
+            .unwrap_print();
+    }
+    crate::Webhook::spawn_worker(
+        app_state.repository.clone(),
+        &app_state.events,
+        config.webhooks.clone(),
+    );
+    let app = get_app(
+        app_state,
+        &config.rate_limits,
+        config.max_in_flight_requests,
+    );
+    let app = match get_cors_layer(config) {
+        Some(cors_layer) => app.layer(cors_layer),
+        None => app,
+    };
+
+    tracing::info!(address = %config.bind_address, "server starting");
+    // Once a signal arrives the server stops accepting connections and waits
+    // for in-flight requests, but for no longer than the shutdown timeout.
+    let shutdown_started = Arc::new(Notify::new());
+    let server = axum::Server::bind(&config.bind_address)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown({
+            let shutdown_started = shutdown_started.clone();
+            async move {
+                shutdown_signal().await;
+                shutdown_started.notify_one();
+            }
+        });
+    let drain_deadline = async {
+        shutdown_started.notified().await;
+        tokio::time::sleep(config.shutdown_timeout).await;
+    };
+
+    tokio::select! {
+        result = server => result.expect("Failed to start server"),
+        _ = drain_deadline => {
+            tracing::warn!(
+                timeout = ?config.shutdown_timeout,
+                "requests still running after the shutdown timeout, shutting down anyway"
+            );
+        }
+    }
+}
+
+/// Runs the `migrate` subcommand if there is one. Otherwise applies the
+/// pending migrations, if enabled, and serves `repository`.
+async fn migrate_and_serve<DB, R>(
+    config: &mut Config::Config,
+    database_connection_pool: &Pool<DB>,
+    repository: R,
+) where
+    DB: MigratedDatabase,
+    DB::Connection: Migrate,
+    R: Repository::Repository,
+{
+    if let Some(migrate_command) = config.migrate_command.take() {
+        crate::Migrations::run_command(database_connection_pool, migrate_command)
+            .await
+            .unwrap_print();
+        return;
+    }
+    if config.database_run_migrations {
+        crate::Migrations::run_pending(database_connection_pool)
+            .await
+            .unwrap_print();
+        tracing::info!("database migrations applied");
+    }
+    serve(config, repository).await;
+}
+
+/// `DATABASE_URL` that selects `MemoryRepository` instead of Postgres.
+const MEMORY_DATABASE_URL: &str = "memory:";
+/// Prefix of the `DATABASE_URL`s that select `SqliteRepository`.
+const SQLITE_DATABASE_URL_SCHEME: &str = "sqlite:";
+
 #[tokio::main]
 async fn main() {
-    dotenv::dotenv().expect("Failed to read environment file");
+    // The `.env` file is only a convenience for local development.
+    dotenv::dotenv().ok();
+    let mut config = Config::Config::load().unwrap_print();
+    crate::Tracing::init_tracing(&config);
 
-    let database_connection_pool = get_postgres_connection_pool().await.unwrap_print();
+    if config.database_url == MEMORY_DATABASE_URL {
+        if config.migrate_command.is_some() {
+            panic!("The in-memory store has no migrations");
+        }
+        tracing::warn!("using the in-memory store, nothing survives a restart");
+        serve(&config, MemoryRepository::MemoryRepository::default()).await;
+        tracing::info!("server stopped");
+        return;
+    }
 
-    let app = Router::new()
-        .nest("/person", crate::Person::get_router())
-        .nest("/fruit", crate::Fruit::get_router())
-        .nest("/salad", crate::Salad::get_router())
-        .nest("/ingredient", crate::SaladIngredient::getRouter())
-        .with_state(database_connection_pool);
-
-    let port = get_server_socket_addr().unwrap_print();
-    println!("Server starting on {}", &port);
-    axum::Server::bind(&port)
-        .serve(app.into_make_service())
-        .await
-        .expect("Failed to start server");
+    if config.database_url.starts_with(SQLITE_DATABASE_URL_SCHEME) {
+        #[cfg(feature = "sqlite")]
+        {
+            let database_connection_pool = get_sqlite_connection_pool(&config).await.unwrap_print();
+            let repository = SqliteRepository::SqliteRepository::new(
+                database_connection_pool.clone(),
+                config.database_max_connections,
+            );
+            migrate_and_serve(&mut config, &database_connection_pool, repository).await;
+            database_connection_pool.close().await;
+            tracing::info!("server stopped");
+            return;
+        }
+        #[cfg(not(feature = "sqlite"))]
+        panic!("SQLite support is not compiled in, build with `--features sqlite`");
+    }
+
+    let database_connection_pool = get_postgres_connection_pool(&config).await.unwrap_print();
+    let repository = PostgresRepository::PostgresRepository::new(
+        database_connection_pool.clone(),
+        config.database_max_connections,
+    );
+    migrate_and_serve(&mut config, &database_connection_pool, repository).await;
+
+    database_connection_pool.close().await;
+    tracing::info!("server stopped");
 }